@@ -0,0 +1,137 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use bid_tracker_rs::api::problem::ProblemDetails;
+use reqwest::StatusCode;
+use thiserror::Error;
+
+/// Errors returned by the `Client`, the api errors are keyed by the status code they were sent with
+/// and carry the problem details of the response
+#[derive(Error, Debug)]
+pub enum ClientError {
+	#[error("Failed to reach the bid tracker: {0}")]
+	Transport(#[from] reqwest::Error),
+
+	#[error("Failed to decode the response: {0}")]
+	Decode(#[from] serde_json::Error),
+
+	#[error("Bad request: {}", .0.detail)]
+	BadRequest(ProblemDetails),
+
+	#[error("Unauthorized: {}", .0.detail)]
+	Unauthorized(ProblemDetails),
+
+	#[error("Forbidden: {}", .0.detail)]
+	Forbidden(ProblemDetails),
+
+	#[error("Not found: {}", .0.detail)]
+	NotFound(ProblemDetails),
+
+	#[error("Conflict: {}", .0.detail)]
+	Conflict(ProblemDetails),
+
+	#[error("Unprocessable: {}", .0.detail)]
+	Unprocessable(ProblemDetails),
+
+	#[error("Too many requests: {}", .0.detail)]
+	TooManyRequests(ProblemDetails),
+
+	#[error("Unexpected response {}: {}", .0.status, .0.detail)]
+	Unexpected(ProblemDetails),
+}
+
+impl ClientError {
+	/// Map an error response to its variant, a body which is not problem details becomes the `detail`
+	pub fn from_response(status: StatusCode, body: &[u8]) -> ClientError {
+		let problem = serde_json::from_slice::<ProblemDetails>(body).unwrap_or_else(|_| {
+			let title = status.canonical_reason().unwrap_or("Error");
+			ProblemDetails {
+				problem_type: "about:blank".to_string(),
+				title: title.to_string(),
+				status: status.as_u16(),
+				code: title.to_lowercase().replace([' ', '-'], "_"),
+				detail: String::from_utf8_lossy(body).into_owned(),
+				instance: None,
+				request_id: None,
+				extensions: Default::default(),
+			}
+		});
+		match status {
+			StatusCode::BAD_REQUEST => ClientError::BadRequest(problem),
+			StatusCode::UNAUTHORIZED => ClientError::Unauthorized(problem),
+			StatusCode::FORBIDDEN => ClientError::Forbidden(problem),
+			StatusCode::NOT_FOUND => ClientError::NotFound(problem),
+			StatusCode::CONFLICT => ClientError::Conflict(problem),
+			StatusCode::UNPROCESSABLE_ENTITY => ClientError::Unprocessable(problem),
+			StatusCode::TOO_MANY_REQUESTS => ClientError::TooManyRequests(problem),
+			_ => ClientError::Unexpected(problem),
+		}
+	}
+
+	/// The problem details sent by the server, if the error is an api error
+	pub fn problem(&self) -> Option<&ProblemDetails> {
+		match self {
+			ClientError::Transport(_) | ClientError::Decode(_) => None,
+			ClientError::BadRequest(problem)
+			| ClientError::Unauthorized(problem)
+			| ClientError::Forbidden(problem)
+			| ClientError::NotFound(problem)
+			| ClientError::Conflict(problem)
+			| ClientError::Unprocessable(problem)
+			| ClientError::TooManyRequests(problem)
+			| ClientError::Unexpected(problem) => Some(problem),
+		}
+	}
+
+	/// The stable code of an api error, e.g. `item_not_biddable`
+	pub fn code(&self) -> Option<&str> {
+		self.problem().map(|problem| problem.code.as_str())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_from_response() {
+		let body = br#"{"type": "about:blank", "title": "Bad Request", "status": 400, "code": "invalid_query",
+			"detail": "Invalid cursor", "request_id": "abc"}"#;
+		match ClientError::from_response(StatusCode::BAD_REQUEST, body) {
+			ClientError::BadRequest(problem) => {
+				assert_eq!(problem.detail, "Invalid cursor");
+				assert_eq!(problem.request_id.as_deref(), Some("abc"));
+			}
+			e => panic!("Unexpected error {:?}", e),
+		}
+
+		let error = ClientError::from_response(StatusCode::SERVICE_UNAVAILABLE, b"Try again later");
+		assert_eq!(error.code(), Some("service_unavailable"));
+		match error {
+			ClientError::Unexpected(problem) => {
+				assert_eq!(problem.status, 503);
+				assert_eq!(problem.detail, "Try again later");
+			}
+			e => panic!("Unexpected error {:?}", e),
+		}
+	}
+}
@@ -0,0 +1,886 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Typed async client for the `/api/v1` routes of bid-tracker-rs.
+//!
+//! The request and response types are the ones of the server, so both always agree on the wire format.
+
+mod error;
+
+pub use bid_tracker_rs::api::api_keys::{ApiKeyScopes, CreatedApiKey, NewApiKey};
+pub use bid_tracker_rs::api::batch::BatchResult;
+pub use bid_tracker_rs::api::blocklist::{Allowlist, Ban, Blocklist, NewBan};
+pub use bid_tracker_rs::api::export::ExportQuery;
+pub use bid_tracker_rs::api::rate_limit::RateLimitsReport;
+pub use bid_tracker_rs::api::webhooks::{NewWebhook, WebhookSummary};
+pub use bid_tracker_rs::api::{CreditLimit, NewItem};
+pub use bid_tracker_rs::api_keys::{ApiKey, Scope};
+pub use bid_tracker_rs::audit::{BrokenLink, ChainHead, Verification};
+pub use bid_tracker_rs::bidtracker::{
+	AuctionItem, BannedUser, Bid, BidPage, BidQuery, BlockReason, BlockedBidCounter, Exposure, UserItem,
+};
+pub use bid_tracker_rs::fraud::{Flag, FlagQuery, FlaggedBid, FraudMode, FraudRule, FraudRules};
+pub use bid_tracker_rs::import::{ImportFormat, ImportReport};
+pub use bid_tracker_rs::notifications::UserPreferences;
+pub use bid_tracker_rs::rate_limit::{Limit, RateLimitCounter, RateLimits};
+pub use bid_tracker_rs::webhooks::{Delivery, WebhookSubscription};
+pub use error::ClientError;
+
+use bid_tracker_rs::api::{routes, ResponseMessage};
+use bid_tracker_rs::api_keys::HEADER_API_KEY;
+use bid_tracker_rs::idempotency::HEADER_IDEMPOTENCY_KEY;
+use reqwest::{header, Method, RequestBuilder};
+use serde::{de::DeserializeOwned, Serialize};
+
+const CONTENT_TYPE_NDJSON: &str = "application/x-ndjson";
+
+/// Client for a single bid tracker, cheap to clone
+#[derive(Debug, Clone)]
+pub struct Client {
+	base_url: String,
+	http: reqwest::Client,
+	token: Option<String>,
+	api_key: Option<String>,
+}
+
+impl Client {
+	/// Create a client for the server at `base_url`, e.g. `http://localhost:3000`
+	pub fn new(base_url: impl Into<String>) -> Client {
+		Client::with_http_client(base_url, reqwest::Client::new())
+	}
+
+	/// Same as `new` but with a preconfigured reqwest client, e.g. to set timeouts
+	pub fn with_http_client(base_url: impl Into<String>, http: reqwest::Client) -> Client {
+		let base_url = base_url.into().trim_end_matches('/').to_string();
+		Client {
+			base_url,
+			http,
+			token: None,
+			api_key: None,
+		}
+	}
+
+	/// Send `token` as the bearer token of every request
+	pub fn with_token(mut self, token: impl Into<String>) -> Client {
+		self.token = Some(token.into());
+		self
+	}
+
+	/// Send `key` as the `X-Api-Key` header of every request, for service to service calls
+	pub fn with_api_key(mut self, key: impl Into<String>) -> Client {
+		self.api_key = Some(key.into());
+		self
+	}
+
+	fn request(&self, method: Method, url: String) -> RequestBuilder {
+		let mut request = self.http.request(method, url);
+		if let Some(token) = &self.token {
+			request = request.bearer_auth(token);
+		}
+		if let Some(key) = &self.api_key {
+			request = request.header(HEADER_API_KEY, key);
+		}
+		request
+	}
+
+	// Fill the `{name}` placeholders of a route from routes.rs
+	fn url(&self, route: &str, params: &[(&str, &uuid::Uuid)]) -> String {
+		let path = params.iter().fold(route.to_string(), |path, (name, value)| {
+			path.replace(&format!("{{{}}}", name), &value.to_string())
+		});
+		format!("{}{}{}", self.base_url, routes::API_PREFIX, path)
+	}
+
+	async fn send<T>(&self, request: RequestBuilder) -> Result<ResponseMessage<T>, ClientError>
+	where
+		T: Serialize + DeserializeOwned,
+	{
+		let response = request.send().await?;
+		let status = response.status();
+		let body = response.bytes().await?;
+		if !status.is_success() {
+			return Err(ClientError::from_response(status, &body));
+		}
+		Ok(serde_json::from_slice(&body)?)
+	}
+
+	async fn send_text(&self, request: RequestBuilder) -> Result<String, ClientError> {
+		let response = request.send().await?;
+		let status = response.status();
+		if !status.is_success() {
+			let body = response.bytes().await?;
+			return Err(ClientError::from_response(status, &body));
+		}
+		Ok(response.text().await?)
+	}
+
+	/// Place a new bid
+	pub async fn place_bid(&self, bid: &Bid) -> Result<Bid, ClientError> {
+		let request = self
+			.request(Method::POST, self.url(routes::URL_BID_ITEM, &[]))
+			.json(bid);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Place a new bid which is only placed once however often it is retried with the same key
+	pub async fn place_bid_idempotent(&self, bid: &Bid, key: &str) -> Result<Bid, ClientError> {
+		let request = self
+			.request(Method::POST, self.url(routes::URL_BID_ITEM, &[]))
+			.header(HEADER_IDEMPOTENCY_KEY, key)
+			.json(bid);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Place a batch of bids, the results of a rejected atomic batch are returned as well
+	pub async fn place_bids(&self, bids: &[Bid], atomic: bool) -> Result<Vec<BatchResult>, ClientError> {
+		let response = self
+			.request(Method::POST, self.url(routes::URL_BID_BATCH, &[]))
+			.query(&[("atomic", atomic)])
+			.json(bids)
+			.send()
+			.await?;
+		let status = response.status();
+		let body = response.bytes().await?;
+		if status.is_success() {
+			return Ok(serde_json::from_slice::<ResponseMessage<Vec<BatchResult>>>(&body)?.data);
+		}
+		let error = ClientError::from_response(status, &body);
+		match error.problem().and_then(|problem| problem.extensions.get("results")) {
+			Some(results) => Ok(serde_json::from_value(results.clone())?),
+			None => Err(error),
+		}
+	}
+
+	/// Get a page of the bids on an item
+	pub async fn get_bids(&self, item_uuid: &uuid::Uuid, query: &BidQuery) -> Result<BidPage, ClientError> {
+		let request = self
+			.request(
+				Method::GET,
+				self.url(routes::URL_BID_GET_ALL, &[("itemuuid", item_uuid)]),
+			)
+			.query(query);
+		let message = self.send(request).await?;
+		Ok(BidPage {
+			bids: message.data,
+			next_cursor: message.next_cursor,
+		})
+	}
+
+	/// Get the current winning bid of an item, `None` while it has no bids
+	pub async fn get_winning_bid(&self, item_uuid: &uuid::Uuid) -> Result<Option<Bid>, ClientError> {
+		let request = self.request(
+			Method::GET,
+			self.url(routes::URL_BID_GET_WINNING, &[("itemuuid", item_uuid)]),
+		);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Get a page of the bids of a user
+	pub async fn get_user_bids(&self, user_uuid: &uuid::Uuid, query: &BidQuery) -> Result<BidPage, ClientError> {
+		let request = self
+			.request(
+				Method::GET,
+				self.url(routes::URL_USER_GET_ALL_BIDS, &[("useruuid", user_uuid)]),
+			)
+			.query(query);
+		let message = self.send(request).await?;
+		Ok(BidPage {
+			bids: message.data,
+			next_cursor: message.next_cursor,
+		})
+	}
+
+	/// Get the items a user has bid on, with the user's standing on each
+	pub async fn get_user_items(&self, user_uuid: &uuid::Uuid) -> Result<Vec<UserItem>, ClientError> {
+		let request = self.request(
+			Method::GET,
+			self.url(routes::URL_USER_GET_ALL_ITEMS, &[("useruuid", user_uuid)]),
+		);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Get the exposure and credit limit of a user
+	pub async fn get_exposure(&self, user_uuid: &uuid::Uuid) -> Result<Exposure, ClientError> {
+		let request = self.request(
+			Method::GET,
+			self.url(routes::URL_USER_EXPOSURE, &[("useruuid", user_uuid)]),
+		);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Set the credit limit of a user, `None` falls back to the default limit
+	pub async fn set_credit_limit(
+		&self,
+		user_uuid: &uuid::Uuid,
+		credit_limit: Option<f64>,
+	) -> Result<Exposure, ClientError> {
+		let request = self
+			.request(
+				Method::PUT,
+				self.url(routes::URL_ADMIN_USER_CREDIT_LIMIT, &[("useruuid", user_uuid)]),
+			)
+			.json(&CreditLimit { credit_limit });
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Put a new item up for auction
+	pub async fn create_item(&self, item: &NewItem) -> Result<AuctionItem, ClientError> {
+		let request = self.request(Method::POST, self.url(routes::URL_ITEMS, &[])).json(item);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Close the bidding on an item, returns its final winning bid
+	pub async fn close_item(&self, item_uuid: &uuid::Uuid) -> Result<Option<Bid>, ClientError> {
+		let request = self.request(
+			Method::POST,
+			self.url(routes::URL_ITEM_CLOSE, &[("itemuuid", item_uuid)]),
+		);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Download the bids on an item in the format asked for in `query`
+	pub async fn export_item_bids(&self, item_uuid: &uuid::Uuid, query: &ExportQuery) -> Result<String, ClientError> {
+		let request = self
+			.request(
+				Method::GET,
+				self.url(routes::URL_BID_EXPORT, &[("itemuuid", item_uuid)]),
+			)
+			.query(query);
+		self.send_text(request).await
+	}
+
+	/// Download the bids on all the items in the format asked for in `query`
+	pub async fn export_all_bids(&self, query: &ExportQuery) -> Result<String, ClientError> {
+		let request = self
+			.request(Method::GET, self.url(routes::URL_EXPORT, &[]))
+			.query(query);
+		self.send_text(request).await
+	}
+
+	/// Import a csv or ndjson file of historical bids
+	pub async fn import_bids(
+		&self,
+		data: Vec<u8>,
+		format: ImportFormat,
+		dry_run: bool,
+	) -> Result<ImportReport, ClientError> {
+		let content_type = match format {
+			ImportFormat::Csv => "text/csv",
+			ImportFormat::Ndjson => CONTENT_TYPE_NDJSON,
+		};
+		let request = self
+			.request(Method::POST, self.url(routes::URL_ADMIN_IMPORT, &[]))
+			.query(&[("dry_run", dry_run)])
+			.header(header::CONTENT_TYPE, content_type)
+			.body(data);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Register a webhook, the returned subscription is the only place its secret shows up
+	pub async fn register_webhook(&self, webhook: &NewWebhook) -> Result<WebhookSubscription, ClientError> {
+		let request = self
+			.request(Method::POST, self.url(routes::URL_WEBHOOKS, &[]))
+			.json(webhook);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// List the registered webhooks
+	pub async fn get_webhooks(&self) -> Result<Vec<WebhookSummary>, ClientError> {
+		let request = self.request(Method::GET, self.url(routes::URL_WEBHOOKS, &[]));
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Remove a webhook
+	pub async fn delete_webhook(&self, webhook_uuid: &uuid::Uuid) -> Result<(), ClientError> {
+		let request = self.request(
+			Method::DELETE,
+			self.url(routes::URL_WEBHOOK, &[("webhookuuid", webhook_uuid)]),
+		);
+		self.send::<uuid::Uuid>(request).await?;
+		Ok(())
+	}
+
+	/// Get the webhook deliveries which ran out of retries
+	pub async fn get_dead_letters(&self) -> Result<Vec<Delivery>, ClientError> {
+		let request = self.request(Method::GET, self.url(routes::URL_WEBHOOK_DEAD_LETTERS, &[]));
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Get the notification preferences of a user
+	pub async fn get_notification_preferences(&self, user_uuid: &uuid::Uuid) -> Result<UserPreferences, ClientError> {
+		let request = self.request(
+			Method::GET,
+			self.url(routes::URL_USER_NOTIFICATIONS, &[("useruuid", user_uuid)]),
+		);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Set or, with None, remove the address outbid emails are sent to
+	pub async fn set_notification_email(
+		&self,
+		user_uuid: &uuid::Uuid,
+		email: Option<&str>,
+	) -> Result<UserPreferences, ClientError> {
+		let request = self
+			.request(
+				Method::PUT,
+				self.url(routes::URL_USER_NOTIFICATIONS, &[("useruuid", user_uuid)]),
+			)
+			.json(&serde_json::json!({ "email": email }));
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Opt a user in or out of the outbid notifications for an item
+	pub async fn set_item_notifications(
+		&self,
+		user_uuid: &uuid::Uuid,
+		item_uuid: &uuid::Uuid,
+		enabled: bool,
+	) -> Result<UserPreferences, ClientError> {
+		let url = self.url(
+			routes::URL_USER_ITEM_NOTIFICATIONS,
+			&[("useruuid", user_uuid), ("itemuuid", item_uuid)],
+		);
+		let request = self
+			.request(Method::PUT, url)
+			.json(&serde_json::json!({ "enabled": enabled }));
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Create an api key, the returned key is the only place the key itself shows up
+	pub async fn create_api_key(&self, new_key: &NewApiKey) -> Result<CreatedApiKey, ClientError> {
+		let request = self
+			.request(Method::POST, self.url(routes::URL_ADMIN_API_KEYS, &[]))
+			.json(new_key);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// List the api keys, including the revoked ones
+	pub async fn get_api_keys(&self) -> Result<Vec<ApiKey>, ClientError> {
+		let request = self.request(Method::GET, self.url(routes::URL_ADMIN_API_KEYS, &[]));
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Replace the scopes of an api key
+	pub async fn set_api_key_scopes(&self, key_uuid: &uuid::Uuid, scopes: Vec<Scope>) -> Result<ApiKey, ClientError> {
+		let request = self
+			.request(
+				Method::PATCH,
+				self.url(routes::URL_ADMIN_API_KEY, &[("keyuuid", key_uuid)]),
+			)
+			.json(&ApiKeyScopes { scopes });
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Revoke an api key
+	pub async fn revoke_api_key(&self, key_uuid: &uuid::Uuid) -> Result<ApiKey, ClientError> {
+		let request = self.request(
+			Method::DELETE,
+			self.url(routes::URL_ADMIN_API_KEY, &[("keyuuid", key_uuid)]),
+		);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Get the rate limits of bid placement and how often they kicked in
+	pub async fn get_rate_limits(&self) -> Result<RateLimitsReport, ClientError> {
+		let request = self.request(Method::GET, self.url(routes::URL_ADMIN_RATE_LIMITS, &[]));
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Replace the rate limits of bid placement
+	pub async fn set_rate_limits(&self, limits: &RateLimits) -> Result<RateLimitsReport, ClientError> {
+		let request = self
+			.request(Method::PUT, self.url(routes::URL_ADMIN_RATE_LIMITS, &[]))
+			.json(limits);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Get the bids flagged by the fraud rules, most recent first
+	pub async fn get_flagged_bids(&self, query: &FlagQuery) -> Result<Vec<FlaggedBid>, ClientError> {
+		let request = self
+			.request(Method::GET, self.url(routes::URL_ADMIN_FRAUD_FLAGS, &[]))
+			.query(query);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Get the mode and thresholds of the fraud rules
+	pub async fn get_fraud_rules(&self) -> Result<FraudRules, ClientError> {
+		let request = self.request(Method::GET, self.url(routes::URL_ADMIN_FRAUD_RULES, &[]));
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Replace the mode and thresholds of the fraud rules
+	pub async fn set_fraud_rules(&self, rules: &FraudRules) -> Result<FraudRules, ClientError> {
+		let request = self
+			.request(Method::PUT, self.url(routes::URL_ADMIN_FRAUD_RULES, &[]))
+			.json(rules);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Get the head of the audit chain
+	pub async fn get_audit_head(&self) -> Result<ChainHead, ClientError> {
+		let request = self.request(Method::GET, self.url(routes::URL_ADMIN_AUDIT_HEAD, &[]));
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Verify the audit chain of the server up to its head
+	pub async fn verify_audit(&self) -> Result<Verification, ClientError> {
+		let request = self.request(Method::GET, self.url(routes::URL_ADMIN_AUDIT_VERIFY, &[]));
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Get the banned users and the counters of bids turned away
+	pub async fn get_blocklist(&self) -> Result<Blocklist, ClientError> {
+		let request = self.request(Method::GET, self.url(routes::URL_ADMIN_BLOCKLIST, &[]));
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Ban a user from bidding, optionally retracting their bids on open items
+	pub async fn ban_user(&self, user_uuid: &uuid::Uuid, ban: &NewBan) -> Result<Ban, ClientError> {
+		let request = self
+			.request(
+				Method::PUT,
+				self.url(routes::URL_ADMIN_BLOCKLIST_USER, &[("useruuid", user_uuid)]),
+			)
+			.json(ban);
+		Ok(self.send(request).await?.data)
+	}
+
+	/// Lift the ban of a user
+	pub async fn unban_user(&self, user_uuid: &uuid::Uuid) -> Result<(), ClientError> {
+		let request = self.request(
+			Method::DELETE,
+			self.url(routes::URL_ADMIN_BLOCKLIST_USER, &[("useruuid", user_uuid)]),
+		);
+		self.send::<uuid::Uuid>(request).await?;
+		Ok(())
+	}
+
+	/// Get the users invited to bid on an item, `None` if everybody may bid on it
+	pub async fn get_allowlist(&self, item_uuid: &uuid::Uuid) -> Result<Option<Vec<uuid::Uuid>>, ClientError> {
+		let request = self.request(
+			Method::GET,
+			self.url(routes::URL_ADMIN_ITEM_ALLOWLIST, &[("itemuuid", item_uuid)]),
+		);
+		Ok(self.send::<Allowlist>(request).await?.data.useruuids)
+	}
+
+	/// Make an item private to the given users, or open it to everybody again with `None`
+	pub async fn set_allowlist(
+		&self,
+		item_uuid: &uuid::Uuid,
+		useruuids: Option<Vec<uuid::Uuid>>,
+	) -> Result<Option<Vec<uuid::Uuid>>, ClientError> {
+		let request = self
+			.request(
+				Method::PUT,
+				self.url(routes::URL_ADMIN_ITEM_ALLOWLIST, &[("itemuuid", item_uuid)]),
+			)
+			.json(&Allowlist { useruuids });
+		Ok(self.send::<Allowlist>(request).await?.data.useruuids)
+	}
+
+	/// Get the OpenAPI document of the server
+	pub async fn openapi(&self) -> Result<serde_json::Value, ClientError> {
+		let response = self
+			.request(Method::GET, self.url(routes::URL_OPENAPI, &[]))
+			.send()
+			.await?;
+		let status = response.status();
+		let body = response.bytes().await?;
+		if !status.is_success() {
+			return Err(ClientError::from_response(status, &body));
+		}
+		Ok(serde_json::from_slice(&body)?)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use actix_web::{App, HttpServer};
+	use bid_tracker_rs::api::AppState;
+	use bid_tracker_rs::auth::JwtKeys;
+	use bid_tracker_rs::bidtracker::{BidManagement, BidTracker};
+	use std::time::Duration;
+
+	const ITEM: &str = "b2f9ee6d-79fe-4b14-9c19-35a69a89219a";
+	const USER: &str = "1c916ab6-255b-4a36-9574-e456e0f774c9";
+
+	// Run the real app on a random local port
+	fn spawn_app() -> Client {
+		spawn_app_with_keys(JwtKeys::disabled())
+	}
+
+	fn spawn_app_with_keys(jwt_keys: JwtKeys) -> Client {
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let state = AppState::for_tests(BidManagement::new(vec![item_uuid]), jwt_keys);
+		let server = HttpServer::new(move || App::new().configure(|cfg| state.configure(cfg)))
+			.workers(1)
+			.bind("127.0.0.1:0")
+			.unwrap();
+		let addr = server.addrs()[0];
+		actix_rt::spawn(server.run());
+		Client::new(format!("http://{}/", addr))
+	}
+
+	fn bid(amount: f64) -> Bid {
+		Bid {
+			item_uuid: uuid::Uuid::parse_str(ITEM).unwrap(),
+			user_uuid: uuid::Uuid::parse_str(USER).unwrap(),
+			timestamp: amount as i64,
+			amount,
+		}
+	}
+
+	#[actix_rt::test]
+	async fn test_bids() {
+		let client = spawn_app();
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let user_uuid = uuid::Uuid::parse_str(USER).unwrap();
+
+		assert!(client.get_winning_bid(&item_uuid).await.unwrap().is_none());
+		assert_eq!(client.place_bid(&bid(30f64)).await.unwrap().amount, 30f64);
+		let key = uuid::Uuid::new_v4().to_string();
+		client.place_bid_idempotent(&bid(31f64), &key).await.unwrap();
+		client.place_bid_idempotent(&bid(31f64), &key).await.unwrap();
+		match client.place_bid_idempotent(&bid(32f64), &key).await {
+			Err(ClientError::Conflict(_)) => {}
+			result => panic!("Unexpected result {:?}", result),
+		}
+
+		let results = client.place_bids(&[bid(33f64), bid(34f64)], false).await.unwrap();
+		assert_eq!(results.len(), 2);
+
+		let query = BidQuery {
+			limit: Some(2),
+			..BidQuery::default()
+		};
+		let page = client.get_bids(&item_uuid, &query).await.unwrap();
+		assert_eq!(page.bids.len(), 2);
+		let query = BidQuery {
+			cursor: page.next_cursor,
+			..BidQuery::default()
+		};
+		let page = client.get_bids(&item_uuid, &query).await.unwrap();
+		assert_eq!(page.bids.len(), 2);
+		assert!(page.next_cursor.is_none());
+
+		assert_eq!(client.get_winning_bid(&item_uuid).await.unwrap().unwrap().amount, 34f64);
+		let page = client.get_user_bids(&user_uuid, &BidQuery::default()).await.unwrap();
+		assert_eq!(page.bids.len(), 4);
+		let items = client.get_user_items(&user_uuid).await.unwrap();
+		assert!(items[0].winning);
+
+		let export = client
+			.export_item_bids(&item_uuid, &ExportQuery::default())
+			.await
+			.unwrap();
+		assert_eq!(export.lines().count(), 4);
+
+		let winner = client.close_item(&item_uuid).await.unwrap().unwrap();
+		assert_eq!(winner.amount, 34f64);
+		match client.place_bid(&bid(35f64)).await {
+			Err(ClientError::Unprocessable(_)) => {}
+			result => panic!("Unexpected result {:?}", result),
+		}
+	}
+
+	#[actix_rt::test]
+	async fn test_items() {
+		let client = spawn_app();
+		let seller = uuid::Uuid::new_v4();
+		let new_item = NewItem {
+			itemuuid: None,
+			seller: Some(seller),
+		};
+		let item = client.create_item(&new_item).await.unwrap();
+		assert_eq!(item.seller, Some(seller));
+
+		let bid = Bid {
+			item_uuid: item.item_uuid,
+			user_uuid: seller,
+			timestamp: 1,
+			amount: 30f64,
+		};
+		match client.place_bid(&bid).await {
+			Err(ClientError::Forbidden(problem)) => assert_eq!(problem.code, "forbidden"),
+			result => panic!("Unexpected result {:?}", result),
+		}
+	}
+
+	#[actix_rt::test]
+	async fn test_credit_limit() {
+		let client = spawn_app();
+		let user_uuid = uuid::Uuid::parse_str(USER).unwrap();
+		let exposure = client.set_credit_limit(&user_uuid, Some(50f64)).await.unwrap();
+		assert_eq!(exposure.available, Some(50f64));
+
+		let bid = |amount| Bid {
+			item_uuid: uuid::Uuid::parse_str(ITEM).unwrap(),
+			user_uuid,
+			timestamp: 1,
+			amount,
+		};
+		client.place_bid(&bid(30f64)).await.unwrap();
+		match client.place_bid(&bid(60f64)).await {
+			Err(error) => assert_eq!(error.code(), Some("credit_limit_exceeded")),
+			result => panic!("Unexpected result {:?}", result),
+		}
+		let exposure = client.get_exposure(&user_uuid).await.unwrap();
+		assert_eq!(exposure.exposure, 30f64);
+	}
+
+	#[actix_rt::test]
+	async fn test_fraud_rules() {
+		let client = spawn_app();
+		let rules = FraudRules {
+			mode: FraudMode::Enforce,
+			velocity_max_bids: 1,
+			..FraudRules::default()
+		};
+		assert_eq!(client.set_fraud_rules(&rules).await.unwrap(), rules);
+
+		let bid = |amount| Bid {
+			item_uuid: uuid::Uuid::parse_str(ITEM).unwrap(),
+			user_uuid: uuid::Uuid::parse_str(USER).unwrap(),
+			timestamp: 1,
+			amount,
+		};
+		client.place_bid(&bid(30f64)).await.unwrap();
+		match client.place_bid(&bid(35f64)).await {
+			Err(ClientError::Forbidden(problem)) => assert_eq!(problem.code, "fraud_suspected"),
+			result => panic!("Unexpected result {:?}", result),
+		}
+		let flagged = client.get_flagged_bids(&FlagQuery::default()).await.unwrap();
+		assert_eq!(flagged.len(), 1);
+		assert_eq!(flagged[0].flags[0].rule, FraudRule::VelocitySpike);
+		assert_eq!(client.get_fraud_rules().await.unwrap().mode, FraudMode::Enforce);
+	}
+
+	#[actix_rt::test]
+	async fn test_audit() {
+		let client = spawn_app();
+		let item = client.create_item(&NewItem::default()).await.unwrap();
+		client.close_item(&item.item_uuid).await.unwrap();
+
+		let head = client.get_audit_head().await.unwrap();
+		assert_eq!(head.seq, 2);
+		let verification = client.verify_audit().await.unwrap();
+		assert!(verification.valid);
+		assert_eq!(verification.head, head);
+	}
+
+	#[actix_rt::test]
+	async fn test_blocklist() {
+		let client = spawn_app();
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let user_uuid = uuid::Uuid::parse_str(USER).unwrap();
+		let bid = |amount| Bid {
+			item_uuid,
+			user_uuid,
+			timestamp: 1,
+			amount,
+		};
+		client.place_bid(&bid(30f64)).await.unwrap();
+
+		let ban = NewBan {
+			reason: Some("Shill bidding".into()),
+			retract_bids: true,
+		};
+		let ban = client.ban_user(&user_uuid, &ban).await.unwrap();
+		assert_eq!(ban.retracted_bids.len(), 1);
+		assert_eq!(client.get_winning_bid(&item_uuid).await.unwrap(), None);
+		match client.place_bid(&bid(35f64)).await {
+			Err(ClientError::Forbidden(problem)) => assert_eq!(problem.code, "bidder_blocked"),
+			result => panic!("Unexpected result {:?}", result),
+		}
+		let blocklist = client.get_blocklist().await.unwrap();
+		assert_eq!(blocklist.banned[0].user_uuid, user_uuid);
+		assert_eq!(blocklist.blocked_bids[0].count, 1);
+
+		client.unban_user(&user_uuid).await.unwrap();
+		let invited = uuid::Uuid::new_v4();
+		let allowlist = client.set_allowlist(&item_uuid, Some(vec![invited])).await.unwrap();
+		assert_eq!(allowlist, Some(vec![invited]));
+		assert!(client.place_bid(&bid(35f64)).await.is_err());
+		client.set_allowlist(&item_uuid, None).await.unwrap();
+		assert_eq!(client.get_allowlist(&item_uuid).await.unwrap(), None);
+		client.place_bid(&bid(35f64)).await.unwrap();
+	}
+
+	#[actix_rt::test]
+	async fn test_rate_limits() {
+		let client = spawn_app();
+		let limits = RateLimits {
+			ip: Limit::from_settings(1, 0.001),
+			..RateLimits::default()
+		};
+		let report = client.set_rate_limits(&limits).await.unwrap();
+		assert_eq!(report.limits, limits);
+
+		let bid = |amount| Bid {
+			item_uuid: uuid::Uuid::parse_str(ITEM).unwrap(),
+			user_uuid: uuid::Uuid::parse_str(USER).unwrap(),
+			timestamp: 1,
+			amount,
+		};
+		client.place_bid(&bid(30f64)).await.unwrap();
+		match client.place_bid(&bid(35f64)).await {
+			Err(ClientError::TooManyRequests(problem)) => assert_eq!(problem.code, "rate_limited"),
+			result => panic!("Unexpected result {:?}", result),
+		}
+		let report = client.get_rate_limits().await.unwrap();
+		assert_eq!(report.counters[0].limited, 1);
+	}
+
+	#[actix_rt::test]
+	async fn test_errors() {
+		let client = spawn_app();
+		let unknown = uuid::Uuid::new_v4();
+		match client.get_winning_bid(&unknown).await {
+			Err(ClientError::NotFound(problem)) => assert_eq!(problem.code, "item_not_found"),
+			result => panic!("Unexpected result {:?}", result),
+		}
+
+		let query = BidQuery {
+			cursor: Some("not-a-cursor".into()),
+			..BidQuery::default()
+		};
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		assert!(client.get_bids(&item_uuid, &query).await.is_err());
+
+		match client.get_user_items(&unknown).await {
+			Err(error) => assert_eq!(error.code(), Some("user_not_found")),
+			result => panic!("Unexpected result {:?}", result),
+		}
+
+		match client.delete_webhook(&unknown).await {
+			Err(ClientError::NotFound(_)) => {}
+			result => panic!("Unexpected result {:?}", result),
+		}
+
+		let unreachable = Client::new("http://127.0.0.1:9");
+		match unreachable.get_winning_bid(&item_uuid).await {
+			Err(ClientError::Transport(_)) => {}
+			result => panic!("Unexpected result {:?}", result),
+		}
+	}
+
+	#[actix_rt::test]
+	async fn test_admin_endpoints() {
+		let client = spawn_app();
+		let user_uuid = uuid::Uuid::parse_str(USER).unwrap();
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+
+		let webhook = NewWebhook {
+			url: "http://127.0.0.1:9/hook".into(),
+			events: vec![],
+			secret: None,
+		};
+		let subscription = client.register_webhook(&webhook).await.unwrap();
+		assert_eq!(client.get_webhooks().await.unwrap().len(), 1);
+		client.delete_webhook(&subscription.id).await.unwrap();
+		assert!(client.get_dead_letters().await.unwrap().is_empty());
+
+		let preferences = client
+			.set_notification_email(&user_uuid, Some("bidder@example.com"))
+			.await
+			.unwrap();
+		assert_eq!(preferences.email.as_deref(), Some("bidder@example.com"));
+		let preferences = client
+			.set_item_notifications(&user_uuid, &item_uuid, false)
+			.await
+			.unwrap();
+		assert!(preferences.muted_items.contains(&item_uuid));
+		assert_eq!(
+			client.get_notification_preferences(&user_uuid).await.unwrap(),
+			preferences
+		);
+
+		let csv = format!("itemuuid,useruuid,timestamp,amount\n{ITEM},{USER},10,30\n");
+		let report = client
+			.import_bids(csv.into_bytes(), ImportFormat::Csv, true)
+			.await
+			.unwrap();
+		assert_eq!(report.accepted, 1);
+
+		let spec = client.openapi().await.unwrap();
+		assert!(spec["paths"]["/bids"].is_object());
+	}
+
+	#[actix_rt::test]
+	async fn test_token() {
+		let path = std::env::temp_dir().join(format!("jwt-{}.secret", uuid::Uuid::new_v4()));
+		std::fs::write(&path, "secret").unwrap();
+		let jwt_keys = JwtKeys::new(Some(path.clone()), None, Duration::MAX).unwrap();
+		let client = spawn_app_with_keys(jwt_keys);
+
+		match client.place_bid(&bid(30f64)).await {
+			Err(ClientError::Unauthorized(problem)) => assert_eq!(problem.code, "unauthorized"),
+			result => panic!("Unexpected result {:?}", result),
+		}
+
+		let claims = bid_tracker_rs::auth::Claims {
+			sub: uuid::Uuid::parse_str(USER).unwrap(),
+			exp: 4_102_444_800,
+			roles: vec![],
+		};
+		let key = jsonwebtoken::EncodingKey::from_secret(b"secret");
+		let token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap();
+		let client = client.with_token(token);
+		assert_eq!(client.place_bid(&bid(30f64)).await.unwrap().amount, 30f64);
+		match client.import_bids(vec![], ImportFormat::Ndjson, true).await {
+			Err(ClientError::Forbidden(_)) => {}
+			result => panic!("Unexpected result {:?}", result),
+		}
+		std::fs::remove_file(path).unwrap();
+	}
+
+	#[actix_rt::test]
+	async fn test_api_key() {
+		let admin = spawn_app();
+		let new_key = NewApiKey {
+			name: "payments".into(),
+			scopes: vec![Scope::Read],
+		};
+		let created = admin.create_api_key(&new_key).await.unwrap();
+		assert_eq!(admin.get_api_keys().await.unwrap(), vec![created.api_key.clone()]);
+
+		let service = admin.clone().with_api_key(created.key);
+		match service.place_bid(&bid(30f64)).await {
+			Err(ClientError::Forbidden(problem)) => assert_eq!(problem.code, "forbidden"),
+			result => panic!("Unexpected result {:?}", result),
+		}
+		admin
+			.set_api_key_scopes(&created.api_key.id, vec![Scope::BidOnBehalf])
+			.await
+			.unwrap();
+		assert_eq!(service.place_bid(&bid(30f64)).await.unwrap().amount, 30f64);
+
+		admin.revoke_api_key(&created.api_key.id).await.unwrap();
+		match service.place_bid(&bid(31f64)).await {
+			Err(ClientError::Unauthorized(_)) => {}
+			result => panic!("Unexpected result {:?}", result),
+		}
+	}
+}
@@ -0,0 +1,208 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::problem::ProblemDetails;
+use super::{send_json, ResponseMessage};
+use crate::api_keys::{ApiKey, ApiKeyStore, Scope};
+use crate::auth::Caller;
+use crate::errors::BidTrackerError;
+use crate::persistence::now_millis;
+use actix_web::{http::StatusCode, web, Error as ActixErr, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct NewApiKey {
+	pub name: String,
+	pub scopes: Vec<Scope>,
+}
+
+/// A newly created api key, the only time the key itself is returned
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct CreatedApiKey {
+	#[serde(flatten)]
+	pub api_key: ApiKey,
+	// Sent as the `X-Api-Key` header
+	pub key: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ApiKeyScopes {
+	pub scopes: Vec<Scope>,
+}
+
+/// Create an api key with the given scopes
+#[utoipa::path(
+	post,
+	path = "/admin/api-keys",
+	tag = "admin",
+	request_body = NewApiKey,
+	responses(
+		(status = 201, description = "The api key was created", body = ResponseMessage<CreatedApiKey>),
+		(status = 401, description = "No valid credentials were sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "The name or the scopes are empty", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn create_api_key(
+	new_key: web::Json<NewApiKey>,
+	caller: Caller,
+	store: web::Data<ApiKeyStore>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let new_key = new_key.into_inner();
+	if new_key.name.trim().is_empty() {
+		return Err(BidTrackerError::InvalidRequest("An api key needs a name".into()).into());
+	}
+	let (api_key, key) = store.create(new_key.name, new_key.scopes, now_millis())?;
+	send_json(
+		StatusCode::CREATED,
+		"Returning from create_api_key",
+		&CreatedApiKey { api_key, key },
+	)
+}
+
+/// List every api key, including the revoked ones
+#[utoipa::path(
+	get,
+	path = "/admin/api-keys",
+	tag = "admin",
+	responses(
+		(status = 200, description = "The api keys, without the keys themselves", body = ResponseMessage<Vec<ApiKey>>),
+		(status = 401, description = "No valid credentials were sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_api_keys(caller: Caller, store: web::Data<ApiKeyStore>) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	send_json(StatusCode::OK, "Returning from get_api_keys", &store.list())
+}
+
+/// Replace the scopes of an api key
+#[utoipa::path(
+	patch,
+	path = "/admin/api-keys/{keyuuid}",
+	tag = "admin",
+	params(("keyuuid" = uuid::Uuid, Path, description = "Uuid of the api key")),
+	request_body = ApiKeyScopes,
+	responses(
+		(status = 200, description = "The updated api key", body = ResponseMessage<ApiKey>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The api key does not exist", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "The scopes are empty", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn patch_api_key(
+	key_uuid: web::Path<uuid::Uuid>,
+	scopes: web::Json<ApiKeyScopes>,
+	caller: Caller,
+	store: web::Data<ApiKeyStore>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let api_key = store.set_scopes(&key_uuid, scopes.into_inner().scopes)?;
+	send_json(StatusCode::OK, "Returning from patch_api_key", &api_key)
+}
+
+/// Revoke an api key, it is rejected from now on
+#[utoipa::path(
+	delete,
+	path = "/admin/api-keys/{keyuuid}",
+	tag = "admin",
+	params(("keyuuid" = uuid::Uuid, Path, description = "Uuid of the api key")),
+	responses(
+		(status = 200, description = "The revoked api key", body = ResponseMessage<ApiKey>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The api key does not exist", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn revoke_api_key(
+	key_uuid: web::Path<uuid::Uuid>,
+	caller: Caller,
+	store: web::Data<ApiKeyStore>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let api_key = store.revoke(&key_uuid, now_millis())?;
+	send_json(StatusCode::OK, "Returning from revoke_api_key", &api_key)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::api::ResponseMessage;
+	use actix_web::{dev::Service, http, test, test::TestRequest, App};
+
+	#[actix_rt::test]
+	async fn test_api_key_management() {
+		let store = web::Data::new(ApiKeyStore::open(None).unwrap());
+		let srv = test::init_service(
+			App::new()
+				.app_data(store.clone())
+				.route("/admin/api-keys", web::post().to(create_api_key))
+				.route("/admin/api-keys", web::get().to(get_api_keys))
+				.route("/admin/api-keys/{keyuuid}", web::patch().to(patch_api_key))
+				.route("/admin/api-keys/{keyuuid}", web::delete().to(revoke_api_key)),
+		)
+		.await;
+
+		let req = TestRequest::post()
+			.uri("/admin/api-keys")
+			.set_json(serde_json::json!({"name": "payments", "scopes": []}))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+
+		let req = TestRequest::post()
+			.uri("/admin/api-keys")
+			.set_json(serde_json::json!({"name": "payments", "scopes": ["read"]}))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::CREATED);
+		let created: ResponseMessage<CreatedApiKey> = test::read_body_json(response).await;
+		assert_eq!(store.verify(&created.data.key).unwrap(), created.data.api_key);
+
+		let uri = format!("/admin/api-keys/{}", created.data.api_key.id);
+		let req = TestRequest::patch()
+			.uri(&uri)
+			.set_json(serde_json::json!({"scopes": ["bid_on_behalf"]}))
+			.to_request();
+		let updated: ResponseMessage<ApiKey> = test::read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(updated.data.scopes, vec![Scope::BidOnBehalf]);
+
+		let response = srv.call(TestRequest::delete().uri(&uri).to_request()).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+		assert!(store.verify(&created.data.key).is_err());
+
+		let req = TestRequest::get().uri("/admin/api-keys").to_request();
+		let listed: ResponseMessage<Vec<serde_json::Value>> = test::read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(listed.data.len(), 1);
+		assert!(listed.data[0].get("key").is_none() && listed.data[0].get("key_hash").is_none());
+		assert!(listed.data[0]["revoked_at"].is_i64());
+
+		let uri = format!("/admin/api-keys/{}", uuid::Uuid::new_v4());
+		let response = srv.call(TestRequest::delete().uri(&uri).to_request()).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+	}
+}
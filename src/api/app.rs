@@ -0,0 +1,188 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{
+	api_keys, audit, batch, blocklist, custom_error_handler, export, fraud, graphql, import, metrics, notifications,
+	openapi, problem, rate_limit, routes, telemetry, webhooks, websocket,
+};
+use crate::api_keys::ApiKeyStore;
+use crate::auth::{self, JwtKeys};
+use crate::bidtracker::BidManagement;
+use crate::config::Config;
+use crate::graphql::build_schema;
+use crate::idempotency::IdempotencyStore;
+use crate::metrics::lock_traced;
+use crate::notifications::NotificationService;
+use crate::rate_limit::RateLimiter;
+use crate::webhooks::WebhookStore;
+use actix_web::{middleware, web};
+use std::sync::Mutex;
+#[cfg(any(test, feature = "test-util"))]
+use std::time::Duration;
+
+/// Everything the handlers share, each part is registered as its own `web::Data`
+#[derive(Clone)]
+pub struct AppState {
+	pub bidtracker: web::Data<Mutex<BidManagement>>,
+	pub config: web::Data<Config>,
+	pub webhook_store: web::Data<WebhookStore>,
+	pub notifications: web::Data<NotificationService>,
+	pub idempotency: web::Data<IdempotencyStore>,
+	pub jwt_keys: web::Data<JwtKeys>,
+	pub api_keys: web::Data<ApiKeyStore>,
+	pub rate_limiter: web::Data<RateLimiter>,
+}
+
+impl AppState {
+	/// State around `bidtracker` with the default settings, in memory stores and no limits, for tests
+	#[cfg(any(test, feature = "test-util"))]
+	pub fn for_tests(bidtracker: BidManagement, jwt_keys: JwtKeys) -> AppState {
+		AppState {
+			bidtracker: web::Data::new(Mutex::new(bidtracker)),
+			config: web::Data::new(serde_json::from_str("{}").expect("every setting has a default")),
+			webhook_store: web::Data::new(WebhookStore::open(None).expect("an in memory store opens")),
+			notifications: web::Data::new(NotificationService::new(vec![], Duration::from_secs(0))),
+			idempotency: web::Data::new(IdempotencyStore::new(Duration::from_secs(60))),
+			jwt_keys: web::Data::new(jwt_keys),
+			api_keys: web::Data::new(ApiKeyStore::open(None).expect("an in memory store opens")),
+			rate_limiter: web::Data::new(RateLimiter::default()),
+		}
+	}
+
+	/// Register the state, the extractor error handlers and the `/api/v1` routes, whose errors are problem details
+	pub fn configure(&self, cfg: &mut web::ServiceConfig) {
+		let (events, tracker_metrics) = {
+			let bdm = lock_traced(&self.bidtracker);
+			(bdm.events(), bdm.metrics())
+		};
+		let schema = build_schema(self.bidtracker.clone().into_inner(), events, tracker_metrics.clone());
+		cfg.app_data(self.bidtracker.clone())
+			.app_data(web::Data::new(schema))
+			.app_data(web::Data::from(tracker_metrics))
+			.app_data(self.config.clone())
+			.app_data(self.webhook_store.clone())
+			.app_data(self.notifications.clone())
+			.app_data(self.idempotency.clone())
+			.app_data(self.jwt_keys.clone())
+			.app_data(self.api_keys.clone())
+			.app_data(self.rate_limiter.clone())
+			.app_data(web::PathConfig::default().error_handler(custom_error_handler::uuid_error_handler))
+			.app_data(web::JsonConfig::default().error_handler(custom_error_handler::json_error_handler))
+			.app_data(web::QueryConfig::default().error_handler(custom_error_handler::query_error_handler))
+			.service(
+				web::scope(routes::API_PREFIX)
+					.wrap(middleware::from_fn(audit::record_admin_actions))
+					.wrap(middleware::from_fn(auth::authenticate))
+					.wrap(middleware::from_fn(problem::problem_details))
+					.wrap(middleware::from_fn(metrics::record_requests))
+					.wrap(middleware::from_fn(telemetry::trace_requests))
+					.service(
+						web::resource(routes::URL_BID_ITEM)
+							.wrap(middleware::from_fn(rate_limit::limit_bids))
+							.route(web::post().to(super::post_bid_new)),
+					)
+					.route(routes::URL_BID_BATCH, web::post().to(batch::post_bids_batch))
+					.route(routes::URL_BID_GET_ALL, web::get().to(super::get_bids))
+					.route(
+						routes::URL_BID_GET_WINNING,
+						web::get().to(super::get_current_winning_bid),
+					)
+					.route(routes::URL_BID_EXPORT, web::get().to(export::export_item_bids))
+					.route(routes::URL_EXPORT, web::get().to(export::export_all_bids))
+					.route(routes::URL_USER_GET_ALL_BIDS, web::get().to(super::get_user_bids))
+					.route(routes::URL_USER_GET_ALL_ITEMS, web::get().to(super::get_user_items))
+					.route(routes::URL_USER_EXPOSURE, web::get().to(super::get_user_exposure))
+					.route(routes::URL_WEBSOCKET, web::get().to(websocket::ws_connect))
+					.route(routes::URL_ITEMS, web::post().to(super::create_item))
+					.route(routes::URL_ITEM_CLOSE, web::post().to(super::close_item))
+					.route(routes::URL_WEBHOOKS, web::post().to(webhooks::register_webhook))
+					.route(routes::URL_WEBHOOKS, web::get().to(webhooks::get_webhooks))
+					.route(
+						routes::URL_WEBHOOK_DEAD_LETTERS,
+						web::get().to(webhooks::get_dead_letters),
+					)
+					.route(routes::URL_WEBHOOK, web::delete().to(webhooks::delete_webhook))
+					.route(
+						routes::URL_USER_NOTIFICATIONS,
+						web::get().to(notifications::get_notification_preferences),
+					)
+					.route(
+						routes::URL_USER_NOTIFICATIONS,
+						web::put().to(notifications::put_notification_contact),
+					)
+					.route(
+						routes::URL_USER_ITEM_NOTIFICATIONS,
+						web::put().to(notifications::put_item_notifications),
+					)
+					.route(routes::URL_ADMIN_IMPORT, web::post().to(import::post_import))
+					.route(routes::URL_ADMIN_API_KEYS, web::post().to(api_keys::create_api_key))
+					.route(routes::URL_ADMIN_API_KEYS, web::get().to(api_keys::get_api_keys))
+					.route(routes::URL_ADMIN_API_KEY, web::patch().to(api_keys::patch_api_key))
+					.route(routes::URL_ADMIN_API_KEY, web::delete().to(api_keys::revoke_api_key))
+					.route(
+						routes::URL_ADMIN_RATE_LIMITS,
+						web::get().to(rate_limit::get_rate_limits),
+					)
+					.route(
+						routes::URL_ADMIN_RATE_LIMITS,
+						web::put().to(rate_limit::put_rate_limits),
+					)
+					.route(routes::URL_ADMIN_FRAUD_FLAGS, web::get().to(fraud::get_flagged_bids))
+					.route(routes::URL_ADMIN_FRAUD_RULES, web::get().to(fraud::get_fraud_rules))
+					.route(routes::URL_ADMIN_FRAUD_RULES, web::put().to(fraud::put_fraud_rules))
+					.route(routes::URL_ADMIN_AUDIT_HEAD, web::get().to(audit::get_audit_head))
+					.route(
+						routes::URL_ADMIN_AUDIT_VERIFY,
+						web::get().to(audit::get_audit_verification),
+					)
+					.route(routes::URL_ADMIN_BLOCKLIST, web::get().to(blocklist::get_blocklist))
+					.route(routes::URL_ADMIN_BLOCKLIST_USER, web::put().to(blocklist::put_ban))
+					.route(
+						routes::URL_ADMIN_BLOCKLIST_USER,
+						web::delete().to(blocklist::delete_ban),
+					)
+					.route(
+						routes::URL_ADMIN_ITEM_ALLOWLIST,
+						web::get().to(blocklist::get_allowlist),
+					)
+					.route(
+						routes::URL_ADMIN_ITEM_ALLOWLIST,
+						web::put().to(blocklist::put_allowlist),
+					)
+					.route(
+						routes::URL_ADMIN_ITEM_ALLOWLIST,
+						web::delete().to(blocklist::delete_allowlist),
+					)
+					.route(
+						routes::URL_ADMIN_USER_CREDIT_LIMIT,
+						web::put().to(super::put_credit_limit),
+					)
+					.route(routes::URL_GRAPHQL, web::post().to(graphql::post_graphql))
+					.route(routes::URL_GRAPHQL, web::get().to(graphql::get_graphiql))
+					.route(routes::URL_GRAPHQL_WS, web::get().to(graphql::graphql_ws))
+					.route(routes::URL_OPENAPI, web::get().to(openapi::get_openapi))
+					.route(routes::URL_DOCS, web::get().to(openapi::get_docs))
+					.route(routes::URL_DOCS_FILE, web::get().to(openapi::get_docs_file)),
+			)
+			.route(routes::METRICS_PATH, web::get().to(metrics::get_metrics));
+	}
+}
@@ -0,0 +1,181 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::metrics::lock_tracker;
+use super::problem::ProblemDetails;
+use super::{routes, send_json, ResponseMessage};
+use crate::audit::{AuditEvent, AuditLog, ChainHead, Verification};
+use crate::auth::Caller;
+use crate::bidtracker::BidManagement;
+use crate::persistence::now_millis;
+use crate::policy::Action;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, Error as ActixErr, HttpMessage, HttpRequest, HttpResponse};
+use std::sync::Mutex;
+
+/// Append every change an admin requests through the `/admin` routes to the audit log, before the
+/// change is made. The request fails if the action cannot be appended.
+///
+/// Must run inside `auth::authenticate`, which identifies the caller. Requests of non-admins are left to
+/// the handlers to reject. The changes themselves, like retracted bids, are appended by the tracker as
+/// they happen.
+pub async fn record_admin_actions(
+	req: ServiceRequest,
+	next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, ActixErr> {
+	let is_admin_change = ![Method::GET, Method::HEAD].contains(req.method())
+		&& req
+			.path()
+			.strip_prefix(routes::API_PREFIX)
+			.is_some_and(|path| path.starts_with("/admin/"));
+	if !is_admin_change {
+		return next.call(req).await;
+	}
+
+	let caller = req
+		.extensions()
+		.get::<Caller>()
+		.cloned()
+		.unwrap_or(Caller::Unrestricted);
+	let audit = req
+		.app_data::<web::Data<Mutex<BidManagement>>>()
+		.map(|bidtracker| lock_tracker(req.request(), bidtracker).audit().clone());
+	if let (Some(audit), true) = (audit, caller.is_allowed(Action::Administer, None)) {
+		let event = AuditEvent::AdminAction {
+			actor: caller.subject(),
+			method: req.method().to_string(),
+			path: req.path().to_string(),
+		};
+		audit.append(event, now_millis())?;
+	}
+	next.call(req).await
+}
+
+// The log is cloned out of the tracker, so verifying does not hold up the bids
+fn audit_log(req: &HttpRequest, bidtracker: &Mutex<BidManagement>) -> AuditLog {
+	lock_tracker(req, bidtracker).audit().clone()
+}
+
+/// Get the head of the audit chain, to be notarised externally
+#[utoipa::path(
+	get,
+	path = "/admin/audit/head",
+	tag = "admin",
+	responses(
+		(status = 200, description = "The last entry of the audit chain", body = ResponseMessage<ChainHead>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_audit_head(
+	req: HttpRequest,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	send_json(
+		StatusCode::OK,
+		"Returning from get_audit_head",
+		&audit_log(&req, &bidtracker).head(),
+	)
+}
+
+/// Verify the audit chain up to its head and report the first broken link
+#[utoipa::path(
+	get,
+	path = "/admin/audit/verify",
+	tag = "admin",
+	responses(
+		(status = 200, description = "The outcome of the verification", body = ResponseMessage<Verification>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_audit_verification(
+	req: HttpRequest,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let verification = audit_log(&req, &bidtracker).verify()?;
+	send_json(StatusCode::OK, "Returning from get_audit_verification", &verification)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bidtracker::{Bid, BidTracker};
+	use actix_web::test::{init_service, read_body_json, TestRequest};
+	use actix_web::{dev::Service, middleware, App};
+
+	const ITEM: &str = "b2f9ee6d-79fe-4b14-9c19-35a69a89219a";
+
+	#[actix_rt::test]
+	async fn test_audit() {
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let user_uuid = uuid::Uuid::new_v4();
+		let bidtracker = web::Data::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let srv = init_service(
+			App::new().app_data(bidtracker.clone()).service(
+				web::scope(routes::API_PREFIX)
+					.wrap(middleware::from_fn(record_admin_actions))
+					.route("/bids", web::post().to(crate::api::post_bid_new))
+					.route(
+						"/admin/blocklist/{useruuid}",
+						web::put().to(crate::api::blocklist::put_ban),
+					)
+					.route("/admin/audit/head", web::get().to(get_audit_head))
+					.route("/admin/audit/verify", web::get().to(get_audit_verification)),
+			),
+		)
+		.await;
+
+		let bid = Bid {
+			item_uuid,
+			user_uuid,
+			timestamp: 12312321321,
+			amount: 30f64,
+		};
+		let req = TestRequest::post().uri("/api/v1/bids").set_json(&bid).to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::OK);
+		let req = TestRequest::put()
+			.uri(&format!("/api/v1/admin/blocklist/{}", user_uuid))
+			.set_json(serde_json::json!({ "retract_bids": true }))
+			.to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::OK);
+		assert!(bidtracker.lock().unwrap().get_bids(&item_uuid).unwrap().is_empty());
+
+		// The bid, its retraction and the ban itself
+		let req = TestRequest::get().uri("/api/v1/admin/audit/head").to_request();
+		let head: ResponseMessage<ChainHead> = read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(head.data.seq, 3);
+
+		let req = TestRequest::get().uri("/api/v1/admin/audit/verify").to_request();
+		let verification: ResponseMessage<Verification> = read_body_json(srv.call(req).await.unwrap()).await;
+		assert!(verification.data.valid);
+		assert_eq!(verification.data.entries, 3);
+		assert_eq!(verification.data.head, head.data);
+	}
+}
@@ -0,0 +1,495 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::metrics::lock_tracker;
+use super::problem::{Problem, ProblemDetails};
+use super::rate_limit;
+use super::{send_json, ResponseMessage};
+use crate::auth::Caller;
+use crate::bidtracker::{Bid, BidManagement};
+use crate::config::Config;
+use crate::errors::BidTrackerError;
+use crate::rate_limit::Decision;
+use actix_web::{http::header, http::StatusCode, web, Error as ActixErr, HttpRequest, HttpResponse};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use utoipa::{IntoParams, ToSchema};
+
+pub const CONTENT_TYPE_NDJSON: &str = "application/x-ndjson";
+
+#[derive(Deserialize, Serialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BatchOptions {
+	// Reject the whole batch if a single bid fails
+	#[serde(default)]
+	pub atomic: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+	Accepted,
+	Rejected,
+	// Valid, but not applied since another bid of an atomic batch was rejected
+	Skipped,
+}
+
+/// Outcome of a single bid of a batch, `code` and `error` match the status code and the problem code of
+/// `post_bid_new`
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct BatchResult {
+	pub index: usize,
+	pub status: BatchStatus,
+	pub code: u16,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+	pub message: String,
+}
+
+impl BatchResult {
+	fn new(index: usize, status: BatchStatus, result: &Result<(), BidTrackerError>) -> BatchResult {
+		match result {
+			Ok(()) => BatchResult {
+				index,
+				status,
+				code: StatusCode::OK.as_u16(),
+				error: None,
+				message: "Bid accepted".into(),
+			},
+			Err(e) => BatchResult {
+				index,
+				status,
+				code: e.status_code().as_u16(),
+				error: Some(e.code().into()),
+				message: e.to_string(),
+			},
+		}
+	}
+}
+
+/// Place bids in order, returns the per bid results and whether all of them were accepted
+pub fn apply_batch(
+	bidtracker: &mut BidManagement,
+	bids: &[Bid],
+	ip: Option<IpAddr>,
+	atomic: bool,
+) -> (Vec<BatchResult>, bool) {
+	if atomic {
+		// A dry run on a copy sees what checking every bid alone misses, like the exposure the bids of the
+		// batch add up to and the fraud rules
+		let mut dry_run = bidtracker.detached_for(bids, ip);
+		let checks: Vec<_> = bids.iter().map(|bid| dry_run.insert_bid_from(bid, ip)).collect();
+		if checks.iter().any(Result::is_err) {
+			let metrics = bidtracker.metrics();
+			checks
+				.iter()
+				.filter(|check| check.is_err())
+				.for_each(|check| metrics.count_bid(check));
+			let results = checks
+				.iter()
+				.enumerate()
+				.map(|(index, check)| {
+					let status = if check.is_ok() {
+						BatchStatus::Skipped
+					} else {
+						BatchStatus::Rejected
+					};
+					BatchResult::new(index, status, check)
+				})
+				.collect();
+			return (results, false);
+		}
+	}
+
+	let mut all_accepted = true;
+	let results = bids
+		.iter()
+		.enumerate()
+		.map(|(index, bid)| {
+			let result = bidtracker.insert_bid_from(bid, ip);
+			let status = if result.is_ok() {
+				BatchStatus::Accepted
+			} else {
+				all_accepted = false;
+				BatchStatus::Rejected
+			};
+			BatchResult::new(index, status, &result)
+		})
+		.collect();
+	(results, all_accepted)
+}
+
+/// Place a batch of bids given as a json array or as newline delimited json.
+/// All the bids are applied under a single lock acquisition, in the order they were sent.
+/// Every bid takes a token from the rate limits of the caller, a batch is limited as a whole.
+#[utoipa::path(
+	post,
+	path = "/bids/batch",
+	tag = "bids",
+	request_body(content((Vec<Bid> = "application/json"), (Bid = "application/x-ndjson"))),
+	params(BatchOptions),
+	responses(
+		(status = 200, description = "The outcome of every bid of the batch", body = ResponseMessage<Vec<BatchResult>>),
+		(status = 401, description = "No valid bearer token was sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The bearer token was issued to another user than one of the bidders", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "The batch is too large or malformed, or an atomic batch was rejected with its `results`", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 429, description = "The caller has fewer tokens left than the batch has bids", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn post_bids_batch(
+	req: HttpRequest,
+	payload: web::Payload,
+	options: web::Query<BatchOptions>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+	config: web::Data<Config>,
+) -> Result<HttpResponse, ActixErr> {
+	let is_ndjson = req
+		.headers()
+		.get(header::CONTENT_TYPE)
+		.and_then(|value| value.to_str().ok())
+		.is_some_and(|value| value.starts_with(CONTENT_TYPE_NDJSON));
+
+	let bids = if is_ndjson {
+		read_ndjson(payload, &config).await?
+	} else {
+		read_json_array(payload, &config).await?
+	};
+	if bids.len() > config.batch_max_bids {
+		return Err(BidTrackerError::InvalidRequest(format!(
+			"A batch may contain at most {} bids",
+			config.batch_max_bids
+		))
+		.into());
+	}
+	for bid in &bids {
+		caller.authorize_bid(bid)?;
+	}
+
+	let decision = rate_limit::limit_batch(&req, &caller, bids.len());
+	let mut res = match decision.map(Decision::into_result) {
+		Some(Err(e)) => HttpResponse::from_error(e),
+		_ => place_batch(&req, &bidtracker, &bids, options.atomic).unwrap_or_else(HttpResponse::from_error),
+	};
+	if let Some(decision) = decision {
+		rate_limit::set_headers(res.headers_mut(), &decision);
+	}
+	Ok(res)
+}
+
+fn place_batch(
+	req: &HttpRequest,
+	bidtracker: &Mutex<BidManagement>,
+	bids: &[Bid],
+	atomic: bool,
+) -> Result<HttpResponse, ActixErr> {
+	let ip = req.peer_addr().map(|addr| addr.ip());
+	let (results, all_accepted) = apply_batch(&mut lock_tracker(req, bidtracker), bids, ip, atomic);
+	if atomic && !all_accepted {
+		let problem = Problem::new(
+			StatusCode::UNPROCESSABLE_ENTITY,
+			"batch_rejected",
+			"Rejected the atomic batch",
+		);
+		Err(problem.with_extension("results", &results).into())
+	} else {
+		send_json(StatusCode::OK, "Returning from post_bids_batch", &results)
+	}
+}
+
+async fn read_json_array(mut payload: web::Payload, config: &Config) -> Result<Vec<Bid>, BidTrackerError> {
+	let mut body = web::BytesMut::new();
+	while let Some(chunk) = payload.next().await {
+		let chunk = chunk.map_err(|e| BidTrackerError::InvalidRequest(e.to_string()))?;
+		if body.len() + chunk.len() > config.batch_max_body_bytes {
+			return Err(BidTrackerError::InvalidRequest(format!(
+				"The batch is larger than {} bytes",
+				config.batch_max_body_bytes
+			)));
+		}
+		body.extend_from_slice(&chunk);
+	}
+	serde_json::from_slice(&body).map_err(|e| BidTrackerError::InvalidRequest(e.to_string()))
+}
+
+// Bids are parsed line by line as the body streams in, so only the parsed bids and the current line are buffered.
+// The whole body counts towards `batch_max_body_bytes`, blank lines included.
+async fn read_ndjson(mut payload: web::Payload, config: &Config) -> Result<Vec<Bid>, BidTrackerError> {
+	let mut bids = vec![];
+	let mut line = Vec::new();
+	let mut line_number = 0;
+	let mut body_len = 0;
+	while let Some(chunk) = payload.next().await {
+		let chunk = chunk.map_err(|e| BidTrackerError::InvalidRequest(e.to_string()))?;
+		body_len += chunk.len();
+		if body_len > config.batch_max_body_bytes {
+			return Err(BidTrackerError::InvalidRequest(format!(
+				"The batch is larger than {} bytes",
+				config.batch_max_body_bytes
+			)));
+		}
+		for byte in chunk {
+			if byte != b'\n' {
+				line.push(byte);
+				continue;
+			}
+			line_number += 1;
+			parse_ndjson_line(&line, line_number, &mut bids)?;
+			line.clear();
+			if bids.len() > config.batch_max_bids {
+				return Err(BidTrackerError::InvalidRequest(format!(
+					"A batch may contain at most {} bids",
+					config.batch_max_bids
+				)));
+			}
+		}
+	}
+	parse_ndjson_line(&line, line_number + 1, &mut bids)?;
+	Ok(bids)
+}
+
+fn parse_ndjson_line(line: &[u8], line_number: usize, bids: &mut Vec<Bid>) -> Result<(), BidTrackerError> {
+	if line.iter().all(u8::is_ascii_whitespace) {
+		return Ok(());
+	}
+	let bid = serde_json::from_slice(line)
+		.map_err(|e| BidTrackerError::InvalidRequest(format!("Invalid bid on line {}: {}", line_number, e)))?;
+	bids.push(bid);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::api::ResponseMessage;
+	use crate::bidtracker::BidTracker;
+	use crate::rate_limit::{Limit, RateLimiter, RateLimits};
+	use actix_web::{dev::Service, http, test, test::TestRequest, App};
+
+	fn bid(item_uuid: &str, amount: f64) -> Bid {
+		Bid {
+			item_uuid: uuid::Uuid::parse_str(item_uuid).unwrap(),
+			user_uuid: uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap(),
+			timestamp: 12312321321,
+			amount,
+		}
+	}
+
+	fn config() -> Config {
+		Config {
+			batch_max_bids: 3,
+			batch_max_body_bytes: 4096,
+			..Config::default()
+		}
+	}
+
+	#[actix_rt::test]
+	async fn test_post_bids_batch() {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let bidmanagement = web::Data::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement.clone())
+				.app_data(web::Data::new(config()))
+				.route("/bids/batch", web::post().to(post_bids_batch)),
+		)
+		.await;
+
+		let batch = vec![
+			bid("b2f9ee6d-79fe-4b14-9c19-35a69a89219a", 30f64),
+			bid("7f272d43-0ff2-4e0f-9ebc-589eae48e3ad", 31f64),
+			bid("b2f9ee6d-79fe-4b14-9c19-35a69a89219a", 32f64),
+		];
+
+		// Atomic batches are all or nothing
+		let req = TestRequest::post()
+			.uri("/bids/batch?atomic=true")
+			.set_json(&batch)
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+		let problem: ProblemDetails = test::read_body_json(response).await;
+		assert_eq!(problem.code, "batch_rejected");
+		let results: Vec<BatchResult> = serde_json::from_value(problem.extensions["results"].clone()).unwrap();
+		let statuses: Vec<_> = results.iter().map(|result| result.status).collect();
+		assert_eq!(
+			statuses,
+			vec![BatchStatus::Skipped, BatchStatus::Rejected, BatchStatus::Skipped]
+		);
+		assert_eq!(results[1].code, 404);
+		assert_eq!(results[1].error.as_deref(), Some("item_not_found"));
+		assert_eq!(results[0].error, None);
+		assert!(bidmanagement.lock().unwrap().get_bids(&item_uuid).unwrap().is_empty());
+
+		let req = TestRequest::post().uri("/bids/batch").set_json(&batch).to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+		let result: ResponseMessage<Vec<BatchResult>> = test::read_body_json(response).await;
+		let statuses: Vec<_> = result.data.iter().map(|result| result.status).collect();
+		assert_eq!(
+			statuses,
+			vec![BatchStatus::Accepted, BatchStatus::Rejected, BatchStatus::Accepted]
+		);
+		let winner = bidmanagement.lock().unwrap().current_winning_bid(&item_uuid).unwrap();
+		assert_eq!(winner.amount, 32f64);
+
+		// Too many bids
+		let req = TestRequest::post()
+			.uri("/bids/batch")
+			.set_json(vec![bid("b2f9ee6d-79fe-4b14-9c19-35a69a89219a", 1f64); 4])
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+	}
+
+	#[actix_rt::test]
+	async fn test_post_bids_batch_ndjson() {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let bidmanagement = web::Data::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement.clone())
+				.app_data(web::Data::new(config()))
+				.route("/bids/batch", web::post().to(post_bids_batch)),
+		)
+		.await;
+
+		let body: String = [30f64, 31f64]
+			.iter()
+			.map(|amount| {
+				let bid = bid("b2f9ee6d-79fe-4b14-9c19-35a69a89219a", *amount);
+				format!("{}\n", serde_json::to_string(&bid).unwrap())
+			})
+			.collect();
+		let req = TestRequest::post()
+			.uri("/bids/batch")
+			.insert_header((header::CONTENT_TYPE, CONTENT_TYPE_NDJSON))
+			.set_payload(format!("{}\n", body))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		let result: ResponseMessage<Vec<BatchResult>> = test::read_body_json(response).await;
+		assert_eq!(result.data.len(), 2);
+		assert_eq!(bidmanagement.lock().unwrap().get_bids(&item_uuid).unwrap().len(), 2);
+
+		let req = TestRequest::post()
+			.uri("/bids/batch")
+			.insert_header((header::CONTENT_TYPE, CONTENT_TYPE_NDJSON))
+			.set_payload(format!("{}{{\"not\": \"a bid\"}}", body))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+		let body = test::read_body(response).await;
+		assert!(String::from_utf8_lossy(&body).contains("line 3"));
+
+		// Blank lines count towards the size of the body
+		let req = TestRequest::post()
+			.uri("/bids/batch")
+			.insert_header((header::CONTENT_TYPE, CONTENT_TYPE_NDJSON))
+			.set_payload("\n".repeat(config().batch_max_body_bytes + 1))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+		assert_eq!(bidmanagement.lock().unwrap().get_bids(&item_uuid).unwrap().len(), 2);
+	}
+
+	#[actix_rt::test]
+	async fn test_post_bids_batch_rate_limited() {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let bidmanagement = web::Data::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let limits = RateLimits {
+			ip: Limit::from_settings(3, 0.5),
+			..RateLimits::default()
+		};
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement.clone())
+				.app_data(web::Data::new(config()))
+				.app_data(web::Data::new(RateLimiter::new(limits).unwrap()))
+				.route("/bids/batch", web::post().to(post_bids_batch)),
+		)
+		.await;
+		let batch = |amounts: &[f64]| {
+			let bids: Vec<_> = amounts
+				.iter()
+				.map(|amount| bid("b2f9ee6d-79fe-4b14-9c19-35a69a89219a", *amount))
+				.collect();
+			TestRequest::post()
+				.uri("/bids/batch")
+				.peer_addr("10.0.0.1:4000".parse().unwrap())
+				.set_json(bids)
+				.to_request()
+		};
+
+		// Every bid takes a token
+		let response = srv.call(batch(&[30f64, 31f64])).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+		assert_eq!(response.headers().get("ratelimit-remaining").unwrap(), "1");
+
+		// A batch with more bids than tokens left is rejected as a whole
+		let response = srv.call(batch(&[32f64, 33f64])).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+		assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "2");
+		let problem: ProblemDetails = test::read_body_json(response).await;
+		assert_eq!(problem.code, "rate_limited");
+		assert_eq!(bidmanagement.lock().unwrap().get_bids(&item_uuid).unwrap().len(), 2);
+
+		let response = srv.call(batch(&[32f64])).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+	}
+
+	#[actix_rt::test]
+	async fn test_apply_batch_atomic_credit_limit() {
+		let items = [
+			"b2f9ee6d-79fe-4b14-9c19-35a69a89219a",
+			"b16ab43e-aa13-4079-b8c5-592e81312c01",
+		];
+		let mut bidtracker =
+			BidManagement::new(items.iter().map(|item| uuid::Uuid::parse_str(item).unwrap()).collect())
+				.with_default_credit_limit(Some(100f64));
+
+		// Each bid fits the credit limit on its own, together they go over it
+		let batch = vec![bid(items[0], 60f64), bid(items[1], 60f64)];
+		let (results, all_accepted) = apply_batch(&mut bidtracker, &batch, None, true);
+		assert!(!all_accepted);
+		let statuses: Vec<_> = results.iter().map(|result| result.status).collect();
+		assert_eq!(statuses, vec![BatchStatus::Skipped, BatchStatus::Rejected]);
+		assert_eq!(results[1].code, 422);
+		assert_eq!(results[1].error.as_deref(), Some("credit_limit_exceeded"));
+		for item in items {
+			let item_uuid = uuid::Uuid::parse_str(item).unwrap();
+			assert!(bidtracker.get_bids(&item_uuid).unwrap().is_empty());
+		}
+		assert_eq!(bidtracker.exposure(&batch[0].user_uuid).exposure, 0f64);
+
+		let (results, all_accepted) = apply_batch(&mut bidtracker, &batch[..1], None, true);
+		assert!(all_accepted);
+		assert_eq!(results[0].status, BatchStatus::Accepted);
+
+		// The dry run only copies the items of the batch and the ones its bidders lead, which still count
+		let (results, all_accepted) = apply_batch(&mut bidtracker, &batch[1..], None, true);
+		assert!(!all_accepted);
+		assert_eq!(results[0].error.as_deref(), Some("credit_limit_exceeded"));
+	}
+}
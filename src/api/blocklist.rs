@@ -0,0 +1,318 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::metrics::lock_tracker;
+use super::problem::ProblemDetails;
+use super::{send_json, ResponseMessage};
+use crate::auth::Caller;
+use crate::bidtracker::{BannedUser, Bid, BidManagement, BlockedBidCounter};
+use crate::persistence::now_millis;
+use actix_web::{http::StatusCode, web, Error as ActixErr, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct Blocklist {
+	pub banned: Vec<BannedUser>,
+	// How many bids were turned away by the blocklist and the allowlists
+	pub blocked_bids: Vec<BlockedBidCounter>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, ToSchema)]
+pub struct NewBan {
+	pub reason: Option<String>,
+	// Remove the bids of the user on open items and recompute their winners, defaults to false
+	#[serde(default)]
+	pub retract_bids: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct Ban {
+	pub banned: BannedUser,
+	pub retracted_bids: Vec<Bid>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct Allowlist {
+	// Everybody may bid on the item if none is set
+	pub useruuids: Option<Vec<uuid::Uuid>>,
+}
+
+/// Get the banned users and the counters of bids turned away
+#[utoipa::path(
+	get,
+	path = "/admin/blocklist",
+	tag = "admin",
+	responses(
+		(status = 200, description = "The banned users", body = ResponseMessage<Blocklist>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_blocklist(
+	req: HttpRequest,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let bdm = lock_tracker(&req, &bidtracker);
+	let blocklist = Blocklist {
+		banned: bdm.banned_users(),
+		blocked_bids: bdm.blocked_bid_counts(),
+	};
+	send_json(StatusCode::OK, "Returning from get_blocklist", &blocklist)
+}
+
+/// Ban a user from bidding, optionally retracting their bids on open items
+#[utoipa::path(
+	put,
+	path = "/admin/blocklist/{useruuid}",
+	tag = "admin",
+	params(("useruuid" = Uuid, Path, description = "The user to ban")),
+	request_body = NewBan,
+	responses(
+		(status = 200, description = "The ban and the retracted bids", body = ResponseMessage<Ban>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn put_ban(
+	req: HttpRequest,
+	user_uuid: web::Path<uuid::Uuid>,
+	new_ban: web::Json<NewBan>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let NewBan { reason, retract_bids } = new_ban.into_inner();
+	let mut bdm = lock_tracker(&req, &bidtracker);
+	let retracted_bids = bdm.ban_user(&user_uuid, reason, retract_bids, now_millis())?;
+	let banned = bdm
+		.banned_users()
+		.into_iter()
+		.find(|banned| banned.user_uuid == *user_uuid)
+		.expect("the user was just banned");
+	send_json(
+		StatusCode::OK,
+		"Returning from put_ban",
+		&Ban { banned, retracted_bids },
+	)
+}
+
+/// Lift the ban of a user, retracted bids are not restored
+#[utoipa::path(
+	delete,
+	path = "/admin/blocklist/{useruuid}",
+	tag = "admin",
+	params(("useruuid" = Uuid, Path, description = "The user to unban")),
+	responses(
+		(status = 200, description = "The user may bid again", body = ResponseMessage<Uuid>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn delete_ban(
+	req: HttpRequest,
+	user_uuid: web::Path<uuid::Uuid>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	lock_tracker(&req, &bidtracker).unban_user(&user_uuid);
+	send_json(StatusCode::OK, "Returning from delete_ban", &*user_uuid)
+}
+
+/// Get the users invited to bid on an item
+#[utoipa::path(
+	get,
+	path = "/admin/items/{itemuuid}/allowlist",
+	tag = "admin",
+	params(("itemuuid" = Uuid, Path, description = "The item")),
+	responses(
+		(status = 200, description = "The allowlist of the item", body = ResponseMessage<Allowlist>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The item does not exist", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_allowlist(
+	req: HttpRequest,
+	item_uuid: web::Path<uuid::Uuid>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let useruuids = lock_tracker(&req, &bidtracker).allowlist(&item_uuid)?;
+	send_json(StatusCode::OK, "Returning from get_allowlist", &Allowlist { useruuids })
+}
+
+/// Make an item private to the given users, bids already placed are kept
+#[utoipa::path(
+	put,
+	path = "/admin/items/{itemuuid}/allowlist",
+	tag = "admin",
+	params(("itemuuid" = Uuid, Path, description = "The item")),
+	request_body = Allowlist,
+	responses(
+		(status = 200, description = "The new allowlist of the item", body = ResponseMessage<Allowlist>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The item does not exist", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn put_allowlist(
+	req: HttpRequest,
+	item_uuid: web::Path<uuid::Uuid>,
+	allowlist: web::Json<Allowlist>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let mut bdm = lock_tracker(&req, &bidtracker);
+	bdm.set_allowlist(&item_uuid, allowlist.into_inner().useruuids)?;
+	let useruuids = bdm.allowlist(&item_uuid)?;
+	send_json(StatusCode::OK, "Returning from put_allowlist", &Allowlist { useruuids })
+}
+
+/// Open an item to every bidder again
+#[utoipa::path(
+	delete,
+	path = "/admin/items/{itemuuid}/allowlist",
+	tag = "admin",
+	params(("itemuuid" = Uuid, Path, description = "The item")),
+	responses(
+		(status = 200, description = "Everybody may bid on the item", body = ResponseMessage<Allowlist>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The item does not exist", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn delete_allowlist(
+	req: HttpRequest,
+	item_uuid: web::Path<uuid::Uuid>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	lock_tracker(&req, &bidtracker).set_allowlist(&item_uuid, None)?;
+	send_json(
+		StatusCode::OK,
+		"Returning from delete_allowlist",
+		&Allowlist { useruuids: None },
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::api::problem::ProblemDetails;
+	use crate::bidtracker::{BidTracker, BlockReason};
+	use actix_web::test::{init_service, read_body_json, TestRequest};
+	use actix_web::{dev::Service, App};
+
+	const ITEM: &str = "b2f9ee6d-79fe-4b14-9c19-35a69a89219a";
+	const USER: &str = "215248b5-8402-4211-93c0-9f71a93e69a9";
+
+	#[actix_rt::test]
+	async fn test_blocklist_admin() {
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let user_uuid = uuid::Uuid::parse_str(USER).unwrap();
+		let bidtracker = web::Data::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let srv = init_service(
+			App::new()
+				.app_data(bidtracker.clone())
+				.route("/bids", web::post().to(crate::api::post_bid_new))
+				.route("/admin/blocklist", web::get().to(get_blocklist))
+				.route("/admin/blocklist/{useruuid}", web::put().to(put_ban))
+				.route("/admin/blocklist/{useruuid}", web::delete().to(delete_ban))
+				.route("/admin/items/{itemuuid}/allowlist", web::get().to(get_allowlist))
+				.route("/admin/items/{itemuuid}/allowlist", web::put().to(put_allowlist))
+				.route("/admin/items/{itemuuid}/allowlist", web::delete().to(delete_allowlist)),
+		)
+		.await;
+		let post = |user_uuid, amount| {
+			TestRequest::post()
+				.uri("/bids")
+				.set_json(Bid {
+					item_uuid,
+					user_uuid,
+					timestamp: 12312321321,
+					amount,
+				})
+				.to_request()
+		};
+
+		let response = srv.call(post(user_uuid, 30f64)).await.unwrap();
+		assert_eq!(response.status(), StatusCode::OK);
+
+		let uri = format!("/admin/blocklist/{}", user_uuid);
+		let req = TestRequest::put()
+			.uri(&uri)
+			.set_json(serde_json::json!({ "reason": "Chargebacks", "retract_bids": true }))
+			.to_request();
+		let ban: ResponseMessage<Ban> = read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(ban.data.banned.reason.as_deref(), Some("Chargebacks"));
+		assert_eq!(ban.data.retracted_bids.len(), 1);
+		assert!(bidtracker.lock().unwrap().get_bids(&item_uuid).unwrap().is_empty());
+
+		let response = srv.call(post(user_uuid, 40f64)).await.unwrap();
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+		let problem: ProblemDetails = read_body_json(response).await;
+		assert_eq!(problem.code, "bidder_blocked");
+
+		let req = TestRequest::get().uri("/admin/blocklist").to_request();
+		let blocklist: ResponseMessage<Blocklist> = read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(blocklist.data.banned.len(), 1);
+		assert_eq!(blocklist.data.blocked_bids[0].reason, BlockReason::Banned);
+		assert_eq!(blocklist.data.blocked_bids[0].count, 1);
+
+		let req = TestRequest::delete().uri(&uri).to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::OK);
+
+		// Private items only take bids from the invited users
+		let uri = format!("/admin/items/{}/allowlist", item_uuid);
+		let invited = uuid::Uuid::new_v4();
+		let req = TestRequest::put()
+			.uri(&uri)
+			.set_json(serde_json::json!({ "useruuids": [invited] }))
+			.to_request();
+		let allowlist: ResponseMessage<Allowlist> = read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(allowlist.data.useruuids, Some(vec![invited]));
+		assert_eq!(
+			srv.call(post(user_uuid, 40f64)).await.unwrap().status(),
+			StatusCode::FORBIDDEN
+		);
+		assert_eq!(srv.call(post(invited, 40f64)).await.unwrap().status(), StatusCode::OK);
+
+		let req = TestRequest::delete().uri(&uri).to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::OK);
+		let req = TestRequest::get().uri(&uri).to_request();
+		let allowlist: ResponseMessage<Allowlist> = read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(allowlist.data.useruuids, None);
+		assert_eq!(srv.call(post(user_uuid, 50f64)).await.unwrap().status(), StatusCode::OK);
+
+		let uri = format!("/admin/items/{}/allowlist", uuid::Uuid::new_v4());
+		let req = TestRequest::get().uri(&uri).to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::NOT_FOUND);
+	}
+}
@@ -19,22 +19,22 @@
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
-use actix_web::{error, http::StatusCode, HttpRequest, HttpResponse};
+use actix_web::{error, http::StatusCode, HttpRequest};
 
-use super::ResponseMessage;
+use super::problem::Problem;
 
 // Custom error handler in case there is an uuid parse error
 pub fn uuid_error_handler(err: error::PathError, _req: &HttpRequest) -> error::Error {
 	tracing::debug!("Failed to parse incoming path component");
 
-	let detail = err.to_string();
-	let rm = ResponseMessage {
-		code: StatusCode::BAD_REQUEST.as_u16(),
-		message: detail,
-		data: "",
-	};
-	let resp = HttpResponse::build(StatusCode::BAD_REQUEST).json(&rm);
-	error::InternalError::from_response(err, resp).into()
+	Problem::new(StatusCode::BAD_REQUEST, "invalid_path", err.to_string()).into()
+}
+
+// Custom error handler in case the query string can not be parsed
+pub fn query_error_handler(err: error::QueryPayloadError, _req: &HttpRequest) -> error::Error {
+	tracing::debug!("Failed to parse incoming query string");
+
+	Problem::new(StatusCode::BAD_REQUEST, "invalid_query", err.to_string()).into()
 }
 
 // Custom error handler in case there is json payload decoding error
@@ -43,27 +43,26 @@ pub fn json_error_handler(err: error::JsonPayloadError, _req: &HttpRequest) -> e
 	tracing::debug!("Failed to parse incoming json component");
 
 	let detail = err.to_string();
-	let resp = match &err {
-		JsonPayloadError::ContentType => HttpResponse::UnsupportedMediaType().body(detail),
+	let problem = match &err {
+		JsonPayloadError::ContentType => {
+			Problem::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type", detail)
+		}
+		JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+			Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", detail)
+		}
 		JsonPayloadError::Deserialize(json_err) if json_err.is_data() => {
-			// HttpResponse::UnprocessableEntity().body(detail)
-			let rm = ResponseMessage {
-				code: StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
-				message: detail,
-				data: "",
-			};
-			// HttpResponse::UnprocessableEntity().body(detail)}
-			HttpResponse::build(StatusCode::UNPROCESSABLE_ENTITY).json(&rm)
+			Problem::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_body", detail)
 		}
-		_ => HttpResponse::BadRequest().body(detail),
+		_ => Problem::new(StatusCode::BAD_REQUEST, "invalid_json", detail),
 	};
-	error::InternalError::from_response(err, resp).into()
+	problem.into()
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
 	use crate::api::handler::{get_bids, post_bid_new};
+	use crate::api::problem::{ProblemDetails, CONTENT_TYPE_PROBLEM};
 	use actix_web::{dev::Service, test::TestRequest};
 	use actix_web::{http, test, web, App};
 
@@ -74,6 +73,7 @@ mod tests {
 				// .app_data(bidmanagement)
 				.app_data(web::PathConfig::default().error_handler(uuid_error_handler))
 				.app_data(web::JsonConfig::default().error_handler(json_error_handler))
+				.app_data(web::QueryConfig::default().error_handler(query_error_handler))
 				.route("/", web::post().to(post_bid_new))
 				.route("/{itemuuid}", web::get().to(get_bids)),
 		)
@@ -85,10 +85,45 @@ mod tests {
 		let req = TestRequest::post().uri("/").set_json(&malformed_json).to_request();
 		let response = srv.call(req).await.unwrap();
 		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+		assert_eq!(
+			response.headers().get(http::header::CONTENT_TYPE).unwrap(),
+			CONTENT_TYPE_PROBLEM
+		);
+		let problem: ProblemDetails = test::read_body_json(response).await;
+		assert_eq!(problem.code, "invalid_body");
+
+		let req = TestRequest::post()
+			.uri("/")
+			.insert_header((http::header::CONTENT_TYPE, "application/json"))
+			.set_payload("{not json")
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+		let problem: ProblemDetails = test::read_body_json(response).await;
+		assert_eq!(problem.code, "invalid_json");
+
+		let req = TestRequest::post()
+			.uri("/")
+			.insert_header((http::header::CONTENT_TYPE, "text/plain"))
+			.set_payload("{}")
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+		let problem: ProblemDetails = test::read_body_json(response).await;
+		assert_eq!(problem.code, "unsupported_media_type");
 
 		let malformed_uuid = "malformed_uuid";
 		let req = TestRequest::get().uri(&format!("/{}", malformed_uuid)).to_request();
 		let response = srv.call(req).await.unwrap();
 		assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+		let problem: ProblemDetails = test::read_body_json(response).await;
+		assert_eq!(problem.code, "invalid_path");
+
+		let malformed_query = "/b2f9ee6d-79fe-4b14-9c19-35a69a89219a?sort=color";
+		let req = TestRequest::get().uri(malformed_query).to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+		let problem: ProblemDetails = test::read_body_json(response).await;
+		assert_eq!(problem.code, "invalid_query");
 	}
 }
@@ -0,0 +1,413 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::metrics::lock_tracker;
+use super::problem::ProblemDetails;
+use crate::auth::Caller;
+use crate::bidtracker::{Bid, BidManagement};
+use actix_web::{
+	http::header::{self, ContentDisposition, DispositionParam, DispositionType},
+	web, Error as ActixErr, HttpRequest, HttpResponse,
+};
+use futures_util::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, sync::Mutex};
+use utoipa::{IntoParams, ToSchema};
+
+// Number of bids copied per lock acquisition
+const EXPORT_CHUNK_SIZE: usize = 1000;
+
+const CONTENT_TYPE_CSV: &str = "text/csv";
+const CONTENT_TYPE_NDJSON: &str = "application/x-ndjson";
+const CONTENT_TYPE_JSONL: &str = "application/jsonl";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+	Csv,
+	Ndjson,
+	Jsonl,
+}
+
+impl ExportFormat {
+	/// Pick the format from the Accept header, newline delimited json if nothing matches
+	fn from_accept(req: &HttpRequest) -> ExportFormat {
+		let accept = req
+			.headers()
+			.get(header::ACCEPT)
+			.and_then(|value| value.to_str().ok())
+			.unwrap_or_default();
+		if accept.contains(CONTENT_TYPE_CSV) {
+			ExportFormat::Csv
+		} else if accept.contains(CONTENT_TYPE_JSONL) {
+			ExportFormat::Jsonl
+		} else {
+			ExportFormat::Ndjson
+		}
+	}
+
+	fn content_type(&self) -> &'static str {
+		match self {
+			ExportFormat::Csv => CONTENT_TYPE_CSV,
+			ExportFormat::Ndjson => CONTENT_TYPE_NDJSON,
+			ExportFormat::Jsonl => CONTENT_TYPE_JSONL,
+		}
+	}
+
+	fn extension(&self) -> &'static str {
+		match self {
+			ExportFormat::Csv => "csv",
+			ExportFormat::Ndjson => "ndjson",
+			ExportFormat::Jsonl => "jsonl",
+		}
+	}
+
+	fn header(&self) -> Option<&'static str> {
+		match self {
+			ExportFormat::Csv => Some("itemuuid,useruuid,timestamp,amount\n"),
+			_ => None,
+		}
+	}
+
+	fn write(&self, bid: &Bid, buf: &mut Vec<u8>) {
+		match self {
+			ExportFormat::Csv => {
+				let line = format!("{},{},{},{}\n", bid.item_uuid, bid.user_uuid, bid.timestamp, bid.amount);
+				buf.extend_from_slice(line.as_bytes());
+			}
+			ExportFormat::Ndjson | ExportFormat::Jsonl => {
+				// Serializing a bid can not fail
+				serde_json::to_writer(&mut *buf, bid).unwrap();
+				buf.push(b'\n');
+			}
+		}
+	}
+}
+
+/// Format and time range of an export, `format` takes precedence over the Accept header
+#[derive(Deserialize, Serialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+	pub format: Option<ExportFormat>,
+	// Inclusive lower bound of the bid timestamp
+	pub since: Option<i64>,
+	// Inclusive upper bound of the bid timestamp
+	pub until: Option<i64>,
+}
+
+impl ExportQuery {
+	fn matches(&self, bid: &Bid) -> bool {
+		self.since.is_none_or(|since| bid.timestamp >= since) && self.until.is_none_or(|until| bid.timestamp <= until)
+	}
+}
+
+struct ExportState {
+	// Kept to time the chunk locks on the metrics of the app
+	req: HttpRequest,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+	query: ExportQuery,
+	format: ExportFormat,
+	header_sent: bool,
+	// Items left to export, with the sequence number of their last bid when the export started
+	items: VecDeque<(uuid::Uuid, u64)>,
+	// Sequence number of the last bid exported of the first item
+	after: Option<u64>,
+}
+
+// The tracker is only locked to copy one chunk of bids at a time, so an export
+// neither buffers all the bids nor blocks bidding until it is done
+fn export_stream(state: ExportState) -> impl Stream<Item = Result<web::Bytes, ActixErr>> {
+	stream::unfold(state, |mut state| async move {
+		if !state.header_sent {
+			state.header_sent = true;
+			if let Some(header) = state.format.header() {
+				return Some((Ok(web::Bytes::from_static(header.as_bytes())), state));
+			}
+		}
+
+		loop {
+			let (item_uuid, last) = *state.items.front()?;
+			let chunk =
+				lock_tracker(&state.req, &state.bidtracker).bids_after(&item_uuid, state.after, EXPORT_CHUNK_SIZE);
+			let chunk: Vec<_> = chunk.into_iter().take_while(|(seq, _)| *seq <= last).collect();
+			let Some((seq, _)) = chunk.last() else {
+				state.items.pop_front();
+				state.after = None;
+				continue;
+			};
+			state.after = Some(*seq);
+
+			let mut buf = Vec::new();
+			for (_, bid) in chunk.iter().filter(|(_, bid)| state.query.matches(bid)) {
+				state.format.write(bid, &mut buf);
+			}
+			if !buf.is_empty() {
+				return Some((Ok(web::Bytes::from(buf)), state));
+			}
+		}
+	})
+}
+
+fn stream_response(
+	req: &HttpRequest,
+	query: ExportQuery,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+	items: VecDeque<(uuid::Uuid, u64)>,
+	filename: String,
+) -> HttpResponse {
+	let format = query.format.unwrap_or_else(|| ExportFormat::from_accept(req));
+	let disposition = ContentDisposition {
+		disposition: DispositionType::Attachment,
+		parameters: vec![DispositionParam::Filename(format!(
+			"{}.{}",
+			filename,
+			format.extension()
+		))],
+	};
+	let state = ExportState {
+		req: req.clone(),
+		bidtracker,
+		query,
+		format,
+		header_sent: false,
+		items,
+		after: None,
+	};
+	HttpResponse::Ok()
+		.content_type(format.content_type())
+		.insert_header(disposition)
+		.streaming(export_stream(state))
+}
+
+/// Export all the bids on a given itemuuid as csv or newline delimited json
+#[utoipa::path(
+	get,
+	path = "/bids/{itemuuid}/export",
+	tag = "export",
+	params(("itemuuid" = uuid::Uuid, Path, description = "Uuid of the item"), ExportQuery),
+	responses(
+		(status = 200, description = "The bids on the item", content((String = "text/csv"), (String = "application/x-ndjson"), (String = "application/jsonl"))),
+		(status = 401, description = "No valid credentials were sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The item is unknown", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn export_item_bids(
+	req: HttpRequest,
+	item_uuid: web::Path<uuid::Uuid>,
+	query: web::Query<ExportQuery>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let last = lock_tracker(&req, &bidtracker).last_bid_seq(&item_uuid)?;
+	let items = last.map(|last| (*item_uuid, last)).into_iter().collect();
+	let filename = format!("bids-{}", item_uuid);
+	Ok(stream_response(&req, query.into_inner(), bidtracker, items, filename))
+}
+
+/// Export the bids on all the items as csv or newline delimited json
+#[utoipa::path(
+	get,
+	path = "/export",
+	tag = "export",
+	params(ExportQuery),
+	responses(
+		(status = 200, description = "The bids on all the items", content((String = "text/csv"), (String = "application/x-ndjson"), (String = "application/jsonl"))),
+		(status = 401, description = "No valid credentials were sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn export_all_bids(
+	req: HttpRequest,
+	query: web::Query<ExportQuery>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let items = VecDeque::from(lock_tracker(&req, &bidtracker).last_bid_seqs());
+	Ok(stream_response(
+		&req,
+		query.into_inner(),
+		bidtracker,
+		items,
+		"bids".into(),
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bidtracker::BidTracker;
+	use actix_web::{dev::Service, http, test, test::TestRequest, App};
+
+	fn bidmanagement() -> web::Data<Mutex<BidManagement>> {
+		let item_uuid1 = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let item_uuid2 = uuid::Uuid::parse_str("b16ab43e-aa13-4079-b8c5-592e81312c01").unwrap();
+		let mut bm = BidManagement::new(vec![item_uuid1, item_uuid2]);
+		// More bids than fit in a single chunk
+		for timestamp in 0..(EXPORT_CHUNK_SIZE as i64 + 500) {
+			bm.insert_bid(&Bid {
+				item_uuid: item_uuid1,
+				user_uuid: uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap(),
+				timestamp,
+				amount: timestamp as f64,
+			})
+			.unwrap();
+		}
+		bm.insert_bid(&Bid {
+			item_uuid: item_uuid2,
+			user_uuid: uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap(),
+			timestamp: 10,
+			amount: 5f64,
+		})
+		.unwrap();
+		web::Data::new(Mutex::new(bm))
+	}
+
+	#[actix_rt::test]
+	async fn test_export_item_bids() {
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement())
+				.route("/bids/{itemuuid}/export", web::get().to(export_item_bids)),
+		)
+		.await;
+
+		let req = TestRequest::get()
+			.uri("/bids/b2f9ee6d-79fe-4b14-9c19-35a69a89219a/export")
+			.insert_header((header::ACCEPT, CONTENT_TYPE_CSV))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), CONTENT_TYPE_CSV);
+		let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+		let lines: Vec<_> = body.lines().collect();
+		assert_eq!(lines.len(), EXPORT_CHUNK_SIZE + 500 + 1);
+		assert_eq!(lines[0], "itemuuid,useruuid,timestamp,amount");
+		assert_eq!(
+			lines[1],
+			"b2f9ee6d-79fe-4b14-9c19-35a69a89219a,1c916ab6-255b-4a36-9574-e456e0f774c9,0,0"
+		);
+
+		// format= wins over the Accept header
+		let req = TestRequest::get()
+			.uri("/bids/b2f9ee6d-79fe-4b14-9c19-35a69a89219a/export?format=ndjson&since=100&until=199")
+			.insert_header((header::ACCEPT, CONTENT_TYPE_CSV))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		let body = test::read_body(response).await;
+		let bids: Vec<Bid> = body
+			.split(|byte| *byte == b'\n')
+			.filter(|line| !line.is_empty())
+			.map(|line| serde_json::from_slice(line).unwrap())
+			.collect();
+		assert_eq!(bids.len(), 100);
+		assert_eq!(bids[0].timestamp, 100);
+
+		let req = TestRequest::get()
+			.uri("/bids/d60da647-9b9b-43db-97af-56760afa6d93/export")
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+	}
+
+	#[actix_rt::test]
+	async fn test_export_survives_retraction() {
+		use futures_util::StreamExt;
+
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let banned = uuid::Uuid::parse_str("215248b5-8402-4211-93c0-9f71a93e69a9").unwrap();
+		let mut bm = BidManagement::new(vec![item_uuid]);
+		for timestamp in 0..(EXPORT_CHUNK_SIZE as i64 + 500) {
+			let user_uuid = match timestamp {
+				0..100 => banned,
+				_ => uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap(),
+			};
+			bm.insert_bid(&Bid {
+				item_uuid,
+				user_uuid,
+				timestamp,
+				amount: timestamp as f64,
+			})
+			.unwrap();
+		}
+		let bidtracker = web::Data::new(Mutex::new(bm));
+		let last = bidtracker.lock().unwrap().last_bid_seq(&item_uuid).unwrap().unwrap();
+		let mut stream = Box::pin(export_stream(ExportState {
+			req: TestRequest::default().to_http_request(),
+			bidtracker: bidtracker.clone(),
+			query: ExportQuery::default(),
+			format: ExportFormat::Ndjson,
+			header_sent: false,
+			items: VecDeque::from([(item_uuid, last)]),
+			after: None,
+		}));
+
+		let timestamps = |chunk: web::Bytes| -> Vec<i64> {
+			chunk
+				.split(|byte| *byte == b'\n')
+				.filter(|line| !line.is_empty())
+				.map(|line| serde_json::from_slice::<Bid>(line).unwrap().timestamp)
+				.collect()
+		};
+		let first = timestamps(stream.next().await.unwrap().unwrap());
+		assert_eq!(first.len(), EXPORT_CHUNK_SIZE);
+
+		// Retracting bids which were already exported does not make the export skip any
+		bidtracker.lock().unwrap().ban_user(&banned, None, true, 0).unwrap();
+		let second = timestamps(stream.next().await.unwrap().unwrap());
+		assert_eq!(
+			second,
+			(EXPORT_CHUNK_SIZE as i64..EXPORT_CHUNK_SIZE as i64 + 500).collect::<Vec<_>>()
+		);
+		assert!(stream.next().await.is_none());
+	}
+
+	#[actix_rt::test]
+	async fn test_export_all_bids() {
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement())
+				.route("/export", web::get().to(export_all_bids)),
+		)
+		.await;
+
+		let req = TestRequest::get().uri("/export?format=jsonl&until=10").to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(
+			response.headers().get(header::CONTENT_TYPE).unwrap(),
+			CONTENT_TYPE_JSONL
+		);
+		let disposition = response.headers().get(header::CONTENT_DISPOSITION).unwrap();
+		assert!(disposition.to_str().unwrap().contains("bids.jsonl"));
+		let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+		// Items are exported in the order of their uuid
+		assert_eq!(body.lines().count(), 12);
+		assert!(body
+			.lines()
+			.next()
+			.unwrap()
+			.contains("b16ab43e-aa13-4079-b8c5-592e81312c01"));
+	}
+}
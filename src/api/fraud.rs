@@ -0,0 +1,175 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::metrics::lock_tracker;
+use super::problem::ProblemDetails;
+use super::{send_json, ResponseMessage};
+use crate::auth::Caller;
+use crate::bidtracker::BidManagement;
+use crate::fraud::{FlagQuery, FlaggedBid, FraudRules};
+use actix_web::{http::StatusCode, web, Error as ActixErr, HttpRequest, HttpResponse};
+use std::sync::Mutex;
+
+/// Get the bids flagged by the fraud rules, most recent first
+#[utoipa::path(
+	get,
+	path = "/admin/fraud/flags",
+	tag = "admin",
+	params(FlagQuery),
+	responses(
+		(status = 200, description = "The flagged bids", body = ResponseMessage<Vec<FlaggedBid>>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_flagged_bids(
+	req: HttpRequest,
+	query: web::Query<FlagQuery>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let flagged = lock_tracker(&req, &bidtracker).fraud().flagged(&query);
+	send_json(StatusCode::OK, "Returning from get_flagged_bids", &flagged)
+}
+
+/// Get the mode and thresholds of the fraud rules
+#[utoipa::path(
+	get,
+	path = "/admin/fraud/rules",
+	tag = "admin",
+	responses(
+		(status = 200, description = "The fraud rules", body = ResponseMessage<FraudRules>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_fraud_rules(
+	req: HttpRequest,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let rules = lock_tracker(&req, &bidtracker).fraud().rules().clone();
+	send_json(StatusCode::OK, "Returning from get_fraud_rules", &rules)
+}
+
+/// Replace the mode and thresholds of the fraud rules, effective for the next bid
+#[utoipa::path(
+	put,
+	path = "/admin/fraud/rules",
+	tag = "admin",
+	request_body = FraudRules,
+	responses(
+		(status = 200, description = "The new fraud rules", body = ResponseMessage<FraudRules>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "A threshold is not positive", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn put_fraud_rules(
+	req: HttpRequest,
+	rules: web::Json<FraudRules>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let mut bdm = lock_tracker(&req, &bidtracker);
+	bdm.fraud_mut().set_rules(rules.into_inner())?;
+	send_json(StatusCode::OK, "Returning from put_fraud_rules", bdm.fraud().rules())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::api::problem::ProblemDetails;
+	use crate::bidtracker::{Bid, BidTracker};
+	use crate::fraud::{FraudMode, FraudRule};
+	use actix_web::test::{init_service, read_body_json, TestRequest};
+	use actix_web::{dev::Service, App};
+
+	const ITEM: &str = "b2f9ee6d-79fe-4b14-9c19-35a69a89219a";
+
+	#[actix_rt::test]
+	async fn test_fraud_admin() {
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let bidtracker = web::Data::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let srv = init_service(
+			App::new()
+				.app_data(bidtracker.clone())
+				.route("/bids", web::post().to(crate::api::post_bid_new))
+				.route("/admin/fraud/flags", web::get().to(get_flagged_bids))
+				.route("/admin/fraud/rules", web::get().to(get_fraud_rules))
+				.route("/admin/fraud/rules", web::put().to(put_fraud_rules)),
+		)
+		.await;
+
+		let rules = serde_json::json!({ "mode": "enforce", "shared_ip_max_users": 1 });
+		let req = TestRequest::put()
+			.uri("/admin/fraud/rules")
+			.set_json(&rules)
+			.to_request();
+		let rules: ResponseMessage<FraudRules> = read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(rules.data.mode, FraudMode::Enforce);
+		assert_eq!(rules.data.velocity_max_bids, FraudRules::default().velocity_max_bids);
+
+		// A second user bidding from the same ip is blocked
+		let bid = |user_uuid, amount| Bid {
+			item_uuid,
+			user_uuid,
+			timestamp: 12312321321,
+			amount,
+		};
+		let post = |bid: Bid| {
+			TestRequest::post()
+				.uri("/bids")
+				.peer_addr("10.0.0.1:4000".parse().unwrap())
+				.set_json(bid)
+				.to_request()
+		};
+		let response = srv.call(post(bid(uuid::Uuid::new_v4(), 30f64))).await.unwrap();
+		assert_eq!(response.status(), StatusCode::OK);
+		let second = uuid::Uuid::new_v4();
+		let response = srv.call(post(bid(second, 40f64))).await.unwrap();
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+		let problem: ProblemDetails = read_body_json(response).await;
+		assert_eq!(problem.code, "fraud_suspected");
+		assert_eq!(bidtracker.lock().unwrap().get_bids(&item_uuid).unwrap().len(), 1);
+
+		let uri = format!("/admin/fraud/flags?useruuid={}&rule=shared_ip", second);
+		let req = TestRequest::get().uri(&uri).to_request();
+		let flagged: ResponseMessage<Vec<FlaggedBid>> = read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(flagged.data.len(), 1);
+		assert!(flagged.data[0].blocked);
+		assert_eq!(flagged.data[0].flags[0].rule, FraudRule::SharedIp);
+
+		let rules = serde_json::json!({ "velocity_window_secs": 0 });
+		let req = TestRequest::put()
+			.uri("/admin/fraud/rules")
+			.set_json(&rules)
+			.to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::UNPROCESSABLE_ENTITY);
+		let req = TestRequest::get().uri("/admin/fraud/rules").to_request();
+		let rules: ResponseMessage<FraudRules> = read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(rules.data.mode, FraudMode::Enforce);
+	}
+}
@@ -0,0 +1,250 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::auth::Caller;
+use crate::graphql::BidSchema;
+use actix_web::{
+	http::header::{self, ContentType, HeaderValue},
+	web, Error as ActixErr, HttpRequest, HttpResponse,
+};
+use actix_ws::{CloseCode, CloseReason, Message};
+use async_graphql::http::{GraphiQLSource, WebSocket, WebSocketProtocols, WsMessage};
+use futures_util::{future, StreamExt};
+
+/// Run a GraphQL query
+#[utoipa::path(
+	post,
+	path = "/graphql",
+	tag = "graphql",
+	request_body(content = serde_json::Value, description = "A GraphQL request with `query`, `variables` and `operationName`"),
+	responses(
+		(status = 200, description = "The GraphQL response, errors are reported in its `errors`", body = serde_json::Value)
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn post_graphql(
+	schema: web::Data<BidSchema>,
+	caller: Caller,
+	request: web::Json<async_graphql::Request>,
+) -> HttpResponse {
+	HttpResponse::Ok().json(schema.execute(request.into_inner().data(caller)).await)
+}
+
+/// Explore the GraphQL schema with GraphiQL
+#[utoipa::path(
+	get,
+	path = "/graphql",
+	tag = "graphql",
+	responses(
+		(status = 200, description = "The GraphiQL page", content_type = "text/html")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_graphiql(req: HttpRequest) -> HttpResponse {
+	let endpoint = req.path().to_string();
+	let page = GraphiQLSource::build()
+		.endpoint(&endpoint)
+		.subscription_endpoint(&format!("{}/ws", endpoint))
+		.finish();
+	HttpResponse::Ok().content_type(ContentType::html()).body(page)
+}
+
+/// Run GraphQL subscriptions over a websocket
+#[utoipa::path(
+	get,
+	path = "/graphql/ws",
+	tag = "graphql",
+	responses(
+		(status = 101, description = "Switched to the `graphql-transport-ws` or `graphql-ws` protocol")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn graphql_ws(
+	req: HttpRequest,
+	body: web::Payload,
+	schema: web::Data<BidSchema>,
+) -> Result<HttpResponse, ActixErr> {
+	// Clients list the protocols they speak, the first one we know is picked
+	let protocol = req
+		.headers()
+		.get(header::SEC_WEBSOCKET_PROTOCOL)
+		.and_then(|value| value.to_str().ok())
+		.and_then(|protocols| protocols.split(',').find_map(|protocol| protocol.trim().parse().ok()))
+		.unwrap_or(WebSocketProtocols::GraphQLWS);
+
+	let (mut response, session, msg_stream) = actix_ws::handle(&req, body)?;
+	response.headers_mut().insert(
+		header::SEC_WEBSOCKET_PROTOCOL,
+		HeaderValue::from_static(protocol.sec_websocket_protocol()),
+	);
+
+	let pong_session = session.clone();
+	let incoming = msg_stream
+		.take_while(|message| future::ready(matches!(message, Ok(message) if !matches!(message, Message::Close(_)))))
+		.filter_map(move |message| {
+			let mut session = pong_session.clone();
+			async move {
+				match message {
+					Ok(Message::Text(text)) => Some(text.into_bytes()),
+					Ok(Message::Binary(bytes)) => Some(bytes),
+					Ok(Message::Ping(bytes)) => {
+						let _ = session.pong(&bytes).await;
+						None
+					}
+					_ => None,
+				}
+			}
+		});
+
+	let schema = schema.into_inner();
+	actix_web::rt::spawn(async move {
+		let mut session = session;
+		let mut outgoing = std::pin::pin!(WebSocket::new((*schema).clone(), incoming, protocol));
+		while let Some(message) = outgoing.next().await {
+			match message {
+				WsMessage::Text(text) => {
+					if session.text(text).await.is_err() {
+						return;
+					}
+				}
+				WsMessage::Close(code, description) => {
+					let reason = CloseReason {
+						code: CloseCode::from(code),
+						description: Some(description),
+					};
+					let _ = session.close(Some(reason)).await;
+					return;
+				}
+			}
+		}
+		let _ = session.close(None).await;
+	});
+	Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bidtracker::{BidManagement, BidTracker};
+	use crate::graphql::build_schema;
+	use actix_web::test::{init_service, read_body, read_body_json, TestRequest};
+	use actix_web::{dev::Service, http, App, HttpServer};
+	use futures_util::SinkExt;
+	use std::sync::{Arc, Mutex};
+	use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message as WsFrame};
+
+	#[actix_rt::test]
+	async fn test_post_graphql() {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let bm = Arc::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let events = bm.lock().unwrap().events();
+		let schema = build_schema(bm.clone(), events, Arc::default());
+		let srv = init_service(
+			App::new()
+				.app_data(web::Data::new(schema))
+				.route("/graphql", web::post().to(post_graphql))
+				.route("/graphql", web::get().to(get_graphiql)),
+		)
+		.await;
+
+		let req = TestRequest::post()
+			.uri("/graphql")
+			.set_json(serde_json::json!({ "query": "{ items { itemuuid winningBid { amount } } }" }))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+		let body: serde_json::Value = read_body_json(response).await;
+		assert_eq!(body["data"]["items"][0]["itemuuid"], item_uuid.to_string());
+		assert!(body["data"]["items"][0]["winningBid"].is_null());
+		assert_eq!(bm.lock().unwrap().get_items(), vec![item_uuid]);
+
+		let req = TestRequest::get().uri("/graphql").to_request();
+		let response = srv.call(req).await.unwrap();
+		let body = read_body(response).await;
+		assert!(String::from_utf8_lossy(&body).contains("/graphql/ws"));
+	}
+
+	async fn next_json<S>(socket: &mut S) -> serde_json::Value
+	where
+		S: futures_util::Stream<Item = Result<WsFrame, tokio_tungstenite::tungstenite::Error>> + Unpin,
+	{
+		loop {
+			if let WsFrame::Text(text) = socket.next().await.unwrap().unwrap() {
+				return serde_json::from_str(&text).unwrap();
+			}
+		}
+	}
+
+	#[actix_rt::test]
+	async fn test_graphql_ws() {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let bm = Arc::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let events = bm.lock().unwrap().events();
+		let schema = web::Data::new(build_schema(bm.clone(), events, Arc::default()));
+		let server = HttpServer::new(move || {
+			App::new()
+				.app_data(schema.clone())
+				.route("/graphql/ws", web::get().to(graphql_ws))
+		})
+		.workers(1)
+		.bind("127.0.0.1:0")
+		.unwrap();
+		let addr = server.addrs()[0];
+		actix_rt::spawn(server.run());
+
+		let mut request = format!("ws://{}/graphql/ws", addr).into_client_request().unwrap();
+		request
+			.headers_mut()
+			.insert("sec-websocket-protocol", "graphql-transport-ws".parse().unwrap());
+		let (mut socket, response) = tokio_tungstenite::connect_async(request).await.unwrap();
+		assert_eq!(
+			response.headers().get("sec-websocket-protocol").unwrap(),
+			"graphql-transport-ws"
+		);
+
+		let init = serde_json::json!({ "type": "connection_init" });
+		socket.send(WsFrame::text(init.to_string())).await.unwrap();
+		assert_eq!(next_json(&mut socket).await["type"], "connection_ack");
+
+		let subscribe = serde_json::json!({
+			"id": "1",
+			"type": "subscribe",
+			"payload": { "query": "subscription { winningBidChanged { itemuuid bid { amount } } }" }
+		});
+		socket.send(WsFrame::text(subscribe.to_string())).await.unwrap();
+		// Give the subscription a moment to start listening
+		tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+		bm.lock()
+			.unwrap()
+			.insert_bid(&crate::bidtracker::Bid {
+				item_uuid,
+				user_uuid: uuid::Uuid::new_v4(),
+				timestamp: 1,
+				amount: 30f64,
+			})
+			.unwrap();
+
+		let message = next_json(&mut socket).await;
+		assert_eq!(message["type"], "next");
+		assert_eq!(message["payload"]["data"]["winningBidChanged"]["bid"]["amount"], 30f64);
+	}
+}
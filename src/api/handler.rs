@@ -20,50 +20,364 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 // use super::response::for_actix;
-use super::response::send_json;
-use crate::bidtracker::{Bid, BidManagement, BidTracker};
+use super::metrics::lock_tracker;
+use super::problem::ProblemDetails;
+use super::{send_json, send_page, ResponseMessage};
+use crate::auth::Caller;
+use crate::bidtracker::{AuctionItem, Bid, BidManagement, BidQuery, BidTracker, Exposure, UserItem};
+use crate::errors::BidTrackerError;
+use crate::idempotency::{self, IdempotencyStore, RecordedResponse};
+use crate::persistence::now_millis;
+use crate::policy::Action;
+use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
+use utoipa::ToSchema;
 
-use actix_web::{http::StatusCode, web, Error as ActixErr, HttpResponse};
+use actix_web::{
+	http::{header::ContentType, StatusCode},
+	web, Error as ActixErr, HttpRequest, HttpResponse,
+};
 
+/// Place a new bid. Retries carrying the same `Idempotency-Key` header
+/// get the original response instead of placing the bid again.
+#[utoipa::path(
+	post,
+	path = "/bids",
+	tag = "bids",
+	request_body = Bid,
+	params(("Idempotency-Key" = Option<String>, Header, description = "Replay the original response when the request is retried")),
+	responses(
+		(status = 200, description = "The bid was placed", body = ResponseMessage<Bid>),
+		(status = 401, description = "No valid bearer token was sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The bearer token was issued to another user than the bidder", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 400, description = "The body is not valid json", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 409, description = "The Idempotency-Key was already used for another bid", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The item is unknown", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "The item is closed for bidding, or the bid is not valid", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
 pub async fn post_bid_new(
+	req: HttpRequest,
 	bid: web::Json<Bid>,
+	caller: Caller,
 	bidtracker: web::Data<Mutex<BidManagement>>,
+	idempotency: Option<web::Data<IdempotencyStore>>,
 ) -> Result<HttpResponse, ActixErr> {
-	let mut bdm = bidtracker.lock().unwrap();
 	let bbid = bid.into_inner();
-	bdm.insert_bid(&bbid)?;
-	send_json(StatusCode::OK, "Returning from post_bid_new bids", &bbid)
+	caller.authorize_bid(&bbid)?;
+	let ip = req.peer_addr().map(|addr| addr.ip());
+	let key = match idempotency {
+		Some(store) => idempotency_key(&req)?.map(|key| (key, store)),
+		None => None,
+	};
+
+	let mut bdm = lock_tracker(&req, &bidtracker);
+	let (key, store) = match key {
+		Some(key) => key,
+		None => {
+			bdm.insert_bid_from(&bbid, ip)?;
+			return send_json(StatusCode::OK, "Returning from post_bid_new bids", &bbid);
+		}
+	};
+
+	let fingerprint = idempotency::fingerprint(&serde_json::to_vec(&bbid)?);
+	let subject = caller.subject();
+	if let Some(recorded) = store.lookup(&subject, &key, &fingerprint, now_millis())? {
+		return Ok(HttpResponse::build(recorded.status)
+			.content_type(ContentType::json())
+			.insert_header((idempotency::HEADER_IDEMPOTENT_REPLAYED, "true"))
+			.body(recorded.body));
+	}
+
+	bdm.insert_bid_from(&bbid, ip)?;
+	let body = serde_json::to_string(&ResponseMessage {
+		code: StatusCode::OK.as_u16(),
+		message: "Returning from post_bid_new bids".into(),
+		next_cursor: None,
+		data: &bbid,
+	})?;
+	store.record(
+		&subject,
+		&key,
+		RecordedResponse {
+			fingerprint,
+			status: StatusCode::OK,
+			body: body.clone(),
+			created_at: now_millis(),
+		},
+	);
+	drop(bdm);
+	store.sweep(now_millis());
+	Ok(HttpResponse::Ok().content_type(ContentType::json()).body(body))
 }
 
-/// Get all the bids for the given itemuuid
+fn idempotency_key(req: &HttpRequest) -> Result<Option<String>, BidTrackerError> {
+	let value = match req.headers().get(idempotency::HEADER_IDEMPOTENCY_KEY) {
+		Some(value) => value,
+		None => return Ok(None),
+	};
+	match value.to_str() {
+		Ok(key) if !key.is_empty() && key.len() <= idempotency::MAX_KEY_LENGTH => Ok(Some(key.to_string())),
+		_ => Err(BidTrackerError::InvalidRequest(format!(
+			"{} must be between 1 and {} visible ascii characters",
+			idempotency::HEADER_IDEMPOTENCY_KEY,
+			idempotency::MAX_KEY_LENGTH
+		))),
+	}
+}
+
+/// Get a page of the bids for the given itemuuid
+#[utoipa::path(
+	get,
+	path = "/bids/{itemuuid}",
+	tag = "bids",
+	params(("itemuuid" = uuid::Uuid, Path, description = "Uuid of the item"), BidQuery),
+	responses(
+		(status = 200, description = "A page of the bids on the item", body = ResponseMessage<Vec<Bid>>),
+		(status = 400, description = "The query string could not be parsed", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 401, description = "The bids are filtered by `user` without a valid bearer token", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The bids are filtered by another `user` than the caller", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The item is unknown", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "The cursor is invalid", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
 pub async fn get_bids(
+	req: HttpRequest,
 	item_uuid: web::Path<uuid::Uuid>,
+	query: web::Query<BidQuery>,
+	caller: Caller,
 	bidtracker: web::Data<Mutex<BidManagement>>,
 ) -> Result<HttpResponse, ActixErr> {
-	let bdm = bidtracker.lock().unwrap();
-	let bids = bdm.get_bids(&item_uuid)?;
-	send_json(StatusCode::OK, "Returning from get_handler bids", &bids)
+	// Filtering by user reveals the bids of that user, like /users/{useruuid}/bids
+	if let Some(user_uuid) = &query.user_uuid {
+		caller.authorize_user(user_uuid)?;
+	}
+	let page = lock_tracker(&req, &bidtracker).query_bids(&item_uuid, &query)?;
+	send_page(
+		StatusCode::OK,
+		"Returning from get_handler bids",
+		&page.bids,
+		page.next_cursor,
+	)
 }
 
-/// Get the current winning bid for a given itemuuid
+/// Get the current winning bid for a given itemuuid, null while the item has no bids
+#[utoipa::path(
+	get,
+	path = "/bids/{itemuuid}/winning",
+	tag = "bids",
+	params(("itemuuid" = uuid::Uuid, Path, description = "Uuid of the item")),
+	responses(
+		(status = 200, description = "The current winning bid of the item, null without bids", body = ResponseMessage<Option<Bid>>),
+		(status = 404, description = "The item is unknown", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
 pub async fn get_current_winning_bid(
+	req: HttpRequest,
 	item_uuid: web::Path<uuid::Uuid>,
 	bidtracker: web::Data<Mutex<BidManagement>>,
 ) -> Result<HttpResponse, ActixErr> {
-	let bdm = bidtracker.lock().unwrap();
-	let bids = bdm.current_winning_bid(&item_uuid)?;
-	send_json(StatusCode::OK, "Returning from get_current_winning_bid", &bids)
+	let bdm = lock_tracker(&req, &bidtracker);
+	match bdm.current_winning_bid(&item_uuid) {
+		Ok(bid) => send_json(StatusCode::OK, "Returning from get_current_winning_bid", &Some(bid)),
+		Err(BidTrackerError::NoBidsYet(_)) => send_json(StatusCode::OK, "No bids on the item yet", &None::<Bid>),
+		Err(e) => Err(e.into()),
+	}
 }
 
-/// Get all the bids from a given user uuid
+/// Get a page of the bids from a given user uuid
+#[utoipa::path(
+	get,
+	path = "/users/{useruuid}/bids",
+	tag = "users",
+	params(("useruuid" = uuid::Uuid, Path, description = "Uuid of the user"), BidQuery),
+	responses(
+		(status = 200, description = "A page of the bids of the user", body = ResponseMessage<Vec<Bid>>),
+		(status = 401, description = "No valid bearer token was sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The bearer token was issued to another user, and not to an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 400, description = "The query string could not be parsed", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The user has no bids", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "The cursor is invalid", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
 pub async fn get_user_bids(
+	req: HttpRequest,
+	user_uuid: web::Path<uuid::Uuid>,
+	query: web::Query<BidQuery>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_user(&user_uuid)?;
+	let page = lock_tracker(&req, &bidtracker).query_bids_by_user(&user_uuid, &query)?;
+	send_page(
+		StatusCode::OK,
+		"Returning from get_user_bids",
+		&page.bids,
+		page.next_cursor,
+	)
+}
+
+/// Get all the items a given user uuid has bid on, with the user's standing on each
+#[utoipa::path(
+	get,
+	path = "/users/{useruuid}/items",
+	tag = "users",
+	params(("useruuid" = uuid::Uuid, Path, description = "Uuid of the user")),
+	responses(
+		(status = 200, description = "The items the user has bid on", body = ResponseMessage<Vec<UserItem>>),
+		(status = 401, description = "No valid bearer token was sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The bearer token was issued to another user, and not to an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The user has no bids", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_user_items(
+	req: HttpRequest,
+	user_uuid: web::Path<uuid::Uuid>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_user(&user_uuid)?;
+	let items = lock_tracker(&req, &bidtracker).get_items_by_user(&user_uuid)?;
+	send_json(StatusCode::OK, "Returning from get_user_items", &items)
+}
+
+/// Get the exposure of a given user uuid: the sum of the amounts on the open items the user is winning
+#[utoipa::path(
+	get,
+	path = "/users/{useruuid}/exposure",
+	tag = "users",
+	params(("useruuid" = uuid::Uuid, Path, description = "Uuid of the user")),
+	responses(
+		(status = 200, description = "The exposure and credit limit of the user", body = ResponseMessage<Exposure>),
+		(status = 401, description = "No valid bearer token was sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The bearer token was issued to another user, and not to an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_user_exposure(
+	req: HttpRequest,
 	user_uuid: web::Path<uuid::Uuid>,
+	caller: Caller,
 	bidtracker: web::Data<Mutex<BidManagement>>,
 ) -> Result<HttpResponse, ActixErr> {
-	let bdm = bidtracker.lock().unwrap();
-	let bids = bdm.get_bids_by_user(&user_uuid)?;
-	send_json(StatusCode::OK, "Returning from get_user_bids", &bids)
+	caller.authorize_user(&user_uuid)?;
+	let exposure = lock_tracker(&req, &bidtracker).exposure(&user_uuid);
+	send_json(StatusCode::OK, "Returning from get_user_exposure", &exposure)
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, ToSchema)]
+pub struct CreditLimit {
+	// The user falls back to the default credit limit if none is given
+	pub credit_limit: Option<f64>,
+}
+
+/// Set the credit limit of a given user uuid, bids which would raise the exposure above it are rejected
+#[utoipa::path(
+	put,
+	path = "/admin/users/{useruuid}/credit-limit",
+	tag = "admin",
+	params(("useruuid" = uuid::Uuid, Path, description = "Uuid of the user")),
+	request_body = CreditLimit,
+	responses(
+		(status = 200, description = "The exposure of the user under the new limit", body = ResponseMessage<Exposure>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "The credit limit is negative", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn put_credit_limit(
+	req: HttpRequest,
+	user_uuid: web::Path<uuid::Uuid>,
+	credit_limit: web::Json<CreditLimit>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let mut bdm = lock_tracker(&req, &bidtracker);
+	bdm.set_credit_limit(&user_uuid, credit_limit.credit_limit)?;
+	send_json(
+		StatusCode::OK,
+		"Returning from put_credit_limit",
+		&bdm.exposure(&user_uuid),
+	)
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, ToSchema)]
+pub struct NewItem {
+	// A random uuid is generated if none is given
+	pub itemuuid: Option<uuid::Uuid>,
+	// Defaults to the caller, items without a seller are sold by the house
+	pub seller: Option<uuid::Uuid>,
+}
+
+/// Put a new item up for auction, its seller may not bid on it
+#[utoipa::path(
+	post,
+	path = "/items",
+	tag = "items",
+	request_body = NewItem,
+	responses(
+		(status = 201, description = "The item is open for bidding", body = ResponseMessage<AuctionItem>),
+		(status = 401, description = "No valid bearer token was sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The caller is neither the seller with the seller role nor an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 409, description = "The item already exists", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn create_item(
+	req: HttpRequest,
+	new_item: web::Json<NewItem>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	let new_item = new_item.into_inner();
+	let item = AuctionItem {
+		item_uuid: new_item.itemuuid.unwrap_or_else(uuid::Uuid::new_v4),
+		seller: new_item.seller.or_else(|| caller.user_uuid()),
+	};
+	caller.authorize(Action::CreateItem, item.seller.as_ref())?;
+	let mut bdm = lock_tracker(&req, &bidtracker);
+	bdm.create_item(item.clone())?;
+	// Accounts bidding from the ip of a seller are flagged as associated with the seller
+	if let (Some(seller), Some(addr)) = (item.seller, req.peer_addr()) {
+		if caller.user_uuid() == Some(seller) {
+			bdm.fraud_mut().observe_user(&seller, addr.ip(), now_millis());
+		}
+	}
+	send_json(StatusCode::CREATED, "Returning from create_item", &item)
+}
+
+/// Close the bidding on a given itemuuid and return its final winning bid.
+/// Only the seller of the item, auctioneers and admins may close it.
+#[utoipa::path(
+	post,
+	path = "/items/{itemuuid}/close",
+	tag = "items",
+	params(("itemuuid" = uuid::Uuid, Path, description = "Uuid of the item")),
+	responses(
+		(status = 200, description = "The final winning bid of the item, null without bids", body = ResponseMessage<Option<Bid>>),
+		(status = 401, description = "No valid bearer token was sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The caller may not close the item", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The item is unknown", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "The item is already closed", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn close_item(
+	req: HttpRequest,
+	item_uuid: web::Path<uuid::Uuid>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+) -> Result<HttpResponse, ActixErr> {
+	let mut bdm = lock_tracker(&req, &bidtracker);
+	caller.authorize(Action::CloseItem, bdm.seller(&item_uuid)?.as_ref())?;
+	let winning_bid = bdm.close_item(&item_uuid)?;
+	send_json(StatusCode::OK, "Returning from close_item", &winning_bid)
 }
 
 #[cfg(test)]
@@ -106,7 +420,7 @@ mod tests {
 
 		let req = TestRequest::post().uri("/").set_json(&non_existent_bid).to_request();
 		let response = srv.call(req).await.unwrap();
-		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+		assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
 
 		let req = TestRequest::post().uri("/").set_json(&bid).to_request();
 		let response = srv.call(req).await.unwrap();
@@ -162,7 +476,7 @@ mod tests {
 			.uri("/d60da647-9b9b-43db-97af-56760afa6d93")
 			.to_request();
 		let response = srv.call(req).await.unwrap();
-		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+		assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
 	}
 
 	#[actix_rt::test]
@@ -211,7 +525,16 @@ mod tests {
 			.uri("/d60da647-9b9b-43db-97af-56760afa6d93/winning")
 			.to_request();
 		let response = srv.call(req).await.unwrap();
-		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+		assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+
+		// Known item without any bids
+		let req = TestRequest::get()
+			.uri("/b16ab43e-aa13-4079-b8c5-592e81312c01/winning")
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+		let res: ResponseMessage<Option<Bid>> = test::read_body_json(response).await;
+		assert!(res.data.is_none());
 	}
 
 	#[actix_rt::test]
@@ -260,6 +583,247 @@ mod tests {
 			.uri("/17ec66e3-4971-4912-824e-f8533a285857/bids")
 			.to_request();
 		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+	}
+
+	#[actix_rt::test]
+	async fn test_close_item() {
+		let bid = Bid {
+			item_uuid: uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap(),
+			user_uuid: uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap(),
+			timestamp: 12312321321,
+			amount: 30f64,
+		};
+
+		let biddable_items = vec![uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap()];
+		let bidmanagement = web::Data::new(Mutex::new(bidtracker::BidManagement::new(biddable_items)));
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement)
+				.route("/", web::post().to(post_bid_new))
+				.route("/{itemuuid}/close", web::post().to(close_item)),
+		)
+		.await;
+
+		let req = TestRequest::post().uri("/").set_json(&bid).to_request();
+		srv.call(req).await.unwrap();
+
+		let req = TestRequest::post()
+			.uri("/b2f9ee6d-79fe-4b14-9c19-35a69a89219a/close")
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		let res: ResponseMessageBid = test::read_body_json(response).await;
+		assert_eq!(res.data.amount, 30f64);
+
+		// Closed items do not accept any more bids
+		let req = TestRequest::post().uri("/").set_json(&bid).to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+	}
+
+	#[actix_rt::test]
+	async fn test_create_item() {
+		let bidmanagement = web::Data::new(Mutex::new(bidtracker::BidManagement::new(vec![])));
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement.clone())
+				.route("/items", web::post().to(create_item)),
+		)
+		.await;
+
+		let item = serde_json::json!({"itemuuid": "b2f9ee6d-79fe-4b14-9c19-35a69a89219a"});
+		let req = TestRequest::post().uri("/items").set_json(&item).to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::CREATED);
+		let created: ResponseMessage<AuctionItem> = test::read_body_json(response).await;
+		assert_eq!(created.data.seller, None);
+		assert!(bidmanagement.lock().unwrap().is_biddable(&created.data.item_uuid));
+
+		let req = TestRequest::post().uri("/items").set_json(&item).to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::CONFLICT);
+	}
+
+	#[actix_rt::test]
+	async fn test_credit_limit() {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let user_uuid = uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap();
+		let bidmanagement = web::Data::new(Mutex::new(bidtracker::BidManagement::new(vec![item_uuid])));
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement)
+				.route("/bids", web::post().to(post_bid_new))
+				.route("/users/{useruuid}/exposure", web::get().to(get_user_exposure))
+				.route("/admin/users/{useruuid}/credit-limit", web::put().to(put_credit_limit)),
+		)
+		.await;
+
+		let uri = format!("/admin/users/{}/credit-limit", user_uuid);
+		let req = TestRequest::put()
+			.uri(&uri)
+			.set_json(CreditLimit {
+				credit_limit: Some(50f64),
+			})
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+
+		let bid = |amount| Bid {
+			item_uuid,
+			user_uuid,
+			timestamp: 12312321321,
+			amount,
+		};
+		let req = TestRequest::post().uri("/bids").set_json(bid(40f64)).to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), http::StatusCode::OK);
+		let req = TestRequest::post().uri("/bids").set_json(bid(60f64)).to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+
+		let uri = format!("/users/{}/exposure", user_uuid);
+		let response = srv.call(TestRequest::get().uri(&uri).to_request()).await.unwrap();
+		let result: ResponseMessage<Exposure> = test::read_body_json(response).await;
+		assert_eq!(result.data.exposure, 40f64);
+		assert_eq!(result.data.credit_limit, Some(50f64));
+		assert_eq!(result.data.available, Some(10f64));
+		assert_eq!(result.data.winning_items, vec![item_uuid]);
+	}
+
+	#[actix_rt::test]
+	async fn test_get_bids_paginated() {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let user_uuid = uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap();
+		let bidmanagement = web::Data::new(Mutex::new(bidtracker::BidManagement::new(vec![item_uuid])));
+		for amount in [30f64, 50f64, 40f64] {
+			let bid = Bid {
+				item_uuid,
+				user_uuid,
+				timestamp: 12312321321,
+				amount,
+			};
+			bidmanagement.lock().unwrap().insert_bid(&bid).unwrap();
+		}
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement)
+				.route("/bids/{itemuuid}", web::get().to(get_bids))
+				.route("/users/{useruuid}/bids", web::get().to(get_user_bids)),
+		)
+		.await;
+
+		let uri = format!("/bids/{}?sort=amount&order=desc&limit=2", item_uuid);
+		let response = srv.call(TestRequest::get().uri(&uri).to_request()).await.unwrap();
+		let result: ResponseMessage<Vec<Bid>> = test::read_body_json(response).await;
+		let amounts: Vec<_> = result.data.iter().map(|bid| bid.amount).collect();
+		assert_eq!(amounts, vec![50f64, 40f64]);
+
+		let uri = format!("{}&cursor={}", uri, result.next_cursor.unwrap());
+		let response = srv.call(TestRequest::get().uri(&uri).to_request()).await.unwrap();
+		let result: ResponseMessage<Vec<Bid>> = test::read_body_json(response).await;
+		assert_eq!(result.data[0].amount, 30f64);
+		assert!(result.next_cursor.is_none());
+
+		let uri = format!("/users/{}/bids?min_amount=35", user_uuid);
+		let response = srv.call(TestRequest::get().uri(&uri).to_request()).await.unwrap();
+		let result: ResponseMessage<Vec<Bid>> = test::read_body_json(response).await;
+		assert_eq!(result.data.len(), 2);
+
+		let uri = format!("/bids/{}?cursor=invalid", item_uuid);
+		let response = srv.call(TestRequest::get().uri(&uri).to_request()).await.unwrap();
 		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
 	}
+
+	#[actix_rt::test]
+	async fn test_get_user_items() {
+		let bid1 = Bid {
+			item_uuid: uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap(),
+			user_uuid: uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap(),
+			timestamp: 12312321321,
+			amount: 30f64,
+		};
+		let bid2 = Bid {
+			amount: 32.5f64,
+			..bid1
+		};
+
+		let biddable_items = vec![
+			uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap(),
+			uuid::Uuid::parse_str("b16ab43e-aa13-4079-b8c5-592e81312c01").unwrap(),
+		];
+		let bidmanagement = web::Data::new(Mutex::new(bidtracker::BidManagement::new(biddable_items)));
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement)
+				.route("/", web::post().to(post_bid_new))
+				.route("/{useruuid}/items", web::get().to(get_user_items)),
+		)
+		.await;
+
+		for bid in [&bid1, &bid2] {
+			let req = TestRequest::post().uri("/").set_json(bid).to_request();
+			srv.call(req).await.unwrap();
+		}
+
+		let req = TestRequest::get()
+			.uri("/1c916ab6-255b-4a36-9574-e456e0f774c9/items")
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		let result: ResponseMessage<Vec<bidtracker::UserItem>> = test::read_body_json(response).await;
+		assert_eq!(result.data.len(), 1);
+		assert_eq!(result.data[0].bid_count, 2);
+		assert_eq!(result.data[0].highest_bid.amount, 32.5);
+		assert!(result.data[0].winning);
+
+		// Missing uuid case
+		let req = TestRequest::get()
+			.uri("/17ec66e3-4971-4912-824e-f8533a285857/items")
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+	}
+
+	#[actix_rt::test]
+	async fn test_post_bid_new_idempotent() {
+		let bid = Bid {
+			item_uuid: uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap(),
+			user_uuid: uuid::Uuid::parse_str("b16ab43e-aa13-4079-b8c5-592e81312c01").unwrap(),
+			timestamp: 12312321321,
+			amount: 30f64,
+		};
+
+		let biddable_items = vec![uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap()];
+		let bidmanagement = web::Data::new(Mutex::new(bidtracker::BidManagement::new(biddable_items)));
+		let store = IdempotencyStore::new(std::time::Duration::from_secs(60));
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement.clone())
+				.app_data(web::Data::new(store))
+				.route("/", web::post().to(post_bid_new)),
+		)
+		.await;
+
+		let post = |bid: &Bid| {
+			TestRequest::post()
+				.uri("/")
+				.insert_header((idempotency::HEADER_IDEMPOTENCY_KEY, "retry-me"))
+				.set_json(bid)
+				.to_request()
+		};
+		let first = test::read_body(srv.call(post(&bid)).await.unwrap()).await;
+
+		let response = srv.call(post(&bid)).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+		assert!(response.headers().contains_key(idempotency::HEADER_IDEMPOTENT_REPLAYED));
+		assert_eq!(test::read_body(response).await, first);
+		assert_eq!(bidmanagement.lock().unwrap().get_bids(&bid.item_uuid).unwrap().len(), 1);
+
+		// Same key, different body
+		let response = srv.call(post(&Bid { amount: 31f64, ..bid })).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::CONFLICT);
+
+		// Without a key every request places a bid
+		let req = TestRequest::post().uri("/").set_json(&bid).to_request();
+		srv.call(req).await.unwrap();
+		assert_eq!(bidmanagement.lock().unwrap().get_bids(&bid.item_uuid).unwrap().len(), 2);
+	}
 }
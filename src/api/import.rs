@@ -0,0 +1,164 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::metrics::lock_tracker;
+use super::problem::ProblemDetails;
+use super::{send_json, ResponseMessage};
+use crate::auth::Caller;
+use crate::bidtracker::BidManagement;
+use crate::config::Config;
+use crate::errors::BidTrackerError;
+use crate::import::{import_bids, parse_bids, ImportFormat, ImportReport};
+use actix_web::{http::header, http::StatusCode, web, Error as ActixErr, HttpRequest, HttpResponse};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use utoipa::IntoParams;
+
+#[derive(Deserialize, Serialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportOptions {
+	// Validate the bids without placing them
+	#[serde(default)]
+	pub dry_run: bool,
+	// Takes precedence over the Content-Type, which defaults to ndjson
+	pub format: Option<ImportFormat>,
+}
+
+/// Import a csv or ndjson file of historical bids and report the outcome of every row.
+/// The bids are replayed in timestamp order a chunk at a time, so bidding goes on during the import.
+#[utoipa::path(
+	post,
+	path = "/admin/import",
+	tag = "admin",
+	request_body(content((String = "text/csv"), (String = "application/x-ndjson"))),
+	params(ImportOptions),
+	responses(
+		(status = 200, description = "The outcome of every row of the file", body = ResponseMessage<ImportReport>),
+		(status = 401, description = "No valid bearer token was sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The bearer token was not issued to an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "The file is too large", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn post_import(
+	req: HttpRequest,
+	mut payload: web::Payload,
+	options: web::Query<ImportOptions>,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+	config: web::Data<Config>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let format = options.format.unwrap_or_else(|| {
+		let is_csv = req
+			.headers()
+			.get(header::CONTENT_TYPE)
+			.and_then(|value| value.to_str().ok())
+			.is_some_and(|value| value.starts_with("text/csv"));
+		if is_csv {
+			ImportFormat::Csv
+		} else {
+			ImportFormat::Ndjson
+		}
+	});
+
+	let mut body = web::BytesMut::new();
+	while let Some(chunk) = payload.next().await {
+		let chunk = chunk.map_err(|e| BidTrackerError::InvalidRequest(e.to_string()))?;
+		if body.len() + chunk.len() > config.import_max_body_bytes {
+			return Err(BidTrackerError::InvalidRequest(format!(
+				"The import is larger than {} bytes",
+				config.import_max_body_bytes
+			))
+			.into());
+		}
+		body.extend_from_slice(&chunk);
+	}
+
+	let rows = parse_bids(&body, format);
+	let report = import_bids(|| lock_tracker(&req, &bidtracker), rows, options.dry_run)?;
+	tracing::info!(
+		"Imported bids, dry_run: {} accepted: {} rejected: {} duplicates: {}",
+		report.dry_run,
+		report.accepted,
+		report.rejected,
+		report.duplicates
+	);
+	send_json(StatusCode::OK, "Returning from post_import", &report)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::api::ResponseMessage;
+	use crate::bidtracker::BidTracker;
+	use actix_web::{dev::Service, http, test, test::TestRequest, App};
+
+	#[actix_rt::test]
+	async fn test_post_import() {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let bidmanagement = web::Data::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let config = Config {
+			import_max_body_bytes: 1024,
+			..Config::default()
+		};
+		let srv = test::init_service(
+			App::new()
+				.app_data(bidmanagement.clone())
+				.app_data(web::Data::new(config))
+				.route("/admin/import", web::post().to(post_import)),
+		)
+		.await;
+
+		let csv = "itemuuid,useruuid,timestamp,amount\n\
+			b2f9ee6d-79fe-4b14-9c19-35a69a89219a,1c916ab6-255b-4a36-9574-e456e0f774c9,10,30\n\
+			b2f9ee6d-79fe-4b14-9c19-35a69a89219a,1c916ab6-255b-4a36-9574-e456e0f774c9,11,0\n";
+
+		let req = TestRequest::post()
+			.uri("/admin/import?dry_run=true")
+			.insert_header((header::CONTENT_TYPE, "text/csv"))
+			.set_payload(csv)
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+		let result: ResponseMessage<ImportReport> = test::read_body_json(response).await;
+		assert_eq!((result.data.accepted, result.data.rejected), (1, 1));
+		assert!(bidmanagement.lock().unwrap().get_bids(&item_uuid).unwrap().is_empty());
+
+		let req = TestRequest::post()
+			.uri("/admin/import?format=csv")
+			.set_payload(csv)
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		let result: ResponseMessage<ImportReport> = test::read_body_json(response).await;
+		assert!(!result.data.dry_run);
+		assert_eq!(bidmanagement.lock().unwrap().get_bids(&item_uuid).unwrap().len(), 1);
+
+		let req = TestRequest::post()
+			.uri("/admin/import")
+			.set_payload(vec![b'\n'; 2048])
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+	}
+}
@@ -0,0 +1,135 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::bidtracker::BidManagement;
+use crate::metrics::{lock_traced, Metrics};
+use crate::rate_limit::RateLimiter;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::ContentType;
+use actix_web::middleware::Next;
+use actix_web::{web, Error as ActixErr, HttpRequest, HttpResponse};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+/// Time every request by its route pattern, so the paths of single items share one histogram
+pub async fn record_requests(
+	req: ServiceRequest,
+	next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, ActixErr> {
+	let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+	let started = Instant::now();
+	let res = next.call(req).await?;
+	if let Some(metrics) = metrics {
+		let request = res.request();
+		let route = request.match_pattern();
+		metrics.observe_request(request.method().as_str(), route.as_deref(), started.elapsed());
+	}
+	Ok(res)
+}
+
+/// Lock the tracker, timing the wait if the metrics are registered
+pub fn lock_tracker<'a>(req: &HttpRequest, bidtracker: &'a Mutex<BidManagement>) -> MutexGuard<'a, BidManagement> {
+	match req.app_data::<web::Data<Metrics>>() {
+		Some(metrics) => metrics.lock(bidtracker),
+		None => lock_traced(bidtracker),
+	}
+}
+
+/// Serve the metrics in the Prometheus text exposition format
+pub async fn get_metrics(
+	metrics: web::Data<Metrics>,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+	rate_limiter: Option<web::Data<RateLimiter>>,
+) -> HttpResponse {
+	let tracker = metrics.lock(&bidtracker).stats();
+	let rate_limits = rate_limiter.map(|limiter| limiter.counters()).unwrap_or_default();
+	HttpResponse::Ok()
+		.insert_header(ContentType("text/plain; version=0.0.4; charset=utf-8".parse().unwrap()))
+		.body(metrics.render(&tracker, &rate_limits))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::api::routes;
+	use crate::bidtracker::{Bid, BidTracker};
+	use actix_web::test::{init_service, read_body, TestRequest};
+	use actix_web::{dev::Service, http::StatusCode, middleware, App};
+
+	const ITEM: &str = "b2f9ee6d-79fe-4b14-9c19-35a69a89219a";
+
+	#[actix_rt::test]
+	async fn test_get_metrics() {
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let bidtracker = BidManagement::new(vec![item_uuid]);
+		let metrics = web::Data::from(bidtracker.metrics());
+		let bidtracker = web::Data::new(Mutex::new(bidtracker));
+		let srv = init_service(
+			App::new()
+				.app_data(bidtracker.clone())
+				.app_data(metrics)
+				.service(
+					web::scope(routes::API_PREFIX)
+						.wrap(middleware::from_fn(record_requests))
+						.route("/bids", web::post().to(crate::api::post_bid_new))
+						.route("/items/{itemuuid}/close", web::post().to(crate::api::close_item)),
+				)
+				.route(routes::METRICS_PATH, web::get().to(get_metrics)),
+		)
+		.await;
+
+		let bid = Bid {
+			item_uuid,
+			user_uuid: uuid::Uuid::new_v4(),
+			timestamp: 12312321321,
+			amount: 30f64,
+		};
+		let req = TestRequest::post().uri("/api/v1/bids").set_json(&bid).to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::OK);
+		let req = TestRequest::post()
+			.uri(&format!("/api/v1/items/{}/close", ITEM))
+			.to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::OK);
+		let req = TestRequest::post().uri("/api/v1/bids").set_json(&bid).to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::UNPROCESSABLE_ENTITY);
+		assert_eq!(bidtracker.lock().unwrap().get_bids(&item_uuid).unwrap().len(), 1);
+
+		let req = TestRequest::get().uri("/metrics").to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), StatusCode::OK);
+		let content_type = response.headers().get("content-type").unwrap().to_str().unwrap();
+		assert!(content_type.starts_with("text/plain; version=0.0.4"));
+		let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+		for line in [
+			"bidtracker_bids_accepted_total 1",
+			"bidtracker_bids_rejected_total{reason=\"item_not_biddable\"} 1",
+			"bidtracker_http_request_duration_seconds_count{method=\"POST\",route=\"/api/v1/bids\"} 2",
+			"bidtracker_http_request_duration_seconds_count{method=\"POST\",route=\"/api/v1/items/{itemuuid}/close\"} 1",
+			"bidtracker_tracker_lock_wait_seconds_count 4",
+			"bidtracker_open_items 0",
+			"bidtracker_bids 1",
+		] {
+			assert!(body.lines().any(|rendered| rendered == line), "{} is missing", line);
+		}
+	}
+}
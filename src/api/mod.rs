@@ -1,8 +1,29 @@
+mod app;
 mod handler;
 mod response;
 
-pub use response::{send_json, ResponseMessage, ResponseMessageBid, ResponseMessageBids};
+pub use app::AppState;
+pub use response::{send_json, send_page, ResponseMessage, ResponseMessageBid, ResponseMessageBids};
 
 pub mod routes;
-pub use handler::{get_bids, get_current_winning_bid, get_user_bids, post_bid_new};
+pub use handler::{
+	close_item, create_item, get_bids, get_current_winning_bid, get_user_bids, get_user_exposure, get_user_items,
+	post_bid_new, put_credit_limit, CreditLimit, NewItem,
+};
+pub mod api_keys;
+pub mod audit;
+pub mod batch;
+pub mod blocklist;
 pub mod custom_error_handler;
+pub mod export;
+pub mod fraud;
+pub mod graphql;
+pub mod import;
+pub mod metrics;
+pub mod notifications;
+pub mod openapi;
+pub mod problem;
+pub mod rate_limit;
+pub mod telemetry;
+pub mod webhooks;
+pub mod websocket;
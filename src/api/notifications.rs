@@ -0,0 +1,179 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::problem::ProblemDetails;
+use super::{send_json, ResponseMessage};
+use crate::auth::Caller;
+use crate::errors::BidTrackerError;
+use crate::notifications::{NotificationService, UserPreferences};
+use actix_web::{http::StatusCode, web, Error as ActixErr, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ContactUpdate {
+	// Remove the address by sending null
+	pub email: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ItemNotificationUpdate {
+	pub enabled: bool,
+}
+
+/// Get the notification preferences of a given useruuid
+#[utoipa::path(
+	get,
+	path = "/users/{useruuid}/notifications",
+	tag = "notifications",
+	params(("useruuid" = uuid::Uuid, Path, description = "Uuid of the user")),
+	responses(
+		(status = 200, description = "The notification preferences of the user", body = ResponseMessage<UserPreferences>),
+		(status = 401, description = "No valid bearer token was sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The bearer token was issued to another user, and not to an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_notification_preferences(
+	user_uuid: web::Path<uuid::Uuid>,
+	caller: Caller,
+	notifications: web::Data<NotificationService>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_user(&user_uuid)?;
+	let preferences = notifications.preferences(&user_uuid);
+	send_json(
+		StatusCode::OK,
+		"Returning from get_notification_preferences",
+		&preferences,
+	)
+}
+
+/// Set the address outbid emails are sent to
+#[utoipa::path(
+	put,
+	path = "/users/{useruuid}/notifications",
+	tag = "notifications",
+	request_body = ContactUpdate,
+	params(("useruuid" = uuid::Uuid, Path, description = "Uuid of the user")),
+	responses(
+		(status = 200, description = "The updated notification preferences", body = ResponseMessage<UserPreferences>),
+		(status = 401, description = "No valid bearer token was sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The bearer token was issued to another user, and not to an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "The email address is not valid", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn put_notification_contact(
+	user_uuid: web::Path<uuid::Uuid>,
+	contact: web::Json<ContactUpdate>,
+	caller: Caller,
+	notifications: web::Data<NotificationService>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_user(&user_uuid)?;
+	let email = contact.into_inner().email;
+	if let Some(email) = &email {
+		email
+			.parse::<lettre::Address>()
+			.map_err(|e| BidTrackerError::InvalidRequest(format!("Invalid email address {}: {}", email, e)))?;
+	}
+	let preferences = notifications.set_email(&user_uuid, email);
+	send_json(StatusCode::OK, "Returning from put_notification_contact", &preferences)
+}
+
+/// Opt a given useruuid in or out of the outbid notifications for an itemuuid
+#[utoipa::path(
+	put,
+	path = "/users/{useruuid}/notifications/{itemuuid}",
+	tag = "notifications",
+	request_body = ItemNotificationUpdate,
+	params(("useruuid" = uuid::Uuid, Path, description = "Uuid of the user"), ("itemuuid" = uuid::Uuid, Path, description = "Uuid of the item")),
+	responses(
+		(status = 200, description = "The updated notification preferences", body = ResponseMessage<UserPreferences>),
+		(status = 401, description = "No valid bearer token was sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The bearer token was issued to another user, and not to an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn put_item_notifications(
+	path: web::Path<(uuid::Uuid, uuid::Uuid)>,
+	update: web::Json<ItemNotificationUpdate>,
+	caller: Caller,
+	notifications: web::Data<NotificationService>,
+) -> Result<HttpResponse, ActixErr> {
+	let (user_uuid, item_uuid) = path.into_inner();
+	caller.authorize_user(&user_uuid)?;
+	let preferences = notifications.set_item_enabled(&user_uuid, &item_uuid, update.enabled);
+	send_json(StatusCode::OK, "Returning from put_item_notifications", &preferences)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::api::ResponseMessage;
+	use crate::notifications::UserPreferences;
+	use actix_web::{dev::Service, http, test, test::TestRequest, App};
+	use std::time::Duration;
+
+	#[actix_rt::test]
+	async fn test_notification_preferences() {
+		let notifications = web::Data::new(NotificationService::new(vec![], Duration::from_secs(60)));
+		let srv = test::init_service(
+			App::new()
+				.app_data(notifications)
+				.route("/{useruuid}/notifications", web::get().to(get_notification_preferences))
+				.route("/{useruuid}/notifications", web::put().to(put_notification_contact))
+				.route(
+					"/{useruuid}/notifications/{itemuuid}",
+					web::put().to(put_item_notifications),
+				),
+		)
+		.await;
+
+		let uri = "/1c916ab6-255b-4a36-9574-e456e0f774c9/notifications";
+		let req = TestRequest::put()
+			.uri(uri)
+			.set_json(serde_json::json!({"email": "not an address"}))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+
+		let req = TestRequest::put()
+			.uri(uri)
+			.set_json(serde_json::json!({"email": "alice@localhost"}))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+
+		let req = TestRequest::put()
+			.uri(&format!("{}/b2f9ee6d-79fe-4b14-9c19-35a69a89219a", uri))
+			.set_json(serde_json::json!({"enabled": false}))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+
+		let req = TestRequest::get().uri(uri).to_request();
+		let response = srv.call(req).await.unwrap();
+		let result: ResponseMessage<UserPreferences> = test::read_body_json(response).await;
+		assert_eq!(result.data.email.as_deref(), Some("alice@localhost"));
+		assert_eq!(result.data.muted_items.len(), 1);
+	}
+}
@@ -0,0 +1,273 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::problem::{Problem, ProblemDetails};
+use super::{
+	api_keys, audit, batch, blocklist, export, fraud, graphql, handler, import, notifications, rate_limit, webhooks,
+	websocket,
+};
+use actix_web::{http::header, http::StatusCode, web, Error as ActixErr, HttpResponse};
+use std::sync::Arc;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::Config;
+
+/// OpenAPI document of the `/api/v1` routes, generated from the handlers and their types
+#[derive(OpenApi)]
+#[openapi(
+	info(title = "bid-tracker-rs", description = "Track the bids placed on auction items"),
+	servers((url = "/api/v1")),
+	paths(
+		handler::post_bid_new,
+		handler::get_bids,
+		handler::get_current_winning_bid,
+		handler::get_user_bids,
+		handler::get_user_items,
+		handler::get_user_exposure,
+		handler::put_credit_limit,
+		handler::create_item,
+		handler::close_item,
+		batch::post_bids_batch,
+		export::export_item_bids,
+		export::export_all_bids,
+		import::post_import,
+		api_keys::create_api_key,
+		api_keys::get_api_keys,
+		api_keys::patch_api_key,
+		api_keys::revoke_api_key,
+		rate_limit::get_rate_limits,
+		rate_limit::put_rate_limits,
+		fraud::get_flagged_bids,
+		fraud::get_fraud_rules,
+		fraud::put_fraud_rules,
+		audit::get_audit_head,
+		audit::get_audit_verification,
+		blocklist::get_blocklist,
+		blocklist::put_ban,
+		blocklist::delete_ban,
+		blocklist::get_allowlist,
+		blocklist::put_allowlist,
+		blocklist::delete_allowlist,
+		webhooks::register_webhook,
+		webhooks::get_webhooks,
+		webhooks::delete_webhook,
+		webhooks::get_dead_letters,
+		notifications::get_notification_preferences,
+		notifications::put_notification_contact,
+		notifications::put_item_notifications,
+		websocket::ws_connect,
+		graphql::post_graphql,
+		graphql::get_graphiql,
+		graphql::graphql_ws,
+		get_openapi,
+		get_docs,
+		get_docs_file,
+	),
+	components(schemas(crate::bidtracker::BidEvent)),
+	modifiers(&Authentication),
+	security((), ("bearer" = []), ("api_key" = []))
+)]
+pub struct ApiDoc;
+
+// The JWT bearer tokens of `auth` and the keys of `api_keys`, reads mostly work without either
+struct Authentication;
+
+impl Modify for Authentication {
+	fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+		let components = openapi.components.get_or_insert_with(Default::default);
+		let scheme = HttpBuilder::new()
+			.scheme(HttpAuthScheme::Bearer)
+			.bearer_format("JWT")
+			.build();
+		components.add_security_scheme("bearer", SecurityScheme::Http(scheme));
+		let api_key = ApiKey::Header(ApiKeyValue::new(crate::api_keys::HEADER_API_KEY));
+		components.add_security_scheme("api_key", SecurityScheme::ApiKey(api_key));
+	}
+}
+
+/// Get the OpenAPI document of the api
+#[utoipa::path(
+	get,
+	path = "/openapi.json",
+	tag = "docs",
+	responses(
+		(status = 200, description = "The OpenAPI 3 document", content_type = "application/json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_openapi() -> HttpResponse {
+	HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Browse the api documentation with Swagger UI
+#[utoipa::path(
+	get,
+	path = "/docs",
+	tag = "docs",
+	responses(
+		(status = 308, description = "Redirects to the Swagger UI page, whose files are relative to `/docs/`")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_docs() -> HttpResponse {
+	HttpResponse::PermanentRedirect()
+		.insert_header((header::LOCATION, "docs/"))
+		.finish()
+}
+
+/// Get a file of the Swagger UI page, its assets are embedded in the binary so the docs work offline
+#[utoipa::path(
+	get,
+	path = "/docs/{file}",
+	tag = "docs",
+	params(("file" = String, Path, description = "Path of the file, the page itself if empty")),
+	responses(
+		(status = 200, description = "The file"),
+		(status = 404, description = "There is no such file", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_docs_file(file: web::Path<String>) -> Result<HttpResponse, ActixErr> {
+	// Relative to the page at `/docs/`
+	let config = Arc::new(Config::from("../openapi.json"));
+	match utoipa_swagger_ui::serve(&file, config) {
+		Ok(Some(file)) => Ok(HttpResponse::Ok()
+			.content_type(file.content_type)
+			.body(file.bytes.into_owned())),
+		Ok(None) => Err(Problem::from_status(StatusCode::NOT_FOUND, format!("No file {} in the docs", file)).into()),
+		Err(e) => Err(Problem::from_status(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use actix_web::test::{init_service, read_body, read_body_json, TestRequest};
+	use actix_web::{dev::Service, http, App};
+	use std::collections::HashMap;
+
+	// The path of every route declared in routes.rs, by the name of its constant
+	fn declared_routes() -> HashMap<String, String> {
+		include_str!("routes.rs")
+			.lines()
+			.filter_map(|line| line.strip_prefix("pub static "))
+			.filter(|line| line.starts_with("URL_"))
+			.map(|line| {
+				let name = line.split(':').next().unwrap().to_string();
+				(name, line.split('"').nth(1).unwrap().to_string())
+			})
+			.collect()
+	}
+
+	// Every (method, route) pair app.rs registers, read from the source so new routes can not be forgotten
+	fn registered_routes() -> Vec<(String, String)> {
+		let declared = declared_routes();
+		let source: String = include_str!("app.rs").split_whitespace().collect();
+		source
+			.split("routes::")
+			.skip(1)
+			.filter(|registration| registration.starts_with("URL_"))
+			.map(|registration| {
+				let name: String = registration
+					.chars()
+					.take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+					.collect();
+				// The route the constant is registered with, or the first of its resource
+				let method = registration.split("web::").nth(1).unwrap().split('(').next().unwrap();
+				(method.to_string(), declared[&name].clone())
+			})
+			.collect()
+	}
+
+	// Routes with a regex segment like `{file:.*}` are documented as `{file}`
+	fn documented_path(route: &str) -> String {
+		route
+			.split('/')
+			.map(|segment| match segment.split_once(':') {
+				Some((name, _)) if segment.starts_with('{') => format!("{}}}", name),
+				_ => segment.to_string(),
+			})
+			.collect::<Vec<_>>()
+			.join("/")
+	}
+
+	#[test]
+	fn test_every_route_is_documented() {
+		let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+		let routes = registered_routes();
+		assert!(routes.len() > declared_routes().len());
+		for (method, route) in routes {
+			let path = documented_path(&route);
+			assert!(
+				spec["paths"][&path][&method].is_object(),
+				"{} {} is missing from the OpenAPI document",
+				method.to_uppercase(),
+				path
+			);
+		}
+	}
+
+	#[actix_rt::test]
+	async fn test_get_openapi() {
+		let srv = init_service(
+			App::new()
+				.route("/openapi.json", web::get().to(get_openapi))
+				.route("/docs", web::get().to(get_docs))
+				.route("/docs/{file:.*}", web::get().to(get_docs_file)),
+		)
+		.await;
+
+		let req = TestRequest::get().uri("/openapi.json").to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+		let spec: serde_json::Value = read_body_json(response).await;
+		assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+		assert!(spec["components"]["schemas"]["Bid"].is_object());
+		assert!(spec["paths"]["/bids"]["post"].is_object());
+		assert_eq!(spec["components"]["securitySchemes"]["bearer"]["scheme"], "bearer");
+		assert_eq!(spec["components"]["securitySchemes"]["api_key"]["name"], "X-Api-Key");
+
+		let req = TestRequest::get().uri("/docs").to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::PERMANENT_REDIRECT);
+		assert_eq!(response.headers().get(header::LOCATION).unwrap(), "docs/");
+
+		// The page and its assets are served from the binary
+		let req = TestRequest::get().uri("/docs/").to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+		let body = read_body(response).await;
+		assert!(String::from_utf8_lossy(&body).contains("swagger-ui-bundle.js"));
+		assert!(!String::from_utf8_lossy(&body).contains("https://"));
+
+		let req = TestRequest::get().uri("/docs/swagger-initializer.js").to_request();
+		let body = read_body(srv.call(req).await.unwrap()).await;
+		assert!(String::from_utf8_lossy(&body).contains("../openapi.json"));
+
+		let req = TestRequest::get().uri("/docs/swagger-ui-bundle.js").to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+
+		let req = TestRequest::get().uri("/docs/missing.js").to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), http::StatusCode::NOT_FOUND);
+	}
+}
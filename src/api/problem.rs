@@ -0,0 +1,411 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Every error response is an RFC 7807 `application/problem+json` document with a stable `code`
+
+use crate::errors::BidTrackerError;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{Error as ActixErr, HttpMessage, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+pub static CONTENT_TYPE_PROBLEM: &str = "application/problem+json";
+pub static HEADER_REQUEST_ID: &str = "X-Request-Id";
+
+static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// Incoming request ids longer than this are replaced by a generated one
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ProblemDetails {
+	// Always "about:blank", the `code` tells the problems apart
+	#[serde(rename = "type")]
+	pub problem_type: String,
+	pub title: String,
+	pub status: u16,
+	// Stable and machine readable, e.g. `item_not_biddable`
+	pub code: String,
+	pub detail: String,
+	// The path of the request which failed
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub instance: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub request_id: Option<String>,
+	// Problem specific members, e.g. the `results` of a rejected atomic batch
+	#[serde(flatten)]
+	#[schema(value_type = Object)]
+	pub extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+/// The request id of the current request, available from the request extensions
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+/// An error which is reported as problem details
+#[derive(Debug)]
+pub struct Problem {
+	status: StatusCode,
+	code: &'static str,
+	detail: String,
+	extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Problem {
+	pub fn new(status: StatusCode, code: &'static str, detail: impl Into<String>) -> Problem {
+		Problem {
+			status,
+			code,
+			detail: detail.into(),
+			extensions: serde_json::Map::new(),
+		}
+	}
+
+	/// A problem for an error which only carries a status, e.g. an unknown route
+	pub fn from_status(status: StatusCode, detail: impl Into<String>) -> Problem {
+		let code = match status {
+			StatusCode::BAD_REQUEST => "bad_request",
+			StatusCode::UNAUTHORIZED => "unauthorized",
+			StatusCode::FORBIDDEN => "forbidden",
+			StatusCode::NOT_FOUND => "not_found",
+			StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+			StatusCode::NOT_ACCEPTABLE => "not_acceptable",
+			StatusCode::CONFLICT => "conflict",
+			StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+			StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+			StatusCode::UNPROCESSABLE_ENTITY => "unprocessable_entity",
+			StatusCode::TOO_MANY_REQUESTS => "too_many_requests",
+			StatusCode::SERVICE_UNAVAILABLE => "service_unavailable",
+			status if status.is_client_error() => "client_error",
+			_ => "internal_error",
+		};
+		Problem::new(status, code, detail)
+	}
+
+	pub fn with_extension(mut self, key: &str, value: impl Serialize) -> Problem {
+		let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+		self.extensions.insert(key.to_string(), value);
+		self
+	}
+
+	pub fn code(&self) -> &'static str {
+		self.code
+	}
+
+	pub fn details(&self, instance: Option<String>, request_id: Option<String>) -> ProblemDetails {
+		ProblemDetails {
+			problem_type: "about:blank".to_string(),
+			title: self.status.canonical_reason().unwrap_or("Error").to_string(),
+			status: self.status.as_u16(),
+			code: self.code.to_string(),
+			detail: self.detail.clone(),
+			instance,
+			request_id,
+			extensions: self.extensions.clone(),
+		}
+	}
+}
+
+impl fmt::Display for Problem {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.detail)
+	}
+}
+
+impl ResponseError for Problem {
+	fn status_code(&self) -> StatusCode {
+		self.status
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		problem_response(self.status, &self.details(None, None))
+	}
+}
+
+impl From<BidTrackerError> for Problem {
+	fn from(e: BidTrackerError) -> Problem {
+		Problem::new(e.status_code(), e.code(), e.to_string())
+	}
+}
+
+fn problem_response(status: StatusCode, details: &ProblemDetails) -> HttpResponse {
+	let body = serde_json::to_string(details).unwrap_or_default();
+	HttpResponse::build(status)
+		.insert_header((header::CONTENT_TYPE, CONTENT_TYPE_PROBLEM))
+		.body(body)
+}
+
+fn request_id(req: &ServiceRequest) -> String {
+	req.headers()
+		.get(HEADER_REQUEST_ID)
+		.and_then(|value| value.to_str().ok())
+		.filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LEN)
+		.map(String::from)
+		.unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Assign every request an id and turn every error response into problem details carrying that id.
+///
+/// The id is taken from the `X-Request-Id` header when the client sent one and echoed in the response.
+pub async fn problem_details(
+	req: ServiceRequest,
+	next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, ActixErr> {
+	let request_id = request_id(&req);
+	req.extensions_mut().insert(RequestId(request_id.clone()));
+	let instance = req.path().to_string();
+
+	let res = next.call(req).await?.map_into_boxed_body();
+	// Incoming ids passed `to_str`, generated ones are uuids, so both are valid header values
+	let header_value = HeaderValue::from_str(&request_id).expect("request id is a valid header value");
+	let status = res.status();
+	if !status.is_client_error() && !status.is_server_error() {
+		let mut res = res;
+		res.headers_mut().insert(REQUEST_ID.clone(), header_value);
+		return Ok(res);
+	}
+
+	let details = match res.response().error() {
+		Some(e) => match e.as_error::<Problem>() {
+			Some(problem) => problem.details(Some(instance), Some(request_id)),
+			None => Problem::from_status(status, e.to_string()).details(Some(instance), Some(request_id)),
+		},
+		None => {
+			let detail = status.canonical_reason().unwrap_or("Error");
+			Problem::from_status(status, detail).details(Some(instance), Some(request_id))
+		}
+	};
+	let (http_req, original) = res.into_parts();
+	let mut response = problem_response(status, &details);
+	for (name, value) in original.headers() {
+		if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+			response.headers_mut().append(name.clone(), value.clone());
+		}
+	}
+	response.headers_mut().insert(REQUEST_ID.clone(), header_value);
+	Ok(ServiceResponse::new(http_req, response))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::api::AppState;
+	use crate::auth::JwtKeys;
+	use crate::bidtracker::{Bid, BidManagement, BidTracker};
+	use crate::idempotency::HEADER_IDEMPOTENCY_KEY;
+	use actix_web::dev::{Service, ServiceResponse};
+	use actix_web::test::{init_service, read_body_json, TestRequest};
+	use actix_web::App;
+
+	const ITEM: &str = "b2f9ee6d-79fe-4b14-9c19-35a69a89219a";
+
+	fn state() -> AppState {
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		AppState::for_tests(BidManagement::new(vec![item_uuid]), JwtKeys::disabled())
+	}
+
+	fn bid(item: &str, amount: f64) -> Bid {
+		Bid {
+			item_uuid: uuid::Uuid::parse_str(item).unwrap(),
+			user_uuid: uuid::Uuid::new_v4(),
+			timestamp: 1,
+			amount,
+		}
+	}
+
+	async fn assert_problem(response: ServiceResponse, status: StatusCode, code: &str) -> ProblemDetails {
+		assert_eq!(response.status(), status);
+		assert_eq!(
+			response.headers().get(header::CONTENT_TYPE).unwrap(),
+			CONTENT_TYPE_PROBLEM
+		);
+		let request_id = response
+			.headers()
+			.get(HEADER_REQUEST_ID)
+			.unwrap()
+			.to_str()
+			.unwrap()
+			.to_string();
+		let problem: ProblemDetails = read_body_json(response).await;
+		assert_eq!(problem.status, status.as_u16());
+		assert_eq!(problem.code, code);
+		assert_eq!(problem.request_id, Some(request_id));
+		assert!(!problem.detail.is_empty());
+		problem
+	}
+
+	#[actix_rt::test]
+	async fn test_error_responses_are_problems() {
+		let state = state();
+		let srv = init_service(App::new().configure(|cfg| state.configure(cfg))).await;
+
+		let unknown = uuid::Uuid::new_v4().to_string();
+		let req = TestRequest::post()
+			.uri("/api/v1/bids")
+			.set_json(bid(&unknown, 10f64))
+			.to_request();
+		let problem = assert_problem(srv.call(req).await.unwrap(), StatusCode::NOT_FOUND, "item_not_found").await;
+		assert_eq!(problem.instance.as_deref(), Some("/api/v1/bids"));
+		assert_eq!(problem.title, "Not Found");
+
+		let req = TestRequest::get()
+			.uri(&format!("/api/v1/users/{}/bids", unknown))
+			.to_request();
+		assert_problem(srv.call(req).await.unwrap(), StatusCode::NOT_FOUND, "user_not_found").await;
+
+		let req = TestRequest::get()
+			.uri(&format!("/api/v1/bids/{}?cursor=invalid", ITEM))
+			.to_request();
+		assert_problem(
+			srv.call(req).await.unwrap(),
+			StatusCode::UNPROCESSABLE_ENTITY,
+			"invalid_request",
+		)
+		.await;
+
+		let req = TestRequest::delete()
+			.uri(&format!("/api/v1/webhooks/{}", unknown))
+			.to_request();
+		assert_problem(srv.call(req).await.unwrap(), StatusCode::NOT_FOUND, "webhook_not_found").await;
+
+		let req = TestRequest::post()
+			.uri("/api/v1/bids")
+			.insert_header((HEADER_IDEMPOTENCY_KEY, "key"))
+			.set_json(bid(ITEM, 10f64))
+			.to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::OK);
+		let req = TestRequest::post()
+			.uri("/api/v1/bids")
+			.insert_header((HEADER_IDEMPOTENCY_KEY, "key"))
+			.set_json(bid(ITEM, 11f64))
+			.to_request();
+		assert_problem(
+			srv.call(req).await.unwrap(),
+			StatusCode::CONFLICT,
+			"idempotency_conflict",
+		)
+		.await;
+
+		let req = TestRequest::post()
+			.uri("/api/v1/bids/batch?atomic=true")
+			.set_json(vec![bid(ITEM, 20f64), bid(&unknown, 21f64)])
+			.to_request();
+		let problem = assert_problem(
+			srv.call(req).await.unwrap(),
+			StatusCode::UNPROCESSABLE_ENTITY,
+			"batch_rejected",
+		)
+		.await;
+		assert_eq!(problem.extensions["results"].as_array().unwrap().len(), 2);
+
+		let req = TestRequest::get().uri("/api/v1/bids/not-a-uuid").to_request();
+		assert_problem(srv.call(req).await.unwrap(), StatusCode::BAD_REQUEST, "invalid_path").await;
+
+		let req = TestRequest::get()
+			.uri(&format!("/api/v1/bids/{}?sort=color", ITEM))
+			.to_request();
+		assert_problem(srv.call(req).await.unwrap(), StatusCode::BAD_REQUEST, "invalid_query").await;
+
+		let req = TestRequest::post()
+			.uri("/api/v1/bids")
+			.insert_header((header::CONTENT_TYPE, "application/json"))
+			.set_payload("{not json")
+			.to_request();
+		assert_problem(srv.call(req).await.unwrap(), StatusCode::BAD_REQUEST, "invalid_json").await;
+
+		let req = TestRequest::post()
+			.uri("/api/v1/bids")
+			.set_json(serde_json::json!({"something": "random"}))
+			.to_request();
+		assert_problem(
+			srv.call(req).await.unwrap(),
+			StatusCode::UNPROCESSABLE_ENTITY,
+			"invalid_body",
+		)
+		.await;
+
+		let req = TestRequest::post()
+			.uri("/api/v1/bids")
+			.insert_header((header::CONTENT_TYPE, "text/plain"))
+			.set_payload("{}")
+			.to_request();
+		assert_problem(
+			srv.call(req).await.unwrap(),
+			StatusCode::UNSUPPORTED_MEDIA_TYPE,
+			"unsupported_media_type",
+		)
+		.await;
+
+		let req = TestRequest::post()
+			.uri(&format!("/api/v1/items/{}/close", ITEM))
+			.to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::OK);
+		let req = TestRequest::post()
+			.uri("/api/v1/bids")
+			.set_json(bid(ITEM, 50f64))
+			.to_request();
+		assert_problem(
+			srv.call(req).await.unwrap(),
+			StatusCode::UNPROCESSABLE_ENTITY,
+			"item_not_biddable",
+		)
+		.await;
+
+		let req = TestRequest::get().uri("/api/v1/nothing-here").to_request();
+		assert_problem(srv.call(req).await.unwrap(), StatusCode::NOT_FOUND, "not_found").await;
+	}
+
+	#[actix_rt::test]
+	async fn test_request_id() {
+		let state = state();
+		let srv = init_service(App::new().configure(|cfg| state.configure(cfg))).await;
+
+		// A request id sent by the client is echoed, on success as well
+		let req = TestRequest::get()
+			.uri(&format!("/api/v1/bids/{}", ITEM))
+			.insert_header((HEADER_REQUEST_ID, "my-request"))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), StatusCode::OK);
+		assert_eq!(response.headers().get(HEADER_REQUEST_ID).unwrap(), "my-request");
+
+		let req = TestRequest::get()
+			.uri("/api/v1/bids/not-a-uuid")
+			.insert_header((HEADER_REQUEST_ID, "my-request"))
+			.to_request();
+		let problem: ProblemDetails = read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(problem.request_id.as_deref(), Some("my-request"));
+
+		// Otherwise one is generated per request
+		let req = TestRequest::get().uri("/api/v1/bids/not-a-uuid").to_request();
+		let first: ProblemDetails = read_body_json(srv.call(req).await.unwrap()).await;
+		let req = TestRequest::get().uri("/api/v1/bids/not-a-uuid").to_request();
+		let second: ProblemDetails = read_body_json(srv.call(req).await.unwrap()).await;
+		assert!(uuid::Uuid::parse_str(first.request_id.as_ref().unwrap()).is_ok());
+		assert_ne!(first.request_id, second.request_id);
+	}
+}
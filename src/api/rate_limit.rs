@@ -0,0 +1,220 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::problem::ProblemDetails;
+use super::{send_json, ResponseMessage};
+use crate::auth::Caller;
+use crate::rate_limit::{client_keys, Decision, RateLimitCounter, RateLimiter, RateLimits};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{http::StatusCode, web, Error as ActixErr, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+static RATE_LIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+static RATE_LIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+static RATE_LIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// The limits in place and how often they kicked in
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct RateLimitsReport {
+	pub limits: RateLimits,
+	pub counters: Vec<RateLimitCounter>,
+}
+
+/// Take a token from the buckets of the caller and its ip, and answer `429` once one of them is empty.
+///
+/// Must run inside `auth::authenticate`, which identifies the caller. Every limited response carries
+/// the `RateLimit-*` headers of the tightest bucket, rejected ones a `Retry-After` as well.
+pub async fn limit_bids(
+	req: ServiceRequest,
+	next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, ActixErr> {
+	let decision = match req.app_data::<web::Data<RateLimiter>>() {
+		Some(limiter) => {
+			let caller = req
+				.extensions()
+				.get::<Caller>()
+				.cloned()
+				.unwrap_or(Caller::Unrestricted);
+			let clients = client_keys(&caller, req.peer_addr().map(|addr| addr.ip()));
+			limiter.check(&clients, Instant::now())
+		}
+		None => None,
+	};
+
+	let mut res = match decision.map(Decision::into_result) {
+		Some(Err(e)) => req.error_response(e),
+		_ => next.call(req).await?.map_into_boxed_body(),
+	};
+	if let Some(decision) = decision {
+		set_headers(res.headers_mut(), &decision);
+	}
+	Ok(res)
+}
+
+/// Take a token per bid of a batch from the buckets of the caller and its ip, `None` if no limit applies.
+/// The batch route is not wrapped in `limit_bids` since the number of bids is only known once the body is read.
+pub fn limit_batch(req: &HttpRequest, caller: &Caller, bids: usize) -> Option<Decision> {
+	let limiter = req.app_data::<web::Data<RateLimiter>>()?;
+	let clients = client_keys(caller, req.peer_addr().map(|addr| addr.ip()));
+	// An empty batch is still a request
+	let tokens = u32::try_from(bids.max(1)).unwrap_or(u32::MAX);
+	limiter.check_tokens(&clients, tokens, Instant::now())
+}
+
+pub fn set_headers(headers: &mut HeaderMap, decision: &Decision) {
+	let seconds = |duration: Duration| HeaderValue::from(duration.as_secs_f64().ceil() as u64);
+	headers.insert(RATE_LIMIT_LIMIT.clone(), HeaderValue::from(decision.limit));
+	headers.insert(RATE_LIMIT_REMAINING.clone(), HeaderValue::from(decision.remaining));
+	headers.insert(RATE_LIMIT_RESET.clone(), seconds(decision.reset));
+	if !decision.allowed {
+		headers.insert(header::RETRY_AFTER, seconds(decision.retry_after));
+	}
+}
+
+/// Get the rate limits of bid placement and how often they kicked in
+#[utoipa::path(
+	get,
+	path = "/admin/rate-limits",
+	tag = "admin",
+	responses(
+		(status = 200, description = "The limits and their counters", body = ResponseMessage<RateLimitsReport>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_rate_limits(caller: Caller, limiter: web::Data<RateLimiter>) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	send_json(StatusCode::OK, "Returning from get_rate_limits", &report(&limiter))
+}
+
+/// Replace the rate limits of bid placement, effective immediately
+#[utoipa::path(
+	put,
+	path = "/admin/rate-limits",
+	tag = "admin",
+	request_body = RateLimits,
+	responses(
+		(status = 200, description = "The new limits and their counters", body = ResponseMessage<RateLimitsReport>),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "A limit is not positive or names an unknown kind of client", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn put_rate_limits(
+	limits: web::Json<RateLimits>,
+	caller: Caller,
+	limiter: web::Data<RateLimiter>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	limiter.set_limits(limits.into_inner())?;
+	send_json(StatusCode::OK, "Returning from put_rate_limits", &report(&limiter))
+}
+
+fn report(limiter: &RateLimiter) -> RateLimitsReport {
+	RateLimitsReport {
+		limits: limiter.limits(),
+		counters: limiter.counters(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::rate_limit::{ClientKind, Limit};
+	use actix_web::test::{init_service, read_body_json, TestRequest};
+	use actix_web::{dev::Service, middleware, App};
+
+	#[actix_rt::test]
+	async fn test_limit_bids() {
+		let limiter = web::Data::new(RateLimiter::new(RateLimits::default()).unwrap());
+		let srv = init_service(
+			App::new()
+				.app_data(limiter.clone())
+				.service(
+					web::resource("/bids")
+						.wrap(middleware::from_fn(limit_bids))
+						.route(web::post().to(HttpResponse::Ok)),
+				)
+				.route("/admin/rate-limits", web::get().to(get_rate_limits))
+				.route("/admin/rate-limits", web::put().to(put_rate_limits)),
+		)
+		.await;
+		let bid = |ip: &str| {
+			TestRequest::post()
+				.uri("/bids")
+				.peer_addr(format!("{}:4000", ip).parse().unwrap())
+				.to_request()
+		};
+
+		// Nothing is limited by default
+		let response = srv.call(bid("10.0.0.1")).await.unwrap();
+		assert_eq!(response.status(), StatusCode::OK);
+		assert!(response.headers().get(&RATE_LIMIT_LIMIT).is_none());
+
+		let limits = serde_json::json!({ "ip": { "burst": 2, "per_sec": 0.5 }, "overrides": { "ip:10.0.0.2": { "burst": 5, "per_sec": 1 } } });
+		let req = TestRequest::put()
+			.uri("/admin/rate-limits")
+			.set_json(&limits)
+			.to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::OK);
+
+		let response = srv.call(bid("10.0.0.1")).await.unwrap();
+		assert_eq!(response.headers().get(&RATE_LIMIT_LIMIT).unwrap(), "2");
+		assert_eq!(response.headers().get(&RATE_LIMIT_REMAINING).unwrap(), "1");
+		assert_eq!(srv.call(bid("10.0.0.1")).await.unwrap().status(), StatusCode::OK);
+		let response = srv.call(bid("10.0.0.1")).await.unwrap();
+		assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+		assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "2");
+		assert_eq!(response.headers().get(&RATE_LIMIT_REMAINING).unwrap(), "0");
+
+		// Other ips have buckets of their own
+		let response = srv.call(bid("10.0.0.2")).await.unwrap();
+		assert_eq!(response.headers().get(&RATE_LIMIT_LIMIT).unwrap(), "5");
+
+		let req = TestRequest::get().uri("/admin/rate-limits").to_request();
+		let report: ResponseMessage<RateLimitsReport> = read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(report.data.limits.ip, Some(Limit { burst: 2, per_sec: 0.5 }));
+		assert_eq!(
+			report.data.counters,
+			vec![RateLimitCounter {
+				kind: ClientKind::Ip,
+				allowed: 3,
+				limited: 1
+			}]
+		);
+
+		let limits = serde_json::json!({ "user": { "burst": 0, "per_sec": 1 } });
+		let req = TestRequest::put()
+			.uri("/admin/rate-limits")
+			.set_json(&limits)
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+		let problem: ProblemDetails = read_body_json(response).await;
+		assert_eq!(problem.code, "invalid_request");
+	}
+}
@@ -26,11 +26,15 @@ use crate::bidtracker::Bid;
 use actix_web::{http::StatusCode, Error as ActixErr, HttpResponse};
 
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct ResponseMessage<T: Serialize + ?Sized> {
 	pub code: u16,
 	pub message: String,
+	// Only present on paginated listings which have more pages
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub next_cursor: Option<String>,
 	pub data: T,
 }
 
@@ -49,12 +53,26 @@ pub struct ResponseMessageBids {
 }
 
 pub fn send_json<T>(status_code: StatusCode, message: &str, data: &T) -> Result<HttpResponse, ActixErr>
+where
+	T: Serialize + ?Sized,
+{
+	send_page(status_code, message, data, None)
+}
+
+/// Same as `send_json` but additionally sets the cursor of the next page
+pub fn send_page<T>(
+	status_code: StatusCode,
+	message: &str,
+	data: &T,
+	next_cursor: Option<String>,
+) -> Result<HttpResponse, ActixErr>
 where
 	T: Serialize + ?Sized,
 {
 	let json = ResponseMessage {
 		code: status_code.as_u16(),
 		message: message.into(),
+		next_cursor,
 		data,
 	};
 	Ok(HttpResponse::build(StatusCode::from_u16(status_code.as_u16()).unwrap()).json(json.borrow()))
@@ -18,14 +18,146 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::api_keys::Scope;
+use actix_web::http::Method;
+
+// APIPrefix every route below is served under
+pub static API_PREFIX: &str = "/api/v1";
+
+// MetricsPath to GET the metrics in the Prometheus text format, outside of API_PREFIX
+pub static METRICS_PATH: &str = "/metrics";
+
 // URLBidItem to POST bid for a given itemuuid
 pub static URL_BID_ITEM: &str = "/bids";
 
+// URLBidBatch to POST many bids at once, as a json array or newline delimited json
+pub static URL_BID_BATCH: &str = "/bids/batch";
+
 // URLBidGetAll to GET all the bids for this given itemuuid
 pub static URL_BID_GET_ALL: &str = "/bids/{itemuuid}";
 
 // URLBidGetWinning to GET winning bids on this itemuuid
 pub static URL_BID_GET_WINNING: &str = "/bids/{itemuuid}/winning";
 
+// URLBidExport to GET all the bids for this given itemuuid as csv or ndjson
+pub static URL_BID_EXPORT: &str = "/bids/{itemuuid}/export";
+
+// URLExport to GET the bids on all the items as csv or ndjson
+pub static URL_EXPORT: &str = "/export";
+
 // URLUserGetAllBids to GET all the bids for this user
 pub static URL_USER_GET_ALL_BIDS: &str = "/users/{useruuid}/bids";
+
+// URLUserGetAllItems to GET all the items this user has bid on
+pub static URL_USER_GET_ALL_ITEMS: &str = "/users/{useruuid}/items";
+
+// URLUserExposure to GET the exposure and credit limit of this useruuid
+pub static URL_USER_EXPOSURE: &str = "/users/{useruuid}/exposure";
+
+// URLWebsocket to subscribe to items and place bids over a websocket
+pub static URL_WEBSOCKET: &str = "/ws";
+
+// URLItems to POST a new item up for auction
+pub static URL_ITEMS: &str = "/items";
+
+// URLItemClose to close the bidding on this itemuuid
+pub static URL_ITEM_CLOSE: &str = "/items/{itemuuid}/close";
+
+// URLWebhooks to register and list webhooks
+pub static URL_WEBHOOKS: &str = "/webhooks";
+
+// URLWebhookDeadLetters to GET the webhook deliveries which ran out of retries
+pub static URL_WEBHOOK_DEAD_LETTERS: &str = "/webhooks/dead-letters";
+
+// URLWebhook to DELETE a webhook
+pub static URL_WEBHOOK: &str = "/webhooks/{webhookuuid}";
+
+// URLUserNotifications to GET and PUT the notification preferences of this useruuid
+pub static URL_USER_NOTIFICATIONS: &str = "/users/{useruuid}/notifications";
+
+// URLUserItemNotifications to opt this useruuid in or out of the notifications for this itemuuid
+pub static URL_USER_ITEM_NOTIFICATIONS: &str = "/users/{useruuid}/notifications/{itemuuid}";
+
+// URLAdminImport to POST a csv or ndjson file of historical bids
+pub static URL_ADMIN_IMPORT: &str = "/admin/import";
+
+// URLGraphQL to POST GraphQL queries, or GET the GraphiQL explorer
+pub static URL_GRAPHQL: &str = "/graphql";
+
+// URLGraphQLWebsocket to run GraphQL subscriptions over a websocket
+pub static URL_GRAPHQL_WS: &str = "/graphql/ws";
+
+// URLAdminApiKeys to create and list api keys
+pub static URL_ADMIN_API_KEYS: &str = "/admin/api-keys";
+
+// URLAdminApiKey to PATCH the scopes of an api key, or DELETE to revoke it
+pub static URL_ADMIN_API_KEY: &str = "/admin/api-keys/{keyuuid}";
+
+// URLAdminRateLimits to GET or PUT the rate limits of bid placement
+pub static URL_ADMIN_RATE_LIMITS: &str = "/admin/rate-limits";
+
+// URLAdminUserCreditLimit to PUT the credit limit of this useruuid
+pub static URL_ADMIN_USER_CREDIT_LIMIT: &str = "/admin/users/{useruuid}/credit-limit";
+
+// URLAdminFraudFlags to GET the bids flagged by the fraud rules
+pub static URL_ADMIN_FRAUD_FLAGS: &str = "/admin/fraud/flags";
+
+// URLAdminFraudRules to GET or PUT the mode and thresholds of the fraud rules
+pub static URL_ADMIN_FRAUD_RULES: &str = "/admin/fraud/rules";
+
+// URLAdminBlocklist to GET the banned users
+pub static URL_ADMIN_BLOCKLIST: &str = "/admin/blocklist";
+
+// URLAdminBlocklistUser to PUT or DELETE the ban of this useruuid
+pub static URL_ADMIN_BLOCKLIST_USER: &str = "/admin/blocklist/{useruuid}";
+
+// URLAdminItemAllowlist to GET, PUT or DELETE the users invited to bid on this itemuuid
+pub static URL_ADMIN_ITEM_ALLOWLIST: &str = "/admin/items/{itemuuid}/allowlist";
+
+// URLAdminAuditHead to GET the head of the audit chain
+pub static URL_ADMIN_AUDIT_HEAD: &str = "/admin/audit/head";
+
+// URLAdminAuditVerify to GET the verification of the audit chain
+pub static URL_ADMIN_AUDIT_VERIFY: &str = "/admin/audit/verify";
+
+// URLOpenApi to GET the OpenAPI document of these routes
+pub static URL_OPENAPI: &str = "/openapi.json";
+
+// URLDocs to browse the OpenAPI document
+pub static URL_DOCS: &str = "/docs";
+
+// URLDocsFile to GET the files of the Swagger UI page, embedded in the binary
+pub static URL_DOCS_FILE: &str = "/docs/{file:.*}";
+
+// The scope an api key needs for each route, every other route needs `Scope::Admin`
+static API_KEY_SCOPES: &[(Method, &str, Scope)] = &[
+	(Method::POST, URL_BID_ITEM, Scope::BidOnBehalf),
+	(Method::POST, URL_BID_BATCH, Scope::BidOnBehalf),
+	(Method::GET, URL_BID_GET_ALL, Scope::Read),
+	(Method::GET, URL_BID_GET_WINNING, Scope::Read),
+	(Method::GET, URL_USER_GET_ALL_BIDS, Scope::Read),
+	(Method::GET, URL_USER_GET_ALL_ITEMS, Scope::Read),
+	(Method::GET, URL_USER_EXPOSURE, Scope::Read),
+	(Method::GET, URL_USER_NOTIFICATIONS, Scope::Read),
+	// Bids over the websocket are authorized one by one
+	(Method::GET, URL_WEBSOCKET, Scope::Read),
+	(Method::POST, URL_GRAPHQL, Scope::Read),
+	(Method::GET, URL_GRAPHQL, Scope::Read),
+	(Method::GET, URL_GRAPHQL_WS, Scope::Read),
+	(Method::GET, URL_OPENAPI, Scope::Read),
+	(Method::GET, URL_DOCS, Scope::Read),
+	(Method::GET, URL_DOCS_FILE, Scope::Read),
+];
+
+/// The scope an api key needs to call `method` on `route`, one of the routes above
+pub fn required_scope(method: &Method, route: &str) -> Scope {
+	// Unknown routes are answered with a 404 by the router
+	if route.is_empty() {
+		return Scope::Read;
+	}
+	API_KEY_SCOPES
+		.iter()
+		.find(|(route_method, route_path, _)| route_method == method && *route_path == route)
+		.map(|(_, _, scope)| *scope)
+		.unwrap_or(Scope::Admin)
+}
@@ -0,0 +1,111 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::telemetry::{self, TRACEPARENT, TRACESTATE};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::Error as ActixErr;
+use tracing::{field, Instrument};
+
+/// Run every request in a server span named after its route pattern, continuing the trace of its `traceparent`
+pub async fn trace_requests(
+	req: ServiceRequest,
+	next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, ActixErr> {
+	let span = tracing::info_span!(
+		"request",
+		otel.name = field::Empty,
+		otel.kind = "server",
+		otel.status_code = field::Empty,
+		http.request.method = %req.method(),
+		http.route = field::Empty,
+		http.response.status_code = field::Empty,
+		url.path = %req.path(),
+	);
+	let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok());
+	telemetry::set_remote_parent(&span, header(TRACEPARENT), header(TRACESTATE));
+
+	let res = next.call(req).instrument(span.clone()).await?;
+	let request = res.request();
+	// The pattern is only known once the scope routed the request
+	let route = request.match_pattern().unwrap_or_else(|| request.path().to_string());
+	span.record("otel.name", format!("{} {}", request.method(), route));
+	span.record("http.route", route);
+	span.record("http.response.status_code", res.status().as_u16());
+	if res.status().is_server_error() {
+		span.record("otel.status_code", "error");
+	}
+	Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::api::routes;
+	use actix_web::test::{init_service, read_body, TestRequest};
+	use actix_web::{dev::Service, middleware, web, App, HttpResponse};
+	use opentelemetry::trace::{TraceContextExt, TracerProvider};
+	use opentelemetry_sdk::trace::SdkTracerProvider;
+	use tracing_opentelemetry::OpenTelemetrySpanExt;
+	use tracing_subscriber::layer::SubscriberExt;
+
+	const TRACEPARENT_VALUE: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+	/// Answer with the trace the handler runs in
+	async fn trace_id() -> HttpResponse {
+		let cx = tracing::Span::current().context();
+		HttpResponse::Ok().body(cx.span().span_context().trace_id().to_string())
+	}
+
+	#[actix_rt::test]
+	async fn test_trace_requests() {
+		let provider = SdkTracerProvider::builder().build();
+		let subscriber =
+			tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+		let _guard = tracing::subscriber::set_default(subscriber);
+		let srv = init_service(
+			App::new().service(
+				web::scope(routes::API_PREFIX)
+					.wrap(middleware::from_fn(trace_requests))
+					.route("/items/{itemuuid}/trace", web::get().to(trace_id)),
+			),
+		)
+		.await;
+
+		let req = TestRequest::get()
+			.uri("/api/v1/items/b2f9ee6d-79fe-4b14-9c19-35a69a89219a/trace")
+			.insert_header((TRACEPARENT, TRACEPARENT_VALUE))
+			.to_request();
+		let body = read_body(srv.call(req).await.unwrap()).await;
+		assert_eq!(body, "4bf92f3577b34da6a3ce929d0e0e4736");
+
+		// Without a traceparent a new trace is started
+		let req = TestRequest::get()
+			.uri("/api/v1/items/b2f9ee6d-79fe-4b14-9c19-35a69a89219a/trace")
+			.to_request();
+		let body = String::from_utf8(read_body(srv.call(req).await.unwrap()).await.to_vec()).unwrap();
+		assert_eq!(body.len(), 32);
+		assert_ne!(body, "4bf92f3577b34da6a3ce929d0e0e4736");
+		assert_ne!(body, "00000000000000000000000000000000");
+	}
+}
@@ -0,0 +1,204 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::problem::ProblemDetails;
+use super::{send_json, ResponseMessage};
+use crate::auth::Caller;
+use crate::errors::BidTrackerError;
+use crate::webhooks::{Delivery, WebhookEventKind, WebhookStore, WebhookSubscription};
+use actix_web::{http::StatusCode, web, Error as ActixErr, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct NewWebhook {
+	pub url: String,
+	#[serde(default)]
+	pub events: Vec<WebhookEventKind>,
+	// A random secret is generated if none is given
+	pub secret: Option<String>,
+}
+
+/// A webhook subscription as listed, without its secret
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct WebhookSummary {
+	pub id: uuid::Uuid,
+	pub url: String,
+	pub events: Vec<WebhookEventKind>,
+}
+
+/// Register a new webhook, the response is the only time the secret is returned
+#[utoipa::path(
+	post,
+	path = "/webhooks",
+	tag = "webhooks",
+	request_body = NewWebhook,
+	responses(
+		(status = 201, description = "The webhook was registered", body = ResponseMessage<WebhookSubscription>),
+		(status = 401, description = "No valid credentials were sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 422, description = "The url is not a valid http(s) url", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn register_webhook(
+	webhook: web::Json<NewWebhook>,
+	caller: Caller,
+	store: web::Data<WebhookStore>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let webhook = webhook.into_inner();
+	match reqwest::Url::parse(&webhook.url) {
+		Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+		_ => {
+			return Err(BidTrackerError::InvalidRequest(format!("Not a valid http(s) url: {}", webhook.url)).into());
+		}
+	}
+	let secret = webhook
+		.secret
+		.unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+
+	let subscription = store.register(webhook.url, webhook.events, secret);
+	send_json(StatusCode::CREATED, "Returning from register_webhook", &subscription)
+}
+
+/// List all the registered webhooks
+#[utoipa::path(
+	get,
+	path = "/webhooks",
+	tag = "webhooks",
+	responses(
+		(status = 200, description = "The registered webhooks", body = ResponseMessage<Vec<WebhookSummary>>),
+		(status = 401, description = "No valid credentials were sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_webhooks(caller: Caller, store: web::Data<WebhookStore>) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	let webhooks: Vec<_> = store
+		.subscriptions()
+		.into_iter()
+		.map(|subscription| WebhookSummary {
+			id: subscription.id,
+			url: subscription.url,
+			events: subscription.events,
+		})
+		.collect();
+	send_json(StatusCode::OK, "Returning from get_webhooks", &webhooks)
+}
+
+/// Remove a webhook and drop its pending deliveries
+#[utoipa::path(
+	delete,
+	path = "/webhooks/{webhookuuid}",
+	tag = "webhooks",
+	params(("webhookuuid" = uuid::Uuid, Path, description = "Uuid of the webhook")),
+	responses(
+		(status = 200, description = "The webhook was removed", body = ResponseMessage<uuid::Uuid>),
+		(status = 401, description = "No valid credentials were sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 404, description = "The webhook does not exist", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn delete_webhook(
+	webhook_uuid: web::Path<uuid::Uuid>,
+	caller: Caller,
+	store: web::Data<WebhookStore>,
+) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	store.remove(&webhook_uuid)?;
+	send_json(StatusCode::OK, "Returning from delete_webhook", &*webhook_uuid)
+}
+
+/// Get the deliveries which ran out of retries
+#[utoipa::path(
+	get,
+	path = "/webhooks/dead-letters",
+	tag = "webhooks",
+	responses(
+		(status = 200, description = "The deliveries which ran out of retries", body = ResponseMessage<Vec<Delivery>>),
+		(status = 401, description = "No valid credentials were sent", body = ProblemDetails, content_type = "application/problem+json"),
+		(status = 403, description = "The caller is not an admin", body = ProblemDetails, content_type = "application/problem+json")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_dead_letters(caller: Caller, store: web::Data<WebhookStore>) -> Result<HttpResponse, ActixErr> {
+	caller.authorize_admin()?;
+	send_json(StatusCode::OK, "Returning from get_dead_letters", &store.dead_letters())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::api::ResponseMessage;
+	use actix_web::{dev::Service, http, test, test::TestRequest, App};
+
+	#[actix_rt::test]
+	async fn test_webhook_management() {
+		let store = web::Data::new(WebhookStore::open(None).unwrap());
+		let srv = test::init_service(
+			App::new()
+				.app_data(store.clone())
+				.route("/webhooks", web::post().to(register_webhook))
+				.route("/webhooks", web::get().to(get_webhooks))
+				.route("/webhooks/dead-letters", web::get().to(get_dead_letters))
+				.route("/webhooks/{webhookuuid}", web::delete().to(delete_webhook)),
+		)
+		.await;
+
+		let req = TestRequest::post()
+			.uri("/webhooks")
+			.set_json(serde_json::json!({"url": "ftp://localhost/hook"}))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+
+		let req = TestRequest::post()
+			.uri("/webhooks")
+			.set_json(serde_json::json!({"url": "http://localhost/hook", "events": ["outbid"]}))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::CREATED);
+		let created: ResponseMessage<serde_json::Value> = test::read_body_json(response).await;
+		assert!(!created.data["secret"].as_str().unwrap().is_empty());
+		assert_eq!(created.data["events"], serde_json::json!(["outbid"]));
+
+		let req = TestRequest::get().uri("/webhooks").to_request();
+		let response = srv.call(req).await.unwrap();
+		let listed: ResponseMessage<Vec<serde_json::Value>> = test::read_body_json(response).await;
+		assert_eq!(listed.data.len(), 1);
+		assert!(listed.data[0].get("secret").is_none());
+
+		let req = TestRequest::get().uri("/webhooks/dead-letters").to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+
+		let uri = format!("/webhooks/{}", created.data["id"].as_str().unwrap());
+		let response = srv.call(TestRequest::delete().uri(&uri).to_request()).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::OK);
+		let response = srv.call(TestRequest::delete().uri(&uri).to_request()).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+		assert!(store.subscriptions().is_empty());
+	}
+}
@@ -0,0 +1,426 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::metrics::lock_tracker;
+use crate::auth::Caller;
+use crate::bidtracker::{Bid, BidEvent, BidManagement};
+use crate::config::Config;
+use crate::errors::BidTrackerError;
+use crate::metrics::{Metrics, Transport};
+use crate::rate_limit::{client_keys, ClientLimiter, RateLimiter};
+use actix_web::{http::StatusCode, web, Error as ActixErr, HttpRequest, HttpResponse};
+use actix_ws::{Message, MessageStream, Session};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::HashSet,
+	net::IpAddr,
+	sync::{Arc, Mutex},
+	time::Instant,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Messages a websocket client can send
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+	/// Start receiving events for the given items
+	Subscribe { items: Vec<uuid::Uuid> },
+	/// Stop receiving events for the given items
+	Unsubscribe { items: Vec<uuid::Uuid> },
+	/// Place a bid, `id` is echoed back in the acknowledgement
+	Bid { id: Option<String>, bid: Bid },
+}
+
+/// Messages the server sends to a websocket client
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+	/// Result of a `bid` message, `code` and `error` match the status code and the problem code of `post_bid_new`
+	Ack {
+		id: Option<String>,
+		code: u16,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		error: Option<String>,
+		message: String,
+		data: Option<Bid>,
+	},
+	/// Items currently subscribed to after a `subscribe` or `unsubscribe` message
+	Subscriptions { items: Vec<uuid::Uuid> },
+	/// An event on one of the subscribed items
+	Event { event: BidEvent },
+	/// The client message could not be processed, `error` is the stable code of the problem
+	Error { code: u16, error: String, message: String },
+}
+
+/// Upgrade the connection to a websocket session
+#[utoipa::path(
+	get,
+	path = "/ws",
+	tag = "bids",
+	responses(
+		(status = 101, description = "Switched to the websocket protocol, clients send `ClientMessage`s and receive `ServerMessage`s")
+	)
+)]
+#[tracing::instrument(skip_all)]
+pub async fn ws_connect(
+	req: HttpRequest,
+	body: web::Payload,
+	caller: Caller,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+	config: web::Data<Config>,
+) -> Result<HttpResponse, ActixErr> {
+	let (response, session, msg_stream) = actix_ws::handle(&req, body)?;
+	let (events, metrics) = {
+		let bdm = lock_tracker(&req, &bidtracker);
+		(bdm.subscribe(), bdm.metrics())
+	};
+	let ip = req.peer_addr().map(|addr| addr.ip());
+	// Bids over the session drain the same buckets as those posted to the bid routes
+	let rate_limit = req.app_data::<web::Data<RateLimiter>>().map(|limiter| ClientLimiter {
+		limiter: limiter.clone().into_inner(),
+		clients: client_keys(&caller, ip),
+	});
+	let subscriber = metrics.subscriber(Transport::Websocket);
+	let connection = Connection {
+		caller,
+		ip,
+		rate_limit,
+		metrics,
+	};
+
+	actix_web::rt::spawn(async move {
+		run_session(session, msg_stream, events, connection, bidtracker, config).await;
+		drop(subscriber);
+	});
+	Ok(response)
+}
+
+/// Who is on the other end of a session
+struct Connection {
+	caller: Caller,
+	ip: Option<IpAddr>,
+	rate_limit: Option<ClientLimiter>,
+	metrics: Arc<Metrics>,
+}
+
+async fn run_session(
+	mut session: Session,
+	mut msg_stream: MessageStream,
+	mut events: broadcast::Receiver<BidEvent>,
+	connection: Connection,
+	bidtracker: web::Data<Mutex<BidManagement>>,
+	config: web::Data<Config>,
+) {
+	let heartbeat_interval = config.ws_heartbeat_interval();
+	let client_timeout = config.ws_client_timeout();
+	let mut subscriptions = HashSet::new();
+	let mut last_seen = Instant::now();
+	let mut heartbeat = tokio::time::interval(heartbeat_interval);
+
+	let reason = loop {
+		tokio::select! {
+			_ = heartbeat.tick() => {
+				if last_seen.elapsed() > client_timeout {
+					tracing::debug!("Websocket client timed out, closing the session");
+					break None;
+				}
+				if session.ping(b"").await.is_err() {
+					return;
+				}
+			}
+			msg = msg_stream.recv() => {
+				let msg = match msg {
+					Some(Ok(msg)) => msg,
+					Some(Err(e)) => {
+						tracing::debug!("Websocket protocol error: {}", e);
+						break None;
+					}
+					None => break None,
+				};
+				last_seen = Instant::now();
+				match msg {
+					Message::Text(text) => {
+						for reply in handle_client_message(&text, &mut subscriptions, &connection, &bidtracker) {
+							if send(&mut session, &reply).await.is_err() {
+								return;
+							}
+						}
+					}
+					Message::Ping(bytes) if session.pong(&bytes).await.is_err() => return,
+					Message::Close(reason) => break reason,
+					_ => {}
+				}
+			}
+			event = events.recv() => {
+				match event {
+					Ok(event) if subscriptions.contains(event.item_uuid()) => {
+						if send(&mut session, &ServerMessage::Event { event }).await.is_err() {
+							return;
+						}
+					}
+					Ok(_) => {}
+					Err(RecvError::Lagged(skipped)) => {
+						tracing::warn!("Websocket session lagged behind, skipped {} events", skipped);
+					}
+					Err(RecvError::Closed) => break None,
+				}
+			}
+		}
+	};
+	let _ = session.close(reason).await;
+}
+
+async fn send(session: &mut Session, message: &ServerMessage) -> Result<(), actix_ws::Closed> {
+	// Serializing our own message types can not fail
+	let text = serde_json::to_string(message).unwrap();
+	session.text(text).await
+}
+
+/// Process a single text frame and return the replies for the client
+fn handle_client_message(
+	text: &str,
+	subscriptions: &mut HashSet<uuid::Uuid>,
+	connection: &Connection,
+	bidtracker: &Mutex<BidManagement>,
+) -> Vec<ServerMessage> {
+	let message = match serde_json::from_str::<ClientMessage>(text) {
+		Ok(message) => message,
+		Err(e) => {
+			// The same problems as an invalid json body of a REST request
+			let (code, error) = if e.is_data() {
+				(StatusCode::UNPROCESSABLE_ENTITY, "invalid_body")
+			} else {
+				(StatusCode::BAD_REQUEST, "invalid_json")
+			};
+			return vec![ServerMessage::Error {
+				code: code.as_u16(),
+				error: error.into(),
+				message: e.to_string(),
+			}];
+		}
+	};
+
+	match message {
+		ClientMessage::Subscribe { items } => {
+			let bdm = connection.metrics.lock(bidtracker);
+			let (known, unknown): (Vec<_>, Vec<_>) = items.into_iter().partition(|item| bdm.is_biddable(item));
+			drop(bdm);
+
+			subscriptions.extend(known);
+			let mut replies = vec![ServerMessage::Subscriptions {
+				items: sorted(subscriptions),
+			}];
+			if !unknown.is_empty() {
+				let e = BidTrackerError::ItemNotBiddable(format!("{:?}", unknown));
+				replies.push(ServerMessage::Error {
+					code: e.status_code().as_u16(),
+					error: e.code().into(),
+					message: e.to_string(),
+				});
+			}
+			replies
+		}
+		ClientMessage::Unsubscribe { items } => {
+			for item in &items {
+				subscriptions.remove(item);
+			}
+			vec![ServerMessage::Subscriptions {
+				items: sorted(subscriptions),
+			}]
+		}
+		ClientMessage::Bid { id, bid } => {
+			let result = connection
+				.rate_limit
+				.as_ref()
+				.map_or(Ok(()), ClientLimiter::acquire)
+				.and_then(|()| connection.caller.authorize_bid(&bid))
+				.and_then(|()| connection.metrics.lock(bidtracker).insert_bid_from(&bid, connection.ip));
+			let ack = match result {
+				Ok(()) => ServerMessage::Ack {
+					id,
+					code: StatusCode::OK.as_u16(),
+					error: None,
+					message: "Returning from post_bid_new bids".into(),
+					data: Some(bid),
+				},
+				Err(e) => ServerMessage::Ack {
+					id,
+					code: e.status_code().as_u16(),
+					error: Some(e.code().into()),
+					message: e.to_string(),
+					data: None,
+				},
+			};
+			vec![ack]
+		}
+	}
+}
+
+fn sorted(subscriptions: &HashSet<uuid::Uuid>) -> Vec<uuid::Uuid> {
+	let mut items: Vec<_> = subscriptions.iter().copied().collect();
+	items.sort();
+	items
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::auth::Claims;
+	use crate::bidtracker::BidTracker;
+	use crate::rate_limit::{Limit, RateLimits};
+	use actix_web::test::{init_service, TestRequest};
+	use actix_web::{dev::Service, http, App};
+
+	fn connection(caller: Caller) -> Connection {
+		Connection {
+			caller,
+			ip: None,
+			rate_limit: None,
+			metrics: Arc::default(),
+		}
+	}
+
+	fn bidtracker() -> Mutex<BidManagement> {
+		Mutex::new(BidManagement::new(vec![
+			uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap(),
+			uuid::Uuid::parse_str("b16ab43e-aa13-4079-b8c5-592e81312c01").unwrap(),
+		]))
+	}
+
+	#[test]
+	fn test_subscribe_and_unsubscribe() {
+		let bidtracker = bidtracker();
+		let mut subscriptions = HashSet::new();
+
+		let msg = r#"{"type": "subscribe", "items": ["b2f9ee6d-79fe-4b14-9c19-35a69a89219a", "7f272d43-0ff2-4e0f-9ebc-589eae48e3ad"]}"#;
+		let replies = handle_client_message(msg, &mut subscriptions, &connection(Caller::Unrestricted), &bidtracker);
+		assert_eq!(replies.len(), 2);
+		assert!(matches!(&replies[0], ServerMessage::Subscriptions { items } if items.len() == 1));
+		assert!(matches!(&replies[1], ServerMessage::Error { code: 422, error, .. } if error == "item_not_biddable"));
+
+		let msg = r#"{"type": "unsubscribe", "items": ["b2f9ee6d-79fe-4b14-9c19-35a69a89219a"]}"#;
+		let replies = handle_client_message(msg, &mut subscriptions, &connection(Caller::Unrestricted), &bidtracker);
+		assert!(matches!(&replies[0], ServerMessage::Subscriptions { items } if items.is_empty()));
+		assert!(subscriptions.is_empty());
+	}
+
+	#[test]
+	fn test_bid_acknowledgement() {
+		let bidtracker = bidtracker();
+		let mut subscriptions = HashSet::new();
+
+		let msg = r#"{"type": "bid", "id": "1", "bid": {"itemuuid": "b2f9ee6d-79fe-4b14-9c19-35a69a89219a",
+			"useruuid": "1c916ab6-255b-4a36-9574-e456e0f774c9", "timestamp": 12312321321, "amount": 30}}"#;
+		let replies = handle_client_message(msg, &mut subscriptions, &connection(Caller::Unrestricted), &bidtracker);
+		assert!(
+			matches!(&replies[0], ServerMessage::Ack { id: Some(id), code: 200, error: None, data: Some(_), .. } if id == "1")
+		);
+
+		// Same error code as post_bid_new for an unknown item
+		let msg = r#"{"type": "bid", "id": "2", "bid": {"itemuuid": "7f272d43-0ff2-4e0f-9ebc-589eae48e3ad",
+			"useruuid": "1c916ab6-255b-4a36-9574-e456e0f774c9", "timestamp": 12312321321, "amount": 30}}"#;
+		let replies = handle_client_message(msg, &mut subscriptions, &connection(Caller::Unrestricted), &bidtracker);
+		assert!(matches!(
+			&replies[0],
+			ServerMessage::Ack {
+				code: 404,
+				error: Some(error),
+				data: None,
+				..
+			} if error == "item_not_found"
+		));
+
+		// Bids are only accepted for the authenticated user
+		let msg = r#"{"type": "bid", "id": "3", "bid": {"itemuuid": "b2f9ee6d-79fe-4b14-9c19-35a69a89219a",
+			"useruuid": "1c916ab6-255b-4a36-9574-e456e0f774c9", "timestamp": 12312321321, "amount": 31}}"#;
+		let replies = handle_client_message(msg, &mut subscriptions, &connection(Caller::Anonymous), &bidtracker);
+		assert!(matches!(&replies[0], ServerMessage::Ack { code: 401, .. }));
+		let other = Caller::User(Claims {
+			sub: uuid::Uuid::new_v4(),
+			exp: u64::MAX,
+			roles: vec![],
+		});
+		let replies = handle_client_message(msg, &mut subscriptions, &connection(other), &bidtracker);
+		assert!(matches!(&replies[0], ServerMessage::Ack { code: 403, .. }));
+
+		// Bids drain the buckets of the session
+		let limits = RateLimits {
+			ip: Limit::from_settings(1, 0.001),
+			..Default::default()
+		};
+		let ip = Some([127, 0, 0, 1].into());
+		let limited = Connection {
+			caller: Caller::Unrestricted,
+			ip,
+			rate_limit: Some(ClientLimiter {
+				limiter: Arc::new(RateLimiter::new(limits).unwrap()),
+				clients: client_keys(&Caller::Unrestricted, ip),
+			}),
+			metrics: Arc::default(),
+		};
+		let replies = handle_client_message(msg, &mut subscriptions, &limited, &bidtracker);
+		assert!(matches!(&replies[0], ServerMessage::Ack { code: 200, .. }));
+		let replies = handle_client_message(msg, &mut subscriptions, &limited, &bidtracker);
+		assert!(matches!(&replies[0], ServerMessage::Ack { code: 429, .. }));
+
+		// Malformed messages
+		let replies = handle_client_message(
+			r#"{"type": "bid"}"#,
+			&mut subscriptions,
+			&connection(Caller::Unrestricted),
+			&bidtracker,
+		);
+		assert!(matches!(&replies[0], ServerMessage::Error { code: 422, error, .. } if error == "invalid_body"));
+		let replies = handle_client_message(
+			"not json",
+			&mut subscriptions,
+			&connection(Caller::Unrestricted),
+			&bidtracker,
+		);
+		assert!(matches!(&replies[0], ServerMessage::Error { code: 400, error, .. } if error == "invalid_json"));
+	}
+
+	#[actix_rt::test]
+	async fn test_ws_connect_handshake() {
+		let srv = init_service(
+			App::new()
+				.app_data(web::Data::new(bidtracker()))
+				.app_data(web::Data::new(Config::default()))
+				.route("/ws", web::get().to(ws_connect)),
+		)
+		.await;
+
+		let req = TestRequest::get().uri("/ws").to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+
+		let req = TestRequest::get()
+			.uri("/ws")
+			.insert_header((http::header::CONNECTION, "upgrade"))
+			.insert_header((http::header::UPGRADE, "websocket"))
+			.insert_header((http::header::SEC_WEBSOCKET_VERSION, "13"))
+			.insert_header((http::header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ=="))
+			.to_request();
+		let response = srv.call(req).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::SWITCHING_PROTOCOLS);
+	}
+}
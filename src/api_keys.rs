@@ -0,0 +1,249 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Long lived api keys for service to service clients. Only a hash of every key is stored.
+
+use crate::errors::BidTrackerError;
+use crate::persistence;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+use utoipa::ToSchema;
+
+pub const HEADER_API_KEY: &str = "X-Api-Key";
+
+// Every key starts with this, so leaked keys are easy to spot
+const KEY_PREFIX: &str = "btk";
+
+/// What an api key may do, every scope includes the ones before it
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+	/// Read items, bids and the data of every user
+	Read,
+	/// Place bids on behalf of any user
+	BidOnBehalf,
+	/// Manage webhooks, imports, items and api keys
+	Admin,
+}
+
+impl Scope {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Scope::Read => "read",
+			Scope::BidOnBehalf => "bid_on_behalf",
+			Scope::Admin => "admin",
+		}
+	}
+}
+
+/// An api key as listed, without the key itself
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ApiKey {
+	pub id: uuid::Uuid,
+	pub name: String,
+	pub scopes: Vec<Scope>,
+	// Unix timestamp in milliseconds
+	pub created_at: i64,
+	// Unix timestamp in milliseconds, revoked keys are rejected
+	pub revoked_at: Option<i64>,
+}
+
+impl ApiKey {
+	pub fn has_scope(&self, scope: Scope) -> bool {
+		self.scopes.iter().any(|granted| *granted >= scope)
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct StoredApiKey {
+	#[serde(flatten)]
+	api_key: ApiKey,
+	key_hash: String,
+}
+
+fn hash_key(key: &str) -> String {
+	hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// ApiKeyStore keeps the api keys, and writes them to `path` after every change so that they survive restarts
+#[derive(Debug, Default)]
+pub struct ApiKeyStore {
+	path: Option<PathBuf>,
+	keys: Mutex<HashMap<uuid::Uuid, StoredApiKey>>,
+}
+
+impl ApiKeyStore {
+	/// Open the store persisted at `path`, or an in-memory one if no path is given
+	pub fn open(path: Option<PathBuf>) -> Result<ApiKeyStore, BidTrackerError> {
+		let keys = match &path {
+			Some(path) => persistence::read_json(path)?,
+			None => HashMap::new(),
+		};
+		Ok(ApiKeyStore {
+			path,
+			keys: Mutex::new(keys),
+		})
+	}
+
+	/// Create a key, the returned key is the only time it is known in the clear
+	pub fn create(&self, name: String, scopes: Vec<Scope>, now: i64) -> Result<(ApiKey, String), BidTrackerError> {
+		validate_scopes(&scopes)?;
+		let id = uuid::Uuid::new_v4();
+		let key = format!("{}_{}_{}", KEY_PREFIX, id.simple(), uuid::Uuid::new_v4().simple());
+		let api_key = ApiKey {
+			id,
+			name,
+			scopes,
+			created_at: now,
+			revoked_at: None,
+		};
+		let stored = StoredApiKey {
+			api_key: api_key.clone(),
+			key_hash: hash_key(&key),
+		};
+		self.update(|keys| {
+			keys.insert(id, stored);
+		});
+		Ok((api_key, key))
+	}
+
+	pub fn list(&self) -> Vec<ApiKey> {
+		let keys = self.keys.lock().unwrap();
+		let mut listed: Vec<_> = keys.values().map(|stored| stored.api_key.clone()).collect();
+		listed.sort_by_key(|api_key| api_key.created_at);
+		listed
+	}
+
+	/// Replace the scopes of a key
+	pub fn set_scopes(&self, id: &uuid::Uuid, scopes: Vec<Scope>) -> Result<ApiKey, BidTrackerError> {
+		validate_scopes(&scopes)?;
+		self.update(|keys| {
+			let stored = keys.get_mut(id)?;
+			stored.api_key.scopes = scopes;
+			Some(stored.api_key.clone())
+		})
+		.ok_or_else(|| BidTrackerError::ApiKeyNotFound(id.to_string()))
+	}
+
+	/// Revoke a key, it stays listed but is rejected from now on
+	pub fn revoke(&self, id: &uuid::Uuid, now: i64) -> Result<ApiKey, BidTrackerError> {
+		self.update(|keys| {
+			let stored = keys.get_mut(id)?;
+			stored.api_key.revoked_at.get_or_insert(now);
+			Some(stored.api_key.clone())
+		})
+		.ok_or_else(|| BidTrackerError::ApiKeyNotFound(id.to_string()))
+	}
+
+	/// The api key `key` was issued as, if it exists and was not revoked
+	pub fn verify(&self, key: &str) -> Result<ApiKey, BidTrackerError> {
+		let invalid = || BidTrackerError::Unauthorized("Invalid api key".into());
+		let id = key
+			.strip_prefix(KEY_PREFIX)
+			.and_then(|rest| rest.strip_prefix('_'))
+			.and_then(|rest| rest.split_once('_'))
+			.and_then(|(id, _)| uuid::Uuid::parse_str(id).ok())
+			.ok_or_else(invalid)?;
+
+		let keys = self.keys.lock().unwrap();
+		match keys.get(&id) {
+			Some(stored) if stored.key_hash == hash_key(key) && stored.api_key.revoked_at.is_none() => {
+				Ok(stored.api_key.clone())
+			}
+			_ => Err(invalid()),
+		}
+	}
+
+	fn update<T>(&self, change: impl FnOnce(&mut HashMap<uuid::Uuid, StoredApiKey>) -> T) -> T {
+		let mut keys = self.keys.lock().unwrap();
+		let result = change(&mut keys);
+		if let Some(path) = &self.path {
+			if let Err(e) = persistence::write_json(path, &*keys) {
+				tracing::error!("Failed to persist the api keys to {}: {}", path.display(), e);
+			}
+		}
+		result
+	}
+}
+
+fn validate_scopes(scopes: &[Scope]) -> Result<(), BidTrackerError> {
+	if scopes.is_empty() {
+		return Err(BidTrackerError::InvalidRequest(
+			"An api key needs at least one scope".into(),
+		));
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_api_keys() {
+		let store = ApiKeyStore::open(None).unwrap();
+		let (api_key, key) = store.create("payments".into(), vec![Scope::Read], 1).unwrap();
+		assert!(key.starts_with("btk_"));
+		assert_eq!(store.verify(&key).unwrap(), api_key);
+		assert!(api_key.has_scope(Scope::Read) && !api_key.has_scope(Scope::BidOnBehalf));
+
+		// Only the hash is kept
+		let stored = store.keys.lock().unwrap()[&api_key.id].clone();
+		assert_ne!(stored.key_hash, key);
+		assert!(!serde_json::to_string(&stored).unwrap().contains(&key));
+
+		assert!(matches!(
+			store.verify("btk_garbage"),
+			Err(BidTrackerError::Unauthorized(_))
+		));
+		let forged = format!("{}x", key);
+		assert!(store.verify(&forged).is_err());
+
+		let updated = store.set_scopes(&api_key.id, vec![Scope::Admin]).unwrap();
+		assert!(updated.has_scope(Scope::BidOnBehalf));
+		assert!(matches!(
+			store.set_scopes(&api_key.id, vec![]),
+			Err(BidTrackerError::InvalidRequest(_))
+		));
+
+		let revoked = store.revoke(&api_key.id, 2).unwrap();
+		assert_eq!(revoked.revoked_at, Some(2));
+		assert!(store.verify(&key).is_err());
+		assert_eq!(store.list().len(), 1);
+		assert!(matches!(
+			store.revoke(&uuid::Uuid::new_v4(), 2),
+			Err(BidTrackerError::ApiKeyNotFound(_))
+		));
+	}
+
+	#[test]
+	fn test_persistence() {
+		let path = std::env::temp_dir().join(format!("api-keys-{}.json", uuid::Uuid::new_v4()));
+		let store = ApiKeyStore::open(Some(path.clone())).unwrap();
+		let (api_key, key) = store.create("payments".into(), vec![Scope::BidOnBehalf], 1).unwrap();
+
+		let reopened = ApiKeyStore::open(Some(path.clone())).unwrap();
+		assert_eq!(reopened.verify(&key).unwrap(), api_key);
+		std::fs::remove_file(path).unwrap();
+	}
+}
@@ -0,0 +1,395 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tamper-evident audit log. Every state change is appended as an entry carrying the hash of the entry
+//! before it, so altering, removing or reordering entries breaks the chain from that point on. The chain
+//! head can be notarised externally to catch a log that was cut short or rewritten as a whole.
+
+use crate::bidtracker::{AuctionItem, Bid};
+use crate::errors::BidTrackerError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+	fs::{self, File, OpenOptions},
+	io::{BufRead, BufReader, Write},
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+};
+use utoipa::ToSchema;
+
+/// The `prev_hash` of the first entry
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A state change worth proving later
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuditEvent {
+	BidPlaced {
+		bid: Bid,
+	},
+	BidsRetracted {
+		#[serde(rename = "itemuuid")]
+		item_uuid: uuid::Uuid,
+		#[serde(rename = "useruuid")]
+		user_uuid: uuid::Uuid,
+		bids: Vec<Bid>,
+	},
+	/// The bids an import placed, appended once the import is done
+	BidsImported {
+		bids: Vec<Bid>,
+	},
+	ItemCreated {
+		item: AuctionItem,
+	},
+	ItemClosed {
+		#[serde(rename = "itemuuid")]
+		item_uuid: uuid::Uuid,
+		winning_bid: Option<Bid>,
+	},
+	/// A change an admin requested through the `/admin` routes, appended before the handler runs
+	AdminAction {
+		actor: String,
+		method: String,
+		path: String,
+	},
+}
+
+/// A single link of the chain, written as one line of json
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+	// Position in the chain, starting at 1
+	pub seq: u64,
+	// Unix timestamp in milliseconds
+	pub timestamp: i64,
+	pub event: AuditEvent,
+	pub prev_hash: String,
+	// Hex encoded sha256 of the other fields
+	pub hash: String,
+}
+
+// The fields covered by the hash of an entry
+#[derive(Serialize)]
+struct Hashed<'a> {
+	seq: u64,
+	timestamp: i64,
+	event: &'a AuditEvent,
+	prev_hash: &'a str,
+}
+
+impl AuditEntry {
+	fn new(seq: u64, timestamp: i64, event: AuditEvent, prev_hash: String) -> Result<AuditEntry, BidTrackerError> {
+		let mut entry = AuditEntry {
+			seq,
+			timestamp,
+			event,
+			prev_hash,
+			hash: String::new(),
+		};
+		entry.hash = entry.compute_hash()?;
+		Ok(entry)
+	}
+
+	fn compute_hash(&self) -> Result<String, BidTrackerError> {
+		let hashed = Hashed {
+			seq: self.seq,
+			timestamp: self.timestamp,
+			event: &self.event,
+			prev_hash: &self.prev_hash,
+		};
+		Ok(hex::encode(Sha256::digest(serde_json::to_vec(&hashed)?)))
+	}
+
+	fn head(&self) -> ChainHead {
+		ChainHead {
+			seq: self.seq,
+			hash: self.hash.clone(),
+		}
+	}
+}
+
+/// The last entry of the chain, `seq` 0 with the genesis hash while the log is empty
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ChainHead {
+	pub seq: u64,
+	pub hash: String,
+}
+
+impl Default for ChainHead {
+	fn default() -> Self {
+		ChainHead {
+			seq: 0,
+			hash: GENESIS_HASH.into(),
+		}
+	}
+}
+
+/// The first entry which does not link up with the ones before it
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct BrokenLink {
+	pub seq: u64,
+	pub reason: String,
+}
+
+/// The outcome of walking the chain
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Verification {
+	pub valid: bool,
+	// Number of entries which link up
+	pub entries: u64,
+	// The last entry which links up
+	pub head: ChainHead,
+	pub broken_link: Option<BrokenLink>,
+}
+
+impl Verification {
+	fn broken(mut self, seq: u64, reason: String) -> Verification {
+		self.valid = false;
+		self.broken_link = Some(BrokenLink { seq, reason });
+		self
+	}
+}
+
+/// Walk the json lines of a chain and report the first broken link
+pub fn verify_lines<I>(lines: I) -> Result<Verification, BidTrackerError>
+where
+	I: IntoIterator<Item = std::io::Result<String>>,
+{
+	let mut verification = Verification {
+		valid: true,
+		entries: 0,
+		head: ChainHead::default(),
+		broken_link: None,
+	};
+	for line in lines {
+		let seq = verification.head.seq + 1;
+		let entry: AuditEntry = match serde_json::from_str(&line?) {
+			Ok(entry) => entry,
+			Err(e) => return Ok(verification.broken(seq, format!("Unreadable entry: {}", e))),
+		};
+		let reason = if entry.seq != seq {
+			Some(format!("Expected entry {} but found entry {}", seq, entry.seq))
+		} else if entry.prev_hash != verification.head.hash {
+			Some("The previous hash does not match the entry before".to_string())
+		} else if entry.compute_hash()? != entry.hash {
+			Some("The hash does not match the content of the entry".to_string())
+		} else {
+			None
+		};
+		if let Some(reason) = reason {
+			return Ok(verification.broken(seq, reason));
+		}
+		verification.entries += 1;
+		verification.head = entry.head();
+	}
+	Ok(verification)
+}
+
+/// Verify the audit log file at `path`
+pub fn verify_file(path: &Path) -> Result<Verification, BidTrackerError> {
+	verify_lines(BufReader::new(File::open(path)?).lines())
+}
+
+#[derive(Debug, Default)]
+struct Chain {
+	head: ChainHead,
+	file: Option<File>,
+	// Only kept when there is no file to read the entries back from
+	entries: Vec<AuditEntry>,
+}
+
+/// AuditLog appends entries to the json lines file at `path`, or keeps them in memory if no path is given.
+/// Clones share the same chain.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+	path: Option<PathBuf>,
+	chain: Arc<Mutex<Chain>>,
+}
+
+impl AuditLog {
+	/// Open the log at `path` and continue its chain, or an in-memory one if no path is given.
+	/// A broken chain is reported but does not stop the log from growing.
+	pub fn open(path: Option<PathBuf>) -> Result<AuditLog, BidTrackerError> {
+		let mut chain = Chain::default();
+		if let Some(path) = &path {
+			if path.exists() {
+				let verification = verify_file(path)?;
+				if let Some(broken) = &verification.broken_link {
+					tracing::error!(
+						"The audit log {} is broken at entry {}: {}",
+						path.display(),
+						broken.seq,
+						broken.reason
+					);
+				}
+				chain.head = last_entry(path)?.map(|entry| entry.head()).unwrap_or_default();
+			}
+			chain.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+		}
+		Ok(AuditLog {
+			path,
+			chain: Arc::new(Mutex::new(chain)),
+		})
+	}
+
+	/// Append an event to the chain. Callers append before they apply the change, so nothing happens
+	/// which the log does not know about.
+	pub fn append(&self, event: AuditEvent, now: i64) -> Result<AuditEntry, BidTrackerError> {
+		let mut chain = self.chain.lock().unwrap();
+		let entry = AuditEntry::new(chain.head.seq + 1, now, event, chain.head.hash.clone())?;
+		match chain.file.as_mut() {
+			Some(file) => {
+				let mut line = serde_json::to_vec(&entry)?;
+				line.push(b'\n');
+				file.write_all(&line)?;
+			}
+			None => chain.entries.push(entry.clone()),
+		}
+		chain.head = entry.head();
+		Ok(entry)
+	}
+
+	pub fn head(&self) -> ChainHead {
+		self.chain.lock().unwrap().head.clone()
+	}
+
+	/// Verify the chain up to the current head. A log which ends before the head was cut short.
+	pub fn verify(&self) -> Result<Verification, BidTrackerError> {
+		let (head, entries) = {
+			let chain = self.chain.lock().unwrap();
+			(chain.head.clone(), chain.entries.clone())
+		};
+		let verification = match &self.path {
+			// Entries appended while verifying are left for the next verification
+			Some(path) => verify_lines(BufReader::new(File::open(path)?).lines().take(head.seq as usize))?,
+			None => verify_lines(
+				entries
+					.iter()
+					.map(|entry| serde_json::to_string(entry).map_err(std::io::Error::from)),
+			)?,
+		};
+		if verification.valid && verification.head != head {
+			let seq = verification.head.seq + 1;
+			let reason = format!("The log ends before the chain head, entry {}", head.seq);
+			return Ok(verification.broken(seq, reason));
+		}
+		Ok(verification)
+	}
+}
+
+// The entry the chain continues from, unreadable lines at the end are skipped
+fn last_entry(path: &Path) -> Result<Option<AuditEntry>, BidTrackerError> {
+	let content = fs::read_to_string(path)?;
+	Ok(content
+		.lines()
+		.rev()
+		.find_map(|line| serde_json::from_str::<AuditEntry>(line).ok()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn bid(amount: f64) -> Bid {
+		Bid {
+			item_uuid: uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap(),
+			user_uuid: uuid::Uuid::parse_str("215248b5-8402-4211-93c0-9f71a93e69a9").unwrap(),
+			timestamp: 1591915318,
+			amount,
+		}
+	}
+
+	#[test]
+	fn test_chain() {
+		let log = AuditLog::default();
+		assert_eq!(log.head(), ChainHead::default());
+		let first = log.append(AuditEvent::BidPlaced { bid: bid(10f64) }, 1).unwrap();
+		let second = log.append(AuditEvent::BidPlaced { bid: bid(20f64) }, 2).unwrap();
+		assert_eq!(first.prev_hash, GENESIS_HASH);
+		assert_eq!(second.prev_hash, first.hash);
+		assert_eq!(
+			log.head(),
+			ChainHead {
+				seq: 2,
+				hash: second.hash
+			}
+		);
+
+		let verification = log.verify().unwrap();
+		assert!(verification.valid);
+		assert_eq!(verification.entries, 2);
+		assert_eq!(verification.head, log.head());
+	}
+
+	#[test]
+	fn test_tampering() {
+		let path = std::env::temp_dir().join(format!("audit-{}.log", uuid::Uuid::new_v4()));
+		let log = AuditLog::open(Some(path.clone())).unwrap();
+		for amount in [10f64, 20f64, 30f64] {
+			log.append(AuditEvent::BidPlaced { bid: bid(amount) }, 1).unwrap();
+		}
+		assert!(log.verify().unwrap().valid);
+
+		// Reopening continues the chain
+		let log = AuditLog::open(Some(path.clone())).unwrap();
+		assert_eq!(log.head().seq, 3);
+		let item = AuctionItem {
+			item_uuid: uuid::Uuid::new_v4(),
+			seller: None,
+		};
+		log.append(AuditEvent::ItemCreated { item }, 2).unwrap();
+		assert!(verify_file(&path).unwrap().valid);
+
+		// Lowering an amount breaks the link of that entry
+		let original = fs::read_to_string(&path).unwrap();
+		fs::write(&path, original.replacen("\"amount\":20.0", "\"amount\":2.0", 1)).unwrap();
+		let verification = log.verify().unwrap();
+		assert!(!verification.valid);
+		assert_eq!(verification.entries, 1);
+		let broken = verification.broken_link.unwrap();
+		assert_eq!(broken.seq, 2);
+		assert!(broken.reason.contains("hash does not match"));
+
+		// So does removing an entry
+		let lines: Vec<_> = original.lines().collect();
+		fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+		let broken = verify_file(&path).unwrap().broken_link.unwrap();
+		assert_eq!(broken.seq, 2);
+		assert!(broken.reason.contains("found entry 3"));
+
+		// A log cut short still links up, but no longer reaches the head
+		fs::write(&path, format!("{}\n{}\n", lines[0], lines[1])).unwrap();
+		assert!(verify_file(&path).unwrap().valid);
+		let verification = log.verify().unwrap();
+		assert_eq!(verification.broken_link.unwrap().seq, 3);
+
+		fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn test_unreadable_entry() {
+		let lines = vec![Ok("not json".to_string())];
+		let verification = verify_lines(lines).unwrap();
+		assert!(!verification.valid);
+		assert_eq!(verification.head, ChainHead::default());
+		assert!(verification.broken_link.unwrap().reason.starts_with("Unreadable entry"));
+	}
+}
@@ -0,0 +1,802 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Bearer token authentication. Tokens are JWTs signed with HS256 or RS256 whose subject is the useruuid.
+
+use crate::api::routes;
+use crate::api_keys::{ApiKey, ApiKeyStore, Scope, HEADER_API_KEY};
+use crate::bidtracker::Bid;
+use crate::config::Config;
+use crate::errors::BidTrackerError;
+use crate::policy::{self, Action, Role};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, Error as ActixErr, FromRequest, HttpMessage, HttpRequest};
+use futures_util::future::{ready, Ready};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::HashMap,
+	fs,
+	path::PathBuf,
+	sync::RwLock,
+	time::{Duration, Instant, SystemTime},
+};
+
+// Browsers can not set headers on websocket requests, so these take the token from the query string
+const ACCESS_TOKEN_PARAM: &str = "access_token";
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Claims {
+	// The useruuid the token was issued to
+	pub sub: uuid::Uuid,
+	// Unix timestamp in seconds after which the token is rejected
+	pub exp: u64,
+	// Names of `policy::Role`s, roles of other applications are ignored
+	#[serde(default)]
+	pub roles: Vec<String>,
+}
+
+impl Claims {
+	/// The roles of the token, every user is a bidder
+	pub fn granted_roles(&self) -> Vec<Role> {
+		let mut roles = vec![Role::Bidder];
+		roles.extend(self.roles.iter().filter_map(|role| Role::parse(role)));
+		roles
+	}
+}
+
+/// Who sent a request, handlers take it as an extractor
+#[derive(Debug, Clone, PartialEq)]
+pub enum Caller {
+	/// Authentication is disabled, everything is allowed
+	Unrestricted,
+	/// No bearer token was sent, only reads are allowed
+	Anonymous,
+	/// The verified claims of the bearer token
+	User(Claims),
+	/// The api key of a service, see `api_keys`
+	Service(ApiKey),
+}
+
+impl Caller {
+	/// Whether the caller may do `action` on something owned by `owner`, as decided by `policy::POLICY`.
+	/// Denials are logged to the audit trail.
+	pub fn authorize(&self, action: Action, owner: Option<&uuid::Uuid>) -> Result<(), BidTrackerError> {
+		let result = self.check(action, owner);
+		if let Err(e) = &result {
+			policy::audit_denial(&self.subject(), action.as_str(), e);
+		}
+		result
+	}
+
+	/// Whether the caller may do `action`, without logging a denial
+	pub fn is_allowed(&self, action: Action, owner: Option<&uuid::Uuid>) -> bool {
+		self.check(action, owner).is_ok()
+	}
+
+	fn check(&self, action: Action, owner: Option<&uuid::Uuid>) -> Result<(), BidTrackerError> {
+		match self {
+			Caller::Unrestricted => Ok(()),
+			Caller::Anonymous => Err(bearer_token_required()),
+			Caller::User(claims) if policy::is_allowed(&claims.granted_roles(), &claims.sub, action, owner) => Ok(()),
+			Caller::User(claims) => Err(BidTrackerError::Forbidden(match owner {
+				Some(owner) => format!("{} may not {} on behalf of {}", claims.sub, action.as_str(), owner),
+				None => format!("{} may not {}", claims.sub, action.as_str()),
+			})),
+			Caller::Service(api_key) => authorize_scope(api_key, action.api_key_scope()),
+		}
+	}
+
+	/// Only the user a bid is placed for may place it
+	pub fn authorize_bid(&self, bid: &Bid) -> Result<(), BidTrackerError> {
+		self.authorize(Action::PlaceBid, Some(&bid.user_uuid))
+	}
+
+	/// The data of a user is only visible to the user, auctioneers and admins
+	pub fn authorize_user(&self, user_uuid: &uuid::Uuid) -> Result<(), BidTrackerError> {
+		self.authorize(Action::ReadUser, Some(user_uuid))
+	}
+
+	pub fn authorize_admin(&self) -> Result<(), BidTrackerError> {
+		self.authorize(Action::Administer, None)
+	}
+
+	/// The useruuid of a bearer token
+	pub fn user_uuid(&self) -> Option<uuid::Uuid> {
+		match self {
+			Caller::User(claims) => Some(claims.sub),
+			_ => None,
+		}
+	}
+
+	/// Who the audit trail names
+	pub fn subject(&self) -> String {
+		match self {
+			Caller::Unrestricted => "unrestricted".into(),
+			Caller::Anonymous => "anonymous".into(),
+			Caller::User(claims) => claims.sub.to_string(),
+			Caller::Service(api_key) => format!("api_key:{}", api_key.id),
+		}
+	}
+}
+
+fn authorize_scope(api_key: &ApiKey, scope: Scope) -> Result<(), BidTrackerError> {
+	if api_key.has_scope(scope) {
+		return Ok(());
+	}
+	Err(BidTrackerError::Forbidden(format!(
+		"The api key {} lacks the {} scope",
+		api_key.id,
+		scope.as_str()
+	)))
+}
+
+fn bearer_token_required() -> BidTrackerError {
+	BidTrackerError::Unauthorized("A bearer token is required".into())
+}
+
+/// Routes outside of the `authenticate` middleware, e.g. in tests, are unrestricted
+impl FromRequest for Caller {
+	type Error = ActixErr;
+	type Future = Ready<Result<Caller, ActixErr>>;
+
+	fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+		ready(Ok(req
+			.extensions()
+			.get::<Caller>()
+			.cloned()
+			.unwrap_or(Caller::Unrestricted)))
+	}
+}
+
+#[derive(Default)]
+struct LoadedKeys {
+	keys: Vec<(Algorithm, DecodingKey)>,
+	// Modification times of the key files the keys were loaded from
+	modified: Vec<Option<SystemTime>>,
+	checked_at: Option<Instant>,
+}
+
+/// JwtKeys verifies bearer tokens. The key files are checked for changes at most every `reload_interval`
+/// and reloaded when they changed, so keys can be rotated without a restart.
+pub struct JwtKeys {
+	files: Vec<(Algorithm, PathBuf)>,
+	reload_interval: Duration,
+	loaded: RwLock<LoadedKeys>,
+}
+
+impl JwtKeys {
+	/// Load the keys, authentication is disabled if neither file is given.
+	///
+	/// The HS256 file holds one secret per line, the RS256 file one or more PEM encoded public keys.
+	/// Several keys let tokens signed with the old and the new key overlap during a rotation.
+	pub fn new(
+		hs256_secret_file: Option<PathBuf>,
+		rs256_public_key_file: Option<PathBuf>,
+		reload_interval: Duration,
+	) -> Result<JwtKeys, BidTrackerError> {
+		let files: Vec<_> = [
+			(Algorithm::HS256, hs256_secret_file),
+			(Algorithm::RS256, rs256_public_key_file),
+		]
+		.into_iter()
+		.filter_map(|(algorithm, path)| path.map(|path| (algorithm, path)))
+		.collect();
+
+		let keys = JwtKeys {
+			loaded: RwLock::new(LoadedKeys {
+				keys: load_keys(&files)?,
+				modified: modified_times(&files),
+				checked_at: Some(Instant::now()),
+			}),
+			files,
+			reload_interval,
+		};
+		Ok(keys)
+	}
+
+	pub fn from_config(config: &Config) -> Result<JwtKeys, BidTrackerError> {
+		let path = |file: &str| (!file.is_empty()).then(|| PathBuf::from(file));
+		JwtKeys::new(
+			path(&config.jwt_hs256_secret_file),
+			path(&config.jwt_rs256_public_key_file),
+			Duration::from_secs(config.jwt_key_reload_secs),
+		)
+	}
+
+	pub fn disabled() -> JwtKeys {
+		JwtKeys {
+			files: vec![],
+			reload_interval: Duration::MAX,
+			loaded: RwLock::new(LoadedKeys::default()),
+		}
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		!self.files.is_empty()
+	}
+
+	/// Verify the signature and expiry of a token and return its claims
+	pub fn verify(&self, token: &str) -> Result<Claims, BidTrackerError> {
+		self.reload_if_changed();
+
+		let invalid =
+			|e: jsonwebtoken::errors::Error| BidTrackerError::Unauthorized(format!("Invalid bearer token: {}", e));
+		let algorithm = jsonwebtoken::decode_header(token).map_err(invalid)?.alg;
+		let mut validation = Validation::new(algorithm);
+		validation.validate_aud = false;
+
+		let loaded = self.loaded.read().unwrap();
+		let mut last_error = None;
+		for (_, key) in loaded
+			.keys
+			.iter()
+			.filter(|(key_algorithm, _)| *key_algorithm == algorithm)
+		{
+			match jsonwebtoken::decode::<Claims>(token, key, &validation) {
+				Ok(data) => return Ok(data.claims),
+				Err(e) => last_error = Some(e),
+			}
+		}
+		Err(match last_error {
+			Some(e) => invalid(e),
+			None => BidTrackerError::Unauthorized(format!("Tokens signed with {:?} are not accepted", algorithm)),
+		})
+	}
+
+	fn reload_if_changed(&self) {
+		let due = |loaded: &LoadedKeys| {
+			loaded
+				.checked_at
+				.is_none_or(|checked_at| checked_at.elapsed() >= self.reload_interval)
+		};
+		if !self.is_enabled() || !due(&self.loaded.read().unwrap()) {
+			return;
+		}
+
+		let mut loaded = self.loaded.write().unwrap();
+		if !due(&loaded) {
+			return;
+		}
+		loaded.checked_at = Some(Instant::now());
+		let modified = modified_times(&self.files);
+		if modified == loaded.modified {
+			return;
+		}
+		match load_keys(&self.files) {
+			Ok(keys) => {
+				tracing::info!("Reloaded {} jwt keys", keys.len());
+				loaded.keys = keys;
+				loaded.modified = modified;
+			}
+			Err(e) => tracing::warn!("Keeping the previous jwt keys, failed to reload them: {}", e),
+		}
+	}
+}
+
+fn modified_times(files: &[(Algorithm, PathBuf)]) -> Vec<Option<SystemTime>> {
+	files
+		.iter()
+		.map(|(_, path)| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+		.collect()
+}
+
+fn load_keys(files: &[(Algorithm, PathBuf)]) -> Result<Vec<(Algorithm, DecodingKey)>, BidTrackerError> {
+	let mut keys = vec![];
+	for (algorithm, path) in files {
+		let invalid = |e: &dyn std::fmt::Display| BidTrackerError::InvalidConfig(format!("{}: {}", path.display(), e));
+		let contents = fs::read_to_string(path).map_err(|e| invalid(&e))?;
+		let loaded: Vec<_> = match algorithm {
+			Algorithm::HS256 => contents
+				.lines()
+				.map(str::trim)
+				.filter(|line| !line.is_empty())
+				.map(|secret| DecodingKey::from_secret(secret.as_bytes()))
+				.collect(),
+			_ => pem_blocks(&contents)
+				.map(|block| DecodingKey::from_rsa_pem(block.as_bytes()).map_err(|e| invalid(&e)))
+				.collect::<Result<_, _>>()?,
+		};
+		if loaded.is_empty() {
+			return Err(invalid(&"no keys found"));
+		}
+		keys.extend(loaded.into_iter().map(|key| (*algorithm, key)));
+	}
+	Ok(keys)
+}
+
+// Split a file into its `-----BEGIN ...` to `-----END ...-----` blocks
+fn pem_blocks(contents: &str) -> PemBlocks<'_> {
+	PemBlocks { rest: contents }
+}
+
+struct PemBlocks<'a> {
+	rest: &'a str,
+}
+
+impl<'a> Iterator for PemBlocks<'a> {
+	type Item = &'a str;
+
+	fn next(&mut self) -> Option<&'a str> {
+		const END: &str = "-----END";
+		let start = self.rest.find("-----BEGIN")?;
+		let end_marker = start + self.rest[start..].find(END)? + END.len();
+		let end = end_marker + self.rest[end_marker..].find("-----")? + "-----".len();
+		let block = &self.rest[start..end];
+		self.rest = &self.rest[end..];
+		Some(block)
+	}
+}
+
+/// Authenticate the bearer token or api key of a request and reject writes without either.
+///
+/// The `Caller` is stored in the request extensions. Invalid tokens and keys are always rejected,
+/// as are api keys without the scope `routes::required_scope` asks for, while reads without a token
+/// are left to the handlers. Every rejection is logged to the audit trail.
+pub async fn authenticate(
+	req: ServiceRequest,
+	next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, ActixErr> {
+	let result = caller(&req)
+		.map_err(|e| ("unauthenticated".to_string(), e))
+		.and_then(|caller| match authorize_route(&caller, &req) {
+			Ok(()) => Ok(caller),
+			Err(e) => Err((caller.subject(), e)),
+		});
+	let caller = match result {
+		Ok(caller) => caller,
+		Err((subject, e)) => {
+			policy::audit_denial(&subject, &format!("{} {}", req.method(), req.path()), &e);
+			let mut res = req.error_response(e);
+			res.headers_mut()
+				.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+			return Ok(res);
+		}
+	};
+	req.extensions_mut().insert(caller);
+	Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+/// The caller presenting `token`, shared by the REST, websocket and gRPC fronts
+pub fn caller_for_token(keys: &JwtKeys, token: Option<&str>) -> Result<Caller, BidTrackerError> {
+	match token {
+		_ if !keys.is_enabled() => Ok(Caller::Unrestricted),
+		Some(token) => Ok(Caller::User(keys.verify(token)?)),
+		None => Ok(Caller::Anonymous),
+	}
+}
+
+fn caller(req: &ServiceRequest) -> Result<Caller, BidTrackerError> {
+	if let Some(key) = req.headers().get(HEADER_API_KEY) {
+		return match req.app_data::<web::Data<ApiKeyStore>>() {
+			Some(api_keys) => Ok(Caller::Service(api_keys.verify(key.to_str().unwrap_or_default())?)),
+			None => Err(BidTrackerError::Unauthorized("Api keys are not accepted".into())),
+		};
+	}
+
+	match req.app_data::<web::Data<JwtKeys>>() {
+		Some(keys) => caller_for_token(keys, bearer_token(req).as_deref()),
+		None => Ok(Caller::Unrestricted),
+	}
+}
+
+// The checks which do not depend on the data a request touches, the handlers do the rest
+fn authorize_route(caller: &Caller, req: &ServiceRequest) -> Result<(), BidTrackerError> {
+	match caller {
+		Caller::Anonymous if is_write(req) => Err(bearer_token_required()),
+		Caller::Service(api_key) => {
+			let route = req.match_pattern().unwrap_or_default();
+			let route = route.strip_prefix(routes::API_PREFIX).unwrap_or(&route);
+			authorize_scope(api_key, routes::required_scope(req.method(), route))
+		}
+		_ => Ok(()),
+	}
+}
+
+fn bearer_token(req: &ServiceRequest) -> Option<String> {
+	let header = req
+		.headers()
+		.get(header::AUTHORIZATION)
+		.and_then(|value| value.to_str().ok());
+	if let Some(value) = header {
+		return parse_bearer(value).map(String::from);
+	}
+
+	let path = req.path();
+	if path.ends_with(routes::URL_WEBSOCKET) || path.ends_with(routes::URL_GRAPHQL_WS) {
+		let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).ok()?;
+		return query.into_inner().remove(ACCESS_TOKEN_PARAM);
+	}
+	None
+}
+
+/// The token of an `Authorization: Bearer <token>` header value
+pub fn parse_bearer(value: &str) -> Option<&str> {
+	let (scheme, token) = value.split_once(' ')?;
+	scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+// GraphQL queries are POSTed but only read
+fn is_write(req: &ServiceRequest) -> bool {
+	!matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) && !req.path().ends_with(routes::URL_GRAPHQL)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::api::problem::ProblemDetails;
+	use crate::api::AppState;
+	use crate::bidtracker::{BidManagement, BidTracker};
+	use actix_web::dev::Service;
+	use actix_web::http::StatusCode;
+	use actix_web::test::{init_service, read_body_json, TestRequest};
+	use actix_web::App;
+	use jsonwebtoken::{EncodingKey, Header};
+	use std::path::Path;
+
+	const ITEM: &str = "b2f9ee6d-79fe-4b14-9c19-35a69a89219a";
+	const USER: &str = "1c916ab6-255b-4a36-9574-e456e0f774c9";
+
+	fn claims(sub: &str, roles: &[&str]) -> Claims {
+		Claims {
+			sub: uuid::Uuid::parse_str(sub).unwrap(),
+			// Far enough in the future for any test run
+			exp: 4_102_444_800,
+			roles: roles.iter().map(|role| role.to_string()).collect(),
+		}
+	}
+
+	fn hs256(secret: &str, claims: &Claims) -> String {
+		let key = EncodingKey::from_secret(secret.as_bytes());
+		jsonwebtoken::encode(&Header::new(Algorithm::HS256), claims, &key).unwrap()
+	}
+
+	fn secret_file(contents: &str) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("jwt-{}.secret", uuid::Uuid::new_v4()));
+		fs::write(&path, contents).unwrap();
+		path
+	}
+
+	fn data_file(name: &str) -> PathBuf {
+		Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data").join(name)
+	}
+
+	#[test]
+	fn test_verify() {
+		let path = secret_file("first-secret\n\nsecond-secret\n");
+		let keys = JwtKeys::new(Some(path.clone()), Some(data_file("jwt_rs256.pub")), Duration::MAX).unwrap();
+		let user = claims(USER, &[]);
+
+		// Every secret of the file is accepted
+		assert_eq!(keys.verify(&hs256("first-secret", &user)).unwrap(), user);
+		assert_eq!(keys.verify(&hs256("second-secret", &user)).unwrap(), user);
+		assert!(matches!(
+			keys.verify(&hs256("other-secret", &user)),
+			Err(BidTrackerError::Unauthorized(_))
+		));
+
+		let private_key = fs::read(data_file("jwt_rs256.key")).unwrap();
+		let key = EncodingKey::from_rsa_pem(&private_key).unwrap();
+		let token = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &user, &key).unwrap();
+		assert_eq!(keys.verify(&token).unwrap(), user);
+
+		let expired = Claims { exp: 1, ..user.clone() };
+		assert!(keys.verify(&hs256("first-secret", &expired)).is_err());
+		assert!(keys.verify("not a token").is_err());
+
+		// Algorithms without a configured key are rejected
+		let keys = JwtKeys::new(Some(path.clone()), None, Duration::MAX).unwrap();
+		assert!(keys.verify(&token).is_err());
+
+		assert!(JwtKeys::new(Some(secret_file("")), None, Duration::MAX).is_err());
+		assert!(!JwtKeys::disabled().is_enabled());
+		fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn test_key_rotation() {
+		let path = secret_file("old-secret");
+		let keys = JwtKeys::new(Some(path.clone()), None, Duration::ZERO).unwrap();
+		let user = claims(USER, &[]);
+		assert!(keys.verify(&hs256("old-secret", &user)).is_ok());
+
+		fs::write(&path, "new-secret").unwrap();
+		// Make sure the change is visible even on file systems with a coarse modification time
+		let file = fs::File::options().write(true).open(&path).unwrap();
+		file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+		assert!(keys.verify(&hs256("old-secret", &user)).is_err());
+		assert!(keys.verify(&hs256("new-secret", &user)).is_ok());
+
+		// A broken file keeps the previous keys
+		fs::write(&path, "").unwrap();
+		file.set_modified(SystemTime::now() + Duration::from_secs(20)).unwrap();
+		assert!(keys.verify(&hs256("new-secret", &user)).is_ok());
+		fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn test_pem_blocks() {
+		let contents = "-----BEGIN PUBLIC KEY-----\nabc\n-----END PUBLIC KEY-----\n\n-----BEGIN PUBLIC KEY-----\ndef\n-----END PUBLIC KEY-----\n";
+		let blocks: Vec<_> = pem_blocks(contents).collect();
+		assert_eq!(blocks.len(), 2);
+		assert!(blocks[1].contains("def") && blocks[1].ends_with("-----END PUBLIC KEY-----"));
+	}
+
+	#[actix_rt::test]
+	async fn test_authenticate() {
+		let path = secret_file("secret");
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let jwt_keys = JwtKeys::new(Some(path.clone()), None, Duration::MAX).unwrap();
+		let state = AppState::for_tests(BidManagement::new(vec![item_uuid]), jwt_keys);
+		let srv = init_service(App::new().configure(|cfg| state.configure(cfg))).await;
+
+		let user = hs256("secret", &claims(USER, &[]));
+		let other = hs256("secret", &claims("7f272d43-0ff2-4e0f-9ebc-589eae48e3ad", &[]));
+		let admin = hs256(
+			"secret",
+			&claims("215248b5-8402-4211-93c0-9f71a93e69a9", &[Role::Admin.as_str()]),
+		);
+		let bid = Bid {
+			item_uuid,
+			user_uuid: uuid::Uuid::parse_str(USER).unwrap(),
+			timestamp: 1,
+			amount: 30f64,
+		};
+		let post_bid = |token: Option<&str>| {
+			let req = TestRequest::post().uri("/api/v1/bids").set_json(&bid);
+			match token {
+				Some(token) => req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token))),
+				None => req,
+			}
+			.to_request()
+		};
+
+		let response = srv.call(post_bid(None)).await.unwrap();
+		assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+		assert_eq!(response.headers().get(header::WWW_AUTHENTICATE).unwrap(), "Bearer");
+		let problem: ProblemDetails = read_body_json(response).await;
+		assert_eq!(problem.code, "unauthorized");
+
+		let response = srv.call(post_bid(Some("garbage"))).await.unwrap();
+		assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+		// The subject of the token must be the bidder, even for admins
+		let response = srv.call(post_bid(Some(&other))).await.unwrap();
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+		let problem: ProblemDetails = read_body_json(response).await;
+		assert_eq!(problem.code, "forbidden");
+		let response = srv.call(post_bid(Some(&admin))).await.unwrap();
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+		let response = srv.call(post_bid(Some(&user))).await.unwrap();
+		assert_eq!(response.status(), StatusCode::OK);
+
+		// Reads of an item stay public
+		let req = TestRequest::get().uri(&format!("/api/v1/bids/{}", ITEM)).to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::OK);
+
+		// The bids of a user are only visible to the user and to admins
+		let user_bids = |token: Option<&str>| {
+			let req = TestRequest::get().uri(&format!("/api/v1/users/{}/bids", USER));
+			match token {
+				Some(token) => req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token))),
+				None => req,
+			}
+			.to_request()
+		};
+		assert_eq!(
+			srv.call(user_bids(None)).await.unwrap().status(),
+			StatusCode::UNAUTHORIZED
+		);
+		assert_eq!(
+			srv.call(user_bids(Some(&other))).await.unwrap().status(),
+			StatusCode::FORBIDDEN
+		);
+		assert_eq!(srv.call(user_bids(Some(&user))).await.unwrap().status(), StatusCode::OK);
+		assert_eq!(
+			srv.call(user_bids(Some(&admin))).await.unwrap().status(),
+			StatusCode::OK
+		);
+
+		let get = |uri: &str, token: Option<&str>| {
+			let req = TestRequest::get().uri(uri);
+			match token {
+				Some(token) => req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token))),
+				None => req,
+			}
+			.to_request()
+		};
+
+		// So is filtering the bids on an item by user
+		let filtered = format!("/api/v1/bids/{}?user={}", ITEM, USER);
+		assert_eq!(
+			srv.call(get(&filtered, None)).await.unwrap().status(),
+			StatusCode::UNAUTHORIZED
+		);
+		assert_eq!(
+			srv.call(get(&filtered, Some(&other))).await.unwrap().status(),
+			StatusCode::FORBIDDEN
+		);
+		assert_eq!(
+			srv.call(get(&filtered, Some(&user))).await.unwrap().status(),
+			StatusCode::OK
+		);
+
+		// Imports, exports and webhooks are for admins only, the dead letters carry the payloads of the bids
+		let req = TestRequest::post()
+			.uri("/api/v1/admin/import?dry_run=true")
+			.insert_header((header::AUTHORIZATION, format!("Bearer {}", user)))
+			.set_payload("")
+			.to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::FORBIDDEN);
+		let item_export = format!("/api/v1/bids/{}/export", ITEM);
+		for uri in [
+			&item_export,
+			"/api/v1/export",
+			"/api/v1/webhooks",
+			"/api/v1/webhooks/dead-letters",
+		] {
+			assert_eq!(
+				srv.call(get(uri, None)).await.unwrap().status(),
+				StatusCode::UNAUTHORIZED
+			);
+			assert_eq!(
+				srv.call(get(uri, Some(&user))).await.unwrap().status(),
+				StatusCode::FORBIDDEN
+			);
+			assert_eq!(srv.call(get(uri, Some(&admin))).await.unwrap().status(), StatusCode::OK);
+		}
+		let req = TestRequest::post()
+			.uri("/api/v1/webhooks")
+			.insert_header((header::AUTHORIZATION, format!("Bearer {}", user)))
+			.set_json(serde_json::json!({"url": "http://localhost/hook"}))
+			.to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::FORBIDDEN);
+		assert!(state.webhook_store.subscriptions().is_empty());
+
+		// GraphQL queries do not need a token, but the data of a user is protected the same way
+		let req = TestRequest::post()
+			.uri("/api/v1/graphql")
+			.set_json(
+				serde_json::json!({ "query": format!("{{ user(useruuid: \"{}\") {{ items {{ bidCount }} }} }}", USER) }),
+			)
+			.to_request();
+		let response: serde_json::Value = read_body_json(srv.call(req).await.unwrap()).await;
+		assert_eq!(response["errors"][0]["extensions"]["code"], "unauthorized");
+		fs::remove_file(path).unwrap();
+	}
+
+	#[actix_rt::test]
+	async fn test_roles() {
+		let path = secret_file("secret");
+		let jwt_keys = JwtKeys::new(Some(path.clone()), None, Duration::MAX).unwrap();
+		let state = AppState::for_tests(BidManagement::new(vec![]), jwt_keys);
+		let srv = init_service(App::new().configure(|cfg| state.configure(cfg))).await;
+
+		let seller = hs256("secret", &claims(USER, &[Role::Seller.as_str()]));
+		let other_seller = hs256(
+			"secret",
+			&claims("7f272d43-0ff2-4e0f-9ebc-589eae48e3ad", &[Role::Seller.as_str()]),
+		);
+		let bidder = hs256("secret", &claims("215248b5-8402-4211-93c0-9f71a93e69a9", &[]));
+		let auctioneer = hs256(
+			"secret",
+			&claims("e5129c2c-718e-4ce6-b327-e74855967ab8", &[Role::Auctioneer.as_str()]),
+		);
+		let post = |uri: &str, token: &str, body: serde_json::Value| {
+			TestRequest::post()
+				.uri(uri)
+				.insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+				.set_json(body)
+				.to_request()
+		};
+
+		let item = serde_json::json!({ "itemuuid": ITEM });
+		let response = srv.call(post("/api/v1/items", &bidder, item.clone())).await.unwrap();
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+		let problem: ProblemDetails = read_body_json(response).await;
+		assert_eq!(problem.code, "forbidden");
+		let response = srv.call(post("/api/v1/items", &seller, item)).await.unwrap();
+		assert_eq!(response.status(), StatusCode::CREATED);
+
+		// Sellers may not bid on their own items
+		let bid = serde_json::json!({ "itemuuid": ITEM, "useruuid": USER, "timestamp": 1, "amount": 30 });
+		let response = srv.call(post("/api/v1/bids", &seller, bid)).await.unwrap();
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+		let problem: ProblemDetails = read_body_json(response).await;
+		assert_eq!(problem.code, "forbidden");
+
+		// Only the seller, auctioneers and admins close an item
+		let close = format!("/api/v1/items/{}/close", ITEM);
+		let response = srv
+			.call(post(&close, &other_seller, serde_json::json!({})))
+			.await
+			.unwrap();
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+		let response = srv.call(post(&close, &bidder, serde_json::json!({}))).await.unwrap();
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+		let response = srv
+			.call(post(&close, &auctioneer, serde_json::json!({})))
+			.await
+			.unwrap();
+		assert_eq!(response.status(), StatusCode::OK);
+
+		// Auctioneers read the data of every user
+		let req = TestRequest::get()
+			.uri(&format!("/api/v1/users/{}/bids", USER))
+			.insert_header((header::AUTHORIZATION, format!("Bearer {}", auctioneer)))
+			.to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::NOT_FOUND);
+		fs::remove_file(path).unwrap();
+	}
+
+	#[actix_rt::test]
+	async fn test_api_key_scopes() {
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let state = AppState::for_tests(BidManagement::new(vec![item_uuid]), JwtKeys::disabled());
+		let api_keys = state.api_keys.clone();
+		let srv = init_service(App::new().configure(|cfg| state.configure(cfg))).await;
+		let (_, read) = api_keys.create("reports".into(), vec![Scope::Read], 0).unwrap();
+		let (_, bid) = api_keys.create("payments".into(), vec![Scope::BidOnBehalf], 0).unwrap();
+		let (_, admin) = api_keys.create("backoffice".into(), vec![Scope::Admin], 0).unwrap();
+
+		let get = |uri: &str, key: &str| {
+			TestRequest::get()
+				.uri(uri)
+				.insert_header((HEADER_API_KEY, key))
+				.to_request()
+		};
+		let user_bids = format!("/api/v1/users/{}/bids", USER);
+		assert_eq!(
+			srv.call(get(&user_bids, &read)).await.unwrap().status(),
+			StatusCode::NOT_FOUND
+		);
+
+		// The webhooks do not check the caller themselves, the scope of the route does
+		let response = srv.call(get("/api/v1/webhooks", &read)).await.unwrap();
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+		let problem: ProblemDetails = read_body_json(response).await;
+		assert_eq!(problem.code, "forbidden");
+		assert_eq!(
+			srv.call(get("/api/v1/webhooks", &bid)).await.unwrap().status(),
+			StatusCode::FORBIDDEN
+		);
+		assert_eq!(
+			srv.call(get("/api/v1/webhooks", &admin)).await.unwrap().status(),
+			StatusCode::OK
+		);
+
+		// Keys act on behalf of any user
+		let req = TestRequest::post()
+			.uri("/api/v1/bids")
+			.insert_header((HEADER_API_KEY, bid.as_str()))
+			.set_json(serde_json::json!({"itemuuid": ITEM, "useruuid": USER, "timestamp": 1, "amount": 30}))
+			.to_request();
+		assert_eq!(srv.call(req).await.unwrap().status(), StatusCode::OK);
+
+		let response = srv.call(get("/api/v1/webhooks", "btk_garbage")).await.unwrap();
+		assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+	}
+}
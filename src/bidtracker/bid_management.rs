@@ -20,115 +20,885 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use super::BidTracker;
+use super::{query::BidLog, BidEvent, BidPage, BidQuery, BidTracker, EventBus};
+use crate::audit::{AuditEvent, AuditLog};
 use crate::errors::BidTrackerError;
+use crate::fraud::{self, FraudEngine, FraudMode};
+use crate::metrics::{Metrics, TrackerStats};
+use crate::persistence::now_millis;
+use crate::policy::{self, Action};
 use anyhow::{self, Result};
+use async_graphql::SimpleObject;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+use utoipa::ToSchema;
 
-#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, ToSchema, SimpleObject)]
 pub struct Bid {
 	#[serde(rename = "itemuuid")]
+	#[graphql(name = "itemuuid")]
 	pub item_uuid: uuid::Uuid,
 	#[serde(rename = "useruuid")]
+	#[graphql(name = "useruuid")]
 	pub user_uuid: uuid::Uuid,
 	pub timestamp: i64,
 	pub amount: f64,
 }
 
 // ItemBidState represents the current state of an item
-#[derive(Deserialize, Serialize, Default, Debug)]
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
 pub struct ItemBidState {
 	pub item_uuid: uuid::Uuid,
-	pub bids: Vec<Bid>,
+	pub bids: BidLog,
 	pub current_winning_bid: Option<Bid>,
+	#[serde(default)]
+	pub closed: bool,
+	// The user selling the item, who may not bid on it
+	#[serde(default)]
+	pub seller: Option<uuid::Uuid>,
+	// The only users who may bid on a private item, everybody may bid if `None`
+	#[serde(default)]
+	pub allowlist: Option<HashSet<uuid::Uuid>>,
+}
+
+/// An item put up for auction
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct AuctionItem {
+	#[serde(rename = "itemuuid")]
+	pub item_uuid: uuid::Uuid,
+	// Items without a seller are sold by the house
+	pub seller: Option<uuid::Uuid>,
+}
+
+// UserItemStats summarises the bids of a user on a single item
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UserItemStats {
+	pub highest_bid: Bid,
+	pub bid_count: usize,
+}
+
+/// An item a user has bid on, together with how the user stands on it
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema, SimpleObject)]
+pub struct UserItem {
+	#[serde(rename = "itemuuid")]
+	#[graphql(name = "itemuuid")]
+	pub item_uuid: uuid::Uuid,
+	pub highest_bid: Bid,
+	pub bid_count: usize,
+	pub winning: bool,
+	pub current_amount: Option<f64>,
+	pub closed: bool,
+}
+
+/// What a user stands to pay: the sum of the amounts on the open items the user is currently winning
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Exposure {
+	#[serde(rename = "useruuid")]
+	pub user_uuid: uuid::Uuid,
+	pub exposure: f64,
+	// `None` if the user may bid without limit
+	pub credit_limit: Option<f64>,
+	// How much more the user may commit to, `None` without a credit limit
+	pub available: Option<f64>,
+	// The open items the user is currently winning, sorted by item_uuid
+	pub winning_items: Vec<uuid::Uuid>,
+}
+
+/// A user banned from bidding
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct BannedUser {
+	#[serde(rename = "useruuid")]
+	pub user_uuid: uuid::Uuid,
+	pub reason: Option<String>,
+	pub banned_at: i64,
+}
+
+/// Why a bidder was turned away
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockReason {
+	/// The bidder is on the blocklist
+	Banned,
+	/// The item is private and the bidder is not on its allowlist
+	NotInvited,
+}
+
+impl BlockReason {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			BlockReason::Banned => "banned",
+			BlockReason::NotInvited => "not_invited",
+		}
+	}
+}
+
+/// How many bids were turned away for a reason
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+pub struct BlockedBidCounter {
+	pub reason: BlockReason,
+	pub count: u64,
+}
+
+/// Bids of an import which were checked but not placed yet, the bids following them are checked as if they were
+#[derive(Debug, Default)]
+pub struct PendingBids {
+	winners: HashMap<uuid::Uuid, Bid>,
+	user_items: HashMap<uuid::Uuid, HashSet<uuid::Uuid>>,
+}
+
+impl PendingBids {
+	fn winner<'a>(&'a self, item_uuid: &uuid::Uuid, state: &'a ItemBidState) -> Option<&'a Bid> {
+		self.winners.get(item_uuid).or(state.current_winning_bid.as_ref())
+	}
 }
 
 #[derive(Deserialize, Serialize, Debug, Default)]
 pub struct BidManagement {
-	user_bids: HashMap<uuid::Uuid, Vec<Bid>>,
+	user_bids: HashMap<uuid::Uuid, BidLog>,
+	// Sequence number of the next bid placed, bids are numbered across all the items
+	#[serde(default)]
+	next_seq: u64,
 	items: HashMap<uuid::Uuid, ItemBidState>,
+	// Index of the items every user has bid on
+	#[serde(default)]
+	user_items: HashMap<uuid::Uuid, HashMap<uuid::Uuid, UserItemStats>>,
+	// Credit limits of single users, in place of the default one
+	#[serde(default)]
+	credit_limits: HashMap<uuid::Uuid, f64>,
+	#[serde(default)]
+	default_credit_limit: Option<f64>,
+	#[serde(default)]
+	banned_users: HashMap<uuid::Uuid, BannedUser>,
+	#[serde(default)]
+	blocked_bids: HashMap<BlockReason, u64>,
+	#[serde(skip)]
+	fraud: FraudEngine,
+	#[serde(skip)]
+	audit: AuditLog,
+	#[serde(skip)]
+	metrics: Arc<Metrics>,
+	#[serde(skip)]
+	events: EventBus,
 }
 
-impl BidTracker for BidManagement {
-	fn new(allowed_item_uuid: Vec<uuid::Uuid>) -> Self {
-		let mut items = HashMap::with_capacity(allowed_item_uuid.len());
-		for item_uuid in allowed_item_uuid {
-			items.insert(item_uuid, ItemBidState::default());
-		}
+impl BidManagement {
+	/// Subscribe to the events emitted on every state change
+	pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<BidEvent> {
+		self.events.subscribe()
+	}
+
+	/// Check if the given item_uuid is open for bidding
+	pub fn is_biddable(&self, item_uuid: &uuid::Uuid) -> bool {
+		self.items.get(item_uuid).is_some_and(|state| !state.closed)
+	}
+
+	/// Handle on the event bus, for subscribers which outlive a single lock acquisition
+	pub fn events(&self) -> EventBus {
+		self.events.clone()
+	}
+
+	/// Copy of the state placing `bids` from `ip` depends on, to try them out on. It does not publish any
+	/// event, write to the audit log or count in the metrics. Only the bidders, the items they bid on and the
+	/// sellers of those items are copied, and without their bids, which placing a bid only appends to.
+	pub fn detached_for(&self, bids: &[Bid], ip: Option<IpAddr>) -> BidManagement {
+		let users: HashSet<uuid::Uuid> = bids.iter().map(|bid| bid.user_uuid).collect();
+		let mut item_uuids: HashSet<&uuid::Uuid> = bids.iter().map(|bid| &bid.item_uuid).collect();
+		// The exposure of a bidder depends on every item the bidder leads
+		item_uuids.extend(
+			users
+				.iter()
+				.filter_map(|user| self.user_items.get(user))
+				.flat_map(HashMap::keys),
+		);
+		let items: HashMap<uuid::Uuid, ItemBidState> = item_uuids
+			.into_iter()
+			.filter_map(|item_uuid| self.items.get_key_value(item_uuid))
+			.map(|(item_uuid, state)| {
+				let state = ItemBidState {
+					item_uuid: state.item_uuid,
+					bids: BidLog::default(),
+					current_winning_bid: state.current_winning_bid.clone(),
+					closed: state.closed,
+					seller: state.seller,
+					allowlist: state.allowlist.clone(),
+				};
+				(*item_uuid, state)
+			})
+			.collect();
+		let mut fraud_users = users.clone();
+		fraud_users.extend(items.values().filter_map(|state| state.seller));
+
 		BidManagement {
-			items,
 			user_bids: HashMap::new(),
+			next_seq: self.next_seq,
+			items,
+			user_items: copy_users(&self.user_items, &users),
+			credit_limits: copy_users(&self.credit_limits, &users),
+			default_credit_limit: self.default_credit_limit,
+			banned_users: copy_users(&self.banned_users, &users),
+			blocked_bids: self.blocked_bids.clone(),
+			fraud: self.fraud.detached_for(&fraud_users, ip),
+			audit: AuditLog::default(),
+			metrics: Arc::default(),
+			events: EventBus::default(),
 		}
 	}
 
-	/// Insert a bid in the internal hashmap
-	fn insert_bid(&mut self, bid: &Bid) -> Result<(), BidTrackerError> {
+	/// Evaluate every bid with `fraud`
+	pub fn with_fraud_engine(mut self, fraud: FraudEngine) -> BidManagement {
+		self.fraud = fraud;
+		self
+	}
+
+	/// Append every state change to `audit` before applying it
+	pub fn with_audit_log(mut self, audit: AuditLog) -> BidManagement {
+		self.audit = audit;
+		self
+	}
+
+	pub fn audit(&self) -> &AuditLog {
+		&self.audit
+	}
+
+	/// The metrics of the tracker, the fronts count their requests and subscribers in the same ones
+	pub fn metrics(&self) -> Arc<Metrics> {
+		self.metrics.clone()
+	}
+
+	/// The state of the tracker as reported by the metrics
+	pub fn stats(&self) -> TrackerStats {
+		TrackerStats {
+			open_items: self.items.values().filter(|state| !state.closed).count() as u64,
+			bids: self.items.values().map(|state| state.bids.len() as u64).sum(),
+			blocked_bids: self.blocked_bid_counts(),
+		}
+	}
+
+	pub fn fraud(&self) -> &FraudEngine {
+		&self.fraud
+	}
+
+	pub fn fraud_mut(&mut self) -> &mut FraudEngine {
+		&mut self.fraud
+	}
+
+	/// Limit the exposure of every user without a credit limit of their own, `None` lifts the limit
+	pub fn with_default_credit_limit(mut self, limit: Option<f64>) -> BidManagement {
+		self.default_credit_limit = limit;
+		self
+	}
+
+	/// The credit limit of a user, `None` if the user may bid without limit
+	pub fn credit_limit(&self, user_uuid: &uuid::Uuid) -> Option<f64> {
+		self.credit_limits.get(user_uuid).copied().or(self.default_credit_limit)
+	}
+
+	/// Set the credit limit of a user, `None` falls back to the default limit
+	pub fn set_credit_limit(&mut self, user_uuid: &uuid::Uuid, limit: Option<f64>) -> Result<(), BidTrackerError> {
+		match limit {
+			Some(limit) if !limit.is_finite() || limit < 0.0 => Err(BidTrackerError::InvalidRequest(format!(
+				"A credit limit can not be negative, got {}",
+				limit
+			))),
+			Some(limit) => {
+				self.credit_limits.insert(*user_uuid, limit);
+				Ok(())
+			}
+			None => {
+				self.credit_limits.remove(user_uuid);
+				Ok(())
+			}
+		}
+	}
+
+	/// The exposure of a user, users who have not bid yet have none
+	pub fn exposure(&self, user_uuid: &uuid::Uuid) -> Exposure {
+		self.exposure_with(user_uuid, &PendingBids::default())
+	}
+
+	// The exposure as if the `pending` bids were placed
+	fn exposure_with(&self, user_uuid: &uuid::Uuid, pending: &PendingBids) -> Exposure {
+		let mut item_uuids: HashSet<&uuid::Uuid> = self
+			.user_items
+			.get(user_uuid)
+			.into_iter()
+			.flat_map(HashMap::keys)
+			.collect();
+		item_uuids.extend(pending.user_items.get(user_uuid).into_iter().flatten());
+
+		let mut winning_items = Vec::new();
+		let mut exposure = 0.0;
+		for item_uuid in item_uuids {
+			let winning = self
+				.items
+				.get(item_uuid)
+				.filter(|state| !state.closed)
+				.and_then(|state| pending.winner(item_uuid, state))
+				.filter(|bid| &bid.user_uuid == user_uuid);
+			if let Some(bid) = winning {
+				winning_items.push(*item_uuid);
+				exposure += bid.amount;
+			}
+		}
+		winning_items.sort();
+
+		let credit_limit = self.credit_limit(user_uuid);
+		Exposure {
+			user_uuid: *user_uuid,
+			exposure,
+			credit_limit,
+			available: credit_limit.map(|limit| (limit - exposure).max(0.0)),
+			winning_items,
+		}
+	}
+
+	/// Insert a bid placed from `ip`, after the fraud rules had a look at it
+	#[tracing::instrument(name = "bidtracker.insert_bid", skip_all, fields(itemuuid = %bid.item_uuid, useruuid = %bid.user_uuid))]
+	pub fn insert_bid_from(&mut self, bid: &Bid, ip: Option<IpAddr>) -> Result<(), BidTrackerError> {
+		let result = self.place_bid(bid, ip);
+		self.metrics.count_bid(&result);
+		result
+	}
+
+	fn place_bid(&mut self, bid: &Bid, ip: Option<IpAddr>) -> Result<(), BidTrackerError> {
+		if let Err(e) = self.check_bid(bid) {
+			if let (BidTrackerError::BidderBlocked(_), Some(reason)) = (&e, self.block_reason(bid)) {
+				*self.blocked_bids.entry(reason).or_default() += 1;
+			}
+			return Err(e);
+		}
+
+		let now = now_millis();
+		let (seller, leader) = self
+			.items
+			.get(&bid.item_uuid)
+			.map(|state| (state.seller, state.current_winning_bid.clone()))
+			.unwrap_or_default();
+		let flags = self.fraud.evaluate(bid, ip, seller, leader.as_ref(), now);
+		if !flags.is_empty() && self.fraud.rules().mode == FraudMode::Enforce {
+			// The details stay with the admins, the bidder only learns which rules were broken
+			let error = BidTrackerError::FraudSuspected(format!("Flagged by {}", fraud::rule_names(&flags)));
+			self.fraud.record(bid, ip, flags, true, now);
+			return Err(error);
+		}
+		self.audit.append(AuditEvent::BidPlaced { bid: bid.clone() }, now)?;
+		self.apply_bid(bid, true)?;
+		self.fraud.observe(bid, ip, leader.as_ref(), now);
+		self.fraud.record(bid, ip, flags, false, now);
+		Ok(())
+	}
+
+	/// Check a historical bid of an import as if the `pending` bids were placed already, and add it to them
+	/// if it would be accepted. The fraud rules are skipped as they judge bids by the time they arrive.
+	pub fn check_import(&self, bid: &Bid, pending: &mut PendingBids) -> Result<(), BidTrackerError> {
+		self.check_bid_with(bid, pending)?;
+		let state = &self.items[&bid.item_uuid];
+		if pending
+			.winner(&bid.item_uuid, state)
+			.is_none_or(|winning| winning.amount < bid.amount)
+		{
+			pending.winners.insert(bid.item_uuid, bid.clone());
+		}
+		pending
+			.user_items
+			.entry(bid.user_uuid)
+			.or_default()
+			.insert(bid.item_uuid);
+		Ok(())
+	}
+
+	/// Place the historical bids of an import, checked with `check_import`, after appending them to the audit
+	/// log as a single entry. No event is published, the bids are old news for the subscribers.
+	pub fn place_imported(&mut self, bids: Vec<Bid>) -> Result<(), BidTrackerError> {
+		if bids.is_empty() {
+			return Ok(());
+		}
+		self.audit
+			.append(AuditEvent::BidsImported { bids: bids.clone() }, now_millis())?;
+		for bid in &bids {
+			let result = self.apply_bid(bid, false);
+			self.metrics.count_bid(&result);
+			result?;
+		}
+		Ok(())
+	}
+
+	// Record a checked bid, publishing the resulting events unless `publish` is false
+	fn apply_bid(&mut self, bid: &Bid, publish: bool) -> Result<(), BidTrackerError> {
+		let seq = self.next_seq;
+		let mut winner_change = None;
 		if let Some(existing) = self.items.get_mut(&bid.item_uuid) {
-			existing.bids.push(bid.clone());
+			existing.bids.push(seq, bid.clone());
 
 			match existing.current_winning_bid.as_ref() {
 				Some(cur_bid) => {
 					// We update the bid status _only_ if the bid amount is larger
 					// in case the bids are equal, the previous bid will be the winner
 					if cur_bid.amount < bid.amount {
-						existing.current_winning_bid = Some(bid.clone());
+						winner_change = Some(existing.current_winning_bid.replace(bid.clone()));
 					}
 				}
-				None => existing.current_winning_bid = Some(bid.clone()),
+				None => {
+					existing.current_winning_bid = Some(bid.clone());
+					winner_change = Some(None);
+				}
 			}
 		} else {
-			return Err(BidTrackerError::ItemNotBiddable(
-				"Requested item_uuid is not available for bidding".into(),
-			));
+			return Err(BidTrackerError::ItemNotFound(bid.item_uuid.to_string()));
 		}
 
-		if let Some(existing) = self.user_bids.get_mut(&bid.user_uuid) {
-			existing.push(bid.clone());
+		self.user_bids.entry(bid.user_uuid).or_default().push(seq, bid.clone());
+		self.next_seq += 1;
+
+		let stats = self
+			.user_items
+			.entry(bid.user_uuid)
+			.or_default()
+			.entry(bid.item_uuid)
+			.or_insert_with(|| UserItemStats {
+				highest_bid: bid.clone(),
+				bid_count: 0,
+			});
+		stats.bid_count += 1;
+		if stats.highest_bid.amount < bid.amount {
+			stats.highest_bid = bid.clone();
+		}
+
+		if !publish {
+			return Ok(());
+		}
+		self.events.publish(BidEvent::BidPlaced { bid: bid.clone() });
+		if let Some(previous) = winner_change {
+			self.events.publish(BidEvent::WinningBidChanged {
+				item_uuid: bid.item_uuid,
+				bid: bid.clone(),
+				previous,
+			});
+		}
+		Ok(())
+	}
+
+	/// Ban a user from bidding. With `retract`, the bids of the user on open items are removed and
+	/// their winners recomputed. Returns the retracted bids.
+	pub fn ban_user(
+		&mut self,
+		user_uuid: &uuid::Uuid,
+		reason: Option<String>,
+		retract: bool,
+		now: i64,
+	) -> Result<Vec<Bid>, BidTrackerError> {
+		self.banned_users.insert(
+			*user_uuid,
+			BannedUser {
+				user_uuid: *user_uuid,
+				reason,
+				banned_at: now,
+			},
+		);
+		if retract {
+			self.retract_bids(user_uuid, now)
 		} else {
-			self.user_bids.insert(bid.user_uuid, vec![bid.clone()]);
+			Ok(vec![])
+		}
+	}
+
+	/// Lift the ban of a user, returns false if the user was not banned
+	pub fn unban_user(&mut self, user_uuid: &uuid::Uuid) -> bool {
+		self.banned_users.remove(user_uuid).is_some()
+	}
+
+	/// Every banned user, sorted by user_uuid
+	pub fn banned_users(&self) -> Vec<BannedUser> {
+		let mut banned: Vec<_> = self.banned_users.values().cloned().collect();
+		banned.sort_by_key(|user| user.user_uuid);
+		banned
+	}
+
+	/// How many bids were turned away for every reason so far
+	pub fn blocked_bid_counts(&self) -> Vec<BlockedBidCounter> {
+		[BlockReason::Banned, BlockReason::NotInvited]
+			.into_iter()
+			.map(|reason| BlockedBidCounter {
+				reason,
+				count: self.blocked_bids.get(&reason).copied().unwrap_or_default(),
+			})
+			.collect()
+	}
+
+	/// The allowlist of an item sorted by user_uuid, `None` if everybody may bid on it
+	pub fn allowlist(&self, item_uuid: &uuid::Uuid) -> Result<Option<Vec<uuid::Uuid>>, BidTrackerError> {
+		let state = self
+			.items
+			.get(item_uuid)
+			.ok_or_else(|| BidTrackerError::ItemNotFound(item_uuid.to_string()))?;
+		Ok(state.allowlist.as_ref().map(|users| {
+			let mut users: Vec<_> = users.iter().copied().collect();
+			users.sort();
+			users
+		}))
+	}
+
+	/// Make an item private to the given users, or open it to everybody again with `None`.
+	/// Bids already placed by users left out of the list are kept.
+	pub fn set_allowlist(
+		&mut self,
+		item_uuid: &uuid::Uuid,
+		users: Option<Vec<uuid::Uuid>>,
+	) -> Result<(), BidTrackerError> {
+		let state = self
+			.items
+			.get_mut(item_uuid)
+			.ok_or_else(|| BidTrackerError::ItemNotFound(item_uuid.to_string()))?;
+		state.allowlist = users.map(|users| users.into_iter().collect());
+		Ok(())
+	}
+
+	fn block_reason(&self, bid: &Bid) -> Option<BlockReason> {
+		if self.banned_users.contains_key(&bid.user_uuid) {
+			return Some(BlockReason::Banned);
+		}
+		let allowlist = self.items.get(&bid.item_uuid)?.allowlist.as_ref()?;
+		(!allowlist.contains(&bid.user_uuid)).then_some(BlockReason::NotInvited)
+	}
+
+	// Closed items keep their bids, their outcome is final
+	fn retract_bids(&mut self, user_uuid: &uuid::Uuid, now: i64) -> Result<Vec<Bid>, BidTrackerError> {
+		let mut item_uuids: Vec<_> = self
+			.user_items
+			.get(user_uuid)
+			.map(|items| items.keys().copied().collect())
+			.unwrap_or_default();
+		item_uuids.sort();
+
+		let mut retracted = vec![];
+		for item_uuid in item_uuids {
+			let Some(state) = self.items.get_mut(&item_uuid).filter(|state| !state.closed) else {
+				continue;
+			};
+			let event = AuditEvent::BidsRetracted {
+				item_uuid,
+				user_uuid: *user_uuid,
+				bids: state
+					.bids
+					.iter()
+					.filter(|bid| &bid.user_uuid == user_uuid)
+					.cloned()
+					.collect(),
+			};
+			self.audit.append(event, now)?;
+			let bids = state.bids.remove_where(|bid| &bid.user_uuid == user_uuid);
+			if state
+				.current_winning_bid
+				.as_ref()
+				.is_some_and(|winning| &winning.user_uuid == user_uuid)
+			{
+				state.current_winning_bid = winner(&state.bids);
+			}
+
+			if let Some(items) = self.user_items.get_mut(user_uuid) {
+				items.remove(&item_uuid);
+			}
+			if let Some(user_bids) = self.user_bids.get_mut(user_uuid) {
+				user_bids.remove_where(|bid| bid.item_uuid == item_uuid);
+			}
+			self.events.publish(BidEvent::BidsRetracted {
+				item_uuid,
+				user_uuid: *user_uuid,
+				bids: bids.clone(),
+				winning_bid: state.current_winning_bid.clone(),
+			});
+			retracted.extend(bids);
+		}
+
+		if self.user_items.get(user_uuid).is_some_and(HashMap::is_empty) {
+			self.user_items.remove(user_uuid);
+			self.user_bids.remove(user_uuid);
+		}
+		Ok(retracted)
+	}
+
+	// Check a bid as if the `pending` bids were placed already
+	fn check_bid_with(&self, bid: &Bid, pending: &PendingBids) -> Result<(), BidTrackerError> {
+		match self.items.get(&bid.item_uuid) {
+			Some(existing) if existing.closed => Err(BidTrackerError::ItemNotBiddable(
+				"Requested item_uuid is closed for bidding".into(),
+			)),
+			Some(existing) if existing.seller == Some(bid.user_uuid) => {
+				let error = BidTrackerError::Forbidden("Sellers may not bid on their own items".into());
+				policy::audit_denial(&bid.user_uuid.to_string(), Action::PlaceBid.as_str(), &error);
+				Err(error)
+			}
+			Some(_) if self.block_reason(bid) == Some(BlockReason::Banned) => Err(BidTrackerError::BidderBlocked(
+				format!("{} is banned from bidding", bid.user_uuid),
+			)),
+			Some(_) if self.block_reason(bid) == Some(BlockReason::NotInvited) => {
+				Err(BidTrackerError::BidderBlocked(format!(
+					"{} is not invited to bid on the private item {}",
+					bid.user_uuid, bid.item_uuid
+				)))
+			}
+			Some(existing) => self.check_credit(bid, existing, pending),
+			None => Err(BidTrackerError::ItemNotFound(bid.item_uuid.to_string())),
 		}
+	}
 
+	// A bid taking the lead adds its amount to the exposure of the bidder, less the amount the bidder was
+	// already leading the item with. Bids which do not take the lead add nothing.
+	fn check_credit(&self, bid: &Bid, state: &ItemBidState, pending: &PendingBids) -> Result<(), BidTrackerError> {
+		let Some(limit) = self.credit_limit(&bid.user_uuid) else {
+			return Ok(());
+		};
+		let current = pending.winner(&bid.item_uuid, state);
+		if current.is_some_and(|winning| winning.amount >= bid.amount) {
+			return Ok(());
+		}
+		let released = current
+			.filter(|winning| winning.user_uuid == bid.user_uuid)
+			.map_or(0.0, |winning| winning.amount);
+		let exposure = self.exposure_with(&bid.user_uuid, pending).exposure - released + bid.amount;
+		if exposure > limit {
+			return Err(BidTrackerError::CreditLimitExceeded(format!(
+				"The bid would raise the exposure of {} to {}, above its credit limit of {}",
+				bid.user_uuid, exposure, limit
+			)));
+		}
 		Ok(())
 	}
 
-	/// Get the current winning bid for a given itemuuid.
+	/// The seller of an item, `None` for items sold by the house
+	pub fn seller(&self, item_uuid: &uuid::Uuid) -> Result<Option<uuid::Uuid>, BidTrackerError> {
+		self.items
+			.get(item_uuid)
+			.map(|state| state.seller)
+			.ok_or_else(|| BidTrackerError::ItemNotFound(item_uuid.to_string()))
+	}
+
+	/// Sequence number of the last bid on every item with bids, sorted by item_uuid
+	pub fn last_bid_seqs(&self) -> Vec<(uuid::Uuid, u64)> {
+		let mut seqs: Vec<_> = self
+			.items
+			.iter()
+			.filter_map(|(uuid, state)| Some((*uuid, state.bids.last_seq()?)))
+			.collect();
+		seqs.sort_by_key(|(uuid, _)| *uuid);
+		seqs
+	}
+
+	/// Sequence number of the last bid on an item, `None` without bids
+	pub fn last_bid_seq(&self, item_uuid: &uuid::Uuid) -> Result<Option<u64>, BidTrackerError> {
+		self.items
+			.get(item_uuid)
+			.map(|state| state.bids.last_seq())
+			.ok_or_else(|| BidTrackerError::ItemNotFound(item_uuid.to_string()))
+	}
+
+	/// Number of bids placed on an item
+	pub fn bid_count(&self, item_uuid: &uuid::Uuid) -> Result<usize, BidTrackerError> {
+		self.items
+			.get(item_uuid)
+			.map(|state| state.bids.len())
+			.ok_or_else(|| BidTrackerError::ItemNotFound(item_uuid.to_string()))
+	}
+
+	/// Clone at most `limit` of the bids on an item placed after the bid numbered `after`, with their sequence
+	/// numbers. Retracting bids does not renumber the others, so exports can resume after any bid.
+	pub fn bids_after(&self, item_uuid: &uuid::Uuid, after: Option<u64>, limit: usize) -> Vec<(u64, Bid)> {
+		self.items
+			.get(item_uuid)
+			.map(|state| state.bids.after(after, limit))
+			.unwrap_or_default()
+	}
+}
+
+// The winner among bids in the order they were placed: the highest amount, the earliest on ties
+fn winner(bids: &[Bid]) -> Option<Bid> {
+	bids.iter()
+		.fold(None::<&Bid>, |winner, bid| match winner {
+			Some(winner) if winner.amount >= bid.amount => Some(winner),
+			_ => Some(bid),
+		})
+		.cloned()
+}
+
+fn copy_users<T: Clone>(map: &HashMap<uuid::Uuid, T>, users: &HashSet<uuid::Uuid>) -> HashMap<uuid::Uuid, T> {
+	users
+		.iter()
+		.filter_map(|user| map.get_key_value(user))
+		.map(|(user, value)| (*user, value.clone()))
+		.collect()
+}
+
+impl BidTracker for BidManagement {
+	fn new(allowed_item_uuid: Vec<uuid::Uuid>) -> Self {
+		let mut items = HashMap::with_capacity(allowed_item_uuid.len());
+		for item_uuid in allowed_item_uuid {
+			items.insert(item_uuid, ItemBidState::default());
+		}
+		BidManagement {
+			items,
+			user_bids: HashMap::new(),
+			next_seq: 0,
+			user_items: HashMap::new(),
+			credit_limits: HashMap::new(),
+			default_credit_limit: None,
+			banned_users: HashMap::new(),
+			blocked_bids: HashMap::new(),
+			fraud: FraudEngine::default(),
+			audit: AuditLog::default(),
+			metrics: Arc::default(),
+			events: EventBus::default(),
+		}
+	}
+
+	/// Check if a bid would be accepted, without inserting it
+	#[tracing::instrument(name = "bidtracker.check_bid", skip_all, fields(itemuuid = %bid.item_uuid, useruuid = %bid.user_uuid))]
+	fn check_bid(&self, bid: &Bid) -> Result<(), BidTrackerError> {
+		self.check_bid_with(bid, &PendingBids::default())
+	}
+
+	/// Insert a bid in the internal hashmap
+	fn insert_bid(&mut self, bid: &Bid) -> Result<(), BidTrackerError> {
+		self.insert_bid_from(bid, None)
+	}
+
+	/// Get the current winning bid for a given itemuuid, `NoBidsYet` if nobody has bid on it
+	#[tracing::instrument(name = "bidtracker.current_winning_bid", skip_all, fields(itemuuid = %item_uuid))]
 	fn current_winning_bid(&self, item_uuid: &uuid::Uuid) -> Result<Bid, BidTrackerError> {
 		if let Some(bid_state) = self.items.get(item_uuid) {
-			bid_state.current_winning_bid.clone().ok_or_else(|| {
-				BidTrackerError::ItemNotBiddable("Requested item_uuid is not available for bidding".into())
-			})
+			bid_state
+				.current_winning_bid
+				.clone()
+				.ok_or_else(|| BidTrackerError::NoBidsYet(item_uuid.to_string()))
 		} else {
-			Err(BidTrackerError::ItemNotBiddable(
-				"Requested item_uuid is not available for bidding".into(),
-			))
+			Err(BidTrackerError::ItemNotFound(item_uuid.to_string()))
 		}
 	}
 
 	/// Get all the bids associated with this item_uuid
+	#[tracing::instrument(name = "bidtracker.get_bids", skip_all, fields(itemuuid = %item_uuid))]
 	fn get_bids(&self, item_uuid: &uuid::Uuid) -> Result<Vec<Bid>, BidTrackerError> {
 		if let Some(bid_state) = self.items.get(item_uuid) {
-			Ok(bid_state.bids.clone())
+			Ok(bid_state.bids.to_vec())
 		} else {
-			Err(BidTrackerError::ItemNotBiddable(
-				"Requested item_uuid is not available for bidding".into(),
-			))
+			Err(BidTrackerError::ItemNotFound(item_uuid.to_string()))
 		}
 	}
 
 	/// Get all the bids associated with a user_uuid
+	#[tracing::instrument(name = "bidtracker.get_bids_by_user", skip_all, fields(useruuid = %user_uuid))]
 	fn get_bids_by_user(&self, user_uuid: &uuid::Uuid) -> Result<Vec<Bid>, BidTrackerError> {
 		if let Some(user_bids) = self.user_bids.get(user_uuid) {
-			Ok(user_bids.clone())
+			Ok(user_bids.to_vec())
 		} else {
-			Err(BidTrackerError::ItemNotBiddable(
-				"Requested user_uuid is not available for bidding".into(),
-			))
+			Err(BidTrackerError::UserNotFound(user_uuid.to_string()))
+		}
+	}
+
+	/// Get every item a user has bid on, with the user's standing on each of them
+	#[tracing::instrument(name = "bidtracker.get_items_by_user", skip_all, fields(useruuid = %user_uuid))]
+	fn get_items_by_user(&self, user_uuid: &uuid::Uuid) -> Result<Vec<UserItem>, BidTrackerError> {
+		let user_items = self
+			.user_items
+			.get(user_uuid)
+			.ok_or_else(|| BidTrackerError::UserNotFound(user_uuid.to_string()))?;
+
+		let mut items: Vec<UserItem> = user_items
+			.iter()
+			.map(|(item_uuid, stats)| {
+				let item = self.items.get(item_uuid);
+				let current = item.and_then(|state| state.current_winning_bid.as_ref());
+				UserItem {
+					item_uuid: *item_uuid,
+					highest_bid: stats.highest_bid.clone(),
+					bid_count: stats.bid_count,
+					winning: current.is_some_and(|bid| &bid.user_uuid == user_uuid),
+					current_amount: current.map(|bid| bid.amount),
+					closed: item.is_some_and(|state| state.closed),
+				}
+			})
+			.collect();
+		items.sort_by_key(|item| item.item_uuid);
+		Ok(items)
+	}
+
+	/// Put a new item up for auction
+	#[tracing::instrument(name = "bidtracker.create_item", skip_all, fields(itemuuid = %item.item_uuid))]
+	fn create_item(&mut self, item: AuctionItem) -> Result<(), BidTrackerError> {
+		if self.items.contains_key(&item.item_uuid) {
+			return Err(BidTrackerError::ItemAlreadyExists(item.item_uuid.to_string()));
+		}
+		self.audit
+			.append(AuditEvent::ItemCreated { item: item.clone() }, now_millis())?;
+		self.items.insert(
+			item.item_uuid,
+			ItemBidState {
+				item_uuid: item.item_uuid,
+				seller: item.seller,
+				..ItemBidState::default()
+			},
+		);
+		Ok(())
+	}
+
+	/// Close the bidding on an item and return its final winning bid
+	#[tracing::instrument(name = "bidtracker.close_item", skip_all, fields(itemuuid = %item_uuid))]
+	fn close_item(&mut self, item_uuid: &uuid::Uuid) -> Result<Option<Bid>, BidTrackerError> {
+		let bid_state = self
+			.items
+			.get_mut(item_uuid)
+			.ok_or_else(|| BidTrackerError::ItemNotFound(item_uuid.to_string()))?;
+		if bid_state.closed {
+			return Err(BidTrackerError::ItemNotBiddable(
+				"Requested item_uuid is already closed".into(),
+			));
+		}
+		let winning_bid = bid_state.current_winning_bid.clone();
+		let event = AuditEvent::ItemClosed {
+			item_uuid: *item_uuid,
+			winning_bid: winning_bid.clone(),
+		};
+		self.audit.append(event, now_millis())?;
+		bid_state.closed = true;
+
+		self.events.publish(BidEvent::AuctionClosed {
+			item_uuid: *item_uuid,
+			winning_bid: winning_bid.clone(),
+		});
+		Ok(winning_bid)
+	}
+
+	/// Get all the items known to the tracker, sorted by item_uuid
+	fn get_items(&self) -> Vec<uuid::Uuid> {
+		let mut items: Vec<_> = self.items.keys().copied().collect();
+		items.sort();
+		items
+	}
+
+	/// Get a page of the bids on an item without cloning all of them
+	#[tracing::instrument(name = "bidtracker.query_bids", skip_all, fields(itemuuid = %item_uuid))]
+	fn query_bids(&self, item_uuid: &uuid::Uuid, query: &BidQuery) -> Result<BidPage, BidTrackerError> {
+		if let Some(bid_state) = self.items.get(item_uuid) {
+			query.paginate_log(&bid_state.bids)
+		} else {
+			Err(BidTrackerError::ItemNotFound(item_uuid.to_string()))
+		}
+	}
+
+	/// Get a page of the bids of a user without cloning all of them
+	#[tracing::instrument(name = "bidtracker.query_bids_by_user", skip_all, fields(useruuid = %user_uuid))]
+	fn query_bids_by_user(&self, user_uuid: &uuid::Uuid, query: &BidQuery) -> Result<BidPage, BidTrackerError> {
+		if let Some(user_bids) = self.user_bids.get(user_uuid) {
+			query.paginate_log(user_bids)
+		} else {
+			Err(BidTrackerError::UserNotFound(user_uuid.to_string()))
 		}
 	}
 }
 
 #[cfg(test)]
+#[allow(clippy::clone_on_copy, clippy::len_zero)]
 mod tests {
 	use super::*;
 
@@ -158,6 +928,46 @@ mod tests {
 		assert!(bm.insert_bid(&bid2).is_err());
 	}
 
+	#[tokio::test]
+	async fn test_insert_bid_publishes_events() {
+		let item_uuid = uuid::Uuid::parse_str("1cb396fd-3242-40ce-aaa1-8e8337c70435").unwrap();
+		let mut bm = BidManagement::new(vec![item_uuid]);
+		let mut events = bm.subscribe();
+
+		let bid1 = Bid {
+			user_uuid: uuid::Uuid::parse_str("e5129c2c-718e-4ce6-b327-e74855967ab8").unwrap(),
+			item_uuid,
+			timestamp: 1591915318,
+			amount: 30f64,
+		};
+		let bid2 = Bid {
+			user_uuid: uuid::Uuid::parse_str("215248b5-8402-4211-93c0-9f71a93e69a9").unwrap(),
+			amount: 31f64,
+			..bid1
+		};
+		bm.insert_bid(&bid1).unwrap();
+		bm.insert_bid(&bid2).unwrap();
+
+		assert!(matches!(events.recv().await.unwrap(), BidEvent::BidPlaced { .. }));
+		assert!(matches!(
+			events.recv().await.unwrap(),
+			BidEvent::WinningBidChanged { previous: None, .. }
+		));
+		assert!(matches!(events.recv().await.unwrap(), BidEvent::BidPlaced { .. }));
+		match events.recv().await.unwrap() {
+			BidEvent::WinningBidChanged { bid, previous, .. } => {
+				assert_eq!(bid.user_uuid, bid2.user_uuid);
+				assert_eq!(previous.unwrap().user_uuid, bid1.user_uuid);
+			}
+			other => panic!("Unexpected event {:?}", other),
+		}
+
+		// A lower bid neither changes the winner nor emits a winner event
+		bm.insert_bid(&Bid { amount: 1f64, ..bid1 }).unwrap();
+		assert!(matches!(events.recv().await.unwrap(), BidEvent::BidPlaced { .. }));
+		assert!(events.try_recv().is_err());
+	}
+
 	#[test]
 	fn test_current_winning_bid() {
 		let item_uuid = uuid::Uuid::parse_str("1cb396fd-3242-40ce-aaa1-8e8337c70435").unwrap();
@@ -172,10 +982,16 @@ mod tests {
 
 		// When wrong item_uuid requested
 		let non_existent_uuid = uuid::Uuid::parse_str("a17b364f-0a5d-4e6e-91dd-0fa38c11b4ea").unwrap();
-		assert!(bm.current_winning_bid(&non_existent_uuid).is_err());
+		assert!(matches!(
+			bm.current_winning_bid(&non_existent_uuid),
+			Err(BidTrackerError::ItemNotFound(_))
+		));
 
 		// When correct item without any bids requested
-		assert!(bm.current_winning_bid(&item_uuid).is_err());
+		assert!(matches!(
+			bm.current_winning_bid(&item_uuid),
+			Err(BidTrackerError::NoBidsYet(_))
+		));
 
 		// When correct item with bid requested
 		bm.insert_bid(&bid).unwrap();
@@ -217,6 +1033,279 @@ mod tests {
 		assert!(bm.get_bids(&item_uuid).unwrap().len() == 2);
 	}
 
+	#[test]
+	fn test_get_items_by_user() {
+		let item_uuid1 = uuid::Uuid::parse_str("1cb396fd-3242-40ce-aaa1-8e8337c70435").unwrap();
+		let item_uuid2 = uuid::Uuid::parse_str("e5129c2c-718e-4ce6-b327-e74855967ab8").unwrap();
+		let mut bm = BidManagement::new(vec![item_uuid1, item_uuid2]);
+
+		let alice = uuid::Uuid::parse_str("215248b5-8402-4211-93c0-9f71a93e69a9").unwrap();
+		let bob = uuid::Uuid::parse_str("a17b364f-0a5d-4e6e-91dd-0fa38c11b4ea").unwrap();
+		assert!(matches!(
+			bm.get_items_by_user(&alice),
+			Err(BidTrackerError::UserNotFound(_))
+		));
+
+		let bids = [
+			(alice, item_uuid1, 30f64),
+			(alice, item_uuid1, 35f64),
+			(bob, item_uuid1, 40f64),
+			(alice, item_uuid2, 10f64),
+		];
+		for (user_uuid, item_uuid, amount) in bids {
+			bm.insert_bid(&Bid {
+				user_uuid,
+				item_uuid,
+				timestamp: 1591915318,
+				amount,
+			})
+			.unwrap();
+		}
+
+		let items = bm.get_items_by_user(&alice).unwrap();
+		assert_eq!(items.len(), 2);
+		let item1 = items.iter().find(|item| item.item_uuid == item_uuid1).unwrap();
+		assert_eq!(item1.bid_count, 2);
+		assert_eq!(item1.highest_bid.amount, 35f64);
+		assert!(!item1.winning);
+		assert_eq!(item1.current_amount, Some(40f64));
+
+		let item2 = items.iter().find(|item| item.item_uuid == item_uuid2).unwrap();
+		assert_eq!(item2.bid_count, 1);
+		assert!(item2.winning);
+
+		let items = bm.get_items_by_user(&bob).unwrap();
+		assert_eq!(items.len(), 1);
+		assert!(items[0].winning);
+	}
+
+	#[test]
+	fn test_close_item() {
+		let item_uuid = uuid::Uuid::parse_str("1cb396fd-3242-40ce-aaa1-8e8337c70435").unwrap();
+		let mut bm = BidManagement::new(vec![item_uuid]);
+		let mut events = bm.subscribe();
+
+		let bid = Bid {
+			user_uuid: uuid::Uuid::parse_str("e5129c2c-718e-4ce6-b327-e74855967ab8").unwrap(),
+			item_uuid,
+			timestamp: 1591915318,
+			amount: 30f64,
+		};
+		bm.insert_bid(&bid).unwrap();
+
+		let winner = bm.close_item(&item_uuid).unwrap();
+		assert_eq!(winner.unwrap().amount, 30f64);
+		assert!(!bm.is_biddable(&item_uuid));
+
+		// No more bids and no second close
+		assert!(bm.insert_bid(&bid).is_err());
+		assert!(bm.close_item(&item_uuid).is_err());
+
+		// Bids stay readable after the close
+		assert_eq!(bm.get_bids(&item_uuid).unwrap().len(), 1);
+
+		let non_existent_uuid = uuid::Uuid::parse_str("a17b364f-0a5d-4e6e-91dd-0fa38c11b4ea").unwrap();
+		assert!(bm.close_item(&non_existent_uuid).is_err());
+
+		let closed =
+			std::iter::from_fn(|| events.try_recv().ok()).find(|event| matches!(event, BidEvent::AuctionClosed { .. }));
+		assert!(closed.is_some());
+	}
+
+	#[test]
+	fn test_create_item() {
+		let item_uuid = uuid::Uuid::parse_str("1cb396fd-3242-40ce-aaa1-8e8337c70435").unwrap();
+		let seller = uuid::Uuid::parse_str("e5129c2c-718e-4ce6-b327-e74855967ab8").unwrap();
+		let mut bm = BidManagement::new(vec![]);
+		let item = AuctionItem {
+			item_uuid,
+			seller: Some(seller),
+		};
+		bm.create_item(item.clone()).unwrap();
+		assert!(bm.is_biddable(&item_uuid));
+		assert_eq!(bm.seller(&item_uuid).unwrap(), Some(seller));
+		assert!(matches!(
+			bm.create_item(item),
+			Err(BidTrackerError::ItemAlreadyExists(_))
+		));
+
+		// Sellers may not bid on their own items
+		let bid = Bid {
+			user_uuid: seller,
+			item_uuid,
+			timestamp: 1591915318,
+			amount: 30f64,
+		};
+		assert!(matches!(bm.insert_bid(&bid), Err(BidTrackerError::Forbidden(_))));
+		let bid = Bid {
+			user_uuid: uuid::Uuid::parse_str("215248b5-8402-4211-93c0-9f71a93e69a9").unwrap(),
+			..bid
+		};
+		bm.insert_bid(&bid).unwrap();
+	}
+
+	#[test]
+	fn test_credit_limit() {
+		let item_a = uuid::Uuid::parse_str("1cb396fd-3242-40ce-aaa1-8e8337c70435").unwrap();
+		let item_b = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let user = uuid::Uuid::parse_str("215248b5-8402-4211-93c0-9f71a93e69a9").unwrap();
+		let other = uuid::Uuid::parse_str("e5129c2c-718e-4ce6-b327-e74855967ab8").unwrap();
+		let mut bm = BidManagement::new(vec![item_a, item_b]).with_default_credit_limit(Some(100f64));
+		let bid = |item_uuid, user_uuid, amount| Bid {
+			item_uuid,
+			user_uuid,
+			timestamp: 1591915318,
+			amount,
+		};
+
+		bm.insert_bid(&bid(item_a, user, 60f64)).unwrap();
+		// Raising the own lead only counts the difference
+		bm.insert_bid(&bid(item_a, user, 70f64)).unwrap();
+		assert!(matches!(
+			bm.insert_bid(&bid(item_b, user, 40f64)),
+			Err(BidTrackerError::CreditLimitExceeded(_))
+		));
+		bm.insert_bid(&bid(item_b, user, 30f64)).unwrap();
+		let exposure = bm.exposure(&user);
+		assert_eq!(exposure.exposure, 100f64);
+		assert_eq!(exposure.available, Some(0f64));
+		assert_eq!(exposure.winning_items, vec![item_a, item_b]);
+
+		// Bids which do not take the lead are accepted
+		bm.insert_bid(&bid(item_b, user, 30f64)).unwrap();
+
+		// Being outbid releases the exposure
+		bm.insert_bid(&bid(item_a, other, 80f64)).unwrap();
+		assert_eq!(bm.exposure(&user).exposure, 30f64);
+		assert_eq!(bm.exposure(&other).exposure, 80f64);
+
+		// Closed items no longer count
+		bm.close_item(&item_a).unwrap();
+		assert_eq!(bm.exposure(&other).exposure, 0f64);
+
+		// Limits of single users take the place of the default one
+		bm.set_credit_limit(&other, None).unwrap();
+		assert_eq!(bm.credit_limit(&other), Some(100f64));
+		bm.set_credit_limit(&other, Some(10f64)).unwrap();
+		assert!(bm.insert_bid(&bid(item_b, other, 40f64)).is_err());
+		assert_eq!(bm.exposure(&other).exposure, 0f64);
+		assert!(matches!(
+			bm.set_credit_limit(&other, Some(-1f64)),
+			Err(BidTrackerError::InvalidRequest(_))
+		));
+
+		let unknown = bm.exposure(&uuid::Uuid::new_v4());
+		assert_eq!(unknown.exposure, 0f64);
+		assert!(unknown.winning_items.is_empty());
+	}
+
+	#[test]
+	fn test_blocklist() {
+		let item_a = uuid::Uuid::parse_str("1cb396fd-3242-40ce-aaa1-8e8337c70435").unwrap();
+		let item_b = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let banned = uuid::Uuid::parse_str("215248b5-8402-4211-93c0-9f71a93e69a9").unwrap();
+		let other = uuid::Uuid::parse_str("e5129c2c-718e-4ce6-b327-e74855967ab8").unwrap();
+		let mut bm = BidManagement::new(vec![item_a, item_b]);
+		let mut events = bm.subscribe();
+		let bid = |item_uuid, user_uuid, amount| Bid {
+			item_uuid,
+			user_uuid,
+			timestamp: 1591915318,
+			amount,
+		};
+
+		bm.insert_bid(&bid(item_a, other, 20f64)).unwrap();
+		bm.insert_bid(&bid(item_a, banned, 30f64)).unwrap();
+		bm.insert_bid(&bid(item_a, other, 30f64)).unwrap();
+		bm.insert_bid(&bid(item_b, banned, 10f64)).unwrap();
+		bm.close_item(&item_b).unwrap();
+		while events.try_recv().is_ok() {}
+
+		// Only bids on open items are retracted and the winner is recomputed
+		let retracted = bm
+			.ban_user(&banned, Some("Shill bidding".into()), true, 1591915400)
+			.unwrap();
+		assert_eq!(retracted, vec![bid(item_a, banned, 30f64)]);
+		assert_eq!(bm.current_winning_bid(&item_a).unwrap(), bid(item_a, other, 30f64));
+		assert_eq!(bm.get_bids(&item_a).unwrap().len(), 2);
+		assert_eq!(bm.get_bids_by_user(&banned).unwrap(), vec![bid(item_b, banned, 10f64)]);
+		assert!(matches!(
+			events.try_recv().unwrap(),
+			BidEvent::BidsRetracted { item_uuid, winning_bid: Some(_), .. } if item_uuid == item_a
+		));
+
+		assert!(matches!(
+			bm.insert_bid(&bid(item_a, banned, 50f64)),
+			Err(BidTrackerError::BidderBlocked(_))
+		));
+		assert_eq!(
+			bm.banned_users(),
+			vec![BannedUser {
+				user_uuid: banned,
+				reason: Some("Shill bidding".into()),
+				banned_at: 1591915400,
+			}]
+		);
+
+		assert!(bm.unban_user(&banned));
+		assert!(!bm.unban_user(&banned));
+		bm.insert_bid(&bid(item_a, banned, 50f64)).unwrap();
+
+		// Banning without retraction keeps the bids
+		assert!(bm.ban_user(&banned, None, false, 1591915500).unwrap().is_empty());
+		assert_eq!(bm.current_winning_bid(&item_a).unwrap().user_uuid, banned);
+	}
+
+	#[test]
+	fn test_allowlist() {
+		let item_uuid = uuid::Uuid::parse_str("1cb396fd-3242-40ce-aaa1-8e8337c70435").unwrap();
+		let invited = uuid::Uuid::parse_str("215248b5-8402-4211-93c0-9f71a93e69a9").unwrap();
+		let other = uuid::Uuid::parse_str("e5129c2c-718e-4ce6-b327-e74855967ab8").unwrap();
+		let mut bm = BidManagement::new(vec![item_uuid]);
+		let bid = |user_uuid| Bid {
+			item_uuid,
+			user_uuid,
+			timestamp: 1591915318,
+			amount: 10f64,
+		};
+
+		assert_eq!(bm.allowlist(&item_uuid).unwrap(), None);
+		bm.set_allowlist(&item_uuid, Some(vec![invited])).unwrap();
+		assert_eq!(bm.allowlist(&item_uuid).unwrap(), Some(vec![invited]));
+		bm.insert_bid(&bid(invited)).unwrap();
+		assert!(matches!(
+			bm.insert_bid(&bid(other)),
+			Err(BidTrackerError::BidderBlocked(_))
+		));
+
+		bm.ban_user(&invited, None, false, 1591915400).unwrap();
+		assert!(bm.insert_bid(&bid(invited)).is_err());
+		assert_eq!(
+			bm.blocked_bid_counts(),
+			vec![
+				BlockedBidCounter {
+					reason: BlockReason::Banned,
+					count: 1,
+				},
+				BlockedBidCounter {
+					reason: BlockReason::NotInvited,
+					count: 1,
+				},
+			]
+		);
+
+		bm.set_allowlist(&item_uuid, None).unwrap();
+		bm.insert_bid(&Bid {
+			amount: 20f64,
+			..bid(other)
+		})
+		.unwrap();
+
+		let unknown = uuid::Uuid::new_v4();
+		assert!(matches!(bm.allowlist(&unknown), Err(BidTrackerError::ItemNotFound(_))));
+		assert!(bm.set_allowlist(&unknown, Some(vec![])).is_err());
+	}
+
 	#[test]
 	fn test_get_bids_by_user() {
 		let item_uuid1 = uuid::Uuid::parse_str("1cb396fd-3242-40ce-aaa1-8e8337c70435").unwrap();
@@ -242,7 +1331,10 @@ mod tests {
 
 		// When wrong  non_existent user_uuid requested
 		let non_existent_uuid = uuid::Uuid::parse_str("a17b364f-0a5d-4e6e-91dd-0fa38c11b4ea").unwrap();
-		assert!(bm.get_bids_by_user(&non_existent_uuid).is_err());
+		assert!(matches!(
+			bm.get_bids_by_user(&non_existent_uuid),
+			Err(BidTrackerError::UserNotFound(_))
+		));
 
 		// When after insertion bids requested
 		bm.insert_bid(&bid1).expect("Failed to insert first bid");
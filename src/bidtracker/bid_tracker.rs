@@ -20,14 +20,30 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use super::Bid;
+use super::{AuctionItem, Bid, BidPage, BidQuery, UserItem};
 use crate::errors::BidTrackerError;
 use anyhow::Result;
 
 pub trait BidTracker {
 	fn new(allowed_bids: Vec<uuid::Uuid>) -> Self;
+	fn check_bid(&self, bid: &Bid) -> Result<(), BidTrackerError>;
 	fn insert_bid(&mut self, bid: &Bid) -> Result<(), BidTrackerError>;
 	fn current_winning_bid(&self, item_uuid: &uuid::Uuid) -> Result<Bid, BidTrackerError>;
 	fn get_bids(&self, item_uuid: &uuid::Uuid) -> Result<Vec<Bid>, BidTrackerError>;
 	fn get_bids_by_user(&self, user_uuid: &uuid::Uuid) -> Result<Vec<Bid>, BidTrackerError>;
+	fn get_items_by_user(&self, user_uuid: &uuid::Uuid) -> Result<Vec<UserItem>, BidTrackerError>;
+	fn create_item(&mut self, item: AuctionItem) -> Result<(), BidTrackerError>;
+	fn close_item(&mut self, item_uuid: &uuid::Uuid) -> Result<Option<Bid>, BidTrackerError>;
+	fn get_items(&self) -> Vec<uuid::Uuid>;
+
+	/// Get a single page of the bids on an item, trackers which can remove bids should number them instead
+	/// of relying on their position
+	fn query_bids(&self, item_uuid: &uuid::Uuid, query: &BidQuery) -> Result<BidPage, BidTrackerError> {
+		query.paginate((0..).zip(&self.get_bids(item_uuid)?))
+	}
+
+	/// Get a single page of the bids of a user
+	fn query_bids_by_user(&self, user_uuid: &uuid::Uuid, query: &BidQuery) -> Result<BidPage, BidTrackerError> {
+		query.paginate((0..).zip(&self.get_bids_by_user(user_uuid)?))
+	}
 }
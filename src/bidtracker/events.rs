@@ -0,0 +1,119 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::Bid;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+// Number of events a slow subscriber may fall behind before it starts losing them
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Events emitted by the tracker whenever its state changes
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BidEvent {
+	/// A bid was accepted for an item
+	BidPlaced { bid: Bid },
+	/// The winning bid of an item changed, `previous` is the bid which lost the lead
+	WinningBidChanged {
+		#[serde(rename = "itemuuid")]
+		item_uuid: uuid::Uuid,
+		bid: Bid,
+		previous: Option<Bid>,
+	},
+	/// The item was closed for bidding, `winning_bid` is the final winner if any
+	AuctionClosed {
+		#[serde(rename = "itemuuid")]
+		item_uuid: uuid::Uuid,
+		winning_bid: Option<Bid>,
+	},
+	/// The bids of a banned user were retracted, `winning_bid` is the recomputed winner of the item
+	BidsRetracted {
+		#[serde(rename = "itemuuid")]
+		item_uuid: uuid::Uuid,
+		#[serde(rename = "useruuid")]
+		user_uuid: uuid::Uuid,
+		bids: Vec<Bid>,
+		winning_bid: Option<Bid>,
+	},
+}
+
+impl BidEvent {
+	/// The item this event belongs to
+	pub fn item_uuid(&self) -> &uuid::Uuid {
+		match self {
+			BidEvent::BidPlaced { bid } => &bid.item_uuid,
+			BidEvent::WinningBidChanged { item_uuid, .. } => item_uuid,
+			BidEvent::AuctionClosed { item_uuid, .. } => item_uuid,
+			BidEvent::BidsRetracted { item_uuid, .. } => item_uuid,
+		}
+	}
+}
+
+/// EventBus fans out `BidEvent`s to any number of subscribers
+#[derive(Debug, Clone)]
+pub struct EventBus {
+	sender: broadcast::Sender<BidEvent>,
+}
+
+impl Default for EventBus {
+	fn default() -> Self {
+		let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+		EventBus { sender }
+	}
+}
+
+impl EventBus {
+	/// Publish an event, it is fine if nobody is listening
+	pub fn publish(&self, event: BidEvent) {
+		let _ = self.sender.send(event);
+	}
+
+	pub fn subscribe(&self) -> broadcast::Receiver<BidEvent> {
+		self.sender.subscribe()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_event_bus() {
+		let bus = EventBus::default();
+		// Publishing without subscribers must not fail
+		bus.publish(BidEvent::BidPlaced { bid: Bid::default() });
+
+		let mut rx = bus.subscribe();
+		let bid = Bid {
+			amount: 10f64,
+			..Bid::default()
+		};
+		bus.publish(BidEvent::BidPlaced { bid: bid.clone() });
+
+		match rx.recv().await.unwrap() {
+			BidEvent::BidPlaced { bid: received } => assert_eq!(received.amount, bid.amount),
+			other => panic!("Unexpected event {:?}", other),
+		}
+	}
+}
@@ -22,7 +22,13 @@
 
 mod bid_management;
 mod bid_tracker;
+mod events;
+mod query;
 
-pub use bid_management::{Bid, BidManagement};
+pub use bid_management::{
+	AuctionItem, BannedUser, Bid, BidManagement, BlockReason, BlockedBidCounter, Exposure, PendingBids, UserItem,
+};
+pub use events::{BidEvent, EventBus};
+pub use query::{BidPage, BidQuery, BidSort, SortOrder, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
 
 pub use bid_tracker::BidTracker;
@@ -0,0 +1,486 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::Bid;
+use crate::errors::BidTrackerError;
+use async_graphql::Enum;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use utoipa::{IntoParams, ToSchema};
+
+// Page size used when the client does not ask for one
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+// Larger page sizes are silently capped to this
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum BidSort {
+	Amount,
+	Timestamp,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema, Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+	#[default]
+	Asc,
+	Desc,
+}
+
+/// Pagination, sorting and filtering of a bid listing, taken from the query string.
+/// Without `sort` the bids are listed in the order they were placed.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BidQuery {
+	pub cursor: Option<String>,
+	pub limit: Option<usize>,
+	pub sort: Option<BidSort>,
+	#[serde(default)]
+	pub order: SortOrder,
+	pub min_amount: Option<f64>,
+	// Inclusive lower bound of the bid timestamp
+	pub since: Option<i64>,
+	// Inclusive upper bound of the bid timestamp
+	pub until: Option<i64>,
+	#[serde(rename = "user")]
+	pub user_uuid: Option<uuid::Uuid>,
+}
+
+/// A single page of bids, `next_cursor` is None on the last page
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct BidPage {
+	pub bids: Vec<Bid>,
+	pub next_cursor: Option<String>,
+}
+
+/// Bids in the order they were placed, each numbered with a sequence number that does not change when
+/// other bids are removed. Cursors and export offsets refer to these numbers, so they survive retractions.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct BidLog {
+	bids: Vec<Bid>,
+	seqs: Vec<u64>,
+}
+
+impl BidLog {
+	/// Append a bid, `seq` must be larger than the sequence number of every bid in the log
+	pub fn push(&mut self, seq: u64, bid: Bid) {
+		self.bids.push(bid);
+		self.seqs.push(seq);
+	}
+
+	/// Remove the bids matching `remove` and return them, the others keep their sequence numbers
+	pub fn remove_where(&mut self, mut remove: impl FnMut(&Bid) -> bool) -> Vec<Bid> {
+		let mut removed = vec![];
+		let mut kept = BidLog::default();
+		for (seq, bid) in self.seqs.drain(..).zip(self.bids.drain(..)) {
+			if remove(&bid) {
+				removed.push(bid);
+			} else {
+				kept.push(seq, bid);
+			}
+		}
+		*self = kept;
+		removed
+	}
+
+	/// The bids with their sequence numbers, in the order they were placed
+	pub fn entries(&self) -> impl Iterator<Item = (u64, &Bid)> {
+		self.seqs.iter().copied().zip(self.bids.iter())
+	}
+
+	/// Sequence number of the bid placed last
+	pub fn last_seq(&self) -> Option<u64> {
+		self.seqs.last().copied()
+	}
+
+	/// Clone at most `limit` bids placed after the bid numbered `after`, or from the first bid without it
+	pub fn after(&self, after: Option<u64>, limit: usize) -> Vec<(u64, Bid)> {
+		let start = after.map_or(0, |after| self.seqs.partition_point(|seq| *seq <= after));
+		let end = self.bids.len().min(start.saturating_add(limit));
+		self.seqs[start..end]
+			.iter()
+			.copied()
+			.zip(self.bids[start..end].iter().cloned())
+			.collect()
+	}
+}
+
+impl std::ops::Deref for BidLog {
+	type Target = [Bid];
+
+	fn deref(&self) -> &[Bid] {
+		&self.bids
+	}
+}
+
+// Where a bid stands in a listing: the value it is sorted by, ties broken by the order bids were placed.
+// Cursors carry the key of the last bid of their page, so they stay valid when that bid is removed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SortKey {
+	value: SortValue,
+	seq: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortValue {
+	Placement,
+	Amount(f64),
+	Timestamp(i64),
+}
+
+impl SortValue {
+	fn compare(&self, other: &SortValue) -> Ordering {
+		match (self, other) {
+			(SortValue::Amount(a), SortValue::Amount(b)) => a.total_cmp(b),
+			(SortValue::Timestamp(a), SortValue::Timestamp(b)) => a.cmp(b),
+			_ => Ordering::Equal,
+		}
+	}
+}
+
+impl BidQuery {
+	fn matches(&self, bid: &Bid) -> bool {
+		self.min_amount.is_none_or(|min| bid.amount >= min)
+			&& self.since.is_none_or(|since| bid.timestamp >= since)
+			&& self.until.is_none_or(|until| bid.timestamp <= until)
+			&& self.user_uuid.is_none_or(|user| bid.user_uuid == user)
+	}
+
+	fn key(&self, seq: u64, bid: &Bid) -> SortKey {
+		let value = match self.sort {
+			Some(BidSort::Amount) => SortValue::Amount(bid.amount),
+			Some(BidSort::Timestamp) => SortValue::Timestamp(bid.timestamp),
+			None => SortValue::Placement,
+		};
+		SortKey { value, seq }
+	}
+
+	fn compare(&self, a: &SortKey, b: &SortKey) -> Ordering {
+		let ordering = a.value.compare(&b.value).then(a.seq.cmp(&b.seq));
+		match self.order {
+			SortOrder::Asc => ordering,
+			SortOrder::Desc => ordering.reverse(),
+		}
+	}
+
+	// The cursor is bound to the sorting it was created with
+	fn cursor_prefix(&self) -> String {
+		format!("{:?}:{:?}:", self.sort, self.order)
+	}
+
+	fn encode_cursor(&self, key: &SortKey) -> String {
+		let value = match key.value {
+			SortValue::Placement => String::new(),
+			SortValue::Amount(amount) => amount.to_bits().to_string(),
+			SortValue::Timestamp(timestamp) => timestamp.to_string(),
+		};
+		hex::encode(format!("{}{}:{}", self.cursor_prefix(), key.seq, value))
+	}
+
+	fn decode_cursor(&self, cursor: &str) -> Result<SortKey, BidTrackerError> {
+		let invalid = || BidTrackerError::InvalidRequest(format!("Invalid cursor {}", cursor));
+		let decoded = hex::decode(cursor).map_err(|_| invalid())?;
+		let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+		let (seq, value) = decoded
+			.strip_prefix(&self.cursor_prefix())
+			.and_then(|key| key.split_once(':'))
+			.ok_or_else(invalid)?;
+		let seq = seq.parse::<u64>().map_err(|_| invalid())?;
+		let value = match self.sort {
+			Some(BidSort::Amount) => value.parse::<u64>().map(|bits| SortValue::Amount(f64::from_bits(bits))),
+			Some(BidSort::Timestamp) => value.parse::<i64>().map(SortValue::Timestamp),
+			None if value.is_empty() => Ok(SortValue::Placement),
+			None => return Err(invalid()),
+		}
+		.map_err(|_| invalid())?;
+		Ok(SortKey { value, seq })
+	}
+
+	fn page_size(&self) -> usize {
+		self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+	}
+
+	fn anchor(&self) -> Result<Option<SortKey>, BidTrackerError> {
+		self.cursor
+			.as_deref()
+			.map(|cursor| self.decode_cursor(cursor))
+			.transpose()
+	}
+
+	/// Select the requested page out of `log`. In placement order the cursor is looked up by its sequence
+	/// number and the bids are only scanned up to the end of the page, other sortings go through `paginate`.
+	pub fn paginate_log(&self, log: &BidLog) -> Result<BidPage, BidTrackerError> {
+		if self.sort.is_some() {
+			return self.paginate(log.entries());
+		}
+		let after = self.anchor()?.map(|anchor| anchor.seq);
+		match self.order {
+			SortOrder::Asc => {
+				let start = after.map_or(0, |after| log.seqs.partition_point(|seq| *seq <= after));
+				Ok(self.scan(log.seqs[start..].iter().copied().zip(&log.bids[start..])))
+			}
+			SortOrder::Desc => {
+				let end = after.map_or(log.len(), |after| log.seqs.partition_point(|seq| *seq < after));
+				Ok(self.scan(log.seqs[..end].iter().copied().zip(&log.bids[..end]).rev()))
+			}
+		}
+	}
+
+	// The page of the matching bids `bids` starts with, they follow the cursor in the order of the listing
+	fn scan<'a>(&self, bids: impl Iterator<Item = (u64, &'a Bid)>) -> BidPage {
+		let limit = self.page_size();
+		let mut page: Vec<(u64, &Bid)> = bids.filter(|(_, bid)| self.matches(bid)).take(limit + 1).collect();
+		let more = page.len() > limit;
+		page.truncate(limit);
+		BidPage {
+			next_cursor: match page.last() {
+				Some((seq, bid)) if more => Some(self.encode_cursor(&self.key(*seq, bid))),
+				_ => None,
+			},
+			bids: page.into_iter().map(|(_, bid)| bid.clone()).collect(),
+		}
+	}
+
+	/// Select the requested page out of `bids`, numbered in the order they were placed.
+	/// Only the bids of the page are cloned.
+	pub fn paginate<'a>(&self, bids: impl IntoIterator<Item = (u64, &'a Bid)>) -> Result<BidPage, BidTrackerError> {
+		let limit = self.page_size();
+		let after = self.anchor()?;
+
+		let mut matching: Vec<(SortKey, &Bid)> = bids
+			.into_iter()
+			.filter(|(_, bid)| self.matches(bid))
+			.map(|(seq, bid)| (self.key(seq, bid), bid))
+			.collect();
+		matching.sort_by(|a, b| self.compare(&a.0, &b.0));
+
+		let start = match after {
+			Some(anchor) => matching.partition_point(|(key, _)| self.compare(key, &anchor) != Ordering::Greater),
+			None => 0,
+		};
+		let remaining = &matching[start..];
+		let page = &remaining[..remaining.len().min(limit)];
+
+		Ok(BidPage {
+			bids: page.iter().map(|(_, bid)| (*bid).clone()).collect(),
+			next_cursor: match page.last() {
+				Some((key, _)) if remaining.len() > limit => Some(self.encode_cursor(key)),
+				_ => None,
+			},
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn bids() -> BidLog {
+		let alice = uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap();
+		let bob = uuid::Uuid::parse_str("215248b5-8402-4211-93c0-9f71a93e69a9").unwrap();
+		[
+			(alice, 10, 30f64),
+			(bob, 20, 35f64),
+			(alice, 30, 32f64),
+			(bob, 40, 35f64),
+			(alice, 50, 40f64),
+		]
+		.into_iter()
+		.zip(1..)
+		.fold(BidLog::default(), |mut log, ((user_uuid, timestamp, amount), seq)| {
+			let bid = Bid {
+				user_uuid,
+				timestamp,
+				amount,
+				..Bid::default()
+			};
+			log.push(seq, bid);
+			log
+		})
+	}
+
+	fn walk(query: &mut BidQuery, bids: &BidLog) -> Vec<Vec<i64>> {
+		let mut pages = vec![];
+		loop {
+			let page = query.paginate_log(bids).unwrap();
+			pages.push(page.bids.iter().map(|bid| bid.timestamp).collect());
+			match page.next_cursor {
+				Some(cursor) => query.cursor = Some(cursor),
+				None => return pages,
+			}
+		}
+	}
+
+	#[test]
+	fn test_paginate_in_placement_order() {
+		let bids = bids();
+		let page = BidQuery::default().paginate(bids.entries()).unwrap();
+		assert_eq!(page.bids.len(), 5);
+		assert!(page.next_cursor.is_none());
+
+		let mut query = BidQuery {
+			limit: Some(2),
+			..BidQuery::default()
+		};
+		assert_eq!(walk(&mut query, &bids), vec![vec![10, 20], vec![30, 40], vec![50]]);
+	}
+
+	#[test]
+	fn test_paginate_sorted() {
+		let bids = bids();
+		let mut query = BidQuery {
+			limit: Some(2),
+			sort: Some(BidSort::Amount),
+			order: SortOrder::Desc,
+			..BidQuery::default()
+		};
+		// Equal amounts are ordered by placement, reversed for desc
+		assert_eq!(walk(&mut query, &bids), vec![vec![50, 40], vec![20, 30], vec![10]]);
+
+		let mut query = BidQuery {
+			limit: Some(3),
+			sort: Some(BidSort::Timestamp),
+			..BidQuery::default()
+		};
+		assert_eq!(walk(&mut query, &bids), vec![vec![10, 20, 30], vec![40, 50]]);
+	}
+
+	#[test]
+	fn test_paginate_filtered() {
+		let bids = bids();
+		let query = BidQuery {
+			min_amount: Some(32f64),
+			since: Some(20),
+			until: Some(40),
+			..BidQuery::default()
+		};
+		let page = query.paginate(bids.entries()).unwrap();
+		assert_eq!(
+			page.bids.iter().map(|bid| bid.timestamp).collect::<Vec<_>>(),
+			vec![20, 30, 40]
+		);
+
+		let query = BidQuery {
+			user_uuid: Some(bids[0].user_uuid),
+			..BidQuery::default()
+		};
+		assert_eq!(query.paginate(bids.entries()).unwrap().bids.len(), 3);
+	}
+
+	#[test]
+	fn test_paginate_log_matches_paginate() {
+		let bids = bids();
+		for order in [SortOrder::Asc, SortOrder::Desc] {
+			for min_amount in [None, Some(35f64)] {
+				let mut query = BidQuery {
+					limit: Some(1),
+					order,
+					min_amount,
+					..BidQuery::default()
+				};
+				loop {
+					let page = query.paginate_log(&bids).unwrap();
+					let expected = query.paginate(bids.entries()).unwrap();
+					assert_eq!(page.bids, expected.bids, "{:?} {:?}", order, min_amount);
+					assert_eq!(page.next_cursor, expected.next_cursor);
+					match page.next_cursor {
+						Some(cursor) => query.cursor = Some(cursor),
+						None => break,
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn test_cursor_survives_removals() {
+		for sort in [None, Some(BidSort::Amount), Some(BidSort::Timestamp)] {
+			let mut bids = bids();
+			let mut query = BidQuery {
+				limit: Some(2),
+				sort,
+				..BidQuery::default()
+			};
+			let mut expected = walk(&mut query.clone(), &bids);
+			let page = query.paginate(bids.entries()).unwrap();
+			let first = expected.remove(0);
+
+			// Removing the bids of the first page, its last one included, neither skips nor repeats bids
+			bids.remove_where(|bid| first.contains(&bid.timestamp));
+			query.cursor = page.next_cursor;
+			assert_eq!(walk(&mut query, &bids), expected, "{:?}", sort);
+		}
+	}
+
+	#[test]
+	fn test_bid_log() {
+		let mut bids = bids();
+		let removed = bids.remove_where(|bid| bid.amount == 35f64);
+		assert_eq!(removed.len(), 2);
+		assert_eq!(bids.entries().map(|(seq, _)| seq).collect::<Vec<_>>(), vec![1, 3, 5]);
+		assert_eq!(bids.last_seq(), Some(5));
+		let after: Vec<_> = bids.after(Some(2), 1).into_iter().map(|(seq, _)| seq).collect();
+		assert_eq!(after, vec![3]);
+		assert_eq!(bids.after(None, 10).len(), 3);
+		assert!(bids.after(Some(5), 10).is_empty());
+	}
+
+	#[test]
+	fn test_invalid_cursor() {
+		let bids = bids();
+		let query = BidQuery {
+			limit: Some(1),
+			..BidQuery::default()
+		};
+		let cursor = query.paginate(bids.entries()).unwrap().next_cursor.unwrap();
+
+		// A cursor is only valid for the sorting it was created with
+		let query = BidQuery {
+			cursor: Some(cursor),
+			sort: Some(BidSort::Amount),
+			..BidQuery::default()
+		};
+		assert!(query.paginate(bids.entries()).is_err());
+
+		let query = BidQuery {
+			cursor: Some("garbage".into()),
+			..BidQuery::default()
+		};
+		assert!(query.paginate(bids.entries()).is_err());
+	}
+
+	#[test]
+	fn test_page_size_is_capped() {
+		let mut bids = BidLog::default();
+		for seq in 0..=MAX_PAGE_SIZE as u64 {
+			bids.push(seq, Bid::default());
+		}
+		let query = BidQuery {
+			limit: Some(MAX_PAGE_SIZE * 10),
+			..BidQuery::default()
+		};
+		let page = query.paginate(bids.entries()).unwrap();
+		assert_eq!(page.bids.len(), MAX_PAGE_SIZE);
+		assert!(page.next_cursor.is_some());
+	}
+}
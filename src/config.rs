@@ -21,7 +21,11 @@
 // SOFTWARE.
 
 use serde::Deserialize;
-use std::env;
+use std::{
+	env,
+	path::{Path, PathBuf},
+	time::Duration,
+};
 
 const DEFAULT_CONFIG_ENV_KEY: &str = "BID_TRACKER_CONFIG_PATH";
 const CONFIG_PREFIX: &str = "BID_TRACKER_";
@@ -39,8 +43,55 @@ impl ConfigFn {
 	fn fn_default_address() -> String {
 		"0.0.0.0:3000".into()
 	}
+	fn fn_default_ws_heartbeat_interval_secs() -> u64 {
+		5
+	}
+	fn fn_default_ws_client_timeout_secs() -> u64 {
+		30
+	}
+	fn fn_default_webhook_max_attempts() -> u32 {
+		8
+	}
+	fn fn_default_webhook_retry_base_delay_ms() -> u64 {
+		1000
+	}
+	fn fn_default_webhook_timeout_secs() -> u64 {
+		10
+	}
+	fn fn_default_batch_max_bids() -> usize {
+		10_000
+	}
+	fn fn_default_batch_max_body_bytes() -> usize {
+		16 * 1024 * 1024
+	}
+	fn fn_default_import_max_body_bytes() -> usize {
+		256 * 1024 * 1024
+	}
+	fn fn_default_idempotency_key_ttl_secs() -> u64 {
+		24 * 60 * 60
+	}
+	fn fn_default_notifiers() -> String {
+		"log".into()
+	}
+	fn fn_default_notification_cooldown_secs() -> u64 {
+		300
+	}
+	fn fn_default_jwt_key_reload_secs() -> u64 {
+		30
+	}
+	fn fn_default_smtp_port() -> u16 {
+		25
+	}
+	fn fn_default_smtp_from() -> String {
+		"bid-tracker@localhost".into()
+	}
+	fn fn_default_fraud_mode() -> String {
+		"monitor".into()
+	}
+	fn fn_default_otlp_service_name() -> String {
+		"bid-tracker".into()
+	}
 
-	#[allow(dead_code)]
 	fn fn_empty_string() -> String {
 		"".into()
 	}
@@ -56,6 +107,156 @@ pub struct Config {
 	// defaults to 0.0.0.0:8080
 	#[serde(default = "ConfigFn::fn_default_address")]
 	pub address: String,
+
+	// Set the address to bind the gRPC server on, e.g. 0.0.0.0:50051, the gRPC server is disabled if empty
+	// defaults to ""
+	#[serde(default = "ConfigFn::fn_empty_string")]
+	pub grpc_address: String,
+
+	// Interval in seconds at which websocket clients are pinged, at least 1
+	// defaults to 5
+	#[serde(default = "ConfigFn::fn_default_ws_heartbeat_interval_secs")]
+	pub ws_heartbeat_interval_secs: u64,
+
+	// Close a websocket connection if nothing was heard from the client for these many seconds, at least 1
+	// defaults to 30
+	#[serde(default = "ConfigFn::fn_default_ws_client_timeout_secs")]
+	pub ws_client_timeout_secs: u64,
+
+	// Directory to persist state in, nothing is persisted if empty
+	// defaults to ""
+	#[serde(default = "ConfigFn::fn_empty_string")]
+	pub data_dir: String,
+
+	// Number of attempts after which a webhook delivery is moved to the dead letters
+	// defaults to 8
+	#[serde(default = "ConfigFn::fn_default_webhook_max_attempts")]
+	pub webhook_max_attempts: u32,
+
+	// Delay before the first webhook retry, doubled on every further retry
+	// defaults to 1000
+	#[serde(default = "ConfigFn::fn_default_webhook_retry_base_delay_ms")]
+	pub webhook_retry_base_delay_ms: u64,
+
+	// Timeout in seconds of a single webhook delivery
+	// defaults to 10
+	#[serde(default = "ConfigFn::fn_default_webhook_timeout_secs")]
+	pub webhook_timeout_secs: u64,
+
+	// Maximum number of bids in a single batch
+	// defaults to 10000
+	#[serde(default = "ConfigFn::fn_default_batch_max_bids")]
+	pub batch_max_bids: usize,
+
+	// Maximum size in bytes of the body of a batch, json array or ndjson
+	// defaults to 16MiB
+	#[serde(default = "ConfigFn::fn_default_batch_max_body_bytes")]
+	pub batch_max_body_bytes: usize,
+
+	// Maximum size in bytes of a file uploaded to the import endpoint
+	// defaults to 256MiB
+	#[serde(default = "ConfigFn::fn_default_import_max_body_bytes")]
+	pub import_max_body_bytes: usize,
+
+	// Seconds for which the response to an Idempotency-Key is replayed
+	// defaults to 86400
+	#[serde(default = "ConfigFn::fn_default_idempotency_key_ttl_secs")]
+	pub idempotency_key_ttl_secs: u64,
+
+	// Comma separated list of the outbid notifiers to enable, out of log, webhook and email
+	// defaults to log
+	#[serde(default = "ConfigFn::fn_default_notifiers")]
+	pub notifiers: String,
+
+	// Do not notify a user about the same item again within these many seconds
+	// defaults to 300
+	#[serde(default = "ConfigFn::fn_default_notification_cooldown_secs")]
+	pub notification_cooldown_secs: u64,
+
+	// Url the webhook notifier POSTs to
+	#[serde(default = "ConfigFn::fn_empty_string")]
+	pub notification_webhook_url: String,
+
+	// Secret the webhook notifier signs its payloads with
+	#[serde(default = "ConfigFn::fn_empty_string")]
+	pub notification_webhook_secret: String,
+
+	// SMTP relay used by the email notifier
+	#[serde(default = "ConfigFn::fn_empty_string")]
+	pub smtp_host: String,
+
+	// defaults to 25
+	#[serde(default = "ConfigFn::fn_default_smtp_port")]
+	pub smtp_port: u16,
+
+	// Sender address of the outbid emails
+	// defaults to bid-tracker@localhost
+	#[serde(default = "ConfigFn::fn_default_smtp_from")]
+	pub smtp_from: String,
+
+	// File with the secrets HS256 bearer tokens are signed with, one per line
+	// authentication is disabled if neither this nor jwt_rs256_public_key_file is set
+	#[serde(default = "ConfigFn::fn_empty_string")]
+	pub jwt_hs256_secret_file: String,
+
+	// PEM file with the public keys of the RS256 bearer tokens
+	#[serde(default = "ConfigFn::fn_empty_string")]
+	pub jwt_rs256_public_key_file: String,
+
+	// Check the key files for changes at most every these many seconds
+	// defaults to 30
+	#[serde(default = "ConfigFn::fn_default_jwt_key_reload_secs")]
+	pub jwt_key_reload_secs: u64,
+
+	// Number of bids a single user may place in a burst, rate limiting of users is disabled if 0
+	// defaults to 0
+	#[serde(default)]
+	pub rate_limit_user_burst: u32,
+
+	// Bids per second a single user may place once the burst is used up
+	// defaults to 0
+	#[serde(default)]
+	pub rate_limit_user_per_sec: f64,
+
+	// Number of bids a single api key may place in a burst, rate limiting of api keys is disabled if 0
+	// defaults to 0
+	#[serde(default)]
+	pub rate_limit_api_key_burst: u32,
+
+	// Bids per second a single api key may place once the burst is used up
+	// defaults to 0
+	#[serde(default)]
+	pub rate_limit_api_key_per_sec: f64,
+
+	// Number of bids a single client ip may place in a burst, rate limiting of ips is disabled if 0
+	// defaults to 0
+	#[serde(default)]
+	pub rate_limit_ip_burst: u32,
+
+	// Bids per second a single client ip may place once the burst is used up
+	// defaults to 0
+	#[serde(default)]
+	pub rate_limit_ip_per_sec: f64,
+
+	// Credit limit of the users without one of their own, users may bid without limit if 0
+	// defaults to 0
+	#[serde(default)]
+	pub default_credit_limit: f64,
+
+	// What happens to the bids flagged by the fraud rules, out of off, monitor and enforce
+	// defaults to monitor
+	#[serde(default = "ConfigFn::fn_default_fraud_mode")]
+	pub fraud_mode: String,
+
+	// OTLP/gRPC collector to export the trace spans to, e.g. http://localhost:4317, nothing is exported if empty
+	// defaults to ""
+	#[serde(default = "ConfigFn::fn_empty_string")]
+	pub otlp_endpoint: String,
+
+	// Service name the exported spans are reported under
+	// defaults to bid-tracker
+	#[serde(default = "ConfigFn::fn_default_otlp_service_name")]
+	pub otlp_service_name: String,
 }
 
 impl Config {
@@ -74,14 +275,38 @@ impl Config {
 			Err(error) => panic!("Failed to read config. Error: {error}", error = error),
 		}
 	}
+
+	/// Interval at which websocket clients are pinged, a zero interval is raised to a second
+	pub fn ws_heartbeat_interval(&self) -> Duration {
+		Duration::from_secs(self.ws_heartbeat_interval_secs.max(1))
+	}
+
+	/// Time after which a silent websocket client is disconnected, a zero timeout is raised to a second
+	pub fn ws_client_timeout(&self) -> Duration {
+		Duration::from_secs(self.ws_client_timeout_secs.max(1))
+	}
+
+	/// Path of a file inside the data directory, None if persistence is disabled
+	pub fn data_file(&self, name: &str) -> Option<PathBuf> {
+		if self.data_dir.is_empty() {
+			None
+		} else {
+			Some(Path::new(&self.data_dir).join(name))
+		}
+	}
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_return, clippy::bool_assert_comparison)]
 mod tests {
 	use super::*;
 
 	fn eq_with_nan_eq(a: &Config, b: &Config) -> bool {
-		return (a.address == b.address) && (a.debug == b.debug);
+		return (a.address == b.address)
+			&& (a.grpc_address == b.grpc_address)
+			&& (a.debug == b.debug)
+			&& (a.ws_heartbeat_interval_secs == b.ws_heartbeat_interval_secs)
+			&& (a.ws_client_timeout_secs == b.ws_client_timeout_secs);
 	}
 
 	fn vec_compare(va: &[Config], vb: &[Config]) -> bool {
@@ -98,7 +323,15 @@ mod tests {
 	  },
 	  {
 		  "address": "0.0.0.0:9080",
-		  "debug": false
+		  "grpc_address": "0.0.0.0:50051",
+		  "debug": false,
+		  "ws_heartbeat_interval_secs": 10,
+		  "ws_client_timeout_secs": 60,
+		  "data_dir": "/var/lib/bid-tracker",
+		  "webhook_max_attempts": 3,
+		  "notifiers": "log,email",
+		  "smtp_host": "localhost",
+		  "smtp_port": 2525
 	  }
 	]
 "#;
@@ -108,10 +341,72 @@ mod tests {
 			Config {
 				debug: true,
 				address: "0.0.0.0:3000".into(),
+				grpc_address: "".into(),
+				ws_heartbeat_interval_secs: 5,
+				ws_client_timeout_secs: 30,
+				data_dir: "".into(),
+				webhook_max_attempts: 8,
+				webhook_retry_base_delay_ms: 1000,
+				webhook_timeout_secs: 10,
+				batch_max_bids: 10_000,
+				batch_max_body_bytes: 16 * 1024 * 1024,
+				import_max_body_bytes: 256 * 1024 * 1024,
+				idempotency_key_ttl_secs: 86400,
+				notifiers: "log".into(),
+				notification_cooldown_secs: 300,
+				notification_webhook_url: "".into(),
+				notification_webhook_secret: "".into(),
+				smtp_host: "".into(),
+				smtp_port: 25,
+				smtp_from: "bid-tracker@localhost".into(),
+				jwt_hs256_secret_file: "".into(),
+				jwt_rs256_public_key_file: "".into(),
+				jwt_key_reload_secs: 30,
+				rate_limit_user_burst: 0,
+				rate_limit_user_per_sec: 0.0,
+				rate_limit_api_key_burst: 0,
+				rate_limit_api_key_per_sec: 0.0,
+				rate_limit_ip_burst: 0,
+				rate_limit_ip_per_sec: 0.0,
+				default_credit_limit: 0.0,
+				fraud_mode: "monitor".into(),
+				otlp_endpoint: "".into(),
+				otlp_service_name: "bid-tracker".into(),
 			},
 			Config {
 				debug: false,
 				address: "0.0.0.0:9080".into(),
+				grpc_address: "0.0.0.0:50051".into(),
+				ws_heartbeat_interval_secs: 10,
+				ws_client_timeout_secs: 60,
+				data_dir: "/var/lib/bid-tracker".into(),
+				webhook_max_attempts: 3,
+				webhook_retry_base_delay_ms: 1000,
+				webhook_timeout_secs: 10,
+				batch_max_bids: 10_000,
+				batch_max_body_bytes: 16 * 1024 * 1024,
+				import_max_body_bytes: 256 * 1024 * 1024,
+				idempotency_key_ttl_secs: 86400,
+				notifiers: "log,email".into(),
+				notification_cooldown_secs: 300,
+				notification_webhook_url: "".into(),
+				notification_webhook_secret: "".into(),
+				smtp_host: "localhost".into(),
+				smtp_port: 2525,
+				smtp_from: "bid-tracker@localhost".into(),
+				jwt_hs256_secret_file: "".into(),
+				jwt_rs256_public_key_file: "".into(),
+				jwt_key_reload_secs: 30,
+				rate_limit_user_burst: 0,
+				rate_limit_user_per_sec: 0.0,
+				rate_limit_api_key_burst: 0,
+				rate_limit_api_key_per_sec: 0.0,
+				rate_limit_ip_burst: 0,
+				rate_limit_ip_per_sec: 0.0,
+				default_credit_limit: 0.0,
+				fraud_mode: "monitor".into(),
+				otlp_endpoint: "".into(),
+				otlp_service_name: "bid-tracker".into(),
 			},
 		];
 		assert_eq!(
@@ -122,6 +417,17 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn test_ws_durations() {
+		let config = Config::default();
+		assert_eq!(config.ws_heartbeat_interval(), Duration::from_secs(1));
+		assert_eq!(config.ws_client_timeout(), Duration::from_secs(1));
+
+		let config: Config = serde_json::from_str("{}").unwrap();
+		assert_eq!(config.ws_heartbeat_interval(), Duration::from_secs(5));
+		assert_eq!(config.ws_client_timeout(), Duration::from_secs(30));
+	}
+
 	#[test]
 	fn test_config_reading() {
 		// better_panic::Settings::debug()
@@ -134,4 +440,16 @@ mod tests {
 		assert!(config.address == "0.0.0.0:9091");
 		assert!(config.debug);
 	}
+
+	#[test]
+	fn test_data_file() {
+		let mut config = Config::default();
+		assert!(config.data_file("webhooks.json").is_none());
+
+		config.data_dir = "/var/lib/bid-tracker".into();
+		assert_eq!(
+			config.data_file("webhooks.json").unwrap(),
+			Path::new("/var/lib/bid-tracker/webhooks.json")
+		);
+	}
 }
@@ -20,25 +20,134 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use actix_web::http::StatusCode;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum BidTrackerError {
-	#[error("Requested item is not present in bidding list: {0}")]
+	#[error("Requested item is not open for bidding: {0}")]
 	ItemNotBiddable(String),
+	#[error("Requested item does not exist: {0}")]
+	ItemNotFound(String),
+	#[error("Item already exists: {0}")]
+	ItemAlreadyExists(String),
+	#[error("Requested item has no bids yet: {0}")]
+	NoBidsYet(String),
+	#[error("Requested user has not placed any bids: {0}")]
+	UserNotFound(String),
+	#[error("Invalid request: {0}")]
+	InvalidRequest(String),
+	#[error("Credit limit exceeded: {0}")]
+	CreditLimitExceeded(String),
+	#[error("Requested webhook does not exist: {0}")]
+	WebhookNotFound(String),
+	#[error("Requested api key does not exist: {0}")]
+	ApiKeyNotFound(String),
+	#[error("Idempotency key conflict: {0}")]
+	IdempotencyConflict(String),
+	#[error("Too many requests, retry after {retry_after_secs} seconds")]
+	RateLimited { retry_after_secs: u64 },
+	#[error("Unauthorized: {0}")]
+	Unauthorized(String),
+	#[error("Forbidden: {0}")]
+	Forbidden(String),
+	#[error("Bid rejected as suspicious: {0}")]
+	FraudSuspected(String),
+	#[error("Bidder is blocked: {0}")]
+	BidderBlocked(String),
+	#[error("Invalid configuration: {0}")]
+	InvalidConfig(String),
+	#[error("Failed to send notification: {0}")]
+	Notification(String),
 	#[error("IO error encountered")]
-	Io { source: std::io::Error },
+	Io {
+		#[from]
+		source: std::io::Error,
+	},
+	#[error("Failed to (de)serialize the state")]
+	Json {
+		#[from]
+		source: serde_json::Error,
+	},
 	#[error("IO error encountered")]
 	Actix { source: actix_web::error::Error },
 }
 
-impl From<BidTrackerError> for actix_web::error::Error {
-	fn from(e: BidTrackerError) -> actix_web::error::Error {
+impl BidTrackerError {
+	/// The status code this error is reported with, shared by the REST and websocket handlers
+	pub fn status_code(&self) -> StatusCode {
+		match self {
+			BidTrackerError::ItemNotBiddable(_)
+			| BidTrackerError::InvalidRequest(_)
+			| BidTrackerError::CreditLimitExceeded(_) => StatusCode::UNPROCESSABLE_ENTITY,
+			BidTrackerError::ItemNotFound(_)
+			| BidTrackerError::NoBidsYet(_)
+			| BidTrackerError::UserNotFound(_)
+			| BidTrackerError::WebhookNotFound(_)
+			| BidTrackerError::ApiKeyNotFound(_) => StatusCode::NOT_FOUND,
+			BidTrackerError::ItemAlreadyExists(_) | BidTrackerError::IdempotencyConflict(_) => StatusCode::CONFLICT,
+			BidTrackerError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+			BidTrackerError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+			BidTrackerError::Forbidden(_) | BidTrackerError::FraudSuspected(_) | BidTrackerError::BidderBlocked(_) => {
+				StatusCode::FORBIDDEN
+			}
+			_ => StatusCode::INTERNAL_SERVER_ERROR,
+		}
+	}
+
+	/// The stable machine readable code of the error, clients can match on it
+	pub fn code(&self) -> &'static str {
+		match self {
+			BidTrackerError::ItemNotBiddable(_) => "item_not_biddable",
+			BidTrackerError::ItemNotFound(_) => "item_not_found",
+			BidTrackerError::ItemAlreadyExists(_) => "item_already_exists",
+			BidTrackerError::NoBidsYet(_) => "no_bids_yet",
+			BidTrackerError::UserNotFound(_) => "user_not_found",
+			BidTrackerError::InvalidRequest(_) => "invalid_request",
+			BidTrackerError::CreditLimitExceeded(_) => "credit_limit_exceeded",
+			BidTrackerError::WebhookNotFound(_) => "webhook_not_found",
+			BidTrackerError::ApiKeyNotFound(_) => "api_key_not_found",
+			BidTrackerError::IdempotencyConflict(_) => "idempotency_conflict",
+			BidTrackerError::RateLimited { .. } => "rate_limited",
+			BidTrackerError::Unauthorized(_) => "unauthorized",
+			BidTrackerError::Forbidden(_) => "forbidden",
+			BidTrackerError::FraudSuspected(_) => "fraud_suspected",
+			BidTrackerError::BidderBlocked(_) => "bidder_blocked",
+			_ => "internal_error",
+		}
+	}
+}
+
+/// Same mapping as the REST api, so both report an error the same way
+impl From<BidTrackerError> for tonic::Status {
+	fn from(e: BidTrackerError) -> tonic::Status {
+		let message = e.to_string();
 		match e {
-			BidTrackerError::ItemNotBiddable(_e) => {
-				actix_web::error::ErrorUnprocessableEntity(format!("Failed to process the bid the db. {:?}", _e))
+			BidTrackerError::ItemNotBiddable(_) | BidTrackerError::CreditLimitExceeded(_) => {
+				tonic::Status::failed_precondition(message)
+			}
+			BidTrackerError::InvalidRequest(_) => tonic::Status::invalid_argument(message),
+			BidTrackerError::ItemNotFound(_)
+			| BidTrackerError::NoBidsYet(_)
+			| BidTrackerError::UserNotFound(_)
+			| BidTrackerError::WebhookNotFound(_)
+			| BidTrackerError::ApiKeyNotFound(_) => tonic::Status::not_found(message),
+			BidTrackerError::ItemAlreadyExists(_) | BidTrackerError::IdempotencyConflict(_) => {
+				tonic::Status::already_exists(message)
 			}
-			_ => actix_web::error::ErrorInternalServerError(format!("Failed to get the bids. {:?}", e.to_string())),
+			BidTrackerError::RateLimited { .. } => tonic::Status::resource_exhausted(message),
+			BidTrackerError::Unauthorized(_) => tonic::Status::unauthenticated(message),
+			BidTrackerError::Forbidden(_) | BidTrackerError::FraudSuspected(_) | BidTrackerError::BidderBlocked(_) => {
+				tonic::Status::permission_denied(message)
+			}
+			_ => tonic::Status::internal(message),
 		}
 	}
 }
+
+/// Reported as `application/problem+json`, see `api::problem`
+impl From<BidTrackerError> for actix_web::error::Error {
+	fn from(e: BidTrackerError) -> actix_web::error::Error {
+		crate::api::problem::Problem::from(e).into()
+	}
+}
@@ -0,0 +1,547 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Rules flagging suspicious bids: narrow leads over the same rival, accounts associated with the seller,
+//! bid velocity spikes and many users sharing one ip.
+
+use crate::bidtracker::{Bid, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::errors::BidTrackerError;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use utoipa::{IntoParams, ToSchema};
+
+// The oldest flagged bids are dropped once there are more than these many
+const MAX_FLAGGED_BIDS: usize = 10_000;
+
+/// What happens to the bids a rule flags
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FraudMode {
+	/// No rule is evaluated
+	Off,
+	/// Flagged bids are recorded and placed
+	#[default]
+	Monitor,
+	/// Flagged bids are recorded and rejected
+	Enforce,
+}
+
+impl FraudMode {
+	pub fn parse(mode: &str) -> Result<FraudMode, BidTrackerError> {
+		match mode {
+			"off" => Ok(FraudMode::Off),
+			"monitor" => Ok(FraudMode::Monitor),
+			"enforce" => Ok(FraudMode::Enforce),
+			_ => Err(BidTrackerError::InvalidConfig(format!(
+				"Unknown fraud mode {}, expected off, monitor or enforce",
+				mode
+			))),
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FraudRule {
+	/// The bidder keeps taking the lead from the same rival by a narrow margin
+	RivalShadowing,
+	/// The bidder was seen on an ip of the seller of the item
+	SellerAssociate,
+	/// The bidder places more bids than usual in a short time
+	VelocitySpike,
+	/// Many users bid from the same ip
+	SharedIp,
+}
+
+impl FraudRule {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			FraudRule::RivalShadowing => "rival_shadowing",
+			FraudRule::SellerAssociate => "seller_associate",
+			FraudRule::VelocitySpike => "velocity_spike",
+			FraudRule::SharedIp => "shared_ip",
+		}
+	}
+}
+
+/// The thresholds of the rules, adjustable at runtime
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
+#[serde(default)]
+pub struct FraudRules {
+	pub mode: FraudMode,
+	// A lead of at most this fraction above the previous amount is a narrow one
+	pub shadow_margin: f64,
+	// Narrow leads over the same rival after which the bidder is flagged
+	pub shadow_min_count: u32,
+	// Bids a user may place within the velocity window
+	pub velocity_max_bids: u32,
+	pub velocity_window_secs: u64,
+	// Users who may bid from the same ip within the shared ip window
+	pub shared_ip_max_users: u32,
+	pub shared_ip_window_secs: u64,
+}
+
+impl Default for FraudRules {
+	fn default() -> FraudRules {
+		FraudRules {
+			mode: FraudMode::default(),
+			shadow_margin: 0.05,
+			shadow_min_count: 3,
+			velocity_max_bids: 10,
+			velocity_window_secs: 60,
+			shared_ip_max_users: 5,
+			shared_ip_window_secs: 3600,
+		}
+	}
+}
+
+impl FraudRules {
+	fn validate(&self) -> Result<(), BidTrackerError> {
+		if !self.shadow_margin.is_finite() || self.shadow_margin < 0.0 {
+			return Err(BidTrackerError::InvalidRequest(format!(
+				"shadow_margin can not be negative, got {}",
+				self.shadow_margin
+			)));
+		}
+		let thresholds = [
+			("shadow_min_count", self.shadow_min_count as u64),
+			("velocity_max_bids", self.velocity_max_bids as u64),
+			("velocity_window_secs", self.velocity_window_secs),
+			("shared_ip_max_users", self.shared_ip_max_users as u64),
+			("shared_ip_window_secs", self.shared_ip_window_secs),
+		];
+		if let Some((name, _)) = thresholds.iter().find(|(_, value)| *value == 0) {
+			return Err(BidTrackerError::InvalidRequest(format!("{} must be positive", name)));
+		}
+		Ok(())
+	}
+}
+
+/// A rule a bid was flagged by
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Flag {
+	pub rule: FraudRule,
+	pub detail: String,
+}
+
+/// A bid together with the rules it was flagged by
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct FlaggedBid {
+	pub bid: Bid,
+	#[schema(value_type = Option<String>)]
+	pub ip: Option<IpAddr>,
+	pub flags: Vec<Flag>,
+	// Rejected in enforcing mode, placed otherwise
+	pub blocked: bool,
+	pub flagged_at: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FlagQuery {
+	pub useruuid: Option<uuid::Uuid>,
+	pub itemuuid: Option<uuid::Uuid>,
+	pub rule: Option<FraudRule>,
+	pub limit: Option<usize>,
+}
+
+/// FraudEngine evaluates the rules against every bid and keeps the bids they flagged.
+/// It only learns from placed bids, a blocked bid counts towards no rule.
+#[derive(Debug, Clone, Default)]
+pub struct FraudEngine {
+	rules: FraudRules,
+	// Narrow leads taken by a bidder from each rival
+	narrow_leads: HashMap<(uuid::Uuid, uuid::Uuid), u32>,
+	// Times of the bids of every user within the velocity window
+	recent_bids: HashMap<uuid::Uuid, VecDeque<i64>>,
+	// Users seen on every ip within the shared ip window, with the time they were last seen
+	ip_users: HashMap<IpAddr, HashMap<uuid::Uuid, i64>>,
+	// Every ip a user was seen on
+	user_ips: HashMap<uuid::Uuid, HashSet<IpAddr>>,
+	flagged: VecDeque<FlaggedBid>,
+}
+
+impl FraudEngine {
+	pub fn new(rules: FraudRules) -> Result<FraudEngine, BidTrackerError> {
+		rules.validate()?;
+		Ok(FraudEngine {
+			rules,
+			..FraudEngine::default()
+		})
+	}
+
+	pub fn rules(&self) -> &FraudRules {
+		&self.rules
+	}
+
+	/// Replace the rules, what was learnt so far is kept
+	pub fn set_rules(&mut self, rules: FraudRules) -> Result<(), BidTrackerError> {
+		rules.validate()?;
+		self.rules = rules;
+		Ok(())
+	}
+
+	/// Copy of what was learnt about `users` and `ip`, enough to evaluate and observe the bids of those users
+	/// placed from `ip`. Flagged bids are not copied.
+	pub fn detached_for(&self, users: &HashSet<uuid::Uuid>, ip: Option<IpAddr>) -> FraudEngine {
+		FraudEngine {
+			rules: self.rules.clone(),
+			narrow_leads: self
+				.narrow_leads
+				.iter()
+				.filter(|((bidder, _), _)| users.contains(bidder))
+				.map(|(key, count)| (*key, *count))
+				.collect(),
+			recent_bids: filter_users(&self.recent_bids, users),
+			ip_users: ip
+				.and_then(|ip| self.ip_users.get_key_value(&ip))
+				.map(|(ip, seen)| (*ip, seen.clone()))
+				.into_iter()
+				.collect(),
+			user_ips: filter_users(&self.user_ips, users),
+			flagged: VecDeque::new(),
+		}
+	}
+
+	/// The rules `bid` would be flagged by, given the `seller` and current `leader` of its item
+	pub fn evaluate(
+		&self,
+		bid: &Bid,
+		ip: Option<IpAddr>,
+		seller: Option<uuid::Uuid>,
+		leader: Option<&Bid>,
+		now: i64,
+	) -> Vec<Flag> {
+		if self.rules.mode == FraudMode::Off {
+			return vec![];
+		}
+		let mut flags = vec![];
+
+		if let Some(rival) = self.narrow_lead(bid, leader) {
+			let count = self
+				.narrow_leads
+				.get(&(bid.user_uuid, rival))
+				.copied()
+				.unwrap_or_default()
+				+ 1;
+			if count >= self.rules.shadow_min_count {
+				flags.push(Flag {
+					rule: FraudRule::RivalShadowing,
+					detail: format!("Took the lead from {} by a narrow margin {} times", rival, count),
+				});
+			}
+		}
+
+		if let (Some(seller), Some(ip)) = (seller, ip) {
+			let seen = self.user_ips.get(&seller).is_some_and(|ips| ips.contains(&ip));
+			if seller != bid.user_uuid && seen {
+				flags.push(Flag {
+					rule: FraudRule::SellerAssociate,
+					detail: format!("Bids from {}, an ip of the seller {}", ip, seller),
+				});
+			}
+		}
+
+		let since = now - self.rules.velocity_window_secs as i64 * 1000;
+		let recent = self
+			.recent_bids
+			.get(&bid.user_uuid)
+			.map_or(0, |times| times.iter().filter(|time| **time > since).count());
+		if recent + 1 > self.rules.velocity_max_bids as usize {
+			flags.push(Flag {
+				rule: FraudRule::VelocitySpike,
+				detail: format!("{} bids within {} seconds", recent + 1, self.rules.velocity_window_secs),
+			});
+		}
+
+		if let Some(ip) = ip {
+			let since = now - self.rules.shared_ip_window_secs as i64 * 1000;
+			let users = self.ip_users.get(&ip).map_or(0, |users| {
+				users
+					.iter()
+					.filter(|(user, seen)| **seen > since && **user != bid.user_uuid)
+					.count()
+			}) + 1;
+			if users > self.rules.shared_ip_max_users as usize {
+				flags.push(Flag {
+					rule: FraudRule::SharedIp,
+					detail: format!(
+						"{} users bid from {} within {} seconds",
+						users, ip, self.rules.shared_ip_window_secs
+					),
+				});
+			}
+		}
+		flags
+	}
+
+	/// Learn from a placed bid, `leader` is the one it was placed against
+	pub fn observe(&mut self, bid: &Bid, ip: Option<IpAddr>, leader: Option<&Bid>, now: i64) {
+		if self.rules.mode == FraudMode::Off {
+			return;
+		}
+		if let Some(rival) = self.narrow_lead(bid, leader) {
+			*self.narrow_leads.entry((bid.user_uuid, rival)).or_default() += 1;
+		}
+
+		let since = now - self.rules.velocity_window_secs as i64 * 1000;
+		let times = self.recent_bids.entry(bid.user_uuid).or_default();
+		while times.front().is_some_and(|time| *time <= since) {
+			times.pop_front();
+		}
+		times.push_back(now);
+
+		if let Some(ip) = ip {
+			self.observe_user(&bid.user_uuid, ip, now);
+		}
+	}
+
+	/// Note that a user was seen on an ip, e.g. a seller putting up an item
+	pub fn observe_user(&mut self, user_uuid: &uuid::Uuid, ip: IpAddr, now: i64) {
+		let since = now - self.rules.shared_ip_window_secs as i64 * 1000;
+		let users = self.ip_users.entry(ip).or_default();
+		users.retain(|_, seen| *seen > since);
+		users.insert(*user_uuid, now);
+		self.user_ips.entry(*user_uuid).or_default().insert(ip);
+	}
+
+	/// Keep a flagged bid for the admins to review
+	pub fn record(&mut self, bid: &Bid, ip: Option<IpAddr>, flags: Vec<Flag>, blocked: bool, now: i64) {
+		if flags.is_empty() {
+			return;
+		}
+		tracing::warn!(
+			"Flagged the bid of {} on {} by {}, blocked: {}",
+			bid.user_uuid,
+			bid.item_uuid,
+			rule_names(&flags),
+			blocked
+		);
+		if self.flagged.len() >= MAX_FLAGGED_BIDS {
+			self.flagged.pop_front();
+		}
+		self.flagged.push_back(FlaggedBid {
+			bid: bid.clone(),
+			ip,
+			flags,
+			blocked,
+			flagged_at: now,
+		});
+	}
+
+	/// The flagged bids matching the query, most recent first
+	pub fn flagged(&self, query: &FlagQuery) -> Vec<FlaggedBid> {
+		let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+		self.flagged
+			.iter()
+			.rev()
+			.filter(|flagged| query.useruuid.is_none_or(|user| flagged.bid.user_uuid == user))
+			.filter(|flagged| query.itemuuid.is_none_or(|item| flagged.bid.item_uuid == item))
+			.filter(|flagged| {
+				query
+					.rule
+					.is_none_or(|rule| flagged.flags.iter().any(|flag| flag.rule == rule))
+			})
+			.take(limit)
+			.cloned()
+			.collect()
+	}
+
+	// The rival a bid takes the lead from by a narrow margin, if any
+	fn narrow_lead(&self, bid: &Bid, leader: Option<&Bid>) -> Option<uuid::Uuid> {
+		let leader = leader?;
+		let narrow = bid.amount > leader.amount && bid.amount <= leader.amount * (1.0 + self.rules.shadow_margin);
+		(narrow && leader.user_uuid != bid.user_uuid).then_some(leader.user_uuid)
+	}
+}
+
+fn filter_users<T: Clone>(map: &HashMap<uuid::Uuid, T>, users: &HashSet<uuid::Uuid>) -> HashMap<uuid::Uuid, T> {
+	users
+		.iter()
+		.filter_map(|user| map.get_key_value(user))
+		.map(|(user, value)| (*user, value.clone()))
+		.collect()
+}
+
+/// The comma separated names of the rules behind the flags
+pub fn rule_names(flags: &[Flag]) -> String {
+	flags
+		.iter()
+		.map(|flag| flag.rule.as_str())
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const ITEM: &str = "b2f9ee6d-79fe-4b14-9c19-35a69a89219a";
+
+	fn bid(user_uuid: uuid::Uuid, amount: f64) -> Bid {
+		Bid {
+			item_uuid: uuid::Uuid::parse_str(ITEM).unwrap(),
+			user_uuid,
+			timestamp: 1591915318,
+			amount,
+		}
+	}
+
+	fn rules(flags: &[Flag]) -> Vec<FraudRule> {
+		flags.iter().map(|flag| flag.rule).collect()
+	}
+
+	#[test]
+	fn test_rival_shadowing() {
+		let mut engine = FraudEngine::default();
+		let (shadow, rival) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+		let mut amount = 100f64;
+		for _ in 0..2 {
+			let leader = bid(rival, amount);
+			let narrow = bid(shadow, amount + 1.0);
+			assert!(engine.evaluate(&narrow, None, None, Some(&leader), 0).is_empty());
+			engine.observe(&narrow, None, Some(&leader), 0);
+			amount += 10.0;
+		}
+		// A wide lead does not count
+		let leader = bid(rival, amount);
+		let wide = bid(shadow, amount * 2.0);
+		assert!(engine.evaluate(&wide, None, None, Some(&leader), 0).is_empty());
+
+		let narrow = bid(shadow, amount + 1.0);
+		let flags = engine.evaluate(&narrow, None, None, Some(&leader), 0);
+		assert_eq!(rules(&flags), vec![FraudRule::RivalShadowing]);
+		// Other rivals are counted apart
+		let other = bid(uuid::Uuid::new_v4(), amount);
+		assert!(engine.evaluate(&narrow, None, None, Some(&other), 0).is_empty());
+	}
+
+	#[test]
+	fn test_seller_associate() {
+		let mut engine = FraudEngine::default();
+		let (seller, bidder) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+		let ip: IpAddr = [10, 0, 0, 1].into();
+		engine.observe_user(&seller, ip, 0);
+
+		let flags = engine.evaluate(&bid(bidder, 30f64), Some(ip), Some(seller), None, 0);
+		assert_eq!(rules(&flags), vec![FraudRule::SellerAssociate]);
+		let elsewhere = [10, 0, 0, 2].into();
+		assert!(engine
+			.evaluate(&bid(bidder, 30f64), Some(elsewhere), Some(seller), None, 0)
+			.is_empty());
+	}
+
+	#[test]
+	fn test_velocity_spike() {
+		let mut engine = FraudEngine::new(FraudRules {
+			velocity_max_bids: 2,
+			velocity_window_secs: 10,
+			..FraudRules::default()
+		})
+		.unwrap();
+		let user = uuid::Uuid::new_v4();
+		for now in [0, 1000] {
+			assert!(engine.evaluate(&bid(user, 30f64), None, None, None, now).is_empty());
+			engine.observe(&bid(user, 30f64), None, None, now);
+		}
+		let flags = engine.evaluate(&bid(user, 30f64), None, None, None, 2000);
+		assert_eq!(rules(&flags), vec![FraudRule::VelocitySpike]);
+		// The window slides past the first bid
+		assert!(engine.evaluate(&bid(user, 30f64), None, None, None, 10_500).is_empty());
+	}
+
+	#[test]
+	fn test_shared_ip() {
+		let mut engine = FraudEngine::new(FraudRules {
+			shared_ip_max_users: 2,
+			..FraudRules::default()
+		})
+		.unwrap();
+		let ip = Some([10, 0, 0, 1].into());
+		let (first, second) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+		engine.observe(&bid(first, 30f64), ip, None, 0);
+		engine.observe(&bid(second, 30f64), ip, None, 0);
+		// Users already seen on the ip are not counted twice
+		assert!(engine.evaluate(&bid(first, 30f64), ip, None, None, 0).is_empty());
+
+		let third = bid(uuid::Uuid::new_v4(), 30f64);
+		let flags = engine.evaluate(&third, ip, None, None, 0);
+		assert_eq!(rules(&flags), vec![FraudRule::SharedIp]);
+		let hour_later = 3_600_001;
+		assert!(engine.evaluate(&third, ip, None, None, hour_later).is_empty());
+	}
+
+	#[test]
+	fn test_flagged_bids() {
+		let mut engine = FraudEngine::default();
+		let user = uuid::Uuid::new_v4();
+		let flag = |rule| Flag {
+			rule,
+			detail: "".into(),
+		};
+		engine.record(&bid(user, 30f64), None, vec![], false, 0);
+		engine.record(&bid(user, 30f64), None, vec![flag(FraudRule::VelocitySpike)], false, 1);
+		engine.record(&bid(user, 35f64), None, vec![flag(FraudRule::SharedIp)], true, 2);
+		engine.record(
+			&bid(uuid::Uuid::new_v4(), 40f64),
+			None,
+			vec![flag(FraudRule::SharedIp)],
+			true,
+			3,
+		);
+
+		let query = FlagQuery {
+			useruuid: Some(user),
+			..FlagQuery::default()
+		};
+		let flagged = engine.flagged(&query);
+		assert_eq!(flagged.iter().map(|f| f.flagged_at).collect::<Vec<_>>(), vec![2, 1]);
+		let query = FlagQuery {
+			rule: Some(FraudRule::SharedIp),
+			limit: Some(1),
+			..FlagQuery::default()
+		};
+		assert_eq!(engine.flagged(&query)[0].flagged_at, 3);
+	}
+
+	#[test]
+	fn test_mode() {
+		assert_eq!(FraudMode::parse("enforce").unwrap(), FraudMode::Enforce);
+		assert!(FraudMode::parse("strict").is_err());
+
+		let mut engine = FraudEngine::new(FraudRules {
+			mode: FraudMode::Off,
+			velocity_max_bids: 1,
+			..FraudRules::default()
+		})
+		.unwrap();
+		let user = uuid::Uuid::new_v4();
+		engine.observe(&bid(user, 30f64), None, None, 0);
+		assert!(engine.evaluate(&bid(user, 30f64), None, None, None, 0).is_empty());
+		assert!(FraudEngine::new(FraudRules {
+			shadow_margin: -1.0,
+			..FraudRules::default()
+		})
+		.is_err());
+	}
+}
@@ -0,0 +1,395 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::auth::Caller;
+use crate::bidtracker::{Bid, BidEvent, BidPage, BidQuery, BidSort, BidTracker, EventBus, SortOrder, UserItem};
+use crate::errors::BidTrackerError;
+use crate::metrics::{Metrics, Transport};
+use async_graphql::{
+	ComplexObject, Context, EmptyMutation, ErrorExtensions, Object, Result, Schema, SimpleObject, Subscription,
+};
+use futures_util::{Stream, StreamExt};
+use std::sync::{Arc, Mutex};
+use tokio_stream::wrappers::BroadcastStream;
+
+pub type BidSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+/// The read side of a `BidTracker`, object safe so the schema does not depend on the backend
+pub trait TrackerSource: Send + Sync {
+	fn items(&self) -> Vec<uuid::Uuid>;
+	fn query_bids(&self, item_uuid: &uuid::Uuid, query: &BidQuery) -> Result<BidPage, BidTrackerError>;
+	fn query_bids_by_user(&self, user_uuid: &uuid::Uuid, query: &BidQuery) -> Result<BidPage, BidTrackerError>;
+	fn winning_bid(&self, item_uuid: &uuid::Uuid) -> Result<Option<Bid>, BidTrackerError>;
+	fn items_by_user(&self, user_uuid: &uuid::Uuid) -> Result<Vec<UserItem>, BidTrackerError>;
+}
+
+// Every lock of the tracker is timed on the metrics, like the locks of the other fronts
+struct TimedTracker<T> {
+	tracker: Arc<Mutex<T>>,
+	metrics: Arc<Metrics>,
+}
+
+impl<T: BidTracker + Send> TrackerSource for TimedTracker<T> {
+	fn items(&self) -> Vec<uuid::Uuid> {
+		self.metrics.lock(&self.tracker).get_items()
+	}
+
+	fn query_bids(&self, item_uuid: &uuid::Uuid, query: &BidQuery) -> Result<BidPage, BidTrackerError> {
+		self.metrics.lock(&self.tracker).query_bids(item_uuid, query)
+	}
+
+	fn query_bids_by_user(&self, user_uuid: &uuid::Uuid, query: &BidQuery) -> Result<BidPage, BidTrackerError> {
+		self.metrics.lock(&self.tracker).query_bids_by_user(user_uuid, query)
+	}
+
+	fn winning_bid(&self, item_uuid: &uuid::Uuid) -> Result<Option<Bid>, BidTrackerError> {
+		match self.metrics.lock(&self.tracker).current_winning_bid(item_uuid) {
+			Ok(bid) => Ok(Some(bid)),
+			Err(BidTrackerError::NoBidsYet(_)) => Ok(None),
+			Err(e) => Err(e),
+		}
+	}
+
+	fn items_by_user(&self, user_uuid: &uuid::Uuid) -> Result<Vec<UserItem>, BidTrackerError> {
+		self.metrics.lock(&self.tracker).get_items_by_user(user_uuid)
+	}
+}
+
+/// Build the schema over `tracker`, subscriptions are fed from `events`
+pub fn build_schema<T: BidTracker + Send + 'static>(
+	tracker: Arc<Mutex<T>>,
+	events: EventBus,
+	metrics: Arc<Metrics>,
+) -> BidSchema {
+	let tracker: Arc<dyn TrackerSource> = Arc::new(TimedTracker {
+		tracker,
+		metrics: metrics.clone(),
+	});
+	Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+		.data(tracker)
+		.data(events)
+		.data(metrics)
+		.finish()
+}
+
+// Errors carry the status code and the problem code the REST api would answer with
+fn graphql_error(e: BidTrackerError) -> async_graphql::Error {
+	let status = e.status_code().as_u16();
+	let code = e.code();
+	async_graphql::Error::new(e.to_string()).extend_with(|_, extensions| {
+		extensions.set("status", status);
+		extensions.set("code", code);
+	})
+}
+
+fn tracker<'a>(ctx: &Context<'a>) -> &'a Arc<dyn TrackerSource> {
+	// The schema is only ever built with a tracker
+	ctx.data_unchecked::<Arc<dyn TrackerSource>>()
+}
+
+static UNRESTRICTED: Caller = Caller::Unrestricted;
+
+// Requests executed without a caller, e.g. in tests, are unrestricted
+fn caller<'a>(ctx: &Context<'a>) -> &'a Caller {
+	ctx.data_opt::<Caller>().unwrap_or(&UNRESTRICTED)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn bid_query(
+	first: Option<usize>,
+	after: Option<String>,
+	sort: Option<BidSort>,
+	order: Option<SortOrder>,
+	min_amount: Option<f64>,
+	since: Option<i64>,
+	until: Option<i64>,
+) -> BidQuery {
+	BidQuery {
+		cursor: after,
+		limit: first,
+		sort,
+		order: order.unwrap_or_default(),
+		min_amount,
+		since,
+		until,
+		user_uuid: None,
+	}
+}
+
+/// A page of bids, pass `nextCursor` as `after` to get the next one
+#[derive(SimpleObject)]
+pub struct BidConnection {
+	nodes: Vec<Bid>,
+	next_cursor: Option<String>,
+}
+
+impl From<BidPage> for BidConnection {
+	fn from(page: BidPage) -> BidConnection {
+		BidConnection {
+			nodes: page.bids,
+			next_cursor: page.next_cursor,
+		}
+	}
+}
+
+/// An item open or closed for bidding
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Item {
+	itemuuid: uuid::Uuid,
+}
+
+#[ComplexObject]
+impl Item {
+	/// The bids on the item, in the order they were placed unless sorted
+	#[allow(clippy::too_many_arguments)]
+	async fn bids(
+		&self,
+		ctx: &Context<'_>,
+		first: Option<usize>,
+		after: Option<String>,
+		sort: Option<BidSort>,
+		order: Option<SortOrder>,
+		min_amount: Option<f64>,
+		since: Option<i64>,
+		until: Option<i64>,
+	) -> Result<BidConnection> {
+		let query = bid_query(first, after, sort, order, min_amount, since, until);
+		let page = tracker(ctx).query_bids(&self.itemuuid, &query).map_err(graphql_error)?;
+		Ok(page.into())
+	}
+
+	/// The current winning bid, null without bids
+	async fn winning_bid(&self, ctx: &Context<'_>) -> Result<Option<Bid>> {
+		tracker(ctx).winning_bid(&self.itemuuid).map_err(graphql_error)
+	}
+}
+
+/// A user who placed bids
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct User {
+	useruuid: uuid::Uuid,
+}
+
+#[ComplexObject]
+impl User {
+	/// The bids of the user, in the order they were placed unless sorted
+	#[allow(clippy::too_many_arguments)]
+	async fn bids(
+		&self,
+		ctx: &Context<'_>,
+		first: Option<usize>,
+		after: Option<String>,
+		sort: Option<BidSort>,
+		order: Option<SortOrder>,
+		min_amount: Option<f64>,
+		since: Option<i64>,
+		until: Option<i64>,
+	) -> Result<BidConnection> {
+		caller(ctx).authorize_user(&self.useruuid).map_err(graphql_error)?;
+		let query = bid_query(first, after, sort, order, min_amount, since, until);
+		let page = tracker(ctx)
+			.query_bids_by_user(&self.useruuid, &query)
+			.map_err(graphql_error)?;
+		Ok(page.into())
+	}
+
+	/// The items the user has bid on, with the user's standing on each
+	async fn items(&self, ctx: &Context<'_>) -> Result<Vec<UserItem>> {
+		caller(ctx).authorize_user(&self.useruuid).map_err(graphql_error)?;
+		tracker(ctx).items_by_user(&self.useruuid).map_err(graphql_error)
+	}
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+	/// All the items known to the tracker
+	async fn items(&self, ctx: &Context<'_>) -> Vec<Item> {
+		tracker(ctx)
+			.items()
+			.into_iter()
+			.map(|itemuuid| Item { itemuuid })
+			.collect()
+	}
+
+	/// A single item, null if the tracker does not know it
+	async fn item(&self, ctx: &Context<'_>, itemuuid: uuid::Uuid) -> Option<Item> {
+		tracker(ctx).items().contains(&itemuuid).then_some(Item { itemuuid })
+	}
+
+	async fn user(&self, useruuid: uuid::Uuid) -> User {
+		User { useruuid }
+	}
+}
+
+/// The winning bid of an item changed, `previous` is the bid which lost the lead
+#[derive(SimpleObject)]
+pub struct WinningBidChange {
+	itemuuid: uuid::Uuid,
+	bid: Bid,
+	previous: Option<Bid>,
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+	/// Changes of the winning bids, of a single item if `itemuuid` is given
+	async fn winning_bid_changed(
+		&self,
+		ctx: &Context<'_>,
+		itemuuid: Option<uuid::Uuid>,
+	) -> impl Stream<Item = WinningBidChange> {
+		let events = ctx.data_unchecked::<EventBus>().subscribe();
+		let subscriber = ctx.data_unchecked::<Arc<Metrics>>().subscriber(Transport::Graphql);
+		let stream = BroadcastStream::new(events).filter_map(move |event| async move {
+			match event {
+				Ok(BidEvent::WinningBidChanged {
+					item_uuid,
+					bid,
+					previous,
+				}) if itemuuid.is_none_or(|wanted| wanted == item_uuid) => Some(WinningBidChange {
+					itemuuid: item_uuid,
+					bid,
+					previous,
+				}),
+				_ => None,
+			}
+		});
+		subscriber.attach(stream)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bidtracker::BidManagement;
+
+	const ITEM: &str = "b2f9ee6d-79fe-4b14-9c19-35a69a89219a";
+	const USER: &str = "1c916ab6-255b-4a36-9574-e456e0f774c9";
+
+	fn schema() -> (Arc<Mutex<BidManagement>>, BidSchema) {
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let bm = Arc::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let (events, metrics) = {
+			let bdm = bm.lock().unwrap();
+			(bdm.events(), bdm.metrics())
+		};
+		(bm.clone(), build_schema(bm, events, metrics))
+	}
+
+	fn place(bm: &Mutex<BidManagement>, amount: f64) {
+		bm.lock()
+			.unwrap()
+			.insert_bid(&Bid {
+				item_uuid: uuid::Uuid::parse_str(ITEM).unwrap(),
+				user_uuid: uuid::Uuid::parse_str(USER).unwrap(),
+				timestamp: amount as i64,
+				amount,
+			})
+			.unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_graphql_query() {
+		let (bm, schema) = schema();
+		for amount in [30f64, 35f64, 32f64] {
+			place(&bm, amount);
+		}
+
+		let query = format!(
+			r#"{{
+				items {{ itemuuid winningBid {{ amount }} }}
+				item(itemuuid: "{ITEM}") {{
+					bids(first: 2, sort: AMOUNT, order: DESC) {{ nodes {{ amount }} nextCursor }}
+				}}
+				user(useruuid: "{USER}") {{
+					bids {{ nodes {{ amount }} }}
+					items {{ itemuuid winning bidCount }}
+				}}
+			}}"#
+		);
+		let response = schema.execute(query).await;
+		assert!(response.errors.is_empty(), "{:?}", response.errors);
+		let data = response.data.into_json().unwrap();
+		assert_eq!(data["items"][0]["winningBid"]["amount"], 35f64);
+		let bids = &data["item"]["bids"];
+		assert_eq!(bids["nodes"][0]["amount"], 35f64);
+		assert_eq!(bids["nodes"][1]["amount"], 32f64);
+		assert!(bids["nextCursor"].is_string());
+		assert_eq!(data["user"]["bids"]["nodes"].as_array().unwrap().len(), 3);
+		assert_eq!(data["user"]["items"][0]["bidCount"], 3);
+
+		let response = schema
+			.execute(r#"{ item(itemuuid: "7f272d43-0ff2-4e0f-9ebc-589eae48e3ad") { itemuuid } }"#)
+			.await;
+		assert!(response.data.into_json().unwrap()["item"].is_null());
+
+		let query = format!(r#"{{ item(itemuuid: "{ITEM}") {{ bids(after: "nope") {{ nextCursor }} }} }}"#);
+		let response = schema.execute(query).await;
+		let extensions = response.errors[0].extensions.as_ref().unwrap();
+		assert_eq!(extensions.get("status"), Some(&async_graphql::Value::from(422)));
+		assert_eq!(
+			extensions.get("code"),
+			Some(&async_graphql::Value::from("invalid_request"))
+		);
+	}
+
+	#[test]
+	fn test_winning_bid() {
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let (bm, _) = schema();
+		let tracker = TimedTracker {
+			tracker: bm.clone(),
+			metrics: Arc::default(),
+		};
+		assert_eq!(tracker.winning_bid(&item_uuid).unwrap(), None);
+		place(&bm, 30f64);
+		assert_eq!(tracker.winning_bid(&item_uuid).unwrap().unwrap().amount, 30f64);
+		assert!(matches!(
+			tracker.winning_bid(&uuid::Uuid::new_v4()),
+			Err(BidTrackerError::ItemNotFound(_))
+		));
+	}
+
+	#[tokio::test]
+	async fn test_graphql_subscription() {
+		let (bm, schema) = schema();
+		let mut stream = schema.execute_stream(format!(
+			r#"subscription {{ winningBidChanged(itemuuid: "{ITEM}") {{ bid {{ amount }} previous {{ amount }} }} }}"#
+		));
+
+		// Subscriptions only start listening once polled
+		let next = tokio::spawn(async move { stream.next().await.unwrap() });
+		tokio::task::yield_now().await;
+		tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+		place(&bm, 30f64);
+
+		let response = next.await.unwrap();
+		let data = response.data.into_json().unwrap();
+		assert_eq!(data["winningBidChanged"]["bid"]["amount"], 30f64);
+		assert!(data["winningBidChanged"]["previous"].is_null());
+	}
+}
@@ -0,0 +1,558 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::proto::{self, bid_event, bid_tracker_server};
+use crate::api_keys::{ApiKeyStore, HEADER_API_KEY};
+use crate::auth::{self, Caller, JwtKeys};
+use crate::bidtracker::{Bid, BidEvent, BidManagement, BidQuery, BidTracker};
+use crate::errors::BidTrackerError;
+use crate::metrics::{lock_traced, Metrics, Transport};
+use crate::rate_limit::{client_keys, RateLimiter};
+use crate::telemetry::{self, TRACEPARENT, TRACESTATE};
+use futures_util::{Stream, StreamExt};
+use std::{
+	net::SocketAddr,
+	pin::Pin,
+	sync::{Arc, Mutex},
+};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tonic::service::{interceptor::InterceptedService, Interceptor};
+use tonic::{Request, Response, Status};
+
+fn parse_uuid(value: &str, field: &str) -> Result<uuid::Uuid, BidTrackerError> {
+	uuid::Uuid::parse_str(value).map_err(|e| BidTrackerError::InvalidRequest(format!("Invalid {}: {}", field, e)))
+}
+
+impl TryFrom<proto::Bid> for Bid {
+	type Error = BidTrackerError;
+
+	fn try_from(bid: proto::Bid) -> Result<Bid, BidTrackerError> {
+		Ok(Bid {
+			item_uuid: parse_uuid(&bid.itemuuid, "itemuuid")?,
+			user_uuid: parse_uuid(&bid.useruuid, "useruuid")?,
+			timestamp: bid.timestamp,
+			amount: bid.amount,
+		})
+	}
+}
+
+impl From<Bid> for proto::Bid {
+	fn from(bid: Bid) -> proto::Bid {
+		proto::Bid {
+			itemuuid: bid.item_uuid.to_string(),
+			useruuid: bid.user_uuid.to_string(),
+			timestamp: bid.timestamp,
+			amount: bid.amount,
+		}
+	}
+}
+
+impl From<BidEvent> for proto::BidEvent {
+	fn from(event: BidEvent) -> proto::BidEvent {
+		let event = match event {
+			BidEvent::BidPlaced { bid } => bid_event::Event::BidPlaced(proto::BidPlaced { bid: Some(bid.into()) }),
+			BidEvent::WinningBidChanged {
+				item_uuid,
+				bid,
+				previous,
+			} => bid_event::Event::WinningBidChanged(proto::WinningBidChanged {
+				itemuuid: item_uuid.to_string(),
+				bid: Some(bid.into()),
+				previous: previous.map(Into::into),
+			}),
+			BidEvent::AuctionClosed { item_uuid, winning_bid } => {
+				bid_event::Event::AuctionClosed(proto::AuctionClosed {
+					itemuuid: item_uuid.to_string(),
+					winning_bid: winning_bid.map(Into::into),
+				})
+			}
+			BidEvent::BidsRetracted {
+				item_uuid,
+				user_uuid,
+				bids,
+				winning_bid,
+			} => bid_event::Event::BidsRetracted(proto::BidsRetracted {
+				itemuuid: item_uuid.to_string(),
+				useruuid: user_uuid.to_string(),
+				bids: bids.into_iter().map(Into::into).collect(),
+				winning_bid: winning_bid.map(Into::into),
+			}),
+		};
+		proto::BidEvent { event: Some(event) }
+	}
+}
+
+fn page_query(cursor: Option<String>, limit: Option<u32>) -> BidQuery {
+	BidQuery {
+		cursor,
+		limit: limit.map(|limit| limit as usize),
+		..BidQuery::default()
+	}
+}
+
+/// gRPC front of the tracker, shares the tracker instance with the actix routes
+#[derive(Clone)]
+pub struct GrpcBidTracker {
+	bidtracker: Arc<Mutex<BidManagement>>,
+	jwt_keys: Arc<JwtKeys>,
+	api_keys: Arc<ApiKeyStore>,
+	rate_limiter: Arc<RateLimiter>,
+	metrics: Arc<Metrics>,
+}
+
+impl GrpcBidTracker {
+	pub fn new(bidtracker: Arc<Mutex<BidManagement>>) -> GrpcBidTracker {
+		let metrics = lock_traced(&bidtracker).metrics();
+		GrpcBidTracker {
+			bidtracker,
+			metrics,
+			jwt_keys: Arc::new(JwtKeys::disabled()),
+			api_keys: Arc::new(ApiKeyStore::default()),
+			rate_limiter: Arc::new(RateLimiter::default()),
+		}
+	}
+
+	/// Authenticate the `authorization` metadata of every call with `jwt_keys`
+	pub fn with_jwt_keys(mut self, jwt_keys: Arc<JwtKeys>) -> GrpcBidTracker {
+		self.jwt_keys = jwt_keys;
+		self
+	}
+
+	/// Accept the keys of `api_keys` in the `x-api-key` metadata
+	pub fn with_api_keys(mut self, api_keys: Arc<ApiKeyStore>) -> GrpcBidTracker {
+		self.api_keys = api_keys;
+		self
+	}
+
+	/// Share the buckets of `rate_limiter` with the other fronts when inserting bids
+	pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> GrpcBidTracker {
+		self.rate_limiter = rate_limiter;
+		self
+	}
+
+	/// The service behind the `Authenticate` interceptor
+	pub fn into_server(self) -> InterceptedService<bid_tracker_server::BidTrackerServer<GrpcBidTracker>, Authenticate> {
+		let authenticate = Authenticate {
+			jwt_keys: self.jwt_keys.clone(),
+			api_keys: self.api_keys.clone(),
+		};
+		bid_tracker_server::BidTrackerServer::with_interceptor(self, authenticate)
+	}
+
+	/// Serve the service on `address` until the process exits
+	pub async fn serve(self, address: SocketAddr) -> Result<(), tonic::transport::Error> {
+		tonic::transport::Server::builder()
+			.trace_fn(trace_call)
+			.add_service(self.into_server())
+			.serve(address)
+			.await
+	}
+}
+
+/// The server span of a call, continuing the trace of its `traceparent` metadata
+pub fn trace_call(request: &tonic::codegen::http::Request<()>) -> tracing::Span {
+	let span = tracing::info_span!(
+		"request",
+		otel.name = request.uri().path().trim_start_matches('/'),
+		otel.kind = "server",
+		rpc.system = "grpc",
+	);
+	let header = |name| request.headers().get(name).and_then(|value| value.to_str().ok());
+	telemetry::set_remote_parent(&span, header(TRACEPARENT), header(TRACESTATE));
+	span
+}
+
+/// Attaches the `Caller` of the `authorization` or `x-api-key` metadata to every call
+#[derive(Clone)]
+pub struct Authenticate {
+	jwt_keys: Arc<JwtKeys>,
+	api_keys: Arc<ApiKeyStore>,
+}
+
+impl Interceptor for Authenticate {
+	fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+		// The scopes of an api key are checked by the calls, like those of a bearer token
+		let metadata_key = HEADER_API_KEY.to_ascii_lowercase();
+		if let Some(key) = request.metadata().get(metadata_key.as_str()) {
+			let api_key = self.api_keys.verify(key.to_str().unwrap_or_default())?;
+			request.extensions_mut().insert(Caller::Service(api_key));
+			return Ok(request);
+		}
+
+		let token = request
+			.metadata()
+			.get("authorization")
+			.and_then(|value| value.to_str().ok())
+			.and_then(auth::parse_bearer);
+		let caller = auth::caller_for_token(&self.jwt_keys, token)?;
+		request.extensions_mut().insert(caller);
+		Ok(request)
+	}
+}
+
+// Calls which did not pass the interceptor are unrestricted, like the actix handlers without the middleware
+fn caller<T>(request: &Request<T>) -> Caller {
+	request
+		.extensions()
+		.get::<Caller>()
+		.cloned()
+		.unwrap_or(Caller::Unrestricted)
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<proto::BidEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl bid_tracker_server::BidTracker for GrpcBidTracker {
+	#[tracing::instrument(skip_all)]
+	async fn insert_bid(
+		&self,
+		request: Request<proto::InsertBidRequest>,
+	) -> Result<Response<proto::InsertBidResponse>, Status> {
+		let caller = caller(&request);
+		let ip = request.remote_addr().map(|addr| addr.ip());
+		self.rate_limiter.acquire(&client_keys(&caller, ip))?;
+		let bid = request
+			.into_inner()
+			.bid
+			.ok_or_else(|| BidTrackerError::InvalidRequest("The bid is missing".into()))?;
+		let bid = Bid::try_from(bid)?;
+		caller.authorize_bid(&bid)?;
+		self.metrics.lock(&self.bidtracker).insert_bid_from(&bid, ip)?;
+		Ok(Response::new(proto::InsertBidResponse { bid: Some(bid.into()) }))
+	}
+
+	#[tracing::instrument(skip_all)]
+	async fn get_bids(&self, request: Request<proto::GetBidsRequest>) -> Result<Response<proto::BidPage>, Status> {
+		let request = request.into_inner();
+		let item_uuid = parse_uuid(&request.itemuuid, "itemuuid")?;
+		let query = page_query(request.cursor, request.limit);
+		let page = self.metrics.lock(&self.bidtracker).query_bids(&item_uuid, &query)?;
+		Ok(Response::new(proto::BidPage {
+			bids: page.bids.into_iter().map(Into::into).collect(),
+			next_cursor: page.next_cursor,
+		}))
+	}
+
+	#[tracing::instrument(skip_all)]
+	async fn get_winning_bid(
+		&self,
+		request: Request<proto::GetWinningBidRequest>,
+	) -> Result<Response<proto::GetWinningBidResponse>, Status> {
+		let item_uuid = parse_uuid(&request.into_inner().itemuuid, "itemuuid")?;
+		let bid = match self.metrics.lock(&self.bidtracker).current_winning_bid(&item_uuid) {
+			Ok(bid) => Some(bid.into()),
+			Err(BidTrackerError::NoBidsYet(_)) => None,
+			Err(e) => return Err(e.into()),
+		};
+		Ok(Response::new(proto::GetWinningBidResponse { bid }))
+	}
+
+	#[tracing::instrument(skip_all)]
+	async fn get_user_bids(
+		&self,
+		request: Request<proto::GetUserBidsRequest>,
+	) -> Result<Response<proto::BidPage>, Status> {
+		let caller = caller(&request);
+		let request = request.into_inner();
+		let user_uuid = parse_uuid(&request.useruuid, "useruuid")?;
+		caller.authorize_user(&user_uuid)?;
+		let query = page_query(request.cursor, request.limit);
+		let page = self
+			.metrics
+			.lock(&self.bidtracker)
+			.query_bids_by_user(&user_uuid, &query)?;
+		Ok(Response::new(proto::BidPage {
+			bids: page.bids.into_iter().map(Into::into).collect(),
+			next_cursor: page.next_cursor,
+		}))
+	}
+
+	type WatchItemStream = EventStream;
+
+	#[tracing::instrument(skip_all)]
+	async fn watch_item(
+		&self,
+		request: Request<proto::WatchItemRequest>,
+	) -> Result<Response<Self::WatchItemStream>, Status> {
+		let item_uuid = parse_uuid(&request.into_inner().itemuuid, "itemuuid")?;
+		let events = {
+			let bdm = self.metrics.lock(&self.bidtracker);
+			// Unknown items are refused the same way a bid on them would be
+			bdm.bid_count(&item_uuid)?;
+			bdm.subscribe()
+		};
+
+		let stream = BroadcastStream::new(events).filter_map(move |event| async move {
+			match event {
+				Ok(event) if *event.item_uuid() == item_uuid => Some(Ok(event.into())),
+				Ok(_) => None,
+				Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+					tracing::warn!("gRPC watcher lagged behind, skipped {} events", skipped);
+					None
+				}
+			}
+		});
+		let subscriber = self.metrics.subscriber(Transport::Grpc);
+		Ok(Response::new(Box::pin(subscriber.attach(stream))))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use proto::bid_tracker_client::BidTrackerClient;
+	use tokio_stream::wrappers::TcpListenerStream;
+	use tonic::{transport::Channel, Code};
+
+	const ITEM: &str = "b2f9ee6d-79fe-4b14-9c19-35a69a89219a";
+	const USER: &str = "1c916ab6-255b-4a36-9574-e456e0f774c9";
+
+	async fn spawn_service() -> (Arc<Mutex<BidManagement>>, BidTrackerClient<Channel>) {
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let bidtracker = Arc::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		spawn_server(GrpcBidTracker::new(bidtracker.clone())).await
+	}
+
+	async fn spawn_server(service: GrpcBidTracker) -> (Arc<Mutex<BidManagement>>, BidTrackerClient<Channel>) {
+		let bidtracker = service.bidtracker.clone();
+
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		tokio::spawn(
+			tonic::transport::Server::builder()
+				.trace_fn(trace_call)
+				.add_service(service.into_server())
+				.serve_with_incoming(TcpListenerStream::new(listener)),
+		);
+		let client = BidTrackerClient::connect(format!("http://{}", addr)).await.unwrap();
+		(bidtracker, client)
+	}
+
+	fn bid(amount: f64) -> proto::Bid {
+		proto::Bid {
+			itemuuid: ITEM.into(),
+			useruuid: USER.into(),
+			timestamp: amount as i64,
+			amount,
+		}
+	}
+
+	#[tokio::test]
+	async fn test_grpc_bids() {
+		let (bidtracker, mut client) = spawn_service().await;
+
+		let winner = client
+			.get_winning_bid(proto::GetWinningBidRequest { itemuuid: ITEM.into() })
+			.await
+			.unwrap()
+			.into_inner();
+		assert!(winner.bid.is_none());
+
+		for amount in [30f64, 35f64, 32f64] {
+			let request = proto::InsertBidRequest { bid: Some(bid(amount)) };
+			client.insert_bid(request).await.unwrap();
+		}
+		// Bids placed over gRPC are visible to the REST api and the other way around
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		assert_eq!(bidtracker.lock().unwrap().get_bids(&item_uuid).unwrap().len(), 3);
+
+		let page = client
+			.get_bids(proto::GetBidsRequest {
+				itemuuid: ITEM.into(),
+				cursor: None,
+				limit: Some(2),
+			})
+			.await
+			.unwrap()
+			.into_inner();
+		assert_eq!(page.bids.len(), 2);
+		assert!(page.next_cursor.is_some());
+
+		let winner = client
+			.get_winning_bid(proto::GetWinningBidRequest { itemuuid: ITEM.into() })
+			.await
+			.unwrap()
+			.into_inner();
+		assert_eq!(winner.bid.unwrap().amount, 35f64);
+
+		let page = client
+			.get_user_bids(proto::GetUserBidsRequest {
+				useruuid: USER.into(),
+				cursor: None,
+				limit: None,
+			})
+			.await
+			.unwrap()
+			.into_inner();
+		assert_eq!(page.bids.len(), 3);
+	}
+
+	#[tokio::test]
+	async fn test_grpc_authentication() {
+		let path = std::env::temp_dir().join(format!("jwt-{}.secret", uuid::Uuid::new_v4()));
+		std::fs::write(&path, "secret").unwrap();
+		let jwt_keys = JwtKeys::new(Some(path.clone()), None, std::time::Duration::MAX).unwrap();
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let bidtracker = Arc::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let service = GrpcBidTracker::new(bidtracker).with_jwt_keys(Arc::new(jwt_keys));
+		let (_, mut client) = spawn_server(service).await;
+
+		let insert = |token: Option<&str>| {
+			let mut request = Request::new(proto::InsertBidRequest { bid: Some(bid(30f64)) });
+			if let Some(token) = token {
+				let value = format!("Bearer {}", token).parse().unwrap();
+				request.metadata_mut().insert("authorization", value);
+			}
+			request
+		};
+		let token = |sub: &str| {
+			let claims = auth::Claims {
+				sub: uuid::Uuid::parse_str(sub).unwrap(),
+				exp: 4_102_444_800,
+				roles: vec![],
+			};
+			let key = jsonwebtoken::EncodingKey::from_secret(b"secret");
+			jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap()
+		};
+
+		let status = client.insert_bid(insert(None)).await.unwrap_err();
+		assert_eq!(status.code(), Code::Unauthenticated);
+		let status = client.insert_bid(insert(Some("garbage"))).await.unwrap_err();
+		assert_eq!(status.code(), Code::Unauthenticated);
+		let other = token("7f272d43-0ff2-4e0f-9ebc-589eae48e3ad");
+		let status = client.insert_bid(insert(Some(&other))).await.unwrap_err();
+		assert_eq!(status.code(), Code::PermissionDenied);
+		client.insert_bid(insert(Some(&token(USER)))).await.unwrap();
+		std::fs::remove_file(path).unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_grpc_rate_limit() {
+		let limits = crate::rate_limit::RateLimits {
+			ip: crate::rate_limit::Limit::from_settings(2, 0.001),
+			..Default::default()
+		};
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		let bidtracker = Arc::new(Mutex::new(BidManagement::new(vec![item_uuid])));
+		let service = GrpcBidTracker::new(bidtracker).with_rate_limiter(Arc::new(RateLimiter::new(limits).unwrap()));
+		let (_, mut client) = spawn_server(service).await;
+
+		for amount in [30f64, 35f64] {
+			let request = proto::InsertBidRequest { bid: Some(bid(amount)) };
+			client.insert_bid(request).await.unwrap();
+		}
+		let request = proto::InsertBidRequest { bid: Some(bid(40f64)) };
+		let status = client.insert_bid(request).await.unwrap_err();
+		assert_eq!(status.code(), Code::ResourceExhausted);
+	}
+
+	#[tokio::test]
+	async fn test_grpc_errors() {
+		let (_, mut client) = spawn_service().await;
+
+		let mut unknown = bid(30f64);
+		unknown.itemuuid = "7f272d43-0ff2-4e0f-9ebc-589eae48e3ad".into();
+		let status = client
+			.insert_bid(proto::InsertBidRequest { bid: Some(unknown) })
+			.await
+			.unwrap_err();
+		assert_eq!(status.code(), Code::NotFound);
+
+		let mut malformed = bid(30f64);
+		malformed.useruuid = "not-a-uuid".into();
+		let status = client
+			.insert_bid(proto::InsertBidRequest { bid: Some(malformed) })
+			.await
+			.unwrap_err();
+		assert_eq!(status.code(), Code::InvalidArgument);
+
+		let status = client
+			.get_bids(proto::GetBidsRequest {
+				itemuuid: ITEM.into(),
+				cursor: Some("not-a-cursor".into()),
+				limit: None,
+			})
+			.await
+			.unwrap_err();
+		assert_eq!(status.code(), Code::InvalidArgument);
+	}
+
+	#[test]
+	fn test_trace_call() {
+		use opentelemetry::trace::{TraceContextExt, TracerProvider};
+		use tracing_opentelemetry::OpenTelemetrySpanExt;
+		use tracing_subscriber::layer::SubscriberExt;
+
+		let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder().build();
+		let subscriber =
+			tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+		tracing::subscriber::with_default(subscriber, || {
+			let request = tonic::codegen::http::Request::builder()
+				.uri("/bidtracker.BidTracker/InsertBid")
+				.header(TRACEPARENT, "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+				.body(())
+				.unwrap();
+			let span = trace_call(&request);
+			let cx = span.context();
+			assert_eq!(
+				cx.span().span_context().trace_id().to_string(),
+				"4bf92f3577b34da6a3ce929d0e0e4736"
+			);
+		});
+	}
+
+	#[tokio::test]
+	async fn test_grpc_watch_item() {
+		let (bidtracker, mut client) = spawn_service().await;
+
+		let mut events = client
+			.watch_item(proto::WatchItemRequest { itemuuid: ITEM.into() })
+			.await
+			.unwrap()
+			.into_inner();
+
+		bidtracker
+			.lock()
+			.unwrap()
+			.insert_bid(&Bid::try_from(bid(30f64)).unwrap())
+			.unwrap();
+
+		let event = events.message().await.unwrap().unwrap();
+		match event.event.unwrap() {
+			bid_event::Event::BidPlaced(placed) => assert_eq!(placed.bid.unwrap().amount, 30f64),
+			event => panic!("Unexpected event {:?}", event),
+		}
+		let event = events.message().await.unwrap().unwrap();
+		match event.event.unwrap() {
+			bid_event::Event::WinningBidChanged(changed) => assert!(changed.previous.is_none()),
+			event => panic!("Unexpected event {:?}", event),
+		}
+
+		let status = client
+			.watch_item(proto::WatchItemRequest {
+				itemuuid: "7f272d43-0ff2-4e0f-9ebc-589eae48e3ad".into(),
+			})
+			.await
+			.unwrap_err();
+		assert_eq!(status.code(), Code::NotFound);
+	}
+}
@@ -0,0 +1,170 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::errors::BidTrackerError;
+use actix_web::http::StatusCode;
+use sha2::{Digest, Sha256};
+use std::{
+	collections::{HashMap, VecDeque},
+	sync::Mutex,
+	time::Duration,
+};
+
+pub const HEADER_IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+pub const HEADER_IDEMPOTENT_REPLAYED: &str = "Idempotent-Replayed";
+pub const MAX_KEY_LENGTH: usize = 255;
+
+/// The response recorded for an idempotency key
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedResponse {
+	// Hash of the request body the key was first used with
+	pub fingerprint: String,
+	pub status: StatusCode,
+	pub body: String,
+	// Unix timestamp in milliseconds
+	pub created_at: i64,
+}
+
+/// IdempotencyStore remembers the responses of requests sent with an `Idempotency-Key` for `ttl`.
+///
+/// Keys belong to the caller who sent them, so two callers never see each other's responses. They are
+/// kept in memory like the bids they were recorded for, a key outliving its bid across a restart would
+/// replay a bid that no longer exists.
+#[derive(Debug)]
+pub struct IdempotencyStore {
+	ttl: Duration,
+	records: Mutex<Records>,
+}
+
+type RecordKey = (String, String);
+
+#[derive(Debug, Default)]
+struct Records {
+	// By the subject of the caller, then by key
+	responses: HashMap<RecordKey, RecordedResponse>,
+	// Keys in the order they were recorded, so expired ones are found without scanning the others
+	recorded: VecDeque<(i64, RecordKey)>,
+}
+
+/// Hash of a request body, used to detect a key being reused for a different request
+pub fn fingerprint(body: &[u8]) -> String {
+	hex::encode(Sha256::digest(body))
+}
+
+impl IdempotencyStore {
+	pub fn new(ttl: Duration) -> IdempotencyStore {
+		IdempotencyStore {
+			ttl,
+			records: Mutex::default(),
+		}
+	}
+
+	fn is_expired(&self, record: &RecordedResponse, now: i64) -> bool {
+		now - record.created_at >= self.ttl.as_millis() as i64
+	}
+
+	/// Get the response recorded for the `key` of `subject`, fails if the key was used with a different body
+	pub fn lookup(
+		&self,
+		subject: &str,
+		key: &str,
+		fingerprint: &str,
+		now: i64,
+	) -> Result<Option<RecordedResponse>, BidTrackerError> {
+		let records = self.records.lock().unwrap();
+		match records.responses.get(&(subject.to_string(), key.to_string())) {
+			Some(record) if self.is_expired(record, now) => Ok(None),
+			Some(record) if record.fingerprint != fingerprint => Err(BidTrackerError::IdempotencyConflict(format!(
+				"Idempotency-Key {} was already used with a different request",
+				key
+			))),
+			Some(record) => Ok(Some(record.clone())),
+			None => Ok(None),
+		}
+	}
+
+	/// Record the response for the `key` of `subject`, expired keys are left to `sweep`
+	pub fn record(&self, subject: &str, key: &str, response: RecordedResponse) {
+		let mut records = self.records.lock().unwrap();
+		let key = (subject.to_string(), key.to_string());
+		records.recorded.push_back((response.created_at, key.clone()));
+		records.responses.insert(key, response);
+	}
+
+	/// Drop the keys expired by `now`, oldest first. Meant to run once the tracker lock is released, it only
+	/// looks at the expired keys and the first one still alive.
+	pub fn sweep(&self, now: i64) {
+		let mut records = self.records.lock().unwrap();
+		let Records { responses, recorded } = &mut *records;
+		while let Some((created_at, key)) = recorded.front() {
+			if now - created_at < self.ttl.as_millis() as i64 {
+				break;
+			}
+			// A key recorded again since has a later entry of its own
+			if responses.get(key).is_some_and(|record| self.is_expired(record, now)) {
+				responses.remove(key);
+			}
+			recorded.pop_front();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn response(fingerprint: &str, created_at: i64) -> RecordedResponse {
+		RecordedResponse {
+			fingerprint: fingerprint.into(),
+			status: StatusCode::OK,
+			body: "{}".into(),
+			created_at,
+		}
+	}
+
+	#[test]
+	fn test_lookup() {
+		let store = IdempotencyStore::new(Duration::from_secs(60));
+		assert_eq!(store.lookup("user", "key", "a", 0).unwrap(), None);
+
+		store.record("user", "key", response("a", 0));
+		assert_eq!(store.lookup("user", "key", "a", 1000).unwrap(), Some(response("a", 0)));
+		assert!(matches!(
+			store.lookup("user", "key", "b", 1000),
+			Err(BidTrackerError::IdempotencyConflict(_))
+		));
+		// Keys are scoped by caller
+		assert_eq!(store.lookup("other", "key", "b", 1000).unwrap(), None);
+
+		// Expired keys can be reused for anything
+		assert_eq!(store.lookup("user", "key", "b", 60_000).unwrap(), None);
+		store.record("user", "key", response("b", 60_000));
+		store.record("user", "other", response("b", 90_000));
+		store.sweep(120_000);
+		let records = store.records.lock().unwrap();
+		assert_eq!(records.responses.len(), 1);
+		assert_eq!(records.recorded.len(), 1);
+		assert!(records
+			.responses
+			.contains_key(&("user".to_string(), "other".to_string())));
+	}
+}
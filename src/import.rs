@@ -0,0 +1,502 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::api::{routes, ResponseMessage};
+use crate::bidtracker::{Bid, BidManagement, BidTracker, PendingBids};
+use crate::errors::BidTrackerError;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, path::Path, sync::MutexGuard};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+	Csv,
+	Ndjson,
+}
+
+impl ImportFormat {
+	/// Guess the format from the extension of a file, newline delimited json unless it ends in .csv
+	pub fn from_path(path: &Path) -> ImportFormat {
+		match path.extension().and_then(|extension| extension.to_str()) {
+			Some(extension) if extension.eq_ignore_ascii_case("csv") => ImportFormat::Csv,
+			_ => ImportFormat::Ndjson,
+		}
+	}
+}
+
+/// A bid read from an import file, together with the line it was read from
+#[derive(Debug)]
+pub struct ImportRow {
+	pub line: usize,
+	pub bid: Result<Bid, String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+	Accepted,
+	Rejected,
+	// Same item, user, timestamp and amount as a bid already tracked or imported
+	Duplicate,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ImportResult {
+	pub line: usize,
+	pub status: ImportStatus,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub reason: Option<String>,
+}
+
+/// Outcome of an import, rows are reported in the order of the file
+#[derive(Deserialize, Serialize, Debug, Default, ToSchema)]
+pub struct ImportReport {
+	pub dry_run: bool,
+	pub accepted: usize,
+	pub rejected: usize,
+	pub duplicates: usize,
+	pub rows: Vec<ImportResult>,
+}
+
+impl ImportReport {
+	fn push(&mut self, line: usize, status: ImportStatus, reason: Option<String>) {
+		match status {
+			ImportStatus::Accepted => self.accepted += 1,
+			ImportStatus::Rejected => self.rejected += 1,
+			ImportStatus::Duplicate => self.duplicates += 1,
+		}
+		self.rows.push(ImportResult { line, status, reason });
+	}
+}
+
+/// Parse a csv file with an `itemuuid,useruuid,timestamp,amount` header, or a newline delimited json file.
+/// Malformed rows are kept so they show up in the report.
+pub fn parse_bids(data: &[u8], format: ImportFormat) -> Vec<ImportRow> {
+	match format {
+		ImportFormat::Csv => parse_csv(data),
+		ImportFormat::Ndjson => parse_ndjson(data),
+	}
+}
+
+fn parse_csv(data: &[u8]) -> Vec<ImportRow> {
+	let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(data);
+	let headers = match reader.headers() {
+		Ok(headers) => headers.clone(),
+		Err(e) => {
+			return vec![ImportRow {
+				line: 1,
+				bid: Err(format!("Invalid header: {}", e)),
+			}]
+		}
+	};
+
+	reader
+		.records()
+		.map(|record| match record {
+			Ok(record) => ImportRow {
+				line: record.position().map_or(0, |position| position.line() as usize),
+				bid: record.deserialize(Some(&headers)).map_err(|e| e.to_string()),
+			},
+			Err(e) => ImportRow {
+				line: e.position().map_or(0, |position| position.line() as usize),
+				bid: Err(e.to_string()),
+			},
+		})
+		.collect()
+}
+
+fn parse_ndjson(data: &[u8]) -> Vec<ImportRow> {
+	data.split(|byte| *byte == b'\n')
+		.enumerate()
+		.filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+		.map(|(index, line)| ImportRow {
+			line: index + 1,
+			bid: serde_json::from_slice(line).map_err(|e| e.to_string()),
+		})
+		.collect()
+}
+
+// Rules historical bids have to follow on top of the ones of the tracker
+fn validate(bid: &Bid) -> Result<(), String> {
+	if !bid.amount.is_finite() || bid.amount <= 0f64 {
+		return Err("The amount must be a positive number".into());
+	}
+	if bid.timestamp < 0 {
+		return Err("The timestamp must not be negative".into());
+	}
+	Ok(())
+}
+
+type BidKey = (uuid::Uuid, uuid::Uuid, i64, u64);
+
+fn bid_key(bid: &Bid) -> BidKey {
+	(bid.item_uuid, bid.user_uuid, bid.timestamp, bid.amount.to_bits())
+}
+
+// Bids replayed per lock acquisition, so an import does not hold up live bidding
+const IMPORT_CHUNK_SIZE: usize = 256;
+
+/// Replay the rows in timestamp order, a chunk of bids per call of `lock`. Each chunk is checked with
+/// `check_import` and appended to the audit log as a single entry before it is placed, a chunk whose entry
+/// cannot be appended is rejected as a whole. Imports publish no events.
+/// A dry run only checks the bids, as if the ones accepted before them were placed.
+pub fn import_bids<'a>(
+	mut lock: impl FnMut() -> MutexGuard<'a, BidManagement>,
+	rows: Vec<ImportRow>,
+	dry_run: bool,
+) -> Result<ImportReport, BidTrackerError> {
+	let mut replay = Replay {
+		report: ImportReport {
+			dry_run,
+			..ImportReport::default()
+		},
+		..Replay::default()
+	};
+
+	let mut bids = Vec::with_capacity(rows.len());
+	for row in rows {
+		match row.bid.and_then(|bid| validate(&bid).map(|_| bid)) {
+			Ok(bid) => bids.push((row.line, bid)),
+			Err(reason) => replay.report.push(row.line, ImportStatus::Rejected, Some(reason)),
+		}
+	}
+	// Stable, so bids sharing a timestamp keep the order of the file
+	bids.sort_by_key(|(_, bid)| bid.timestamp);
+
+	// A dry run keeps the bids it accepted pending for the whole import, a real one places them chunk by chunk
+	let mut pending = PendingBids::default();
+	for chunk in bids.chunks(IMPORT_CHUNK_SIZE) {
+		let mut bidtracker = lock();
+		let accepted = replay.check(&bidtracker, chunk, &mut pending);
+		if dry_run {
+			replay.accept(accepted.iter().map(|(line, _)| *line));
+			continue;
+		}
+		let lines: Vec<usize> = accepted.iter().map(|(line, _)| *line).collect();
+		match bidtracker.place_imported(accepted.into_iter().map(|(_, bid)| bid).collect()) {
+			Ok(()) => replay.accept(lines),
+			Err(e) => {
+				for line in lines {
+					replay.report.push(line, ImportStatus::Rejected, Some(e.to_string()));
+				}
+			}
+		}
+		pending = PendingBids::default();
+	}
+
+	replay.report.rows.sort_by_key(|row| row.line);
+	Ok(replay.report)
+}
+
+#[derive(Default)]
+struct Replay {
+	report: ImportReport,
+	seen: HashSet<BidKey>,
+	loaded_items: HashSet<uuid::Uuid>,
+}
+
+impl Replay {
+	// Check the bids of a chunk, reporting the rejected ones and returning the accepted ones with their line
+	fn check(
+		&mut self,
+		bidtracker: &BidManagement,
+		chunk: &[(usize, Bid)],
+		pending: &mut PendingBids,
+	) -> Vec<(usize, Bid)> {
+		let mut accepted = Vec::new();
+		for (line, bid) in chunk {
+			if self.loaded_items.insert(bid.item_uuid) {
+				if let Ok(existing) = bidtracker.get_bids(&bid.item_uuid) {
+					self.seen.extend(existing.iter().map(bid_key));
+				}
+			}
+			if !self.seen.insert(bid_key(bid)) {
+				self.report.push(
+					*line,
+					ImportStatus::Duplicate,
+					Some("The bid was already placed".into()),
+				);
+				continue;
+			}
+			match bidtracker.check_import(bid, pending) {
+				Ok(()) => accepted.push((*line, bid.clone())),
+				Err(e) => self.report.push(*line, ImportStatus::Rejected, Some(e.to_string())),
+			}
+		}
+		accepted
+	}
+
+	fn accept(&mut self, lines: impl IntoIterator<Item = usize>) {
+		for line in lines {
+			self.report.push(line, ImportStatus::Accepted, None);
+		}
+	}
+}
+
+/// Upload a file to the import endpoint of a running server, the bids only live in its memory
+pub async fn upload(
+	server: &str,
+	path: &Path,
+	format: Option<ImportFormat>,
+	dry_run: bool,
+	token: Option<&str>,
+) -> anyhow::Result<ImportReport> {
+	let data = tokio::fs::read(path)
+		.await
+		.context(format!("Failed to read {}", path.display()))?;
+	let format = format.unwrap_or_else(|| ImportFormat::from_path(path));
+	let format = match format {
+		ImportFormat::Csv => "csv",
+		ImportFormat::Ndjson => "ndjson",
+	};
+
+	let url = format!("{}/api/v1{}", server.trim_end_matches('/'), routes::URL_ADMIN_IMPORT);
+	let mut request = reqwest::Client::new()
+		.post(&url)
+		.query(&[("format", format), ("dry_run", &dry_run.to_string())])
+		.body(data);
+	if let Some(token) = token {
+		request = request.bearer_auth(token);
+	}
+	let response = request.send().await.context(format!("Failed to reach {}", url))?;
+	let status = response.status();
+	if !status.is_success() {
+		let body = response.text().await.unwrap_or_default();
+		anyhow::bail!("The import was refused with {}: {}", status, body);
+	}
+	let message: ResponseMessage<ImportReport> = response.json().await?;
+	Ok(message.data)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::Config;
+	use crate::fraud::{FlagQuery, FraudMode, FraudRules};
+	use crate::persistence::now_millis;
+	use crate::webhooks::WebhookStore;
+	use actix_web::{web, App, HttpServer};
+	use std::sync::Mutex;
+
+	const ITEM: &str = "b2f9ee6d-79fe-4b14-9c19-35a69a89219a";
+
+	fn bidmanagement() -> BidManagement {
+		BidManagement::new(vec![uuid::Uuid::parse_str(ITEM).unwrap()])
+	}
+
+	#[test]
+	fn test_parse_bids() {
+		let csv = format!(
+			"itemuuid,useruuid,timestamp,amount\n\
+			{ITEM},1c916ab6-255b-4a36-9574-e456e0f774c9,10,30\n\
+			{ITEM},not-a-uuid,11,31\n"
+		);
+		let rows = parse_bids(csv.as_bytes(), ImportFormat::Csv);
+		assert_eq!(rows.len(), 2);
+		assert_eq!(rows[0].line, 2);
+		assert_eq!(rows[0].bid.as_ref().unwrap().amount, 30f64);
+		assert_eq!(rows[1].line, 3);
+		assert!(rows[1].bid.is_err());
+
+		let ndjson = format!(
+			"{{\"itemuuid\": \"{ITEM}\", \"useruuid\": \"1c916ab6-255b-4a36-9574-e456e0f774c9\", \"timestamp\": 10, \"amount\": 30}}\n\n{{}}\n"
+		);
+		let rows = parse_bids(ndjson.as_bytes(), ImportFormat::Ndjson);
+		assert_eq!(rows.len(), 2);
+		assert!(rows[0].bid.is_ok());
+		assert_eq!(rows[1].line, 3);
+		assert!(rows[1].bid.is_err());
+
+		assert_eq!(ImportFormat::from_path(Path::new("bids.CSV")), ImportFormat::Csv);
+		assert_eq!(ImportFormat::from_path(Path::new("bids.jsonl")), ImportFormat::Ndjson);
+	}
+
+	#[test]
+	fn test_import_bids() {
+		let csv = format!(
+			"itemuuid,useruuid,timestamp,amount\n\
+			{ITEM},1c916ab6-255b-4a36-9574-e456e0f774c9,20,40\n\
+			{ITEM},ae8f7716-867b-4479-b455-c5769e7475ba,10,30\n\
+			7f272d43-0ff2-4e0f-9ebc-589eae48e3ad,ae8f7716-867b-4479-b455-c5769e7475ba,10,30\n\
+			{ITEM},ae8f7716-867b-4479-b455-c5769e7475ba,10,30\n\
+			{ITEM},ae8f7716-867b-4479-b455-c5769e7475ba,12,-3\n"
+		);
+		let bm = Mutex::new(bidmanagement());
+		let mut events = bm.lock().unwrap().subscribe();
+		let import = |dry_run| {
+			import_bids(
+				|| bm.lock().unwrap(),
+				parse_bids(csv.as_bytes(), ImportFormat::Csv),
+				dry_run,
+			)
+		};
+
+		let report = import(true).unwrap();
+		assert!(report.dry_run);
+		assert_eq!((report.accepted, report.rejected, report.duplicates), (2, 2, 1));
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		assert!(bm.lock().unwrap().get_bids(&item_uuid).unwrap().is_empty());
+		assert!(events.try_recv().is_err());
+		assert_eq!(bm.lock().unwrap().audit().head().seq, 0);
+
+		let report = import(false).unwrap();
+		let statuses: Vec<_> = report.rows.iter().map(|row| row.status).collect();
+		assert_eq!(
+			statuses,
+			vec![
+				ImportStatus::Accepted,
+				ImportStatus::Accepted,
+				ImportStatus::Rejected,
+				ImportStatus::Duplicate,
+				ImportStatus::Rejected,
+			]
+		);
+		assert!(report.rows[2]
+			.reason
+			.as_ref()
+			.unwrap()
+			.contains("Requested item does not exist"));
+		// Replayed in timestamp order
+		let bids = bm.lock().unwrap().get_bids(&item_uuid).unwrap();
+		assert_eq!(bids[0].timestamp, 10);
+		assert_eq!(
+			bm.lock().unwrap().current_winning_bid(&item_uuid).unwrap().amount,
+			40f64
+		);
+		// Each chunk is a single entry of the audit log, this import fits in one
+		assert_eq!(bm.lock().unwrap().audit().head().seq, 1);
+
+		// Importing the same file again only yields duplicates, and nothing is audited
+		let report = import(false).unwrap();
+		assert_eq!((report.accepted, report.rejected, report.duplicates), (0, 2, 3));
+		assert_eq!(bm.lock().unwrap().audit().head().seq, 1);
+	}
+
+	#[test]
+	fn test_import_skips_fraud_rules() {
+		let bm = Mutex::new(bidmanagement());
+		let rules = FraudRules {
+			mode: FraudMode::Enforce,
+			velocity_max_bids: 2,
+			..FraudRules::default()
+		};
+		bm.lock().unwrap().fraud_mut().set_rules(rules).unwrap();
+
+		// More bids of a single user than the velocity rule allows, spread over more chunks than one
+		let ndjson: String = (1..=IMPORT_CHUNK_SIZE + 2)
+			.map(|amount| {
+				format!(
+					"{{\"itemuuid\": \"{ITEM}\", \"useruuid\": \"1c916ab6-255b-4a36-9574-e456e0f774c9\", \"timestamp\": {amount}, \"amount\": {amount}}}\n"
+				)
+			})
+			.collect();
+		let report = import_bids(
+			|| bm.lock().unwrap(),
+			parse_bids(ndjson.as_bytes(), ImportFormat::Ndjson),
+			false,
+		)
+		.unwrap();
+		assert_eq!(report.accepted, IMPORT_CHUNK_SIZE + 2);
+		let bdm = bm.lock().unwrap();
+		assert!(bdm.fraud().flagged(&FlagQuery::default()).is_empty());
+		// One audit entry per chunk
+		assert_eq!(bdm.audit().head().seq, 2);
+	}
+
+	#[test]
+	fn test_import_queues_no_webhook_delivery() {
+		let bm = Mutex::new(bidmanagement());
+		let store = WebhookStore::open(None).unwrap();
+		store.register("http://localhost/hook".into(), vec![], "secret".into());
+		let mut events = bm.lock().unwrap().subscribe();
+
+		let ndjson = format!(
+			"{{\"itemuuid\": \"{ITEM}\", \"useruuid\": \"1c916ab6-255b-4a36-9574-e456e0f774c9\", \"timestamp\": 10, \"amount\": 30}}\n"
+		);
+		let report = import_bids(
+			|| bm.lock().unwrap(),
+			parse_bids(ndjson.as_bytes(), ImportFormat::Ndjson),
+			false,
+		)
+		.unwrap();
+		assert_eq!(report.accepted, 1);
+		while let Ok(event) = events.try_recv() {
+			store.enqueue(&event, now_millis());
+		}
+		assert!(store.pending().is_empty());
+
+		// A live bid does get delivered
+		let bid = Bid {
+			item_uuid: uuid::Uuid::parse_str(ITEM).unwrap(),
+			user_uuid: uuid::Uuid::new_v4(),
+			timestamp: now_millis(),
+			amount: 40f64,
+		};
+		bm.lock().unwrap().insert_bid(&bid).unwrap();
+		while let Ok(event) = events.try_recv() {
+			store.enqueue(&event, now_millis());
+		}
+		assert!(!store.pending().is_empty());
+	}
+
+	#[actix_rt::test]
+	async fn test_upload() {
+		let bidmanagement = web::Data::new(Mutex::new(bidmanagement()));
+		let app_bidmanagement = bidmanagement.clone();
+		let server = HttpServer::new(move || {
+			App::new()
+				.app_data(app_bidmanagement.clone())
+				.app_data(web::Data::new(Config {
+					import_max_body_bytes: 4096,
+					..Config::default()
+				}))
+				.route("/api/v1/admin/import", web::post().to(crate::api::import::post_import))
+		})
+		.workers(1)
+		.bind("127.0.0.1:0")
+		.unwrap();
+		let addr = server.addrs()[0];
+		actix_rt::spawn(server.run());
+
+		let path = std::env::temp_dir().join(format!("bids-{}.csv", uuid::Uuid::new_v4()));
+		std::fs::write(
+			&path,
+			format!("itemuuid,useruuid,timestamp,amount\n{ITEM},1c916ab6-255b-4a36-9574-e456e0f774c9,10,30\n"),
+		)
+		.unwrap();
+
+		let server = format!("http://{}/", addr);
+		let report = upload(&server, &path, None, true, None).await.unwrap();
+		assert!(report.dry_run);
+		assert_eq!(report.accepted, 1);
+
+		let report = upload(&server, &path, None, false, None).await.unwrap();
+		assert_eq!(report.accepted, 1);
+		let item_uuid = uuid::Uuid::parse_str(ITEM).unwrap();
+		assert_eq!(bidmanagement.lock().unwrap().get_bids(&item_uuid).unwrap().len(), 1);
+
+		std::fs::remove_file(&path).unwrap();
+		assert!(upload(&server, &path, None, false, None).await.is_err());
+	}
+}
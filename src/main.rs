@@ -20,74 +20,236 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-mod api;
-mod bidtracker;
-mod config;
-mod errors;
-
 use actix_web::{middleware, web, App, HttpServer};
-use api::custom_error_handler;
+use bid_tracker_rs::{api, bidtracker, import};
 
 use anyhow::{self, Context};
+use api::AppState;
+use bid_tracker_rs::api_keys::ApiKeyStore;
+use bid_tracker_rs::audit::{self, AuditLog};
+use bid_tracker_rs::auth::JwtKeys;
+use bid_tracker_rs::config::Config;
+use bid_tracker_rs::fraud::{FraudEngine, FraudMode, FraudRules};
+use bid_tracker_rs::grpc::GrpcBidTracker;
+use bid_tracker_rs::idempotency::IdempotencyStore;
+use bid_tracker_rs::metrics::lock_traced;
+use bid_tracker_rs::notifications::NotificationService;
+use bid_tracker_rs::rate_limit::{Limit, RateLimiter, RateLimits};
+use bid_tracker_rs::telemetry::Telemetry;
+use bid_tracker_rs::webhooks::{RetryPolicy, WebhookDispatcher, WebhookStore};
 use bidtracker::BidTracker;
-use config::Config;
-use std::{env, sync::Mutex};
-use tracing_subscriber::{self, EnvFilter};
-
-async fn spawn_server(
-	config: &Config,
-	bidtracker: web::Data<Mutex<bidtracker::BidManagement>>,
-) -> Result<(), std::io::Error> {
+use clap::{Parser, Subcommand};
+use std::{
+	env,
+	path::PathBuf,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+use tracing_subscriber::{self, prelude::*, EnvFilter};
+
+// Name of the audit log in the data directory
+const AUDIT_LOG: &str = "audit.log";
+
+/// Track the bids placed on auction items
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+	#[command(subcommand)]
+	command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// Run the server, the default when no command is given
+	Serve,
+	/// Import a csv or ndjson file of historical bids into a running server
+	Import {
+		file: PathBuf,
+		/// Validate the bids without placing them
+		#[arg(long)]
+		dry_run: bool,
+		/// Format of the file, guessed from its extension by default
+		#[arg(long, value_enum)]
+		format: Option<import::ImportFormat>,
+		/// Base url of the server to import into
+		#[arg(long, default_value = "http://localhost:3000")]
+		server: String,
+		/// Bearer token of an admin, required when the server has authentication enabled
+		#[arg(long)]
+		token: Option<String>,
+	},
+	/// Verify the hash chain of an audit log and report its first broken link
+	VerifyAudit {
+		/// The audit log, `audit.log` in the data directory by default
+		file: Option<PathBuf>,
+	},
+}
+
+async fn spawn_server(address: &str, state: AppState) -> Result<(), std::io::Error> {
 	HttpServer::new(move || {
 		App::new()
-			.app_data(bidtracker.clone())
-			.app_data(web::PathConfig::default().error_handler(custom_error_handler::uuid_error_handler))
-			.app_data(web::JsonConfig::default().error_handler(custom_error_handler::json_error_handler))
-			.wrap(middleware::Logger::default())
+			.configure(|cfg| state.configure(cfg))
+			.wrap(middleware::Logger::new(
+				r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{X-Request-Id}o"#,
+			))
 			.wrap(middleware::Compress::default())
 			.service(web::resource("/healthz").route(web::get().to(|| async { "Healthy bruh" })))
-			.service(
-				web::scope("/api/v1")
-					.route(api::routes::URL_BID_ITEM, web::post().to(api::post_bid_new))
-					.route(api::routes::URL_BID_GET_ALL, web::get().to(api::get_bids))
-					.route(
-						api::routes::URL_BID_GET_WINNING,
-						web::get().to(api::get_current_winning_bid),
-					)
-					.route(api::routes::URL_USER_GET_ALL_BIDS, web::get().to(api::get_user_bids)),
-			)
 	})
-	.bind(&config.address)?
+	.bind(address)?
 	.run()
 	.await
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+	let cli = Cli::parse();
+
+	let config = Config::new();
+
+	if env::var_os("RUST_LOG").is_none() {
+		env::set_var("RUST_LOG", "bid_tracker_rs=info");
+	}
+	let telemetry =
+		Telemetry::from_config(&config).map_err(|e| anyhow::anyhow!("Failed to set up the tracing: {}", e))?;
+	tracing_subscriber::registry()
+		.with(EnvFilter::from_default_env())
+		.with(tracing_subscriber::fmt::layer())
+		.with(telemetry.layer())
+		.init();
+
+	let exporting = telemetry.clone();
 	ctrlc::set_handler(move || {
 		use std::process::exit;
+		exporting.shutdown();
 		exit(0);
 	})
 	.expect("Error setting Ctrl-C handler");
 
-	if env::var_os("RUST_LOG").is_none() {
-		env::set_var("RUST_LOG", "bid_tracker_rs=info");
+	match cli.command {
+		Some(Command::Import {
+			file,
+			dry_run,
+			format,
+			server,
+			token,
+		}) => {
+			let report = import::upload(&server, &file, format, dry_run, token.as_deref()).await?;
+			println!("{}", serde_json::to_string_pretty(&report)?);
+			return Ok(());
+		}
+		Some(Command::VerifyAudit { file }) => {
+			let file = file
+				.or_else(|| config.data_file(AUDIT_LOG))
+				.context("No audit log given and BID_TRACKER_DATA_DIR is not set")?;
+			let verification = audit::verify_file(&file)
+				.map_err(|e| anyhow::anyhow!("Failed to read the audit log {}: {}", file.display(), e))?;
+			println!("{}", serde_json::to_string_pretty(&verification)?);
+			if let Some(broken) = verification.broken_link {
+				anyhow::bail!("The audit log is broken at entry {}: {}", broken.seq, broken.reason);
+			}
+			return Ok(());
+		}
+		Some(Command::Serve) | None => {}
 	}
-	tracing_subscriber::fmt()
-		.with_env_filter(EnvFilter::from_default_env())
-		.init();
-
-	let config = Config::new();
 
 	let biddable_items = vec![
 		uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap(),
 		uuid::Uuid::parse_str("b16ab43e-aa13-4079-b8c5-592e81312c01").unwrap(),
 	];
-	let bidmanagement = web::Data::new(Mutex::new(bidtracker::BidManagement::new(biddable_items)));
+	if !config.data_dir.is_empty() {
+		std::fs::create_dir_all(&config.data_dir)
+			.context(format!("Failed to create the data directory {}", &config.data_dir))?;
+	}
+	let audit_log = AuditLog::open(config.data_file(AUDIT_LOG))
+		.map_err(|e| anyhow::anyhow!("Failed to open the audit log: {}", e))?;
+
+	let default_credit_limit = (config.default_credit_limit > 0.0).then_some(config.default_credit_limit);
+	let fraud_rules = FraudRules {
+		mode: FraudMode::parse(&config.fraud_mode).map_err(|e| anyhow::anyhow!("{}", e))?,
+		..FraudRules::default()
+	};
+	let bidmanagement = bidtracker::BidManagement::new(biddable_items)
+		.with_default_credit_limit(default_credit_limit)
+		.with_fraud_engine(FraudEngine::new(fraud_rules).map_err(|e| anyhow::anyhow!("{}", e))?)
+		.with_audit_log(audit_log);
+	let bidmanagement = web::Data::new(Mutex::new(bidmanagement));
+
+	let webhook_store = WebhookStore::open(config.data_file("webhooks.json"))
+		.map_err(|e| anyhow::anyhow!("Failed to load the webhook state: {}", e))?;
+	let webhook_store = Arc::new(webhook_store);
+	let retry_policy = RetryPolicy {
+		max_attempts: config.webhook_max_attempts,
+		base_delay: Duration::from_millis(config.webhook_retry_base_delay_ms),
+	};
+	WebhookDispatcher::new(
+		webhook_store.clone(),
+		retry_policy,
+		Duration::from_secs(config.webhook_timeout_secs),
+	)
+	.spawn(lock_traced(&bidmanagement).subscribe());
+
+	let notifications = NotificationService::from_config(&config)
+		.map_err(|e| anyhow::anyhow!("Failed to set up the notifications: {}", e))?;
+	let notifications = Arc::new(notifications);
+	notifications.clone().spawn(lock_traced(&bidmanagement).subscribe());
+
+	let idempotency = IdempotencyStore::new(Duration::from_secs(config.idempotency_key_ttl_secs));
+
+	let api_keys = ApiKeyStore::open(config.data_file("api_keys.json"))
+		.map_err(|e| anyhow::anyhow!("Failed to load the api keys: {}", e))?;
+	let api_keys = Arc::new(api_keys);
+
+	let rate_limiter = RateLimiter::new(RateLimits {
+		user: Limit::from_settings(config.rate_limit_user_burst, config.rate_limit_user_per_sec),
+		api_key: Limit::from_settings(config.rate_limit_api_key_burst, config.rate_limit_api_key_per_sec),
+		ip: Limit::from_settings(config.rate_limit_ip_burst, config.rate_limit_ip_per_sec),
+		overrides: Default::default(),
+	})
+	.map_err(|e| anyhow::anyhow!("Invalid rate limits: {}", e))?;
+	let rate_limiter = Arc::new(rate_limiter);
+
+	let jwt_keys =
+		Arc::new(JwtKeys::from_config(&config).map_err(|e| anyhow::anyhow!("Failed to load the jwt keys: {}", e))?);
+	if !jwt_keys.is_enabled() {
+		tracing::warn!("No jwt keys are configured, authentication is disabled");
+	}
+
+	if !config.grpc_address.is_empty() {
+		let grpc_address = config
+			.grpc_address
+			.parse()
+			.context(format!("Invalid gRPC address {}", &config.grpc_address))?;
+		let service = GrpcBidTracker::new(bidmanagement.clone().into_inner())
+			.with_jwt_keys(jwt_keys.clone())
+			.with_api_keys(api_keys.clone())
+			.with_rate_limiter(rate_limiter.clone());
+		tracing::info!("Spawning gRPC server on {}", &config.grpc_address);
+		tokio::spawn(async move {
+			if let Err(e) = service.serve(grpc_address).await {
+				tracing::error!("The gRPC server stopped: {}", e);
+			}
+		});
+	}
+
 	tracing::info!("Spawning server on {}", &config.address);
-	spawn_server(&config, bidmanagement)
+	let address = config.address.clone();
+	let state = AppState {
+		bidtracker: bidmanagement,
+		config: web::Data::new(config),
+		webhook_store: web::Data::from(webhook_store),
+		notifications: web::Data::from(notifications),
+		idempotency: web::Data::new(idempotency),
+		jwt_keys: web::Data::from(jwt_keys),
+		api_keys: web::Data::from(api_keys),
+		rate_limiter: web::Data::from(rate_limiter),
+	};
+	if telemetry.is_enabled() {
+		tracing::info!("Exporting the trace spans to {}", &state.config.otlp_endpoint);
+	}
+	spawn_server(&address, state)
 		.await
-		.context(format!("Failed to launch the server on {}", &config.address))?;
+		.context(format!("Failed to launch the server on {}", &address))?;
+	tokio::task::spawn_blocking(move || telemetry.shutdown()).await?;
 
 	Ok(())
 }
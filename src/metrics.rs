@@ -0,0 +1,454 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Prometheus metrics. The tracker owns a `Metrics` and shares it with every front, `render` writes the
+//! text exposition format served at `/metrics`. The metric names are part of the api, see `METRIC_NAMES`.
+
+use crate::bidtracker::BlockedBidCounter;
+use crate::errors::BidTrackerError;
+use crate::rate_limit::RateLimitCounter;
+use futures_util::{Stream, StreamExt};
+use std::{
+	collections::BTreeMap,
+	fmt::Write,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Mutex, MutexGuard,
+	},
+	time::{Duration, Instant},
+};
+
+// Upper bounds of the request latency buckets, in seconds
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// Upper bounds of the lock wait buckets, in seconds
+const LOCK_WAIT_BUCKETS: &[f64] = &[0.000_01, 0.000_1, 0.001, 0.01, 0.1, 1.0];
+
+/// Every metric `render` writes, with its type
+pub const METRIC_NAMES: &[(&str, &str)] = &[
+	("bidtracker_bids_accepted_total", "counter"),
+	("bidtracker_bids_rejected_total", "counter"),
+	("bidtracker_bids_blocked_total", "counter"),
+	("bidtracker_rate_limit_requests_total", "counter"),
+	("bidtracker_http_request_duration_seconds", "histogram"),
+	("bidtracker_tracker_lock_wait_seconds", "histogram"),
+	("bidtracker_open_items", "gauge"),
+	("bidtracker_bids", "gauge"),
+	("bidtracker_subscribers", "gauge"),
+];
+
+/// How events reach a subscriber
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+	Websocket,
+	Graphql,
+	Grpc,
+}
+
+impl Transport {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Transport::Websocket => "websocket",
+			Transport::Graphql => "graphql",
+			Transport::Grpc => "grpc",
+		}
+	}
+
+	const ALL: [Transport; 3] = [Transport::Websocket, Transport::Graphql, Transport::Grpc];
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+	bounds: &'static [f64],
+	// Cumulative, like the buckets of the exposition format
+	buckets: Vec<u64>,
+	sum: f64,
+	count: u64,
+}
+
+impl Histogram {
+	fn new(bounds: &'static [f64]) -> Histogram {
+		Histogram {
+			bounds,
+			buckets: vec![0; bounds.len()],
+			sum: 0.0,
+			count: 0,
+		}
+	}
+
+	fn observe(&mut self, value: f64) {
+		for (bound, bucket) in self.bounds.iter().zip(self.buckets.iter_mut()) {
+			if value <= *bound {
+				*bucket += 1;
+			}
+		}
+		self.sum += value;
+		self.count += 1;
+	}
+}
+
+/// The state of the tracker at the time of a scrape
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackerStats {
+	pub open_items: u64,
+	pub bids: u64,
+	pub blocked_bids: Vec<BlockedBidCounter>,
+}
+
+/// Metrics collects what happens in between two scrapes, clones of its `Arc` share the counts
+#[derive(Debug)]
+pub struct Metrics {
+	bids_accepted: AtomicU64,
+	// By problem code
+	bids_rejected: Mutex<BTreeMap<&'static str, u64>>,
+	// By method and route pattern
+	requests: Mutex<BTreeMap<(String, String), Histogram>>,
+	lock_wait: Mutex<Histogram>,
+	subscribers: [AtomicU64; 3],
+}
+
+impl Default for Metrics {
+	fn default() -> Self {
+		Metrics {
+			bids_accepted: AtomicU64::new(0),
+			bids_rejected: Mutex::default(),
+			requests: Mutex::default(),
+			lock_wait: Mutex::new(Histogram::new(LOCK_WAIT_BUCKETS)),
+			subscribers: Default::default(),
+		}
+	}
+}
+
+impl Metrics {
+	/// Count the outcome of placing a bid
+	pub fn count_bid(&self, result: &Result<(), BidTrackerError>) {
+		match result {
+			Ok(()) => {
+				self.bids_accepted.fetch_add(1, Ordering::Relaxed);
+			}
+			Err(e) => *self.bids_rejected.lock().unwrap().entry(e.code()).or_default() += 1,
+		}
+	}
+
+	/// Time a request to a route pattern, `None` for requests no route matched
+	pub fn observe_request(&self, method: &str, route: Option<&str>, elapsed: Duration) {
+		let key = (method.to_string(), route.unwrap_or("unmatched").to_string());
+		self.requests
+			.lock()
+			.unwrap()
+			.entry(key)
+			.or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+			.observe(elapsed.as_secs_f64());
+	}
+
+	/// Lock `mutex`, recording how long that took
+	pub fn lock<'a, T>(&self, mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+		let started = Instant::now();
+		let guard = lock_traced(mutex);
+		self.lock_wait.lock().unwrap().observe(started.elapsed().as_secs_f64());
+		guard
+	}
+
+	/// Count a subscriber until the returned guard is dropped
+	pub fn subscriber(self: &Arc<Self>, transport: Transport) -> Subscriber {
+		self.subscribers[transport as usize].fetch_add(1, Ordering::Relaxed);
+		Subscriber {
+			metrics: self.clone(),
+			transport,
+		}
+	}
+
+	/// Write every metric of `METRIC_NAMES` in the Prometheus text exposition format
+	pub fn render(&self, tracker: &TrackerStats, rate_limits: &[RateLimitCounter]) -> String {
+		let mut out = String::new();
+
+		family(&mut out, "bidtracker_bids_accepted_total", "Bids placed on an item");
+		sample(
+			&mut out,
+			"bidtracker_bids_accepted_total",
+			&[],
+			self.bids_accepted.load(Ordering::Relaxed),
+		);
+
+		family(
+			&mut out,
+			"bidtracker_bids_rejected_total",
+			"Bids the tracker refused, by problem code",
+		);
+		for (reason, count) in self.bids_rejected.lock().unwrap().iter() {
+			sample(&mut out, "bidtracker_bids_rejected_total", &[("reason", reason)], count);
+		}
+
+		family(
+			&mut out,
+			"bidtracker_bids_blocked_total",
+			"Bids turned away by the blocklist and the allowlists",
+		);
+		for counter in &tracker.blocked_bids {
+			let labels = [("reason", counter.reason.as_str())];
+			sample(&mut out, "bidtracker_bids_blocked_total", &labels, counter.count);
+		}
+
+		family(
+			&mut out,
+			"bidtracker_rate_limit_requests_total",
+			"Bid requests checked against the rate limits",
+		);
+		for counter in rate_limits {
+			for (outcome, count) in [("allowed", counter.allowed), ("limited", counter.limited)] {
+				let labels = [("client", counter.kind.as_str()), ("outcome", outcome)];
+				sample(&mut out, "bidtracker_rate_limit_requests_total", &labels, count);
+			}
+		}
+
+		family(
+			&mut out,
+			"bidtracker_http_request_duration_seconds",
+			"Latency of the api requests, by route",
+		);
+		for ((method, route), histogram) in self.requests.lock().unwrap().iter() {
+			let labels = [("method", method.as_str()), ("route", route.as_str())];
+			write_histogram(&mut out, "bidtracker_http_request_duration_seconds", &labels, histogram);
+		}
+
+		family(
+			&mut out,
+			"bidtracker_tracker_lock_wait_seconds",
+			"Time spent waiting for the tracker lock",
+		);
+		let lock_wait = self.lock_wait.lock().unwrap().clone();
+		write_histogram(&mut out, "bidtracker_tracker_lock_wait_seconds", &[], &lock_wait);
+
+		family(&mut out, "bidtracker_open_items", "Items open for bidding");
+		sample(&mut out, "bidtracker_open_items", &[], tracker.open_items);
+
+		family(&mut out, "bidtracker_bids", "Bids held in memory");
+		sample(&mut out, "bidtracker_bids", &[], tracker.bids);
+
+		family(
+			&mut out,
+			"bidtracker_subscribers",
+			"Clients subscribed to events, by transport",
+		);
+		for transport in Transport::ALL {
+			let count = self.subscribers[transport as usize].load(Ordering::Relaxed);
+			sample(
+				&mut out,
+				"bidtracker_subscribers",
+				&[("transport", transport.as_str())],
+				count,
+			);
+		}
+		out
+	}
+}
+
+/// A counted subscriber, see `Metrics::subscriber`
+#[derive(Debug)]
+pub struct Subscriber {
+	metrics: Arc<Metrics>,
+	transport: Transport,
+}
+
+impl Subscriber {
+	/// Keep the subscriber counted for as long as `stream` lives
+	pub fn attach<S: Stream>(self, stream: S) -> impl Stream<Item = S::Item> {
+		stream.map(move |item| {
+			let _counted = &self;
+			item
+		})
+	}
+}
+
+impl Drop for Subscriber {
+	fn drop(&mut self) {
+		self.metrics.subscribers[self.transport as usize].fetch_sub(1, Ordering::Relaxed);
+	}
+}
+
+/// Lock `mutex` in a `bidtracker.lock` span, where the metrics to time the wait on are not known yet
+pub fn lock_traced<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	let _span = tracing::info_span!("bidtracker.lock").entered();
+	mutex.lock().unwrap()
+}
+
+fn type_of(name: &str) -> &'static str {
+	METRIC_NAMES
+		.iter()
+		.find(|(known, _)| *known == name)
+		.map(|(_, kind)| *kind)
+		.unwrap_or("untyped")
+}
+
+fn family(out: &mut String, name: &str, help: &str) {
+	let _ = writeln!(out, "# HELP {} {}", name, help);
+	let _ = writeln!(out, "# TYPE {} {}", name, type_of(name));
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+	if labels.is_empty() {
+		let _ = writeln!(out, "{} {}", name, value);
+		return;
+	}
+	let labels: Vec<_> = labels
+		.iter()
+		.map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+		.collect();
+	let _ = writeln!(out, "{}{{{}}} {}", name, labels.join(","), value);
+}
+
+fn write_histogram(out: &mut String, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+	let bucket = format!("{}_bucket", name);
+	for (bound, count) in histogram.bounds.iter().zip(&histogram.buckets) {
+		let le = bound.to_string();
+		sample(out, &bucket, &[labels, &[("le", le.as_str())]].concat(), count);
+	}
+	sample(out, &bucket, &[labels, &[("le", "+Inf")]].concat(), histogram.count);
+	sample(out, &format!("{}_sum", name), labels, histogram.sum);
+	sample(out, &format!("{}_count", name), labels, histogram.count);
+}
+
+fn escape(value: &str) -> String {
+	value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bidtracker::BlockReason;
+	use crate::rate_limit::ClientKind;
+
+	fn types(rendered: &str) -> Vec<(String, String)> {
+		rendered
+			.lines()
+			.filter_map(|line| line.strip_prefix("# TYPE "))
+			.map(|line| {
+				let (name, kind) = line.split_once(' ').unwrap();
+				(name.to_string(), kind.to_string())
+			})
+			.collect()
+	}
+
+	#[test]
+	fn test_metric_names() {
+		// Dashboards and alerts depend on these names, renaming one is a breaking change
+		let expected = vec![
+			("bidtracker_bids_accepted_total", "counter"),
+			("bidtracker_bids_rejected_total", "counter"),
+			("bidtracker_bids_blocked_total", "counter"),
+			("bidtracker_rate_limit_requests_total", "counter"),
+			("bidtracker_http_request_duration_seconds", "histogram"),
+			("bidtracker_tracker_lock_wait_seconds", "histogram"),
+			("bidtracker_open_items", "gauge"),
+			("bidtracker_bids", "gauge"),
+			("bidtracker_subscribers", "gauge"),
+		];
+		let rendered = Metrics::default().render(&TrackerStats::default(), &[]);
+		let expected: Vec<_> = expected
+			.into_iter()
+			.map(|(name, kind)| (name.to_string(), kind.to_string()))
+			.collect();
+		assert_eq!(types(&rendered), expected);
+	}
+
+	#[test]
+	fn test_render() {
+		let metrics = Arc::new(Metrics::default());
+		metrics.count_bid(&Ok(()));
+		metrics.count_bid(&Err(BidTrackerError::ItemNotBiddable("closed".into())));
+		metrics.count_bid(&Err(BidTrackerError::ItemNotBiddable("closed".into())));
+		metrics.observe_request("POST", Some("/api/v1/bids"), Duration::from_millis(3));
+		metrics.observe_request("GET", None, Duration::from_secs(20));
+		drop(metrics.lock(&Mutex::new(())));
+		let subscriber = metrics.subscriber(Transport::Websocket);
+
+		let tracker = TrackerStats {
+			open_items: 2,
+			bids: 5,
+			blocked_bids: vec![BlockedBidCounter {
+				reason: BlockReason::NotInvited,
+				count: 1,
+			}],
+		};
+		let rate_limits = [RateLimitCounter {
+			kind: ClientKind::Ip,
+			allowed: 4,
+			limited: 1,
+		}];
+		let rendered = metrics.render(&tracker, &rate_limits);
+		for line in [
+			"bidtracker_bids_accepted_total 1",
+			"bidtracker_bids_rejected_total{reason=\"item_not_biddable\"} 2",
+			"bidtracker_bids_blocked_total{reason=\"not_invited\"} 1",
+			"bidtracker_rate_limit_requests_total{client=\"ip\",outcome=\"limited\"} 1",
+			"bidtracker_http_request_duration_seconds_bucket{method=\"POST\",route=\"/api/v1/bids\",le=\"0.001\"} 0",
+			"bidtracker_http_request_duration_seconds_bucket{method=\"POST\",route=\"/api/v1/bids\",le=\"0.005\"} 1",
+			"bidtracker_http_request_duration_seconds_bucket{method=\"GET\",route=\"unmatched\",le=\"+Inf\"} 1",
+			"bidtracker_http_request_duration_seconds_count{method=\"GET\",route=\"unmatched\"} 1",
+			"bidtracker_tracker_lock_wait_seconds_count 1",
+			"bidtracker_open_items 2",
+			"bidtracker_bids 5",
+			"bidtracker_subscribers{transport=\"websocket\"} 1",
+			"bidtracker_subscribers{transport=\"grpc\"} 0",
+		] {
+			assert!(rendered.lines().any(|rendered| rendered == line), "{} is missing", line);
+		}
+
+		drop(subscriber);
+		let rendered = metrics.render(&tracker, &rate_limits);
+		assert!(rendered.contains("bidtracker_subscribers{transport=\"websocket\"} 0"));
+	}
+
+	#[test]
+	fn test_escape() {
+		assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+	}
+
+	/// Keeps the names of the spans opened while it is the subscriber
+	#[derive(Clone, Default)]
+	struct SpanNames(Arc<Mutex<Vec<&'static str>>>);
+
+	impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanNames {
+		fn on_new_span(
+			&self,
+			attrs: &tracing::span::Attributes<'_>,
+			_: &tracing::span::Id,
+			_: tracing_subscriber::layer::Context<'_, S>,
+		) {
+			self.0.lock().unwrap().push(attrs.metadata().name());
+		}
+	}
+
+	#[test]
+	fn test_lock_span() {
+		use tracing_subscriber::prelude::*;
+
+		let names = SpanNames::default();
+		let subscriber = tracing_subscriber::registry().with(names.clone());
+		let mutex = Mutex::new(());
+		tracing::subscriber::with_default(subscriber, || {
+			drop(Metrics::default().lock(&mutex));
+			drop(lock_traced(&mutex));
+		});
+		assert_eq!(*names.0.lock().unwrap(), vec!["bidtracker.lock", "bidtracker.lock"]);
+	}
+}
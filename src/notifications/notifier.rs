@@ -0,0 +1,269 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::bidtracker::Bid;
+use crate::errors::BidTrackerError;
+use crate::webhooks;
+use futures_util::future::BoxFuture;
+use lettre::{message::Mailbox, transport::smtp::AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Tells a user that their winning bid on an item was outbid
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Notification {
+	#[serde(rename = "useruuid")]
+	pub user_uuid: uuid::Uuid,
+	#[serde(rename = "itemuuid")]
+	pub item_uuid: uuid::Uuid,
+	// The user's bid which lost the lead
+	pub outbid: Bid,
+	pub winning_bid: Bid,
+	// Address of the user, only used by the email notifier
+	#[serde(skip)]
+	pub email: Option<String>,
+}
+
+impl Notification {
+	pub fn text(&self) -> String {
+		format!(
+			"Your bid of {} on item {} was outbid, the winning bid is now {}.",
+			self.outbid.amount, self.item_uuid, self.winning_bid.amount
+		)
+	}
+}
+
+/// A channel through which outbid notifications reach users
+pub trait Notifier: Send + Sync {
+	fn name(&self) -> &'static str;
+	fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), BidTrackerError>>;
+}
+
+/// LogNotifier only writes the notifications to the log
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+	fn name(&self) -> &'static str {
+		"log"
+	}
+
+	fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), BidTrackerError>> {
+		Box::pin(async move {
+			tracing::info!("Notifying user {}: {}", notification.user_uuid, notification.text());
+			Ok(())
+		})
+	}
+}
+
+/// WebhookNotifier POSTs every notification to a single url, signed like the event webhooks
+pub struct WebhookNotifier {
+	url: String,
+	secret: String,
+	client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+	pub fn new(url: String, secret: String, timeout: Duration) -> WebhookNotifier {
+		let client = reqwest::Client::builder()
+			.timeout(timeout)
+			.build()
+			.expect("Failed to build the notification http client");
+		WebhookNotifier { url, secret, client }
+	}
+}
+
+impl Notifier for WebhookNotifier {
+	fn name(&self) -> &'static str {
+		"webhook"
+	}
+
+	fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), BidTrackerError>> {
+		Box::pin(async move {
+			let body = serde_json::to_vec(notification)?;
+			let response = self
+				.client
+				.post(&self.url)
+				.header(reqwest::header::CONTENT_TYPE, "application/json")
+				.header(webhooks::HEADER_SIGNATURE, webhooks::sign(&self.secret, &body))
+				.header(webhooks::HEADER_EVENT, "outbid")
+				.body(body)
+				.send()
+				.await
+				.map_err(|e| BidTrackerError::Notification(e.to_string()))?;
+
+			if response.status().is_success() {
+				Ok(())
+			} else {
+				Err(BidTrackerError::Notification(format!(
+					"Webhook responded with {}",
+					response.status()
+				)))
+			}
+		})
+	}
+}
+
+/// EmailNotifier sends notifications over SMTP to users who registered an address
+pub struct EmailNotifier {
+	from: Mailbox,
+	transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl EmailNotifier {
+	pub fn new(host: &str, port: u16, from: &str) -> Result<EmailNotifier, BidTrackerError> {
+		let from = from
+			.parse()
+			.map_err(|e| BidTrackerError::InvalidRequest(format!("Invalid sender address {}: {}", from, e)))?;
+		// The relay is expected to be a local MTA, hence no TLS
+		let transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+			.port(port)
+			.build();
+		Ok(EmailNotifier { from, transport })
+	}
+}
+
+impl Notifier for EmailNotifier {
+	fn name(&self) -> &'static str {
+		"email"
+	}
+
+	fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), BidTrackerError>> {
+		Box::pin(async move {
+			let to = match &notification.email {
+				Some(email) => email
+					.parse()
+					.map_err(|e| BidTrackerError::Notification(format!("Invalid address {}: {}", email, e)))?,
+				None => {
+					tracing::debug!("User {} has no email address, skipping", notification.user_uuid);
+					return Ok(());
+				}
+			};
+			let message = Message::builder()
+				.from(self.from.clone())
+				.to(to)
+				.subject("You have been outbid")
+				.body(notification.text())
+				.map_err(|e| BidTrackerError::Notification(e.to_string()))?;
+
+			self.transport
+				.send(message)
+				.await
+				.map_err(|e| BidTrackerError::Notification(e.to_string()))?;
+			Ok(())
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio::{
+		io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+		net::TcpListener,
+	};
+
+	fn notification(email: Option<&str>) -> Notification {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		Notification {
+			user_uuid: uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap(),
+			item_uuid,
+			outbid: Bid {
+				item_uuid,
+				amount: 30f64,
+				..Bid::default()
+			},
+			winning_bid: Bid {
+				item_uuid,
+				amount: 35f64,
+				..Bid::default()
+			},
+			email: email.map(String::from),
+		}
+	}
+
+	// Local stand-in for an SMTP relay, returns the DATA of the first message it receives
+	async fn smtp_stand_in(listener: TcpListener) -> String {
+		let (stream, _) = listener.accept().await.unwrap();
+		let (reader, mut writer) = stream.into_split();
+		let mut lines = BufReader::new(reader).lines();
+		writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+
+		let mut data = String::new();
+		let mut in_data = false;
+		while let Some(line) = lines.next_line().await.unwrap() {
+			if in_data {
+				if line == "." {
+					in_data = false;
+					writer.write_all(b"250 OK\r\n").await.unwrap();
+				} else {
+					data.push_str(&line);
+					data.push('\n');
+				}
+				continue;
+			}
+			let reply: &[u8] = match line
+				.split_whitespace()
+				.next()
+				.unwrap_or_default()
+				.to_uppercase()
+				.as_str()
+			{
+				"EHLO" | "HELO" => b"250 localhost\r\n",
+				"DATA" => {
+					in_data = true;
+					b"354 End data with <CR><LF>.<CR><LF>\r\n"
+				}
+				"QUIT" => {
+					writer.write_all(b"221 Bye\r\n").await.unwrap();
+					break;
+				}
+				_ => b"250 OK\r\n",
+			};
+			writer.write_all(reply).await.unwrap();
+		}
+		data
+	}
+
+	#[tokio::test]
+	async fn test_log_notifier() {
+		assert!(LogNotifier.notify(&notification(None)).await.is_ok());
+	}
+
+	#[tokio::test]
+	async fn test_email_notifier() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let received = tokio::spawn(smtp_stand_in(listener));
+
+		let notifier = EmailNotifier::new("127.0.0.1", port, "bid-tracker@localhost").unwrap();
+		// Users without an address are silently skipped
+		notifier.notify(&notification(None)).await.unwrap();
+		notifier.notify(&notification(Some("bidder@localhost"))).await.unwrap();
+
+		let data = received.await.unwrap();
+		assert!(data.contains("To: bidder@localhost"));
+		assert!(data.contains("Subject: You have been outbid"));
+		assert!(data.contains("winning bid is now 35"));
+
+		assert!(EmailNotifier::new("127.0.0.1", port, "not an address").is_err());
+	}
+}
@@ -0,0 +1,344 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{EmailNotifier, LogNotifier, Notification, Notifier, WebhookNotifier};
+use crate::bidtracker::BidEvent;
+use crate::config::Config;
+use crate::errors::BidTrackerError;
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::{BTreeSet, HashMap},
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use utoipa::ToSchema;
+
+/// How a user wants to be notified
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, ToSchema)]
+pub struct UserPreferences {
+	pub email: Option<String>,
+	// Items the user opted out of, every other item is notified
+	pub muted_items: BTreeSet<uuid::Uuid>,
+}
+
+/// NotificationService tells users through every configured `Notifier` when they are outbid
+pub struct NotificationService {
+	notifiers: Vec<Box<dyn Notifier>>,
+	cooldown: Duration,
+	preferences: Mutex<HashMap<uuid::Uuid, UserPreferences>>,
+	// Last time a user was notified about an item, within the cool-down
+	last_sent: Mutex<HashMap<(uuid::Uuid, uuid::Uuid), Instant>>,
+}
+
+impl NotificationService {
+	pub fn new(notifiers: Vec<Box<dyn Notifier>>, cooldown: Duration) -> NotificationService {
+		NotificationService {
+			notifiers,
+			cooldown,
+			preferences: Mutex::new(HashMap::new()),
+			last_sent: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Build the service with the notifiers enabled in the config
+	pub fn from_config(config: &Config) -> Result<NotificationService, BidTrackerError> {
+		let mut notifiers: Vec<Box<dyn Notifier>> = vec![];
+		for name in config
+			.notifiers
+			.split(',')
+			.map(str::trim)
+			.filter(|name| !name.is_empty())
+		{
+			match name {
+				"log" => notifiers.push(Box::new(LogNotifier)),
+				"webhook" => {
+					if config.notification_webhook_url.is_empty() {
+						return Err(BidTrackerError::InvalidConfig(
+							"The webhook notifier needs a notification_webhook_url".into(),
+						));
+					}
+					notifiers.push(Box::new(WebhookNotifier::new(
+						config.notification_webhook_url.clone(),
+						config.notification_webhook_secret.clone(),
+						Duration::from_secs(config.webhook_timeout_secs),
+					)));
+				}
+				"email" => {
+					if config.smtp_host.is_empty() {
+						return Err(BidTrackerError::InvalidConfig(
+							"The email notifier needs a smtp_host".into(),
+						));
+					}
+					notifiers.push(Box::new(EmailNotifier::new(
+						&config.smtp_host,
+						config.smtp_port,
+						&config.smtp_from,
+					)?));
+				}
+				other => return Err(BidTrackerError::InvalidConfig(format!("Unknown notifier {}", other))),
+			}
+		}
+		Ok(NotificationService::new(
+			notifiers,
+			Duration::from_secs(config.notification_cooldown_secs),
+		))
+	}
+
+	pub fn preferences(&self, user_uuid: &uuid::Uuid) -> UserPreferences {
+		let preferences = self.preferences.lock().unwrap();
+		preferences.get(user_uuid).cloned().unwrap_or_default()
+	}
+
+	pub fn set_email(&self, user_uuid: &uuid::Uuid, email: Option<String>) -> UserPreferences {
+		let mut preferences = self.preferences.lock().unwrap();
+		let user = preferences.entry(*user_uuid).or_default();
+		user.email = email;
+		user.clone()
+	}
+
+	/// Opt a user in or out of the notifications for an item
+	pub fn set_item_enabled(&self, user_uuid: &uuid::Uuid, item_uuid: &uuid::Uuid, enabled: bool) -> UserPreferences {
+		let mut preferences = self.preferences.lock().unwrap();
+		let user = preferences.entry(*user_uuid).or_default();
+		if enabled {
+			user.muted_items.remove(item_uuid);
+		} else {
+			user.muted_items.insert(*item_uuid);
+		}
+		user.clone()
+	}
+
+	/// Build the outbid notification for an event, unless the user opted out
+	/// or was already notified about the item within the cool-down period
+	pub fn outbid_notification(&self, event: &BidEvent, now: Instant) -> Option<Notification> {
+		let (item_uuid, bid, previous) = match event {
+			BidEvent::WinningBidChanged {
+				item_uuid,
+				bid,
+				previous: Some(previous),
+			} => (item_uuid, bid, previous),
+			_ => return None,
+		};
+		// Raising your own winning bid does not outbid you
+		if previous.user_uuid == bid.user_uuid {
+			return None;
+		}
+
+		let preferences = self.preferences(&previous.user_uuid);
+		if preferences.muted_items.contains(item_uuid) {
+			return None;
+		}
+
+		let mut last_sent = self.last_sent.lock().unwrap();
+		let key = (previous.user_uuid, *item_uuid);
+		if let Some(sent) = last_sent.get(&key) {
+			if now.duration_since(*sent) < self.cooldown {
+				return None;
+			}
+		}
+		// Entries past the cool-down no longer hold anything back
+		last_sent.retain(|_, sent| now.saturating_duration_since(*sent) < self.cooldown);
+		last_sent.insert(key, now);
+
+		Some(Notification {
+			user_uuid: previous.user_uuid,
+			item_uuid: *item_uuid,
+			outbid: previous.clone(),
+			winning_bid: bid.clone(),
+			email: preferences.email,
+		})
+	}
+
+	/// Hand the notification to every notifier, failures are only logged
+	pub async fn dispatch(&self, notification: &Notification) {
+		for notifier in &self.notifiers {
+			if let Err(e) = notifier.notify(notification).await {
+				tracing::warn!(
+					"Notifier {} failed to notify user {}: {}",
+					notifier.name(),
+					notification.user_uuid,
+					e
+				);
+			}
+		}
+	}
+
+	/// Spawn the background task which turns tracker events into notifications
+	pub fn spawn(self: Arc<Self>, mut events: broadcast::Receiver<BidEvent>) {
+		tokio::spawn(async move {
+			loop {
+				match events.recv().await {
+					Ok(event) => {
+						if let Some(notification) = self.outbid_notification(&event, Instant::now()) {
+							self.dispatch(&notification).await;
+						}
+					}
+					Err(RecvError::Lagged(skipped)) => {
+						tracing::warn!("Notification service lagged behind, skipped {} events", skipped);
+					}
+					Err(RecvError::Closed) => break,
+				}
+			}
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bidtracker::{Bid, BidManagement, BidTracker};
+	use crate::errors::BidTrackerError;
+	use futures_util::future::BoxFuture;
+
+	#[derive(Clone, Default)]
+	struct RecordingNotifier {
+		sent: Arc<Mutex<Vec<Notification>>>,
+	}
+
+	impl Notifier for RecordingNotifier {
+		fn name(&self) -> &'static str {
+			"recording"
+		}
+
+		fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), BidTrackerError>> {
+			self.sent.lock().unwrap().push(notification.clone());
+			Box::pin(async { Ok(()) })
+		}
+	}
+
+	fn outbid(previous_user: uuid::Uuid, user: uuid::Uuid) -> BidEvent {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		BidEvent::WinningBidChanged {
+			item_uuid,
+			bid: Bid {
+				item_uuid,
+				user_uuid: user,
+				amount: 31f64,
+				..Bid::default()
+			},
+			previous: Some(Bid {
+				item_uuid,
+				user_uuid: previous_user,
+				amount: 30f64,
+				..Bid::default()
+			}),
+		}
+	}
+
+	#[test]
+	fn test_outbid_notification() {
+		let service = NotificationService::new(vec![], Duration::from_secs(60));
+		let alice = uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap();
+		let bob = uuid::Uuid::parse_str("215248b5-8402-4211-93c0-9f71a93e69a9").unwrap();
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let now = Instant::now();
+
+		// Outbidding yourself is not worth a notification
+		assert!(service.outbid_notification(&outbid(alice, alice), now).is_none());
+
+		service.set_email(&alice, Some("alice@localhost".into()));
+		let notification = service.outbid_notification(&outbid(alice, bob), now).unwrap();
+		assert_eq!(notification.user_uuid, alice);
+		assert_eq!(notification.email.as_deref(), Some("alice@localhost"));
+
+		// De-duplicated within the cool-down
+		assert!(service
+			.outbid_notification(&outbid(alice, bob), now + Duration::from_secs(30))
+			.is_none());
+		assert!(service
+			.outbid_notification(&outbid(alice, bob), now + Duration::from_secs(61))
+			.is_some());
+
+		// Opted out of the item
+		service.set_item_enabled(&alice, &item_uuid, false);
+		assert!(service
+			.outbid_notification(&outbid(alice, bob), now + Duration::from_secs(200))
+			.is_none());
+		service.set_item_enabled(&alice, &item_uuid, true);
+		assert!(service
+			.outbid_notification(&outbid(alice, bob), now + Duration::from_secs(200))
+			.is_some());
+
+		// Notifying bob drops the entry of alice, whose cool-down is over
+		assert!(service
+			.outbid_notification(&outbid(bob, alice), now + Duration::from_secs(300))
+			.is_some());
+		let last_sent = service.last_sent.lock().unwrap();
+		assert_eq!(last_sent.keys().collect::<Vec<_>>(), vec![&(bob, item_uuid)]);
+	}
+
+	#[test]
+	fn test_from_config() {
+		let mut config = Config {
+			notifiers: "log, webhook".into(),
+			..Config::default()
+		};
+		assert!(NotificationService::from_config(&config).is_err());
+
+		config.notification_webhook_url = "http://localhost/notify".into();
+		let service = NotificationService::from_config(&config).unwrap();
+		let names: Vec<_> = service.notifiers.iter().map(|notifier| notifier.name()).collect();
+		assert_eq!(names, vec!["log", "webhook"]);
+
+		config.notifiers = "email".into();
+		assert!(NotificationService::from_config(&config).is_err());
+		config.notifiers = "pigeon".into();
+		assert!(NotificationService::from_config(&config).is_err());
+	}
+
+	#[tokio::test]
+	async fn test_notifications_from_tracker_events() {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let alice = uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap();
+		let bob = uuid::Uuid::parse_str("215248b5-8402-4211-93c0-9f71a93e69a9").unwrap();
+		let mut bm = BidManagement::new(vec![item_uuid]);
+
+		let recorder = RecordingNotifier::default();
+		let service = Arc::new(NotificationService::new(
+			vec![Box::new(recorder.clone())],
+			Duration::from_secs(60),
+		));
+		service.spawn(bm.subscribe());
+
+		for (user_uuid, amount) in [(alice, 30f64), (bob, 31f64), (alice, 32f64), (bob, 33f64)] {
+			bm.insert_bid(&Bid {
+				item_uuid,
+				user_uuid,
+				amount,
+				timestamp: 0,
+			})
+			.unwrap();
+		}
+
+		for _ in 0..100 {
+			if recorder.sent.lock().unwrap().len() == 2 {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		let sent = recorder.sent.lock().unwrap();
+		assert_eq!(sent.len(), 2);
+		assert_eq!(sent[0].user_uuid, alice);
+		assert_eq!(sent[1].user_uuid, bob);
+	}
+}
@@ -0,0 +1,72 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::errors::BidTrackerError;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+	fs,
+	path::Path,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Current unix timestamp in milliseconds, the unit of every timestamp in the state files
+pub fn now_millis() -> i64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_millis() as i64)
+		.unwrap_or_default()
+}
+
+/// Read a json state file, falling back to the default state if it does not exist yet
+pub fn read_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T, BidTrackerError> {
+	if !path.exists() {
+		return Ok(T::default());
+	}
+	Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Write a json state file through a rename, so a crash never leaves a partial file behind
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), BidTrackerError> {
+	let tmp = path.with_extension("tmp");
+	fs::write(&tmp, serde_json::to_vec(value)?)?;
+	fs::rename(&tmp, path)?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+
+	#[test]
+	fn test_read_write_json() {
+		let path = std::env::temp_dir().join(format!("state-{}.json", uuid::Uuid::new_v4()));
+		let state: HashMap<String, u32> = read_json(&path).unwrap();
+		assert!(state.is_empty());
+
+		write_json(&path, &HashMap::from([("bids".to_string(), 2)])).unwrap();
+		let state: HashMap<String, u32> = read_json(&path).unwrap();
+		assert_eq!(state["bids"], 2);
+		assert!(!path.with_extension("tmp").exists());
+		fs::remove_file(path).unwrap();
+	}
+}
@@ -0,0 +1,168 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Role based access control. `POLICY` decides which role may do what, and every denial is logged
+//! to the audit trail, the `audit` tracing target.
+
+use crate::api_keys::Scope;
+use crate::errors::BidTrackerError;
+
+/// Target of the audit trail, e.g. `RUST_LOG=info,audit=info`
+pub const AUDIT_TARGET: &str = "audit";
+
+/// The roles a bearer token can carry in its `roles` claim, every user is a bidder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+	Bidder,
+	Seller,
+	Auctioneer,
+	Admin,
+}
+
+impl Role {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Role::Bidder => "bidder",
+			Role::Seller => "seller",
+			Role::Auctioneer => "auctioneer",
+			Role::Admin => "admin",
+		}
+	}
+
+	/// The role named `role`, `None` for roles of other applications
+	pub fn parse(role: &str) -> Option<Role> {
+		[Role::Bidder, Role::Seller, Role::Auctioneer, Role::Admin]
+			.into_iter()
+			.find(|known| known.as_str() == role)
+	}
+}
+
+/// What a caller asks to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+	/// Place a bid for a user
+	PlaceBid,
+	/// Read the bids, items and notification settings of a user
+	ReadUser,
+	/// Put an item up for auction for a seller
+	CreateItem,
+	/// Close the bidding on an item
+	CloseItem,
+	/// Manage webhooks, imports and api keys, export the bids of every user
+	Administer,
+}
+
+impl Action {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Action::PlaceBid => "place_bid",
+			Action::ReadUser => "read_user",
+			Action::CreateItem => "create_item",
+			Action::CloseItem => "close_item",
+			Action::Administer => "administer",
+		}
+	}
+
+	/// The scope an api key needs for the action, api keys act on behalf of everybody
+	pub fn api_key_scope(&self) -> Scope {
+		match self {
+			Action::PlaceBid => Scope::BidOnBehalf,
+			Action::ReadUser => Scope::Read,
+			Action::CreateItem | Action::CloseItem | Action::Administer => Scope::Admin,
+		}
+	}
+}
+
+/// How far a grant of `POLICY` reaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reach {
+	/// Only what the caller owns: its own bids and data, and the items it sells
+	Own,
+	/// Everything
+	Any,
+}
+
+/// Which role may do what, everything not listed is denied
+pub static POLICY: &[(Role, Action, Reach)] = &[
+	(Role::Bidder, Action::PlaceBid, Reach::Own),
+	(Role::Bidder, Action::ReadUser, Reach::Own),
+	(Role::Seller, Action::CreateItem, Reach::Own),
+	(Role::Seller, Action::CloseItem, Reach::Own),
+	(Role::Auctioneer, Action::ReadUser, Reach::Any),
+	(Role::Auctioneer, Action::CloseItem, Reach::Any),
+	(Role::Admin, Action::ReadUser, Reach::Any),
+	(Role::Admin, Action::CreateItem, Reach::Any),
+	(Role::Admin, Action::CloseItem, Reach::Any),
+	(Role::Admin, Action::Administer, Reach::Any),
+];
+
+/// Whether `subject` with `roles` may do `action` on something owned by `owner`, `None` if nobody owns it
+pub fn is_allowed(roles: &[Role], subject: &uuid::Uuid, action: Action, owner: Option<&uuid::Uuid>) -> bool {
+	POLICY.iter().any(|(role, allowed, reach)| {
+		*allowed == action && roles.contains(role) && (*reach == Reach::Any || owner == Some(subject))
+	})
+}
+
+/// Log a denied action, an `Action` or a route, to the audit trail
+pub fn audit_denial(subject: &str, action: &str, error: &BidTrackerError) {
+	tracing::warn!(
+		target: AUDIT_TARGET,
+		subject,
+		action,
+		code = error.code(),
+		"Denied: {}",
+		error
+	);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_is_allowed() {
+		let user = uuid::Uuid::new_v4();
+		let other = uuid::Uuid::new_v4();
+
+		assert!(is_allowed(&[Role::Bidder], &user, Action::PlaceBid, Some(&user)));
+		assert!(!is_allowed(&[Role::Bidder], &user, Action::PlaceBid, Some(&other)));
+		// Not even admins bid on behalf of somebody else
+		assert!(!is_allowed(&[Role::Admin], &user, Action::PlaceBid, Some(&other)));
+
+		// Only the seller of an item, auctioneers and admins close it
+		assert!(is_allowed(&[Role::Seller], &user, Action::CloseItem, Some(&user)));
+		assert!(!is_allowed(&[Role::Seller], &user, Action::CloseItem, Some(&other)));
+		assert!(!is_allowed(&[Role::Seller], &user, Action::CloseItem, None));
+		assert!(is_allowed(&[Role::Auctioneer], &user, Action::CloseItem, None));
+		assert!(!is_allowed(&[Role::Bidder], &user, Action::CloseItem, Some(&user)));
+
+		assert!(is_allowed(&[Role::Auctioneer], &user, Action::ReadUser, Some(&other)));
+		assert!(!is_allowed(&[Role::Auctioneer], &user, Action::Administer, None));
+		assert!(is_allowed(&[Role::Admin], &user, Action::Administer, None));
+	}
+
+	#[test]
+	fn test_parse_role() {
+		assert_eq!(Role::parse("auctioneer"), Some(Role::Auctioneer));
+		assert_eq!(Role::parse("offline_access"), None);
+	}
+}
@@ -0,0 +1,474 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Token bucket rate limits of bid placement, per user, per api key and per client ip.
+
+use crate::auth::Caller;
+use crate::errors::BidTrackerError;
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::HashMap,
+	fmt,
+	net::IpAddr,
+	sync::{Arc, Mutex, RwLock},
+	time::{Duration, Instant},
+};
+use utoipa::ToSchema;
+
+// Idle buckets are dropped once there are more than these many
+const MAX_BUCKETS: usize = 100_000;
+
+/// A token bucket, holding up to `burst` tokens and refilled with `per_sec` tokens every second
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+pub struct Limit {
+	pub burst: u32,
+	pub per_sec: f64,
+}
+
+impl Limit {
+	/// The limit of a `burst` and `per_sec` pair of settings, none if either is zero
+	pub fn from_settings(burst: u32, per_sec: f64) -> Option<Limit> {
+		(burst > 0 && per_sec > 0.0).then_some(Limit { burst, per_sec })
+	}
+
+	fn validate(&self) -> Result<(), BidTrackerError> {
+		if self.burst == 0 || !self.per_sec.is_finite() || self.per_sec <= 0.0 {
+			return Err(BidTrackerError::InvalidRequest(format!(
+				"A limit needs a positive burst and rate, got {:?}",
+				self
+			)));
+		}
+		Ok(())
+	}
+}
+
+/// The limits of every kind of client, `None` disables the limit
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, ToSchema)]
+pub struct RateLimits {
+	pub user: Option<Limit>,
+	pub api_key: Option<Limit>,
+	pub ip: Option<Limit>,
+	// Limits of single clients in place of the one of their kind, keyed like `user:<useruuid>`,
+	// `api_key:<keyuuid>` or `ip:<address>`
+	#[serde(default)]
+	pub overrides: HashMap<String, Limit>,
+}
+
+impl RateLimits {
+	fn validate(&self) -> Result<(), BidTrackerError> {
+		for limit in [&self.user, &self.api_key, &self.ip].into_iter().flatten() {
+			limit.validate()?;
+		}
+		for (client, limit) in &self.overrides {
+			if !["user:", "api_key:", "ip:"].iter().any(|kind| client.starts_with(kind)) {
+				return Err(BidTrackerError::InvalidRequest(format!(
+					"Unknown kind of client {}",
+					client
+				)));
+			}
+			limit.validate()?;
+		}
+		Ok(())
+	}
+
+	fn limit(&self, client: &ClientKey) -> Option<Limit> {
+		if let Some(limit) = self.overrides.get(&client.to_string()) {
+			return Some(*limit);
+		}
+		match client.kind {
+			ClientKind::User => self.user,
+			ClientKind::ApiKey => self.api_key,
+			ClientKind::Ip => self.ip,
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientKind {
+	User,
+	ApiKey,
+	Ip,
+}
+
+impl ClientKind {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ClientKind::User => "user",
+			ClientKind::ApiKey => "api_key",
+			ClientKind::Ip => "ip",
+		}
+	}
+}
+
+/// A client a bucket is kept for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClientKey {
+	pub kind: ClientKind,
+	pub id: String,
+}
+
+impl fmt::Display for ClientKey {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}", self.kind.as_str(), self.id)
+	}
+}
+
+/// The clients a request is limited as: the user or api key of the caller, and the ip it came from
+pub fn client_keys(caller: &Caller, ip: Option<IpAddr>) -> Vec<ClientKey> {
+	let mut clients = Vec::with_capacity(2);
+	match caller {
+		Caller::User(claims) => clients.push(ClientKey {
+			kind: ClientKind::User,
+			id: claims.sub.to_string(),
+		}),
+		Caller::Service(api_key) => clients.push(ClientKey {
+			kind: ClientKind::ApiKey,
+			id: api_key.id.to_string(),
+		}),
+		Caller::Unrestricted | Caller::Anonymous => {}
+	}
+	if let Some(ip) = ip {
+		clients.push(ClientKey {
+			kind: ClientKind::Ip,
+			id: ip.to_string(),
+		});
+	}
+	clients
+}
+
+/// The outcome of a check against the tightest bucket of a request, sent as the `RateLimit-*` headers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decision {
+	pub allowed: bool,
+	pub limit: u32,
+	pub remaining: u32,
+	// Until the bucket is full again
+	pub reset: Duration,
+	// Until the next request is allowed, zero if this one was
+	pub retry_after: Duration,
+}
+
+impl Decision {
+	pub fn into_result(self) -> Result<(), BidTrackerError> {
+		if self.allowed {
+			return Ok(());
+		}
+		Err(BidTrackerError::RateLimited {
+			retry_after_secs: self.retry_after.as_secs_f64().ceil() as u64,
+		})
+	}
+}
+
+/// How many requests of a kind of client were allowed and limited
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+pub struct RateLimitCounter {
+	pub kind: ClientKind,
+	pub allowed: u64,
+	pub limited: u64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+	limit: Limit,
+	tokens: f64,
+	updated: Instant,
+}
+
+impl Bucket {
+	fn refill(&mut self, limit: Limit, now: Instant) {
+		let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * self.limit.per_sec).min(limit.burst as f64);
+		self.limit = limit;
+		self.updated = now;
+	}
+
+	fn decision(&self, allowed: bool, cost: f64) -> Decision {
+		let missing = |tokens: f64| Duration::from_secs_f64(tokens.max(0.0) / self.limit.per_sec);
+		Decision {
+			allowed,
+			limit: self.limit.burst,
+			remaining: self.tokens.floor() as u32,
+			reset: missing(self.limit.burst as f64 - self.tokens),
+			retry_after: if allowed {
+				Duration::ZERO
+			} else {
+				missing(cost - self.tokens)
+			},
+		}
+	}
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+	buckets: HashMap<ClientKey, Bucket>,
+	counters: HashMap<ClientKind, RateLimitCounter>,
+}
+
+/// RateLimiter keeps a token bucket for every client, the limits can be replaced at runtime
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+	limits: RwLock<RateLimits>,
+	buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+	pub fn new(limits: RateLimits) -> Result<RateLimiter, BidTrackerError> {
+		limits.validate()?;
+		Ok(RateLimiter {
+			limits: RwLock::new(limits),
+			buckets: Mutex::default(),
+		})
+	}
+
+	pub fn limits(&self) -> RateLimits {
+		self.limits.read().unwrap().clone()
+	}
+
+	/// Replace the limits, the buckets keep their tokens up to the new burst
+	pub fn set_limits(&self, limits: RateLimits) -> Result<(), BidTrackerError> {
+		limits.validate()?;
+		*self.limits.write().unwrap() = limits;
+		Ok(())
+	}
+
+	/// Take a token from the bucket of every client, or none if one of them is empty.
+	/// Returns the decision of the tightest bucket, `None` if no limit applies to any of the clients.
+	pub fn check(&self, clients: &[ClientKey], now: Instant) -> Option<Decision> {
+		self.check_tokens(clients, 1, now)
+	}
+
+	/// Same as `check`, taking `tokens` tokens from every bucket or none if one of them holds fewer.
+	/// A bucket never holds more than its burst, so asking for more is always limited.
+	pub fn check_tokens(&self, clients: &[ClientKey], tokens: u32, now: Instant) -> Option<Decision> {
+		let cost = tokens as f64;
+		let limits = self.limits.read().unwrap();
+		let mut state = self.buckets.lock().unwrap();
+		let Buckets { buckets, counters } = &mut *state;
+
+		let limited: Vec<_> = clients
+			.iter()
+			.filter_map(|client| limits.limit(client).map(|limit| (client, limit)))
+			.collect();
+		if limited.is_empty() {
+			return None;
+		}
+		if buckets.len() > MAX_BUCKETS {
+			buckets.retain(|client, bucket| {
+				let limit = limits.limit(client).unwrap_or(bucket.limit);
+				bucket.refill(limit, now);
+				bucket.tokens < limit.burst as f64
+			});
+		}
+
+		for (client, limit) in &limited {
+			buckets
+				.entry((*client).clone())
+				.or_insert_with(|| Bucket {
+					limit: *limit,
+					tokens: limit.burst as f64,
+					updated: now,
+				})
+				.refill(*limit, now);
+		}
+		let allowed = limited.iter().all(|(client, _)| buckets[*client].tokens >= cost);
+
+		let mut tightest: Option<Decision> = None;
+		for (client, _) in &limited {
+			let bucket = buckets.get_mut(*client).unwrap();
+			if allowed {
+				bucket.tokens -= cost;
+			}
+			let decision = bucket.decision(allowed, cost);
+			let counter = counters.entry(client.kind).or_insert(RateLimitCounter {
+				kind: client.kind,
+				allowed: 0,
+				limited: 0,
+			});
+			if allowed {
+				counter.allowed += 1;
+			} else if bucket.tokens < cost {
+				counter.limited += 1;
+			}
+			let tighter = match &tightest {
+				None => true,
+				Some(current) if allowed => decision.remaining < current.remaining,
+				Some(current) => decision.retry_after > current.retry_after,
+			};
+			if tighter {
+				tightest = Some(decision);
+			}
+		}
+		tightest
+	}
+
+	/// Same as `check` for callers which only need the verdict
+	pub fn acquire(&self, clients: &[ClientKey]) -> Result<(), BidTrackerError> {
+		match self.check(clients, Instant::now()) {
+			Some(decision) => decision.into_result(),
+			None => Ok(()),
+		}
+	}
+
+	/// The counters of every kind of client which was limited so far, sorted by kind
+	pub fn counters(&self) -> Vec<RateLimitCounter> {
+		let state = self.buckets.lock().unwrap();
+		let mut counters: Vec<_> = state.counters.values().copied().collect();
+		counters.sort_by_key(|counter| counter.kind.as_str());
+		counters
+	}
+}
+
+/// The buckets of a single client which places many bids over one connection, like a websocket session
+#[derive(Debug, Clone)]
+pub struct ClientLimiter {
+	pub limiter: Arc<RateLimiter>,
+	pub clients: Vec<ClientKey>,
+}
+
+impl ClientLimiter {
+	pub fn acquire(&self) -> Result<(), BidTrackerError> {
+		self.limiter.acquire(&self.clients)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::auth::Claims;
+
+	fn user() -> ClientKey {
+		ClientKey {
+			kind: ClientKind::User,
+			id: "1c916ab6-255b-4a36-9574-e456e0f774c9".into(),
+		}
+	}
+
+	fn ip(address: &str) -> ClientKey {
+		ClientKey {
+			kind: ClientKind::Ip,
+			id: address.into(),
+		}
+	}
+
+	#[test]
+	fn test_token_bucket() {
+		let limits = RateLimits {
+			user: Limit::from_settings(2, 1.0),
+			ip: Limit::from_settings(5, 10.0),
+			..RateLimits::default()
+		};
+		let limiter = RateLimiter::new(limits).unwrap();
+		let clients = [user(), ip("10.0.0.1")];
+		let start = Instant::now();
+
+		// The user bucket is the tightest one
+		let decision = limiter.check(&clients, start).unwrap();
+		assert!(decision.allowed);
+		assert_eq!((decision.limit, decision.remaining), (2, 1));
+		assert!(limiter.check(&clients, start).unwrap().allowed);
+		let decision = limiter.check(&clients, start).unwrap();
+		assert!(!decision.allowed);
+		assert_eq!(decision.retry_after, Duration::from_secs(1));
+		assert!(matches!(
+			decision.into_result(),
+			Err(BidTrackerError::RateLimited { retry_after_secs: 1 })
+		));
+
+		// A rejected request takes no token from the other buckets
+		let decision = limiter.check(&[ip("10.0.0.1")], start).unwrap();
+		assert_eq!((decision.limit, decision.remaining), (5, 2));
+
+		let decision = limiter.check(&clients, start + Duration::from_millis(1500)).unwrap();
+		assert!(decision.allowed);
+		assert_eq!(decision.remaining, 0);
+
+		assert_eq!(
+			limiter.counters(),
+			vec![
+				RateLimitCounter {
+					kind: ClientKind::Ip,
+					allowed: 4,
+					limited: 0
+				},
+				RateLimitCounter {
+					kind: ClientKind::User,
+					allowed: 3,
+					limited: 1
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn test_limits() {
+		let limiter = RateLimiter::default();
+		let now = Instant::now();
+		assert_eq!(limiter.check(&[user(), ip("10.0.0.1")], now), None);
+
+		let mut limits = RateLimits {
+			ip: Limit::from_settings(1, 1.0),
+			..RateLimits::default()
+		};
+		limits
+			.overrides
+			.insert("ip:10.0.0.2".into(), Limit { burst: 3, per_sec: 1.0 });
+		limiter.set_limits(limits.clone()).unwrap();
+		assert_eq!(limiter.limits(), limits);
+		assert_eq!(limiter.check(&[ip("10.0.0.1")], now).unwrap().limit, 1);
+		assert_eq!(limiter.check(&[ip("10.0.0.2")], now).unwrap().limit, 3);
+		assert_eq!(limiter.check(&[user()], now), None);
+
+		assert_eq!(Limit::from_settings(0, 1.0), None);
+		assert_eq!(Limit::from_settings(1, 0.0), None);
+		let invalid = RateLimits {
+			user: Some(Limit {
+				burst: 1,
+				per_sec: -1.0,
+			}),
+			..RateLimits::default()
+		};
+		assert!(limiter.set_limits(invalid).is_err());
+		let mut unknown = RateLimits::default();
+		unknown
+			.overrides
+			.insert("host:example.org".into(), Limit { burst: 1, per_sec: 1.0 });
+		assert!(matches!(
+			limiter.set_limits(unknown),
+			Err(BidTrackerError::InvalidRequest(_))
+		));
+		assert_eq!(limiter.limits(), limits);
+	}
+
+	#[test]
+	fn test_client_keys() {
+		let caller = Caller::User(Claims {
+			sub: uuid::Uuid::parse_str("1c916ab6-255b-4a36-9574-e456e0f774c9").unwrap(),
+			exp: u64::MAX,
+			roles: vec![],
+		});
+		let clients = client_keys(&caller, Some([10, 0, 0, 1].into()));
+		assert_eq!(clients, vec![user(), ip("10.0.0.1")]);
+		assert_eq!(clients[1].to_string(), "ip:10.0.0.1");
+		assert!(client_keys(&Caller::Anonymous, None).is_empty());
+	}
+}
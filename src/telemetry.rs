@@ -0,0 +1,219 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Distributed tracing. The `tracing` spans of the handlers, the tracker lock and the `BidTracker` calls are
+//! exported over OTLP when an endpoint is configured, joined to the trace of the caller through its W3C
+//! `traceparent` header.
+
+use crate::config::Config;
+use crate::errors::BidTrackerError;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::Context;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::collections::HashMap;
+use tracing::Subscriber;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Header carrying the trace and parent span of a request
+pub const TRACEPARENT: &str = "traceparent";
+/// Header carrying vendor specific trace state along with `traceparent`
+pub const TRACESTATE: &str = "tracestate";
+
+/// Exports the spans to an OTLP collector, does nothing if no endpoint is configured
+#[derive(Clone, Default)]
+pub struct Telemetry {
+	provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+	/// Export to the `otlp_endpoint` of the config, if any
+	pub fn from_config(config: &Config) -> Result<Telemetry, BidTrackerError> {
+		if config.otlp_endpoint.is_empty() {
+			Ok(Telemetry::default())
+		} else {
+			Telemetry::export_to(&config.otlp_endpoint, &config.otlp_service_name)
+		}
+	}
+
+	/// Export in batches to the OTLP/gRPC collector at `endpoint`, must be called within a tokio runtime
+	pub fn export_to(endpoint: &str, service_name: &str) -> Result<Telemetry, BidTrackerError> {
+		let exporter = opentelemetry_otlp::SpanExporter::builder()
+			.with_tonic()
+			.with_endpoint(endpoint)
+			.build()
+			.map_err(|e| BidTrackerError::InvalidConfig(format!("Invalid OTLP endpoint {}: {}", endpoint, e)))?;
+		let provider = SdkTracerProvider::builder()
+			.with_batch_exporter(exporter)
+			.with_resource(Resource::builder().with_service_name(service_name.to_string()).build())
+			.build();
+		Ok(Telemetry {
+			provider: Some(provider),
+		})
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.provider.is_some()
+	}
+
+	/// The layer turning `tracing` spans into exported spans, None if nothing is exported
+	pub fn layer<S>(&self) -> Option<OpenTelemetryLayer<S, SdkTracer>>
+	where
+		S: Subscriber + for<'span> LookupSpan<'span>,
+	{
+		let tracer = self.provider.as_ref()?.tracer(env!("CARGO_PKG_NAME"));
+		Some(tracing_opentelemetry::layer().with_tracer(tracer))
+	}
+
+	/// Export the spans still queued and stop exporting, blocks until the collector answered
+	pub fn shutdown(&self) {
+		if let Some(provider) = &self.provider {
+			if let Err(e) = provider.shutdown() {
+				tracing::warn!("Failed to flush the spans: {}", e);
+			}
+		}
+	}
+}
+
+/// The remote span described by the `traceparent` and `tracestate` headers, an empty context if they are
+/// missing or malformed
+pub fn remote_context(traceparent: Option<&str>, tracestate: Option<&str>) -> Context {
+	let mut carrier = HashMap::new();
+	for (key, value) in [(TRACEPARENT, traceparent), (TRACESTATE, tracestate)] {
+		if let Some(value) = value {
+			carrier.insert(key.to_string(), value.to_string());
+		}
+	}
+	TraceContextPropagator::new().extract(&carrier)
+}
+
+/// Continue the trace of the caller in `span`
+pub fn set_remote_parent(span: &tracing::Span, traceparent: Option<&str>, tracestate: Option<&str>) {
+	if traceparent.is_some() {
+		// Fails only if no spans are exported
+		let _ = span.set_parent(remote_context(traceparent, tracestate));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use opentelemetry::trace::TraceContextExt;
+	use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{TraceService, TraceServiceServer};
+	use opentelemetry_proto::tonic::collector::trace::v1::{ExportTraceServiceRequest, ExportTraceServiceResponse};
+	use opentelemetry_proto::tonic::trace::v1::Span;
+	use std::sync::{Arc, Mutex};
+	use tracing_subscriber::layer::SubscriberExt;
+
+	const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+	const PARENT_ID: &str = "00f067aa0ba902b7";
+
+	fn traceparent() -> String {
+		format!("00-{}-{}-01", TRACE_ID, PARENT_ID)
+	}
+
+	/// Stands in for an OTLP collector, keeping the spans it received
+	#[derive(Clone, Default)]
+	struct Collector {
+		spans: Arc<Mutex<Vec<Span>>>,
+	}
+
+	#[tonic::async_trait]
+	impl TraceService for Collector {
+		async fn export(
+			&self,
+			request: tonic::Request<ExportTraceServiceRequest>,
+		) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+			let spans = request
+				.into_inner()
+				.resource_spans
+				.into_iter()
+				.flat_map(|resource| resource.scope_spans)
+				.flat_map(|scope| scope.spans);
+			self.spans.lock().unwrap().extend(spans);
+			Ok(tonic::Response::new(ExportTraceServiceResponse {
+				partial_success: None,
+			}))
+		}
+	}
+
+	#[test]
+	fn test_remote_context() {
+		let cx = remote_context(Some(&traceparent()), Some("vendor=value"));
+		let span = cx.span();
+		let remote = span.span_context();
+		assert!(remote.is_valid() && remote.is_remote() && remote.is_sampled());
+		assert_eq!(remote.trace_id().to_string(), TRACE_ID);
+		assert_eq!(remote.span_id().to_string(), PARENT_ID);
+		assert_eq!(remote.trace_state().get("vendor"), Some("value"));
+
+		for traceparent in [
+			None,
+			Some("garbage"),
+			Some("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+		] {
+			assert!(!remote_context(traceparent, None).span().span_context().is_valid());
+		}
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_export() {
+		let collector = Collector::default();
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let endpoint = format!("http://{}", listener.local_addr().unwrap());
+		tokio::spawn(
+			tonic::transport::Server::builder()
+				.add_service(TraceServiceServer::new(collector.clone()))
+				.serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+		);
+
+		assert!(!Telemetry::default().is_enabled());
+		let telemetry = Telemetry::export_to(&endpoint, "bid-tracker-test").unwrap();
+		assert!(telemetry.is_enabled());
+		let subscriber = tracing_subscriber::registry().with(telemetry.layer());
+		tracing::subscriber::with_default(subscriber, || {
+			let request = tracing::info_span!("request");
+			set_remote_parent(&request, Some(&traceparent()), None);
+			request.in_scope(|| tracing::info_span!("bidtracker.insert_bid").in_scope(|| {}));
+		});
+		let flushed = telemetry.clone();
+		tokio::task::spawn_blocking(move || flushed.shutdown()).await.unwrap();
+
+		let spans = collector.spans.lock().unwrap();
+		assert_eq!(spans.len(), 2);
+		let request = spans.iter().find(|span| span.name == "request").unwrap();
+		let insert = spans.iter().find(|span| span.name == "bidtracker.insert_bid").unwrap();
+		for span in spans.iter() {
+			assert_eq!(hex(&span.trace_id), TRACE_ID);
+		}
+		assert_eq!(hex(&request.parent_span_id), PARENT_ID);
+		assert_eq!(insert.parent_span_id, request.span_id);
+	}
+
+	fn hex(bytes: &[u8]) -> String {
+		bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+	}
+}
@@ -0,0 +1,258 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{Delivery, RetryPolicy, WebhookStore};
+use crate::bidtracker::BidEvent;
+use crate::persistence::now_millis;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{
+	broadcast::{self, error::RecvError},
+	Notify,
+};
+
+pub const HEADER_SIGNATURE: &str = "X-Webhook-Signature";
+pub const HEADER_EVENT: &str = "X-Webhook-Event";
+pub const HEADER_DELIVERY: &str = "X-Webhook-Delivery";
+
+// How long the delivery loop sleeps if there is nothing pending
+const IDLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Sign a payload with the subscription secret, the result is sent in `X-Webhook-Signature`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+	let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+	mac.update(body);
+	format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// WebhookDispatcher turns tracker events into deliveries and POSTs them to the subscribers
+pub struct WebhookDispatcher {
+	store: Arc<WebhookStore>,
+	client: reqwest::Client,
+	policy: RetryPolicy,
+	wakeup: Notify,
+}
+
+impl WebhookDispatcher {
+	pub fn new(store: Arc<WebhookStore>, policy: RetryPolicy, timeout: Duration) -> WebhookDispatcher {
+		let client = reqwest::Client::builder()
+			.timeout(timeout)
+			.build()
+			.expect("Failed to build the webhook http client");
+		WebhookDispatcher {
+			store,
+			client,
+			policy,
+			wakeup: Notify::new(),
+		}
+	}
+
+	/// Spawn the background tasks which queue and deliver webhooks
+	pub fn spawn(self, events: broadcast::Receiver<BidEvent>) {
+		let dispatcher = Arc::new(self);
+		tokio::spawn(dispatcher.clone().collect_events(events));
+		tokio::spawn(dispatcher.deliver_pending());
+	}
+
+	async fn collect_events(self: Arc<Self>, mut events: broadcast::Receiver<BidEvent>) {
+		loop {
+			match events.recv().await {
+				Ok(event) => {
+					if self.store.enqueue(&event, now_millis()) > 0 {
+						self.wakeup.notify_one();
+					}
+				}
+				Err(RecvError::Lagged(skipped)) => {
+					tracing::warn!("Webhook dispatcher lagged behind, skipped {} events", skipped);
+				}
+				Err(RecvError::Closed) => break,
+			}
+		}
+	}
+
+	async fn deliver_pending(self: Arc<Self>) {
+		loop {
+			for delivery in self.store.due(now_millis()) {
+				match self.attempt(&delivery).await {
+					Ok(()) => self.store.mark_delivered(&delivery.id),
+					Err(e) => {
+						tracing::debug!("Delivery {} to {} failed: {}", delivery.id, delivery.url, e);
+						self.store.mark_failed(&delivery.id, e, now_millis(), &self.policy);
+					}
+				}
+			}
+
+			let sleep = match self.store.next_attempt_at() {
+				Some(at) => Duration::from_millis((at - now_millis()).max(0) as u64),
+				None => IDLE_INTERVAL,
+			};
+			tokio::select! {
+				_ = self.wakeup.notified() => {}
+				_ = tokio::time::sleep(sleep) => {}
+			}
+		}
+	}
+
+	async fn attempt(&self, delivery: &Delivery) -> Result<(), String> {
+		let secret = self
+			.store
+			.subscriptions()
+			.into_iter()
+			.find(|subscription| subscription.id == delivery.webhook_id)
+			.map(|subscription| subscription.secret)
+			.ok_or_else(|| "Webhook was removed".to_string())?;
+		let body = serde_json::to_vec(&delivery.payload).map_err(|e| e.to_string())?;
+
+		let response = self
+			.client
+			.post(&delivery.url)
+			.header(reqwest::header::CONTENT_TYPE, "application/json")
+			.header(HEADER_SIGNATURE, sign(&secret, &body))
+			.header(HEADER_EVENT, delivery.payload.event.as_str())
+			.header(HEADER_DELIVERY, delivery.id.to_string())
+			.body(body)
+			.send()
+			.await
+			.map_err(|e| e.to_string())?;
+
+		if response.status().is_success() {
+			Ok(())
+		} else {
+			Err(format!("Webhook responded with {}", response.status()))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bidtracker::{Bid, BidManagement, BidTracker};
+	use crate::webhooks::WebhookEventKind;
+	use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+	use std::sync::Mutex;
+
+	#[derive(Default)]
+	struct Received {
+		requests: Mutex<Vec<(String, String, web::Bytes)>>,
+	}
+
+	// Local stand-in for a webhook receiver which fails the very first request
+	async fn receiver(req: HttpRequest, body: web::Bytes, received: web::Data<Received>) -> HttpResponse {
+		let header = |name| req.headers().get(name).unwrap().to_str().unwrap().to_string();
+		let mut requests = received.requests.lock().unwrap();
+		requests.push((header(HEADER_SIGNATURE), header(HEADER_EVENT), body));
+		if requests.len() == 1 {
+			HttpResponse::InternalServerError().finish()
+		} else {
+			HttpResponse::Ok().finish()
+		}
+	}
+
+	async fn wait_until(condition: impl Fn() -> bool) {
+		for _ in 0..200 {
+			if condition() {
+				return;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		panic!("Condition not met in time");
+	}
+
+	#[test]
+	fn test_sign() {
+		// Reference value from RFC 4231, test case 2
+		let signature = sign("Jefe", b"what do ya want for nothing?");
+		assert_eq!(
+			signature,
+			"sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+		);
+	}
+
+	#[actix_rt::test]
+	async fn test_delivery_with_retry() {
+		let received = web::Data::new(Received::default());
+		let app_received = received.clone();
+		let server = HttpServer::new(move || {
+			App::new()
+				.app_data(app_received.clone())
+				.route("/hook", web::post().to(receiver))
+		})
+		.workers(1)
+		.bind("127.0.0.1:0")
+		.unwrap();
+		let addr = server.addrs()[0];
+		actix_rt::spawn(server.run());
+
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let mut bm = BidManagement::new(vec![item_uuid]);
+		let store = Arc::new(WebhookStore::open(None).unwrap());
+		let subscription = store.register(
+			format!("http://{}/hook", addr),
+			vec![WebhookEventKind::BidPlaced],
+			"secret".into(),
+		);
+		let policy = RetryPolicy {
+			max_attempts: 3,
+			base_delay: Duration::from_millis(10),
+		};
+		WebhookDispatcher::new(store.clone(), policy, Duration::from_secs(5)).spawn(bm.subscribe());
+
+		bm.insert_bid(&Bid {
+			item_uuid,
+			amount: 30f64,
+			..Bid::default()
+		})
+		.unwrap();
+
+		wait_until(|| received.requests.lock().unwrap().len() == 2).await;
+		wait_until(|| store.pending().is_empty()).await;
+		assert!(store.dead_letters().is_empty());
+
+		let requests = received.requests.lock().unwrap();
+		let (signature, event, body) = &requests[1];
+		assert_eq!(signature, &sign(&subscription.secret, body));
+		assert_eq!(event, "bid_placed");
+		// The retry carries the very same payload
+		assert_eq!(body, &requests[0].2);
+	}
+
+	#[actix_rt::test]
+	async fn test_dead_letter_on_unreachable_webhook() {
+		let item_uuid = uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap();
+		let mut bm = BidManagement::new(vec![item_uuid]);
+		let store = Arc::new(WebhookStore::open(None).unwrap());
+		// Nothing listens on the discard port
+		store.register("http://127.0.0.1:9/hook".into(), vec![], "secret".into());
+		let policy = RetryPolicy {
+			max_attempts: 2,
+			base_delay: Duration::from_millis(10),
+		};
+		WebhookDispatcher::new(store.clone(), policy, Duration::from_secs(1)).spawn(bm.subscribe());
+
+		bm.close_item(&item_uuid).unwrap();
+
+		wait_until(|| store.dead_letters().len() == 1).await;
+		assert_eq!(store.dead_letters()[0].attempts, 2);
+		assert!(store.pending().is_empty());
+	}
+}
@@ -0,0 +1,375 @@
+// MIT License
+//
+// Copyright (c) 2021 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::bidtracker::BidEvent;
+use crate::errors::BidTrackerError;
+use crate::persistence;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf, sync::Mutex, time::Duration};
+use utoipa::ToSchema;
+
+/// Kinds of events a webhook can subscribe to
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventKind {
+	BidPlaced,
+	Outbid,
+	AuctionClosed,
+}
+
+impl WebhookEventKind {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			WebhookEventKind::BidPlaced => "bid_placed",
+			WebhookEventKind::Outbid => "outbid",
+			WebhookEventKind::AuctionClosed => "auction_closed",
+		}
+	}
+
+	/// Map a tracker event to the webhook event it triggers, if any
+	pub fn from_event(event: &BidEvent) -> Option<WebhookEventKind> {
+		match event {
+			BidEvent::BidPlaced { .. } => Some(WebhookEventKind::BidPlaced),
+			// The very first winning bid on an item does not outbid anybody
+			BidEvent::WinningBidChanged { previous: Some(_), .. } => Some(WebhookEventKind::Outbid),
+			BidEvent::WinningBidChanged { previous: None, .. } => None,
+			BidEvent::AuctionClosed { .. } => Some(WebhookEventKind::AuctionClosed),
+			// Retractions are operational, the users affected by them are not notified
+			BidEvent::BidsRetracted { .. } => None,
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct WebhookSubscription {
+	pub id: uuid::Uuid,
+	pub url: String,
+	// An empty list subscribes to every event
+	pub events: Vec<WebhookEventKind>,
+	pub secret: String,
+}
+
+impl WebhookSubscription {
+	pub fn wants(&self, kind: WebhookEventKind) -> bool {
+		self.events.is_empty() || self.events.contains(&kind)
+	}
+}
+
+/// The body POSTed to a webhook url
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct WebhookPayload {
+	pub id: uuid::Uuid,
+	pub event: WebhookEventKind,
+	pub timestamp: i64,
+	pub data: BidEvent,
+}
+
+/// A single payload to be delivered to a single webhook
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct Delivery {
+	pub id: uuid::Uuid,
+	pub webhook_id: uuid::Uuid,
+	pub url: String,
+	pub payload: WebhookPayload,
+	pub attempts: u32,
+	// Unix timestamp in milliseconds of the next delivery attempt
+	pub next_attempt_at: i64,
+	pub last_error: Option<String>,
+}
+
+/// How often and how fast failed deliveries are retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+	pub max_attempts: u32,
+	pub base_delay: Duration,
+}
+
+// Never wait longer than this between two attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+impl RetryPolicy {
+	/// Delay before the next attempt after `attempts` failed ones, doubling every time
+	pub fn backoff(&self, attempts: u32) -> Duration {
+		let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+		self.base_delay.saturating_mul(factor).min(MAX_RETRY_DELAY)
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct WebhookState {
+	subscriptions: HashMap<uuid::Uuid, WebhookSubscription>,
+	pending: Vec<Delivery>,
+	dead_letters: Vec<Delivery>,
+}
+
+/// WebhookStore keeps subscriptions and delivery state, and writes
+/// it to `path` after every change so that it survives restarts
+#[derive(Debug, Default)]
+pub struct WebhookStore {
+	path: Option<PathBuf>,
+	state: Mutex<WebhookState>,
+}
+
+impl WebhookStore {
+	/// Open the store persisted at `path`, or an in-memory one if no path is given
+	pub fn open(path: Option<PathBuf>) -> Result<WebhookStore, BidTrackerError> {
+		let state = match &path {
+			Some(path) => persistence::read_json(path)?,
+			None => WebhookState::default(),
+		};
+		Ok(WebhookStore {
+			path,
+			state: Mutex::new(state),
+		})
+	}
+
+	pub fn register(&self, url: String, events: Vec<WebhookEventKind>, secret: String) -> WebhookSubscription {
+		let subscription = WebhookSubscription {
+			id: uuid::Uuid::new_v4(),
+			url,
+			events,
+			secret,
+		};
+		self.update(|state| {
+			state.subscriptions.insert(subscription.id, subscription.clone());
+		});
+		subscription
+	}
+
+	pub fn subscriptions(&self) -> Vec<WebhookSubscription> {
+		let state = self.state.lock().unwrap();
+		let mut subscriptions: Vec<_> = state.subscriptions.values().cloned().collect();
+		subscriptions.sort_by_key(|subscription| subscription.id);
+		subscriptions
+	}
+
+	/// Remove a subscription together with its pending deliveries
+	pub fn remove(&self, id: &uuid::Uuid) -> Result<(), BidTrackerError> {
+		self.update(|state| {
+			state.pending.retain(|delivery| &delivery.webhook_id != id);
+			state.subscriptions.remove(id)
+		})
+		.map(|_| ())
+		.ok_or_else(|| BidTrackerError::WebhookNotFound(id.to_string()))
+	}
+
+	/// Queue a delivery of `event` for every interested subscription, returns how many were queued
+	pub fn enqueue(&self, event: &BidEvent, now: i64) -> usize {
+		let kind = match WebhookEventKind::from_event(event) {
+			Some(kind) => kind,
+			None => return 0,
+		};
+		let payload = WebhookPayload {
+			id: uuid::Uuid::new_v4(),
+			event: kind,
+			timestamp: now,
+			data: event.clone(),
+		};
+
+		let mut state = self.state.lock().unwrap();
+		let deliveries: Vec<_> = state
+			.subscriptions
+			.values()
+			.filter(|subscription| subscription.wants(kind))
+			.map(|subscription| Delivery {
+				id: uuid::Uuid::new_v4(),
+				webhook_id: subscription.id,
+				url: subscription.url.clone(),
+				payload: payload.clone(),
+				attempts: 0,
+				next_attempt_at: now,
+				last_error: None,
+			})
+			.collect();
+		let queued = deliveries.len();
+		if queued > 0 {
+			state.pending.extend(deliveries);
+			self.persist(&state);
+		}
+		queued
+	}
+
+	/// Deliveries whose next attempt is due at `now`
+	pub fn due(&self, now: i64) -> Vec<Delivery> {
+		let state = self.state.lock().unwrap();
+		state
+			.pending
+			.iter()
+			.filter(|delivery| delivery.next_attempt_at <= now)
+			.cloned()
+			.collect()
+	}
+
+	/// The earliest time at which a pending delivery should be attempted
+	pub fn next_attempt_at(&self) -> Option<i64> {
+		let state = self.state.lock().unwrap();
+		state.pending.iter().map(|delivery| delivery.next_attempt_at).min()
+	}
+
+	pub fn mark_delivered(&self, id: &uuid::Uuid) {
+		self.update(|state| state.pending.retain(|delivery| &delivery.id != id));
+	}
+
+	/// Schedule a retry for a failed delivery, or move it to the dead letters once out of attempts
+	pub fn mark_failed(&self, id: &uuid::Uuid, error: String, now: i64, policy: &RetryPolicy) {
+		self.update(|state| {
+			let position = match state.pending.iter().position(|delivery| &delivery.id == id) {
+				Some(position) => position,
+				None => return,
+			};
+			let delivery = &mut state.pending[position];
+			delivery.attempts += 1;
+			delivery.last_error = Some(error);
+
+			if delivery.attempts >= policy.max_attempts {
+				let delivery = state.pending.remove(position);
+				tracing::warn!(
+					"Giving up on delivery {} to {} after {} attempts",
+					delivery.id,
+					delivery.url,
+					delivery.attempts
+				);
+				state.dead_letters.push(delivery);
+			} else {
+				delivery.next_attempt_at = now + policy.backoff(delivery.attempts).as_millis() as i64;
+			}
+		});
+	}
+
+	#[cfg(test)]
+	pub fn pending(&self) -> Vec<Delivery> {
+		self.state.lock().unwrap().pending.clone()
+	}
+
+	pub fn dead_letters(&self) -> Vec<Delivery> {
+		self.state.lock().unwrap().dead_letters.clone()
+	}
+
+	fn update<T>(&self, f: impl FnOnce(&mut WebhookState) -> T) -> T {
+		let mut state = self.state.lock().unwrap();
+		let result = f(&mut state);
+		self.persist(&state);
+		result
+	}
+
+	fn persist(&self, state: &WebhookState) {
+		if let Some(path) = &self.path {
+			if let Err(e) = persistence::write_json(path, state) {
+				tracing::error!("Failed to persist webhook state to {}: {}", path.display(), e);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bidtracker::Bid;
+
+	fn outbid_event() -> BidEvent {
+		BidEvent::WinningBidChanged {
+			item_uuid: uuid::Uuid::parse_str("b2f9ee6d-79fe-4b14-9c19-35a69a89219a").unwrap(),
+			bid: Bid {
+				amount: 31f64,
+				..Bid::default()
+			},
+			previous: Some(Bid {
+				amount: 30f64,
+				..Bid::default()
+			}),
+		}
+	}
+
+	#[test]
+	fn test_event_filters() {
+		let store = WebhookStore::open(None).unwrap();
+		store.register("http://localhost/all".into(), vec![], "secret".into());
+		store.register(
+			"http://localhost/closed".into(),
+			vec![WebhookEventKind::AuctionClosed],
+			"secret".into(),
+		);
+
+		assert_eq!(store.enqueue(&outbid_event(), 0), 1);
+		assert_eq!(store.pending()[0].url, "http://localhost/all");
+
+		// The first winning bid is not an outbid
+		let first_winner = BidEvent::WinningBidChanged {
+			item_uuid: uuid::Uuid::nil(),
+			bid: Bid::default(),
+			previous: None,
+		};
+		assert_eq!(store.enqueue(&first_winner, 0), 0);
+
+		let closed = BidEvent::AuctionClosed {
+			item_uuid: uuid::Uuid::nil(),
+			winning_bid: None,
+		};
+		assert_eq!(store.enqueue(&closed, 0), 2);
+	}
+
+	#[test]
+	fn test_retries_and_dead_letters() {
+		let store = WebhookStore::open(None).unwrap();
+		let policy = RetryPolicy {
+			max_attempts: 3,
+			base_delay: Duration::from_millis(100),
+		};
+		assert_eq!(policy.backoff(1), Duration::from_millis(100));
+		assert_eq!(policy.backoff(3), Duration::from_millis(400));
+		assert_eq!(policy.backoff(100), MAX_RETRY_DELAY);
+
+		store.register("http://localhost/".into(), vec![], "secret".into());
+		store.enqueue(&outbid_event(), 0);
+		let id = store.due(0)[0].id;
+
+		store.mark_failed(&id, "boom".into(), 0, &policy);
+		assert!(store.due(99).is_empty());
+		assert_eq!(store.next_attempt_at(), Some(100));
+
+		store.mark_failed(&id, "boom".into(), 100, &policy);
+		assert_eq!(store.next_attempt_at(), Some(300));
+
+		store.mark_failed(&id, "boom".into(), 300, &policy);
+		assert!(store.pending().is_empty());
+		assert_eq!(store.dead_letters()[0].attempts, 3);
+		assert_eq!(store.dead_letters()[0].last_error.as_deref(), Some("boom"));
+	}
+
+	#[test]
+	fn test_persistence() {
+		let path = std::env::temp_dir().join(format!("webhooks-{}.json", uuid::Uuid::new_v4()));
+		let store = WebhookStore::open(Some(path.clone())).unwrap();
+		let subscription = store.register("http://localhost/".into(), vec![], "secret".into());
+		store.enqueue(&outbid_event(), 0);
+		drop(store);
+
+		let store = WebhookStore::open(Some(path.clone())).unwrap();
+		assert_eq!(store.subscriptions()[0].id, subscription.id);
+		assert_eq!(store.pending().len(), 1);
+
+		store.remove(&subscription.id).unwrap();
+		assert!(store.pending().is_empty());
+		assert!(store.remove(&subscription.id).is_err());
+		std::fs::remove_file(path).unwrap();
+	}
+}